@@ -0,0 +1,89 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use stroemung::builder::SimulationBuilder;
+use stroemung::grid::presets::{self, Walls};
+use stroemung::simulation::Simulation;
+use stroemung::types::GridSize;
+
+// Fixed so that numbers are comparable between runs and machines. These match
+// the defaults of the command-line flags.
+const GRID_SIZE: GridSize = [100, 20];
+// Ticks to run before measuring, so the flow has developed past the initial
+// state at rest.
+const WARMUP_TICKS: usize = 20;
+
+// The obstacle preset after `WARMUP_TICKS` ticks, saved as JSON so every
+// iteration can start from the same state
+fn warmed_up_simulation() -> String {
+    let inflow = [1.0, 0.0];
+    let grid = presets::obstacle(
+        GRID_SIZE,
+        inflow,
+        (20, GRID_SIZE[1] / 2),
+        5.0,
+        Walls::default(),
+    )
+    .unwrap();
+    let mut sim = SimulationBuilder::new().grid(grid).build().unwrap();
+    for _ in 0..WARMUP_TICKS {
+        sim.run_simulation_tick().unwrap();
+    }
+    sim.to_json_pretty()
+}
+
+fn load(json: &str) -> Simulation {
+    Simulation::from_json_str(json).unwrap()
+}
+
+fn simulation_benchmarks(c: &mut Criterion) {
+    let json = warmed_up_simulation();
+
+    c.bench_function("run_simulation_tick", |b| {
+        b.iter_batched(
+            || load(&json),
+            |mut sim| sim.run_simulation_tick().unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("calculate_f_and_g", |b| {
+        b.iter_batched(
+            || {
+                let mut sim = load(&json);
+                sim.grid
+                    .set_boundary_u_and_v(sim.delt, sim.cell_size())
+                    .unwrap();
+                sim
+            },
+            |mut sim| sim.bench_calculate_f_and_g(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("solve_sor", |b| {
+        b.iter_batched(
+            || {
+                let mut sim = load(&json);
+                sim.grid
+                    .set_boundary_u_and_v(sim.delt, sim.cell_size())
+                    .unwrap();
+                sim.bench_calculate_f_and_g();
+                sim.bench_calculate_rhs();
+                sim
+            },
+            |mut sim| sim.bench_solve_sor().unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("rebuild_boundary_list", |b| {
+        b.iter_batched(
+            || load(&json),
+            |mut sim| sim.grid.rebuild_boundary_list().unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, simulation_benchmarks);
+criterion_main!(benches);
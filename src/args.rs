@@ -1,38 +1,979 @@
 use clap::Parser;
+use log::LevelFilter;
+use thiserror::Error;
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-pub struct Args {
-    #[arg(long, default_value_t = 100)]
+use crate::builder::SimulationBuilder;
+use crate::cell::{BoundaryCell, Cell};
+use crate::grid::presets::Preset;
+use crate::history::DEFAULT_SNAPSHOT_INTERVAL;
+use crate::math::Real;
+use crate::simulation::{
+    NormReset, PressureSolver, SorOrdering, SorStoppingCriterion, TimeScheme,
+};
+use crate::types::{GridIndex, Velocity};
+use crate::visualization::ColorType;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Error, Debug)]
+pub enum ArgsError {
+    #[error("Invalid settings:{}", list(.0))]
+    InvalidSettings(Vec<String>),
+}
+
+fn list(violations: &[String]) -> String {
+    violations
+        .iter()
+        .map(|violation| format!("\n  {}", violation))
+        .collect()
+}
+
+/// The settings that break a constraint, described by their flags
+#[derive(Debug, Default)]
+struct Violations(Vec<String>);
+
+impl Violations {
+    /// Record a violation of `constraint` by the `flag` setting, unless
+    /// `valid`
+    fn check(
+        &mut self,
+        valid: bool,
+        flag: &str,
+        value: impl fmt::Display,
+        constraint: &str,
+    ) {
+        if !valid {
+            self.0
+                .push(format!("--{} {}, got {}", flag, constraint, value));
+        }
+    }
+
+    fn into_result(self) -> Result<(), ArgsError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ArgsError::InvalidSettings(self.0))
+        }
+    }
+}
+
+/// Parse a pair of `what`s given as `x,y`
+fn parse_pair<T: FromStr>(s: &str, what: &str) -> Result<(T, T), String> {
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse()
+            .map_err(|_| format!("expected {}, got `{}`", what, value))
+    };
+    match s.split_once(',') {
+        Some((x, y)) => Ok((parse(x)?, parse(y)?)),
+        None => Err(format!("expected `x,y`, got `{}`", s)),
+    }
+}
+
+fn parse_grid_index(s: &str) -> Result<GridIndex, String> {
+    parse_pair(s, "a cell index")
+}
+
+fn parse_velocity(s: &str) -> Result<Velocity, String> {
+    parse_pair(s, "a number").map(|(u, v)| [u, v])
+}
+
+/// Parse a whole number that is at least `min`
+fn parse_at_least<T: FromStr + PartialOrd + fmt::Display>(
+    s: &str,
+    min: T,
+) -> Result<T, String> {
+    match s.parse() {
+        Ok(n) if n >= min => Ok(n),
+        Ok(n) => Err(format!("expected at least {}, got {}", min, n)),
+        Err(_) => Err(format!("expected a whole number, got `{}`", s)),
+    }
+}
+
+fn parse_cell_count(s: &str) -> Result<usize, String> {
+    parse_at_least(s, 3)
+}
+
+fn parse_positive(s: &str) -> Result<u32, String> {
+    parse_at_least(s, 1)
+}
+
+impl FromStr for SorStoppingCriterion {
+    type Err = String;
+
+    /// Parse `absolute-or-improved`, `absolute:<epsilon>` or `relative:<factor>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_value = |value: &str| {
+            value
+                .parse()
+                .map_err(|_| format!("expected a number, got `{}`", value))
+        };
+        match s.split_once(':') {
+            None if s == "absolute-or-improved" => {
+                Ok(SorStoppingCriterion::AbsoluteOrImproved)
+            }
+            Some(("absolute", epsilon)) => Ok(SorStoppingCriterion::Absolute {
+                epsilon: parse_value(epsilon)?,
+            }),
+            Some(("relative", factor)) => Ok(SorStoppingCriterion::Relative {
+                factor: parse_value(factor)?,
+            }),
+            _ => Err(format!(
+                "expected `absolute-or-improved`, `absolute:<epsilon>` or \
+                 `relative:<factor>`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SorStoppingCriterion {
+    /// Write the criterion the way `from_str` parses it
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SorStoppingCriterion::AbsoluteOrImproved => write!(f, "absolute-or-improved"),
+            SorStoppingCriterion::Absolute { epsilon } => {
+                write!(f, "absolute:{}", epsilon)
+            }
+            SorStoppingCriterion::Relative { factor } => write!(f, "relative:{}", factor),
+        }
+    }
+}
+
+impl FromStr for NormReset {
+    type Err = String;
+
+    /// Parse `never`, `every-tick` or `every:<n>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "never" => Ok(NormReset::Never),
+            None if s == "every-tick" => Ok(NormReset::EveryTick),
+            Some(("every", n)) => n
+                .parse()
+                .map(NormReset::EveryNTicks)
+                .map_err(|_| format!("expected a number of ticks, got `{}`", n)),
+            _ => Err(format!(
+                "expected `never`, `every-tick` or `every:<n>`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NormReset {
+    /// Write the policy the way `from_str` parses it
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NormReset::Never => write!(f, "never"),
+            NormReset::EveryTick => write!(f, "every-tick"),
+            NormReset::EveryNTicks(n) => write!(f, "every:{}", n),
+        }
+    }
+}
+
+impl FromStr for Cell {
+    type Err = String;
+
+    /// Parse `fluid`, `noslip`, `symmetry`, `outflow`, `inflow:<u>,<v>` or
+    /// `convective-outflow:<speed>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let boundary = match s.split_once(':') {
+            None if s == "fluid" => return Ok(Cell::Fluid),
+            None if s == "noslip" => BoundaryCell::NoSlip,
+            None if s == "symmetry" => BoundaryCell::Symmetry,
+            None if s == "outflow" => BoundaryCell::Outflow,
+            Some(("inflow", velocity)) => BoundaryCell::Inflow {
+                velocity: parse_velocity(velocity)?,
+            },
+            Some(("convective-outflow", speed)) => BoundaryCell::ConvectiveOutflow {
+                speed: speed
+                    .parse()
+                    .map_err(|_| format!("expected a number, got `{}`", speed))?,
+            },
+            _ => {
+                return Err(format!(
+                    "expected `fluid`, `noslip`, `symmetry`, `outflow`, \
+                     `inflow:<u>,<v>` or `convective-outflow:<speed>`, got `{}`",
+                    s
+                ))
+            }
+        };
+        Ok(Cell::Boundary(boundary))
+    }
+}
+
+/// The SOR relaxation factor requested on the command line
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Omega {
+    Auto,
+    Value(Real),
+}
+
+impl FromStr for Omega {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Omega::Auto);
+        }
+        s.parse()
+            .map(Omega::Value)
+            .map_err(|_| format!("expected a number or `auto`, got `{}`", s))
+    }
+}
+
+// In config files, omega is either a number or the string `auto`.
+impl Serialize for Omega {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Omega::Auto => serializer.serialize_str("auto"),
+            Omega::Value(omega) => omega.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Omega {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Value(Real),
+            Text(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Value(omega) => Ok(Omega::Value(omega)),
+            Repr::Text(text) => text.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+/// The boundary type of an outer wall of the channel presets
+#[derive(Debug, Copy, Clone, PartialEq, clap::ValueEnum)]
+pub enum WallType {
+    #[value(name = "noslip")]
+    NoSlip,
+    #[value(name = "freeslip")]
+    FreeSlip,
+    Outflow,
+}
+
+impl From<WallType> for BoundaryCell {
+    fn from(wall: WallType) -> Self {
+        match wall {
+            WallType::NoSlip => BoundaryCell::NoSlip,
+            WallType::FreeSlip => BoundaryCell::Symmetry,
+            WallType::Outflow => BoundaryCell::Outflow,
+        }
+    }
+}
+
+/// The parameters of a new simulation: the grid and geometry of the presets,
+/// the fluid and the pressure solver
+#[derive(clap::Args, Debug)]
+pub struct SimulationArgs {
+    /// Number of cells across, including the walls. At least 3.
+    #[arg(long, default_value_t = 100, value_parser = parse_cell_count)]
     pub x_cells: usize,
 
-    #[arg(long, default_value_t = 20)]
+    /// Number of cells down, including the walls. At least 3.
+    #[arg(long, default_value_t = 20, value_parser = parse_cell_count)]
     pub y_cells: usize,
 
     #[arg(long, default_value_t = 0.1)]
-    pub x_cell_width: f64,
+    pub x_cell_width: Real,
 
     #[arg(long, default_value_t = 0.2)]
-    pub y_cell_height: f64,
+    pub y_cell_height: Real,
 
     #[arg(long, default_value_t = 0.005)]
-    pub delta_t: f64,
+    pub delta_t: Real,
 
+    /// Upwind blending factor between 0 (central differences) and 1 (upwind
+    /// differences)
     #[arg(long, default_value_t = 0.9)]
-    pub gamma: f64,
+    pub gamma: Real,
+
+    /// Set gamma every tick to the smallest value that is stable for the
+    /// current velocities, instead of using --gamma
+    #[arg(long)]
+    pub auto_gamma: bool,
 
     #[arg(long, default_value_t = 100.0)]
-    pub reynolds: f64,
+    pub reynolds: Real,
+
+    /// Density of the fluid
+    #[arg(long, default_value_t = 1.0)]
+    pub density: Real,
+
+    /// Horizontal velocity of the fluid entering the preset channels
+    #[arg(long, default_value_t = 1.0)]
+    pub inflow_u: Real,
+
+    /// Vertical velocity of the fluid entering the preset channels
+    #[arg(long, default_value_t = 0.0)]
+    pub inflow_v: Real,
+
+    /// Start the fluid of the presets moving at `u,v` instead of at rest
+    #[arg(long, value_parser = parse_velocity)]
+    pub initial_velocity: Option<Velocity>,
+
+    /// Horizontal position of the center of the obstacle preset's obstacle
+    #[arg(long, default_value_t = 20)]
+    pub obstacle_x: usize,
+
+    /// Vertical position of the center of the obstacle preset's obstacle.
+    /// Defaults to the middle of the channel.
+    #[arg(long)]
+    pub obstacle_y: Option<usize>,
+
+    /// Radius of the obstacle preset's obstacle, in cells
+    #[arg(long, default_value_t = 5.0)]
+    pub obstacle_radius: Real,
+
+    /// Boundary type of the top wall of the channel presets: `noslip`,
+    /// `freeslip` or `outflow`
+    #[arg(long, value_enum, default_value_t = WallType::NoSlip)]
+    pub top_wall: WallType,
+
+    /// Boundary type of the bottom wall of the channel presets
+    #[arg(long, value_enum, default_value_t = WallType::NoSlip)]
+    pub bottom_wall: WallType,
+
+    /// Body force (e.g. gravity) in the x direction
+    #[arg(long, default_value_t = 0.0)]
+    pub gx: Real,
+
+    /// Body force (e.g. gravity) in the y direction. Positive values point
+    /// down the screen.
+    #[arg(long, default_value_t = 0.0)]
+    pub gy: Real,
 
     #[arg(long, default_value_t = 0.001)]
-    pub sor_epsilon: f64,
+    pub sor_epsilon: Real,
 
-    #[arg(long, default_value_t = 100)]
+    #[arg(long, default_value_t = 100, value_parser = parse_positive)]
     pub sor_max_iterations: u32,
 
-    #[arg(long, default_value_t = 1.7)]
-    pub omega: f64,
+    /// Stop the simulation if the SOR norm grows for this many consecutive
+    /// iterations
+    #[arg(long, default_value_t = 10, value_parser = parse_positive)]
+    pub sor_divergence_limit: u32,
+
+    /// When SOR accepts the pressure: `absolute-or-improved` (stop below
+    /// --sor-epsilon or the norm of the first tick), `absolute:<epsilon>` or
+    /// `relative:<factor>` (stop below factor times the norm at the start of
+    /// the tick)
+    #[arg(long, default_value = "absolute-or-improved")]
+    pub sor_stopping_criterion: SorStoppingCriterion,
+
+    /// Stop SOR once the norm is below this fraction of the norm at the start
+    /// of the tick, which doesn't depend on the scale of the flow. Short for
+    /// `--sor-stopping-criterion relative:<factor>`.
+    #[arg(long, conflicts_with = "sor_stopping_criterion")]
+    pub sor_rel_epsilon: Option<Real>,
+
+    /// When to recompute the norm used by `absolute-or-improved`: `never`,
+    /// `every-tick` or `every:<n>` ticks
+    #[arg(long, default_value = "never")]
+    pub norm_reset: NormReset,
 
+    /// Stop SOR early once an iteration reduces the norm by less than this
+    /// fraction. 0 disables the check.
+    #[arg(long, default_value_t = 0.0)]
+    pub sor_stagnation_factor: Real,
+
+    /// SOR relaxation factor, or `auto` to derive the optimal value from the
+    /// grid size
+    #[arg(long, default_value = "1.7")]
+    pub omega: Omega,
+
+    /// Start each SOR solve with plain Gauss-Seidel (omega = 1) and ramp up to
+    /// --omega once the norm is decreasing, which helps SOR converge on the
+    /// first ticks of a new flow
+    #[arg(long)]
+    pub omega_ramp: bool,
+
+    /// The order in which SOR visits the cells of the grid
+    #[arg(long, value_enum, default_value_t = SorOrdering::Lexicographic)]
+    pub sor_ordering: SorOrdering,
+
+    /// How to solve the pressure equation. `jacobi` is much slower to converge
+    /// than SOR, but doesn't depend on the order of the cells.
+    #[arg(long, value_enum, default_value_t = PressureSolver::Sor)]
+    pub pressure_solver: PressureSolver,
+
+    /// How to advance the velocity. `explicit-euler` skips the pressure solve,
+    /// so the flow isn't kept divergence-free and soon becomes unstable.
+    #[arg(long, value_enum, default_value_t = TimeScheme::ProjectionSor)]
+    pub time_scheme: TimeScheme,
+}
+
+/// The settings of the `run` and `simulate` subcommands
+#[derive(Parser, Debug)]
+pub struct Args {
+    #[command(flatten)]
+    pub simulation: SimulationArgs,
+
+    /// Load a saved simulation instead of a preset. Files ending in `.msgpack`
+    /// or `.mpk` are read as MessagePack, anything else as JSON.
     #[arg(long)]
     pub sim_file: Option<String>,
+
+    /// Thicken boundary walls that are one cell thick in `--sim-file` and in
+    /// simulations loaded in the UI, instead of refusing to load them
+    #[arg(long)]
+    pub repair_geometry: bool,
+
+    /// Where Ctrl+S saves the simulation in the UI, in the format matching
+    /// the extension like `--sim-file`. Load it again with `--sim-file`.
+    #[arg(long, default_value = "simulation.json")]
+    pub save_file: String,
+
+    /// How many recent states the UI keeps to roll back to when the
+    /// simulation diverges. 0 disables rolling back. Overrides the memory
+    /// budget of the simulation, which keeps 5 by default.
+    #[arg(long)]
+    pub snapshots: Option<usize>,
+
+    /// How many ticks of stats the simulation keeps. Overrides the memory
+    /// budget of the simulation, which keeps 1000 by default.
+    #[arg(long)]
+    pub stats_history: Option<usize>,
+
+    /// Keep a state to roll back to every this many ticks
+    #[arg(long, default_value_t = DEFAULT_SNAPSHOT_INTERVAL)]
+    pub snapshot_interval: u32,
+
+    /// Initial width of the window, in pixels. The grid is scaled to fit.
+    #[arg(long, default_value_t = 1280)]
+    pub window_width: i32,
+
+    /// Initial height of the window, in pixels
+    #[arg(long, default_value_t = 800)]
+    pub window_height: i32,
+
+    /// Run the simulation without opening a window, like the `simulate`
+    /// subcommand
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of ticks to run in headless mode
+    #[arg(long, default_value_t = 100)]
+    pub ticks: u32,
+
+    /// Record the pressure and speed of the cell at `x,y` every tick. Can be
+    /// given more than once.
+    #[arg(long = "probe", value_parser = parse_grid_index)]
+    pub probes: Vec<GridIndex>,
+
+    /// Write the probe history of a headless run to this CSV file instead of
+    /// standard output
+    #[arg(long)]
+    pub probe_csv: Option<String>,
+
+    /// Average the velocity and pressure of every cell over all ticks of a
+    /// headless run and write the means and variances to this CSV file
+    #[arg(long)]
+    pub time_average: Option<String>,
+
+    /// Print the average time of each stage of a tick every this many ticks of
+    /// a headless run
+    #[arg(long)]
+    pub timing_interval: Option<u32>,
+
+    /// Print how long each stage of a tick took at the end of a headless run.
+    /// Requires the `profiling` feature.
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Only log warnings and errors, and don't show a progress bar during a
+    /// headless run. Without the `progress` feature, there is no progress bar
+    /// to begin with.
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log more: `-v` adds a line for every tick, `-vv` everything else.
+    /// `RUST_LOG` takes precedence over this and `--quiet`.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Read settings from this TOML file. The keys are the names of the flags
+    /// without the leading `--`, and flags on the command line take
+    /// precedence.
+    #[arg(long)]
+    pub config: Option<String>,
+
+    /// Print the settings the simulation would run with as TOML, for use with
+    /// --config, and exit
+    #[arg(long)]
+    pub dump_config: bool,
+}
+
+/// What `convert` reads
+#[derive(Debug, Copy, Clone, PartialEq, clap::ValueEnum)]
+pub enum InputKind {
+    /// A saved simulation
+    Simulation,
+    /// A grid without the parameters of a simulation, which are taken from
+    /// the flags instead
+    Grid,
+}
+
+/// What `convert` writes
+#[derive(Debug, Copy, Clone, PartialEq, clap::ValueEnum)]
+pub enum OutputKind {
+    /// A simulation that can be loaded with `--sim-file`
+    Simulation,
+    /// Only the grid of the simulation
+    Grid,
+    /// The cell types as text, one character per cell and one line per row
+    Ascii,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct InfoArgs {
+    /// The saved simulation. Files ending in `.gz` are decompressed first.
+    pub file: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    /// The file to convert. Files ending in `.gz` are decompressed first, and
+    /// the extension before that picks the format like for `--sim-file`.
+    pub input: String,
+
+    /// Where to write the result, in the format that matches its extension.
+    /// A `.gz` ending compresses it.
+    pub output: String,
+
+    /// What the input file holds
+    #[arg(long, value_enum, default_value_t = InputKind::Simulation)]
+    pub from: InputKind,
+
+    /// What to write
+    #[arg(long, value_enum, default_value_t = OutputKind::Simulation)]
+    pub to: OutputKind,
+
+    /// Thicken boundary walls that are one cell thick in the input, instead
+    /// of refusing to convert it
+    #[arg(long)]
+    pub repair_geometry: bool,
+
+    #[command(flatten)]
+    pub simulation: SimulationArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RenderArgs {
+    /// The saved simulation. Files ending in `.gz` are decompressed first.
+    pub file: String,
+
+    /// The PNG file to write
+    #[arg(long)]
+    pub out: String,
+
+    /// Which field to color the cells by
+    #[arg(long, value_enum, default_value_t = ColorType::Pressure)]
+    pub color_type: ColorType,
+
+    /// Put zero in the middle of the pressure colors
+    #[arg(long)]
+    pub center_zero: bool,
+
+    /// Size of a cell in the image, in pixels
+    #[arg(long, default_value_t = 4, value_parser = parse_positive)]
+    pub scale: u32,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Open a window and run the simulation. This is the default.
+    Run(Args),
+    /// Run the simulation without opening a window
+    Simulate(Args),
+    /// Print the parameters and state of a saved simulation
+    Info(InfoArgs),
+    /// Convert between simulation and grid files, JSON and MessagePack,
+    /// compressed and not, or to ASCII
+    Convert(ConvertArgs),
+    /// Draw a saved simulation to a PNG file, without running it
+    Render(RenderArgs),
+}
+
+// Without a subcommand, the flags are those of `run`.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: Args,
+}
+
+impl SimulationArgs {
+    // Record the settings that would make the simulation fail, most of them
+    // with NaNs or panics long after it started. The comparisons are written
+    // so that NaN is rejected as well.
+    fn check(&self, violations: &mut Violations) {
+        violations.check(
+            self.x_cells >= 3,
+            "x-cells",
+            self.x_cells,
+            "must be at least 3",
+        );
+        violations.check(
+            self.y_cells >= 3,
+            "y-cells",
+            self.y_cells,
+            "must be at least 3",
+        );
+        for (flag, value) in [
+            ("x-cell-width", self.x_cell_width),
+            ("y-cell-height", self.y_cell_height),
+            ("delta-t", self.delta_t),
+            ("reynolds", self.reynolds),
+            ("density", self.density),
+            ("sor-epsilon", self.sor_epsilon),
+        ] {
+            violations.check(value > 0.0, flag, value, "must be greater than 0");
+        }
+        violations.check(
+            (0.0..=1.0).contains(&self.gamma),
+            "gamma",
+            self.gamma,
+            "must be between 0 and 1",
+        );
+        if let Omega::Value(omega) = self.omega {
+            violations.check(
+                omega > 0.0 && omega < 2.0,
+                "omega",
+                omega,
+                "must be between 0 and 2 (exclusive)",
+            );
+        }
+        violations.check(
+            self.sor_max_iterations >= 1,
+            "sor-max-iterations",
+            self.sor_max_iterations,
+            "must be at least 1",
+        );
+        violations.check(
+            self.sor_divergence_limit >= 1,
+            "sor-divergence-limit",
+            self.sor_divergence_limit,
+            "must be at least 1",
+        );
+        violations.check(
+            (0.0..1.0).contains(&self.sor_stagnation_factor),
+            "sor-stagnation-factor",
+            self.sor_stagnation_factor,
+            "must be at least 0 and less than 1",
+        );
+        if let Some(factor) = self.sor_rel_epsilon {
+            violations.check(
+                factor > 0.0 && factor < 1.0,
+                "sor-rel-epsilon",
+                factor,
+                "must be between 0 and 1 (exclusive)",
+            );
+        }
+        match self.sor_stopping_criterion {
+            SorStoppingCriterion::AbsoluteOrImproved => {}
+            SorStoppingCriterion::Absolute { epsilon } => violations.check(
+                epsilon > 0.0,
+                "sor-stopping-criterion",
+                self.sor_stopping_criterion,
+                "must have an epsilon greater than 0",
+            ),
+            SorStoppingCriterion::Relative { factor } => violations.check(
+                factor > 0.0 && factor < 1.0,
+                "sor-stopping-criterion",
+                self.sor_stopping_criterion,
+                "must have a factor between 0 and 1 (exclusive)",
+            ),
+        }
+        violations.check(
+            self.norm_reset != NormReset::EveryNTicks(0),
+            "norm-reset",
+            self.norm_reset,
+            "must reset every 1 or more ticks",
+        );
+    }
+
+    /// Check that the settings can make a simulation, listing every setting
+    /// that can't in the error
+    pub fn validate(&self) -> Result<(), ArgsError> {
+        let mut violations = Violations::default();
+        self.check(&mut violations);
+        violations.into_result()
+    }
+
+    /// Warnings about settings that are valid but likely to make the flow
+    /// unstable
+    ///
+    /// The time step is compared with the CFL limit for the inflow velocity,
+    /// which is 1 by default, and the limit of the diffusion term for the
+    /// Reynolds number.
+    pub fn stability_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let [delx, dely] = [self.x_cell_width, self.y_cell_height];
+        let speed = self.inflow_u.hypot(self.inflow_v);
+        if speed > 0.0 {
+            let cfl_limit = delx.min(dely) / speed;
+            if self.delta_t > cfl_limit {
+                warnings.push(format!(
+                    "--delta-t {} is above the CFL limit of {:.4} for the inflow speed \
+                     of {} on cells of {}x{}",
+                    self.delta_t, cfl_limit, speed, delx, dely
+                ));
+            }
+        }
+        let diffusion_limit =
+            self.reynolds / 2.0 / (1.0 / delx.powi(2) + 1.0 / dely.powi(2));
+        if self.delta_t > diffusion_limit {
+            warnings.push(format!(
+                "--delta-t {} is above the diffusion limit of {:.4} for a Reynolds \
+                 number of {} on cells of {}x{}",
+                self.delta_t, diffusion_limit, self.reynolds, delx, dely
+            ));
+        }
+        warnings
+    }
+}
+
+impl Args {
+    // Record the obstacle and probes that aren't inside the grid of the
+    // presets
+    fn check_grid(&self, violations: &mut Violations) {
+        let simulation = &self.simulation;
+        let [w, h] = [simulation.x_cells, simulation.y_cells];
+        violations.check(
+            simulation.obstacle_x > 0 && simulation.obstacle_x < w.saturating_sub(1),
+            "obstacle-x",
+            simulation.obstacle_x,
+            &format!(
+                "must be inside the walls, between 1 and {}",
+                w.saturating_sub(2)
+            ),
+        );
+        if let Some(obstacle_y) = simulation.obstacle_y {
+            violations.check(
+                obstacle_y > 0 && obstacle_y < h.saturating_sub(1),
+                "obstacle-y",
+                obstacle_y,
+                &format!(
+                    "must be inside the walls, between 1 and {}",
+                    h.saturating_sub(2)
+                ),
+            );
+        }
+        // The obstacle can't fill the whole height of the channel.
+        let channel = h.saturating_sub(2) as Real;
+        violations.check(
+            simulation.obstacle_radius >= 0.0
+                && 2.0 * simulation.obstacle_radius < channel,
+            "obstacle-radius",
+            simulation.obstacle_radius,
+            &format!("must be at least 0 and less than {}", channel / 2.0),
+        );
+        for &(x, y) in &self.probes {
+            violations.check(
+                x < w && y < h,
+                "probe",
+                format!("{},{}", x, y),
+                &format!("must be inside the {}x{} grid", w, h),
+            );
+        }
+        // Headless runs start from the obstacle preset, whose obstacle can
+        // still be invalid inside the walls, e.g. a single boundary cell for
+        // radii of 1 or less.
+        if violations.0.is_empty() {
+            let obstacle = SimulationBuilder::from(simulation)
+                .grid((Preset::Obstacle, [w, h]))
+                .build();
+            if let Err(err) = obstacle {
+                violations.check(
+                    false,
+                    "obstacle-radius",
+                    simulation.obstacle_radius,
+                    &format!("must draw a valid obstacle ({})", err),
+                );
+            }
+        }
+    }
+
+    /// Check that the settings can run, listing every setting that can't in
+    /// the error
+    ///
+    /// Besides `SimulationArgs::validate`, this checks that the obstacle of
+    /// the obstacle preset is valid and, like the probes, inside the grid,
+    /// unless the grid comes from `--sim-file`.
+    pub fn validate(&self) -> Result<(), ArgsError> {
+        let mut violations = Violations::default();
+        self.simulation.check(&mut violations);
+        // A saved simulation brings its own grid.
+        if self.sim_file.is_none() {
+            self.check_grid(&mut violations);
+        }
+        violations.into_result()
+    }
+
+    /// The level to log at unless `RUST_LOG` says otherwise
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Warn,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_level() {
+        let level =
+            |args: &[&str]| Args::parse_from([&["stroemung"], args].concat()).log_level();
+        assert_eq!(level(&[]), LevelFilter::Info);
+        assert_eq!(level(&["--quiet"]), LevelFilter::Warn);
+        assert_eq!(level(&["-v"]), LevelFilter::Debug);
+        assert_eq!(level(&["-vv"]), LevelFilter::Trace);
+        assert!(Args::try_parse_from(["stroemung", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn parse_cell() {
+        assert_eq!("fluid".parse(), Ok(Cell::Fluid));
+        assert_eq!("noslip".parse(), Ok(Cell::Boundary(BoundaryCell::NoSlip)));
+        assert_eq!(
+            "inflow:1.5, -0.5".parse(),
+            Ok(Cell::Boundary(BoundaryCell::Inflow {
+                velocity: [1.5, -0.5]
+            }))
+        );
+        assert_eq!(
+            "convective-outflow:2".parse(),
+            Ok(Cell::Boundary(BoundaryCell::ConvectiveOutflow {
+                speed: 2.0
+            }))
+        );
+        assert!("inflow:1".parse::<Cell>().is_err());
+        assert!("wall".parse::<Cell>().is_err());
+    }
+
+    fn parse(args: &[&str]) -> Args {
+        Args::parse_from([&["stroemung"], args].concat())
+    }
+
+    fn violations(args: &[&str]) -> Vec<String> {
+        match parse(args).validate() {
+            Ok(()) => Vec::new(),
+            Err(ArgsError::InvalidSettings(violations)) => violations,
+        }
+    }
+
+    #[test]
+    fn rejected_settings() {
+        assert!(parse(&[]).validate().is_ok());
+        for flags in [
+            ["--x-cells", "1"],
+            ["--y-cells", "2"],
+            ["--sor-max-iterations", "0"],
+            ["--sor-divergence-limit", "0"],
+            ["--x-cells", "many"],
+        ] {
+            assert!(Args::try_parse_from([&["stroemung"], &flags[..]].concat()).is_err());
+        }
+
+        for (flags, flag) in [
+            (vec!["--omega", "3.0"], "--omega"),
+            (vec!["--omega", "0"], "--omega"),
+            (vec!["--delta-t", "0"], "--delta-t"),
+            (vec!["--delta-t", "NaN"], "--delta-t"),
+            (vec!["--gamma", "1.5"], "--gamma"),
+            (vec!["--reynolds=-100"], "--reynolds"),
+            (vec!["--density", "0"], "--density"),
+            (vec!["--x-cell-width", "0"], "--x-cell-width"),
+            (vec!["--sor-epsilon", "0"], "--sor-epsilon"),
+            (vec!["--sor-rel-epsilon", "1"], "--sor-rel-epsilon"),
+            (
+                vec!["--sor-stopping-criterion", "relative:2"],
+                "--sor-stopping-criterion",
+            ),
+            (
+                vec!["--sor-stagnation-factor", "1"],
+                "--sor-stagnation-factor",
+            ),
+            (vec!["--norm-reset", "every:0"], "--norm-reset"),
+            (vec!["--obstacle-x", "0"], "--obstacle-x"),
+            (vec!["--x-cells", "15"], "--obstacle-x"),
+            (vec!["--obstacle-y", "19"], "--obstacle-y"),
+            (vec!["--obstacle-radius", "9"], "--obstacle-radius"),
+            (vec!["--obstacle-radius", "1"], "--obstacle-radius"),
+            (vec!["--probe", "100,5"], "--probe"),
+        ] {
+            let violations = violations(&flags);
+            assert_eq!(violations.len(), 1, "{:?}: {:?}", flags, violations);
+            assert!(violations[0].starts_with(flag), "{:?}", violations);
+        }
+
+        // A saved simulation brings its own grid, so the obstacle and probes
+        // aren't checked against the flags.
+        assert!(violations(&["--sim-file", "a.json", "--probe", "100,5"]).is_empty());
+    }
+
+    #[test]
+    fn every_violation_is_listed() {
+        let mut args = parse(&["--omega", "3.0", "--delta-t", "0", "--gamma=-1"]);
+        // Config files skip the parsers of the flags.
+        args.simulation.x_cells = 1;
+        let err = args.validate().unwrap_err();
+        let ArgsError::InvalidSettings(violations) = &err;
+        assert_eq!(violations.len(), 5, "{:?}", violations);
+        let message = err.to_string();
+        for flag in [
+            "--x-cells",
+            "--omega",
+            "--delta-t",
+            "--gamma",
+            "--obstacle-x",
+        ] {
+            assert!(message.contains(&format!("\n  {} ", flag)), "{}", message);
+        }
+        // The convert subcommand has no obstacle.
+        assert!(args.simulation.validate().is_err());
+        assert!(parse(&["--x-cells", "15"]).simulation.validate().is_ok());
+    }
+
+    #[test]
+    fn stability_warnings() {
+        assert!(parse(&[]).simulation.stability_warnings().is_empty());
+
+        let warnings = parse(&["--delta-t", "0.15"])
+            .simulation
+            .stability_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].contains("CFL limit of 0.1000"),
+            "{:?}",
+            warnings
+        );
+        // Faster inflow needs a shorter time step.
+        let fast = parse(&["--delta-t", "0.05", "--inflow-u", "3", "--inflow-v", "4"]);
+        assert_eq!(fast.simulation.stability_warnings().len(), 1);
+        let still = parse(&["--delta-t", "0.15", "--inflow-u", "0"]);
+        assert!(still.simulation.stability_warnings().is_empty());
+
+        let viscous = parse(&["--reynolds", "0.01"])
+            .simulation
+            .stability_warnings();
+        assert_eq!(viscous.len(), 1);
+        assert!(viscous[0].contains("diffusion limit"), "{:?}", viscous);
+    }
 }
@@ -0,0 +1,343 @@
+use std::io::{self, Write};
+
+use ndarray::{Array, Zip};
+
+use crate::grid::SimulationGrid;
+use crate::math::Real;
+use crate::simulation::Simulation;
+use crate::types::{GridArray, GridSize};
+
+/// Running means of the fields of a simulation over many ticks
+///
+/// The means are updated with Welford's algorithm, which stays accurate over
+/// long runs where summing the values and dividing at the end would lose
+/// precision. The variance is only kept if asked for, since it doubles the
+/// memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldAverager {
+    samples: u32,
+    mean_u: GridArray<Real>,
+    mean_v: GridArray<Real>,
+    mean_pressure: GridArray<Real>,
+    /// The sums of the squared differences from the means of u, v and the
+    /// pressure, if the variance is kept
+    squared_differences: Option<[GridArray<Real>; 3]>,
+}
+
+// Add `value` as sample number `samples` to `mean` and, if given, the sum of
+// squared differences from it.
+fn update(
+    samples: u32,
+    mean: &mut GridArray<Real>,
+    squared_differences: Option<&mut GridArray<Real>>,
+    value: &GridArray<Real>,
+) {
+    let weight = 1.0 / samples as Real;
+    match squared_differences {
+        Some(squared_differences) => Zip::from(mean)
+            .and(squared_differences)
+            .and(value)
+            .for_each(|mean, squared_differences, &value| {
+                let difference = value - *mean;
+                *mean += difference * weight;
+                *squared_differences += difference * (value - *mean);
+            }),
+        None => Zip::from(mean)
+            .and(value)
+            .for_each(|mean, &value| *mean += (value - *mean) * weight),
+    }
+}
+
+impl FieldAverager {
+    /// Create an averager for a grid of `size` cells
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size of the grid of the simulation to average
+    /// * `variance` - Whether to keep the variance as well as the means
+    pub fn new(size: GridSize, variance: bool) -> Self {
+        FieldAverager {
+            samples: 0,
+            mean_u: Array::zeros(size),
+            mean_v: Array::zeros(size),
+            mean_pressure: Array::zeros(size),
+            squared_differences: variance
+                .then(|| [Array::zeros(size), Array::zeros(size), Array::zeros(size)]),
+        }
+    }
+
+    /// The number of ticks accumulated so far
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Add the current fields of `simulation` to the means
+    ///
+    /// Panics if the grid of `simulation` isn't the size the averager was
+    /// created for.
+    pub fn accumulate(&mut self, simulation: &Simulation) {
+        self.accumulate_grid(&simulation.grid);
+    }
+
+    fn accumulate_grid(&mut self, grid: &SimulationGrid) {
+        self.samples += 1;
+        let [u, v, pressure] = match &mut self.squared_differences {
+            Some([u, v, pressure]) => [Some(u), Some(v), Some(pressure)],
+            None => [None, None, None],
+        };
+        update(self.samples, &mut self.mean_u, u, &grid.u);
+        update(self.samples, &mut self.mean_v, v, &grid.v);
+        update(
+            self.samples,
+            &mut self.mean_pressure,
+            pressure,
+            &grid.pressure,
+        );
+    }
+
+    pub fn mean_u(&self) -> &GridArray<Real> {
+        &self.mean_u
+    }
+
+    pub fn mean_v(&self) -> &GridArray<Real> {
+        &self.mean_v
+    }
+
+    pub fn mean_pressure(&self) -> &GridArray<Real> {
+        &self.mean_pressure
+    }
+
+    // The population variance from a sum of squared differences
+    fn variance(&self, field: usize) -> Option<GridArray<Real>> {
+        let squared_differences = &self.squared_differences.as_ref()?[field];
+        Some(squared_differences / Real::max(self.samples as Real, 1.0))
+    }
+
+    /// The variance of u, if the averager keeps it
+    pub fn variance_u(&self) -> Option<GridArray<Real>> {
+        self.variance(0)
+    }
+
+    /// The variance of v, if the averager keeps it
+    pub fn variance_v(&self) -> Option<GridArray<Real>> {
+        self.variance(1)
+    }
+
+    /// The variance of the pressure, if the averager keeps it
+    pub fn variance_pressure(&self) -> Option<GridArray<Real>> {
+        self.variance(2)
+    }
+
+    /// Write the means of every cell as CSV, one row per cell, with the
+    /// variances in extra columns if the averager keeps them
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let variances = self
+            .variance_u()
+            .zip(self.variance_v())
+            .zip(self.variance_pressure());
+        write!(writer, "x,y,mean_u,mean_v,mean_pressure")?;
+        if variances.is_some() {
+            write!(writer, ",variance_u,variance_v,variance_pressure")?;
+        }
+        writeln!(writer)?;
+        for ((x, y), mean_u) in self.mean_u.indexed_iter() {
+            let idx = (x, y);
+            write!(
+                writer,
+                "{},{},{},{},{}",
+                x, y, mean_u, self.mean_v[idx], self.mean_pressure[idx]
+            )?;
+            if let Some(((u, v), pressure)) = &variances {
+                write!(writer, ",{},{},{}", u[idx], v[idx], pressure[idx])?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Time averages of the flow that a `Simulation` keeps up to date itself
+///
+/// Attach one with `Simulation::attach_field_accumulator`. After the first
+/// `warm_up_ticks` ticks, which let the flow settle, every tick is added to
+/// the means. The fluctuation of a field is the root mean square of its
+/// difference from the mean, which is zero for a steady flow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldAccumulator {
+    warm_up_ticks: u32,
+    ticks: u32,
+    averager: FieldAverager,
+}
+
+impl FieldAccumulator {
+    /// Create an accumulator for a grid of `size` cells that skips the first
+    /// `warm_up_ticks` ticks
+    pub fn new(size: GridSize, warm_up_ticks: u32) -> Self {
+        FieldAccumulator {
+            warm_up_ticks,
+            ticks: 0,
+            averager: FieldAverager::new(size, true),
+        }
+    }
+
+    // Add the fields at the end of a tick, once the warm-up is over
+    pub(crate) fn record(&mut self, grid: &SimulationGrid) {
+        self.ticks += 1;
+        if self.ticks > self.warm_up_ticks {
+            self.averager.accumulate_grid(grid);
+        }
+    }
+
+    // Forget the averages and start the warm-up again
+    pub(crate) fn restart(&mut self) {
+        *self =
+            FieldAccumulator::new(self.averager.mean_u.dim().into(), self.warm_up_ticks);
+    }
+
+    /// The number of ticks in the means, which leaves out the warm-up
+    pub fn samples(&self) -> u32 {
+        self.averager.samples()
+    }
+
+    pub fn mean_u(&self) -> &GridArray<Real> {
+        self.averager.mean_u()
+    }
+
+    pub fn mean_v(&self) -> &GridArray<Real> {
+        self.averager.mean_v()
+    }
+
+    pub fn mean_pressure(&self) -> &GridArray<Real> {
+        self.averager.mean_pressure()
+    }
+
+    pub fn fluctuation_u(&self) -> GridArray<Real> {
+        self.averager.variance_u().unwrap().mapv(Real::sqrt)
+    }
+
+    pub fn fluctuation_v(&self) -> GridArray<Real> {
+        self.averager.variance_v().unwrap().mapv(Real::sqrt)
+    }
+
+    pub fn fluctuation_pressure(&self) -> GridArray<Real> {
+        self.averager.variance_pressure().unwrap().mapv(Real::sqrt)
+    }
+
+    /// The means and the variances of every cell, see `FieldAverager`
+    pub fn averager(&self) -> &FieldAverager {
+        &self.averager
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::SimulationBuilder;
+    use crate::grid::presets::{self, Walls};
+    use crate::math::assert_close;
+
+    #[test]
+    fn constant_field() {
+        let size = [6, 4];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::empty(size))
+            .build()
+            .unwrap();
+        sim.grid.u.fill(0.75);
+        sim.grid.v.fill(-0.25);
+        sim.grid.pressure.fill(2.0);
+
+        let mut averager = FieldAverager::new(size, true);
+        for _ in 0..10 {
+            averager.accumulate(&sim);
+        }
+        assert_eq!(averager.samples(), 10);
+        assert!(averager.mean_u().iter().all(|&u| u == 0.75));
+        assert!(averager.mean_v().iter().all(|&v| v == -0.25));
+        assert!(averager.mean_pressure().iter().all(|&p| p == 2.0));
+        for variance in [
+            averager.variance_u(),
+            averager.variance_v(),
+            averager.variance_pressure(),
+        ] {
+            assert!(variance.unwrap().iter().all(|&variance| variance == 0.0));
+        }
+
+        let mut csv = Vec::new();
+        averager.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 1 + 6 * 4);
+        assert!(csv.starts_with("x,y,mean_u,mean_v,mean_pressure,variance_u"));
+    }
+
+    #[test]
+    fn changing_field() {
+        let size = [3, 3];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::empty(size))
+            .build()
+            .unwrap();
+        let mut averager = FieldAverager::new(size, true);
+        for u in [1.0, 2.0, 3.0, 6.0] {
+            sim.grid.u.fill(u);
+            averager.accumulate(&sim);
+        }
+        assert_close!(averager.mean_u()[(1, 1)], 3.0);
+        assert_close!(averager.variance_u().unwrap()[(1, 1)], 3.5);
+
+        // Without the variance, the means are the same.
+        let mut means_only = FieldAverager::new(size, false);
+        for u in [1.0, 2.0, 3.0, 6.0] {
+            sim.grid.u.fill(u);
+            means_only.accumulate(&sim);
+        }
+        assert_eq!(means_only.mean_u(), averager.mean_u());
+        assert!(means_only.variance_u().is_none());
+    }
+
+    #[test]
+    fn steady_flow() {
+        // Uniform flow between free-slip walls doesn't change.
+        let size = [30, 12];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(
+                size,
+                [1.0, 0.0],
+                Walls::free_stream(),
+            ))
+            .build()
+            .unwrap();
+        sim.grid.set_uniform_velocity([1.0, 0.0]);
+        sim.attach_field_accumulator(3);
+        for _ in 0..10 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        let accumulator = sim.field_accumulator().unwrap();
+        assert_eq!(accumulator.samples(), 7);
+        let tolerance = 1e-5;
+        for (mean, field) in [
+            (accumulator.mean_u(), &sim.grid.u),
+            (accumulator.mean_v(), &sim.grid.v),
+            (accumulator.mean_pressure(), &sim.grid.pressure),
+        ] {
+            for (mean, value) in mean.iter().zip(field) {
+                assert_close!(*mean, *value, tolerance);
+            }
+        }
+        for fluctuation in [
+            accumulator.fluctuation_u(),
+            accumulator.fluctuation_v(),
+            accumulator.fluctuation_pressure(),
+        ] {
+            assert!(fluctuation.iter().all(|&rms| rms < tolerance));
+        }
+
+        // Starting the flow over starts the averages over too.
+        sim.reset_fields();
+        sim.run_simulation_tick().unwrap();
+        assert_eq!(sim.field_accumulator().unwrap().samples(), 0);
+        assert_eq!(sim.detach_field_accumulator().unwrap().samples(), 0);
+        assert!(sim.field_accumulator().is_none());
+    }
+}
@@ -0,0 +1,419 @@
+use crate::args::{Omega, SimulationArgs};
+use crate::grid::presets::{self, NacaProfile, Preset, Walls};
+use crate::grid::{SimulationGrid, UnfinalizedSimulationGrid};
+use crate::history::MemoryBudget;
+use crate::math::Real;
+use crate::simulation::{
+    NormReset, PressureSolver, Simulation, SimulationError, SorOrdering,
+    SorStoppingCriterion, TimeScheme, UnfinalizedSimulation,
+};
+use crate::types::{GridIndex, GridSize, Velocity};
+
+/// Where the grid of a `SimulationBuilder` comes from
+#[derive(Debug)]
+pub enum GridSource {
+    /// A grid that was built or loaded elsewhere
+    Grid(Box<UnfinalizedSimulationGrid>),
+    /// One of the presets, with this many cells including the outer ring
+    Preset(Preset, GridSize),
+}
+
+impl From<UnfinalizedSimulationGrid> for GridSource {
+    fn from(grid: UnfinalizedSimulationGrid) -> Self {
+        GridSource::Grid(Box::new(grid))
+    }
+}
+
+impl From<SimulationGrid> for GridSource {
+    fn from(grid: SimulationGrid) -> Self {
+        GridSource::Grid(Box::new(grid.into()))
+    }
+}
+
+impl From<(Preset, GridSize)> for GridSource {
+    fn from((preset, size): (Preset, GridSize)) -> Self {
+        GridSource::Preset(preset, size)
+    }
+}
+
+/// Builds a new `Simulation`, starting from the same defaults as the command
+/// line
+///
+/// Every parameter has a setter, and `build` checks them the same way as
+/// loading a simulation file does.
+#[derive(Debug)]
+pub struct SimulationBuilder {
+    grid: GridSource,
+    cell_size: [Real; 2],
+    delt: Real,
+    gamma: Real,
+    auto_gamma: bool,
+    reynolds: Real,
+    density: Real,
+    body_force: [Real; 2],
+    sor_absolute_epsilon: Real,
+    max_iterations: u32,
+    sor_divergence_limit: u32,
+    sor_stagnation_factor: Real,
+    sor_stopping_criterion: SorStoppingCriterion,
+    norm_reset: NormReset,
+    omega: Omega,
+    omega_ramp: bool,
+    forced_cells: Vec<(GridIndex, Velocity)>,
+    sor_ordering: SorOrdering,
+    pressure_solver: PressureSolver,
+    time_scheme: TimeScheme,
+    memory_budget: MemoryBudget,
+    inflow: Velocity,
+    walls: Walls,
+    obstacle_x: usize,
+    obstacle_y: Option<usize>,
+    obstacle_radius: Real,
+    initial_velocity: Option<Velocity>,
+}
+
+impl Default for SimulationBuilder {
+    /// The obstacle preset of 100x20 cells, with the defaults of the command
+    /// line for everything else
+    fn default() -> Self {
+        SimulationBuilder {
+            grid: GridSource::Preset(Preset::Obstacle, [100, 20]),
+            cell_size: [0.1, 0.2],
+            delt: 0.005,
+            gamma: 0.9,
+            auto_gamma: false,
+            reynolds: 100.0,
+            density: 1.0,
+            body_force: [0.0, 0.0],
+            sor_absolute_epsilon: 0.001,
+            max_iterations: 100,
+            sor_divergence_limit: 10,
+            sor_stagnation_factor: 0.0,
+            sor_stopping_criterion: SorStoppingCriterion::AbsoluteOrImproved,
+            norm_reset: NormReset::Never,
+            omega: Omega::Value(1.7),
+            omega_ramp: false,
+            forced_cells: Vec::new(),
+            sor_ordering: SorOrdering::Lexicographic,
+            pressure_solver: PressureSolver::Sor,
+            time_scheme: TimeScheme::ProjectionSor,
+            memory_budget: MemoryBudget::default(),
+            inflow: [1.0, 0.0],
+            walls: Walls::default(),
+            obstacle_x: 20,
+            obstacle_y: None,
+            obstacle_radius: 5.0,
+            initial_velocity: None,
+        }
+    }
+}
+
+impl From<&SimulationArgs> for SimulationBuilder {
+    /// The parameters given on the command line, with the obstacle preset as
+    /// the grid
+    fn from(args: &SimulationArgs) -> Self {
+        SimulationBuilder {
+            grid: GridSource::Preset(Preset::Obstacle, [args.x_cells, args.y_cells]),
+            cell_size: [args.x_cell_width, args.y_cell_height],
+            delt: args.delta_t,
+            gamma: args.gamma,
+            auto_gamma: args.auto_gamma,
+            reynolds: args.reynolds,
+            density: args.density,
+            body_force: [args.gx, args.gy],
+            sor_absolute_epsilon: args.sor_epsilon,
+            max_iterations: args.sor_max_iterations,
+            sor_divergence_limit: args.sor_divergence_limit,
+            sor_stagnation_factor: args.sor_stagnation_factor,
+            sor_stopping_criterion: match args.sor_rel_epsilon {
+                Some(factor) => SorStoppingCriterion::Relative { factor },
+                None => args.sor_stopping_criterion,
+            },
+            norm_reset: args.norm_reset,
+            omega: args.omega,
+            omega_ramp: args.omega_ramp,
+            forced_cells: Vec::new(),
+            sor_ordering: args.sor_ordering,
+            pressure_solver: args.pressure_solver,
+            time_scheme: args.time_scheme,
+            memory_budget: MemoryBudget::default(),
+            inflow: [args.inflow_u, args.inflow_v],
+            walls: Walls {
+                top: args.top_wall.into(),
+                bottom: args.bottom_wall.into(),
+            },
+            obstacle_x: args.obstacle_x,
+            obstacle_y: args.obstacle_y,
+            obstacle_radius: args.obstacle_radius,
+            initial_velocity: args.initial_velocity,
+        }
+    }
+}
+
+// A consuming setter for each field
+macro_rules! setters {
+    ($($(#[$doc:meta])* $field:ident: $type:ty),* $(,)?) => {
+        $(
+            $(#[$doc])*
+            pub fn $field(mut self, $field: $type) -> Self {
+                self.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+impl SimulationBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `grid`, which is either an existing grid or a preset and its size
+    ///
+    /// The size of the simulation is the size of the grid.
+    pub fn grid(mut self, grid: impl Into<GridSource>) -> Self {
+        self.grid = grid.into();
+        self
+    }
+
+    setters!(
+        /// Width and height of a cell
+        cell_size: [Real; 2],
+        /// Length of a tick
+        delt: Real,
+        /// Upwind blending factor between 0 (central differences) and 1
+        /// (upwind differences)
+        gamma: Real,
+        /// Set gamma every tick to the smallest stable value instead
+        auto_gamma: bool,
+        reynolds: Real,
+        density: Real,
+        /// Body force (e.g. gravity) in the x and y directions
+        body_force: [Real; 2],
+        sor_absolute_epsilon: Real,
+        /// Maximum number of SOR iterations per tick
+        max_iterations: u32,
+        sor_divergence_limit: u32,
+        sor_stagnation_factor: Real,
+        sor_stopping_criterion: SorStoppingCriterion,
+        norm_reset: NormReset,
+        /// The SOR relaxation factor, or `Omega::Auto` to use
+        /// `Simulation::optimal_omega` for the grid
+        omega: Omega,
+        omega_ramp: bool,
+        /// Fluid cells that are held at a fixed velocity
+        forced_cells: Vec<(GridIndex, Velocity)>,
+        sor_ordering: SorOrdering,
+        pressure_solver: PressureSolver,
+        time_scheme: TimeScheme,
+        memory_budget: MemoryBudget,
+        /// Velocity of the fluid entering the presets
+        inflow: Velocity,
+        /// Boundary types of the top and bottom walls of the channel presets
+        walls: Walls,
+        /// Horizontal position of the center of the obstacle preset's obstacle
+        obstacle_x: usize,
+        /// Vertical position of the center of the obstacle preset's obstacle.
+        /// `None` is the middle of the channel.
+        obstacle_y: Option<usize>,
+        /// Radius of the obstacle preset's obstacle, in cells
+        obstacle_radius: Real,
+        /// Start the fluid of the presets moving at this velocity instead of
+        /// at rest
+        initial_velocity: Option<Velocity>,
+    );
+
+    /// Create the simulation, checking the parameters and the grid
+    pub fn build(self) -> Result<Simulation, SimulationError> {
+        let grid = match self.grid {
+            GridSource::Grid(grid) => SimulationGrid::try_from(*grid)?,
+            GridSource::Preset(preset, size) => {
+                let mut grid = match preset {
+                    Preset::Obstacle => {
+                        let center =
+                            (self.obstacle_x, self.obstacle_y.unwrap_or(size[1] / 2));
+                        presets::obstacle(
+                            size,
+                            self.inflow,
+                            center,
+                            self.obstacle_radius,
+                            self.walls,
+                        )?
+                    }
+                    Preset::Inflow | Preset::Channel => {
+                        presets::simple_inflow(size, self.inflow, self.walls)
+                    }
+                    // The middle fifth of the bottom wall, at the inflow speed
+                    Preset::Jet => presets::bottom_jet(
+                        size,
+                        (2 * size[0] / 5, (3 * size[0] / 5).saturating_sub(1)),
+                        self.inflow[0],
+                    )?,
+                    // The cylinders are half as wide as the space between them.
+                    Preset::Cylinders => {
+                        let rows = 2;
+                        let radius = (size[1] - 2) as Real / rows as Real / 4.0;
+                        presets::cylinder_array(
+                            size,
+                            self.inflow,
+                            rows,
+                            3,
+                            radius,
+                            self.walls,
+                        )?
+                    }
+                    // A quarter of the channel long
+                    Preset::Airfoil => presets::naca_airfoil(
+                        size,
+                        self.inflow,
+                        NacaProfile::from_digits(2412).unwrap(),
+                        size[0] as Real / 4.0,
+                        8.0,
+                        self.walls,
+                    )?,
+                };
+                if let Some(velocity) = self.initial_velocity {
+                    grid.set_uniform_velocity(velocity);
+                }
+                grid
+            }
+        };
+        let mut sim = Simulation::try_from(UnfinalizedSimulation {
+            size: grid.size,
+            cell_size: self.cell_size,
+            delt: self.delt,
+            gamma: self.gamma,
+            auto_gamma: self.auto_gamma,
+            reynolds: self.reynolds,
+            density: self.density,
+            body_force: self.body_force,
+            sor_absolute_epsilon: self.sor_absolute_epsilon,
+            max_iterations: self.max_iterations,
+            sor_divergence_limit: self.sor_divergence_limit,
+            sor_stagnation_factor: self.sor_stagnation_factor,
+            sor_stopping_criterion: self.sor_stopping_criterion,
+            initial_norm_squared: None,
+            norm_reset: self.norm_reset,
+            iterations: 0,
+            time: 0.0,
+            omega: match self.omega {
+                Omega::Value(omega) => omega,
+                // Replaced below once the grid is known.
+                Omega::Auto => 1.0,
+            },
+            omega_ramp: self.omega_ramp,
+            forced_cells: self.forced_cells,
+            sor_ordering: self.sor_ordering,
+            pressure_solver: self.pressure_solver,
+            time_scheme: self.time_scheme,
+            memory_budget: self.memory_budget,
+            inflow_ramp: None,
+            grid: grid.into(),
+        })?;
+        if self.omega == Omega::Auto {
+            sim.set_omega(sim.optimal_omega());
+        }
+        Ok(sim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use crate::cell::{BoundaryCell, Cell};
+    use crate::grid::SimulationGridError;
+    use clap::Parser;
+
+    #[test]
+    fn defaults() {
+        let sim = SimulationBuilder::new().build().unwrap();
+        assert_eq!(sim.size, [100, 20]);
+        assert_eq!(sim.cell_size(), [0.1, 0.2]);
+        assert_eq!(sim.delt, 0.005);
+        assert_eq!(sim.gamma, 0.9);
+        assert_eq!(sim.reynolds, 100.0);
+        assert_eq!(sim.omega(), 1.7);
+        assert_eq!(sim.pressure_solver, PressureSolver::Sor);
+        assert_eq!(
+            sim.grid.cell_type[(20, 10)],
+            Cell::Boundary(BoundaryCell::NoSlip)
+        );
+
+        // The defaults are the same as those of the command line.
+        let args = Args::parse_from(["stroemung"]);
+        let from_args = SimulationBuilder::from(&args.simulation).build().unwrap();
+        assert_eq!(sim.approx_eq(&from_args, 0.0), None);
+    }
+
+    #[test]
+    fn relative_epsilon_arg() {
+        let args = Args::parse_from(["stroemung", "--sor-rel-epsilon", "0.01"]);
+        let sim = SimulationBuilder::from(&args.simulation).build().unwrap();
+        assert_eq!(
+            sim.sor_stopping_criterion,
+            SorStoppingCriterion::Relative { factor: 0.01 }
+        );
+        let both = Args::try_parse_from([
+            "stroemung",
+            "--sor-rel-epsilon",
+            "0.01",
+            "--sor-stopping-criterion",
+            "absolute:0.001",
+        ]);
+        assert!(both.is_err());
+    }
+
+    #[test]
+    fn setters() {
+        let sim = SimulationBuilder::new()
+            .grid((Preset::Channel, [30, 10]))
+            .delt(0.01)
+            .reynolds(500.0)
+            .omega(Omega::Auto)
+            .initial_velocity(Some([0.5, 0.0]))
+            .build()
+            .unwrap();
+        assert_eq!(sim.size, [30, 10]);
+        assert_eq!(sim.delt, 0.01);
+        assert_eq!(sim.reynolds, 500.0);
+        assert_eq!(sim.omega(), sim.optimal_omega());
+        assert_eq!(sim.grid.u[(15, 5)], 0.5);
+
+        let grid = presets::empty([8, 6]);
+        let sim = SimulationBuilder::new().grid(grid.clone()).build().unwrap();
+        assert_eq!(sim.size, [8, 6]);
+        assert_eq!(sim.grid.cell_type, grid.cell_type);
+    }
+
+    #[test]
+    fn obstacle_presets() {
+        // The default size of the command line
+        for preset in [Preset::Cylinders, Preset::Airfoil] {
+            let sim = SimulationBuilder::new().grid((preset, [100, 20])).build();
+            assert!(sim.is_ok(), "{:?}: {:?}", preset, sim.err());
+        }
+    }
+
+    #[test]
+    fn validation() {
+        let omega = SimulationBuilder::new().omega(Omega::Value(2.5)).build();
+        assert!(matches!(
+            omega,
+            Err(SimulationError::InvalidParameter { field: "omega", .. })
+        ));
+        let delt = SimulationBuilder::new().delt(-1.0).build();
+        assert!(matches!(
+            delt,
+            Err(SimulationError::InvalidParameter { field: "delt", .. })
+        ));
+        // Too narrow for the jet
+        let jet = SimulationBuilder::new().grid((Preset::Jet, [3, 5])).build();
+        assert!(matches!(
+            jet,
+            Err(SimulationError::GridError(
+                SimulationGridError::PatchOutOfBoundsError(..)
+            ))
+        ));
+    }
+}
@@ -1,13 +1,25 @@
 use serde::{Deserialize, Serialize};
 
+use crate::math::Real;
 use crate::types::Velocity;
 use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum BoundaryCell {
-    Inflow { velocity: Velocity },
+    Inflow {
+        velocity: Velocity,
+    },
     Outflow,
+    /// An outflow boundary that advects the flow out of the domain at the
+    /// characteristic `speed`, which reflects less of the flow back into the
+    /// domain than the zero-gradient `Outflow`.
+    ConvectiveOutflow {
+        speed: Real,
+    },
     NoSlip,
+    /// A symmetry plane: no flow through the boundary and no change in the
+    /// tangential velocity or pressure across it, like a free-slip wall.
+    Symmetry,
 }
 
 impl fmt::Display for BoundaryCell {
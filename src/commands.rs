@@ -0,0 +1,310 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use macroquad::color::colors;
+use macroquad::prelude::Image;
+use ndarray::Array;
+use thiserror::Error;
+
+use crate::args::{ConvertArgs, InfoArgs, InputKind, OutputKind, RenderArgs};
+use crate::builder::SimulationBuilder;
+use crate::cell::{BoundaryCell, Cell};
+use crate::grid::{SimulationGrid, UnfinalizedSimulationGrid};
+use crate::simulation::{Format, Simulation, SimulationError, UnfinalizedSimulation};
+use crate::visualization::{render_simulation, ColorScale};
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error(transparent)]
+    SimulationError(#[from] SimulationError),
+    #[error("Could not read or write a file: `{0}`")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not write the image: `{0}`")]
+    ImageError(#[from] image::ImageError),
+}
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"))
+}
+
+/// The format of the file at `path`, from the extension before `.gz` for
+/// compressed files
+pub fn file_format(path: &Path) -> Format {
+    match path.file_stem() {
+        Some(stem) if is_gzip(path) => Format::from_path(Path::new(stem)),
+        _ => Format::from_path(path),
+    }
+}
+
+/// Open `path` for reading, decompressing it if it ends in `.gz`
+pub fn open_file(path: &Path) -> std::io::Result<Box<dyn Read>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(if is_gzip(path) {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Create `path` and fill it with `write`, compressing it if it ends in `.gz`
+pub fn write_file(
+    path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), CommandError>,
+) -> Result<(), CommandError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    if is_gzip(path) {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        write(&mut encoder)?;
+        writer = encoder.finish()?;
+    } else {
+        write(&mut writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Load a saved simulation in the format that matches the extension of
+/// `path`, see `file_format`
+pub fn load_simulation(path: &Path) -> Result<Simulation, CommandError> {
+    let reader = open_file(path)?;
+    Ok(Simulation::from_reader_format(reader, file_format(path))?)
+}
+
+/// Load a saved simulation like `load_simulation`, but thicken the boundary
+/// walls that are one cell thick instead of rejecting them, see
+/// `SimulationGrid::repair_thin_boundaries`
+pub fn load_repaired_simulation(path: &Path) -> Result<Simulation, CommandError> {
+    let reader = open_file(path)?;
+    let mut unfinalized: UnfinalizedSimulation = match file_format(path) {
+        Format::Json => serde_json::from_reader(reader).map_err(SimulationError::from)?,
+        Format::MessagePack => {
+            rmp_serde::from_read(reader).map_err(SimulationError::from)?
+        }
+    };
+    repair_grid(&mut unfinalized.grid, path)?;
+    Ok(Simulation::try_from(unfinalized)?)
+}
+
+// Thicken the thin walls of the grid loaded from `path` and say how many
+// cells that took
+fn repair_grid(
+    grid: &mut UnfinalizedSimulationGrid,
+    path: &Path,
+) -> Result<(), CommandError> {
+    let added = grid
+        .repair_thin_boundaries()
+        .map_err(SimulationError::from)?;
+    if added > 0 {
+        log::info!(
+            "Thickened the thin walls of {} with {} cells",
+            path.display(),
+            added
+        );
+    }
+    Ok(())
+}
+
+/// Save the simulation in the format that matches the extension of `path`,
+/// see `file_format`
+pub fn save_simulation(sim: &Simulation, path: &Path) -> Result<(), CommandError> {
+    write_file(path, |writer| {
+        Ok(sim.to_writer_format(writer, file_format(path))?)
+    })
+}
+
+// Load a grid, thickening its thin walls if `repair` is set
+fn load_grid(path: &Path, repair: bool) -> Result<SimulationGrid, CommandError> {
+    let reader = open_file(path)?;
+    let mut unfinalized: UnfinalizedSimulationGrid = match file_format(path) {
+        Format::Json => serde_json::from_reader(reader).map_err(SimulationError::from)?,
+        Format::MessagePack => {
+            rmp_serde::from_read(reader).map_err(SimulationError::from)?
+        }
+    };
+    if repair {
+        repair_grid(&mut unfinalized, path)?;
+    }
+    Ok(SimulationGrid::try_from(unfinalized).map_err(SimulationError::from)?)
+}
+
+fn save_grid(grid: &SimulationGrid, path: &Path) -> Result<(), CommandError> {
+    let unfinalized = UnfinalizedSimulationGrid::from(grid.clone());
+    write_file(path, |writer| {
+        match file_format(path) {
+            Format::Json => serde_json::to_writer_pretty(writer, &unfinalized)
+                .map_err(SimulationError::SerializationError)?,
+            Format::MessagePack => rmp_serde::encode::write_named(writer, &unfinalized)
+                .map_err(SimulationError::from)?,
+        }
+        Ok(())
+    })
+}
+
+/// The name of the kind of `cell`, as used by `Cell::from_str`
+fn cell_name(cell: &Cell) -> &'static str {
+    match cell {
+        Cell::Fluid => "fluid",
+        Cell::Boundary(BoundaryCell::NoSlip) => "noslip",
+        Cell::Boundary(BoundaryCell::Symmetry) => "symmetry",
+        Cell::Boundary(BoundaryCell::Inflow { .. }) => "inflow",
+        Cell::Boundary(BoundaryCell::Outflow) => "outflow",
+        Cell::Boundary(BoundaryCell::ConvectiveOutflow { .. }) => "convective-outflow",
+    }
+}
+
+fn cell_char(cell: &Cell) -> char {
+    match cell {
+        Cell::Fluid => '.',
+        Cell::Boundary(BoundaryCell::NoSlip) => '#',
+        Cell::Boundary(BoundaryCell::Symmetry) => 'S',
+        Cell::Boundary(BoundaryCell::Inflow { .. }) => 'I',
+        Cell::Boundary(BoundaryCell::Outflow) => 'O',
+        Cell::Boundary(BoundaryCell::ConvectiveOutflow { .. }) => 'C',
+    }
+}
+
+/// The cell types of `grid` as text, with one line per row from the top
+/// (`y = 0`) down
+///
+/// Fluid cells are `.`, no-slip walls `#`, symmetry planes `S`, inflows `I`,
+/// outflows `O` and convective outflows `C`.
+pub fn grid_to_ascii(grid: &SimulationGrid) -> String {
+    let [w, h] = grid.size;
+    let mut ascii = String::with_capacity((w + 1) * h);
+    for y in 0..h {
+        ascii.extend((0..w).map(|x| cell_char(&grid.cell_type[(x, y)])));
+        ascii.push('\n');
+    }
+    ascii
+}
+
+/// A description of the saved simulation, for the `info` subcommand
+pub fn info(args: &InfoArgs) -> Result<String, CommandError> {
+    let sim = load_simulation(Path::new(&args.file))?;
+    let mut lines = vec![format!(
+        "{}: time {} after {} ticks",
+        args.file, sim.time, sim.iterations
+    )];
+    lines.extend(sim.parameter_summary());
+
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for cell in &sim.grid.cell_type {
+        let name = cell_name(cell);
+        match counts.iter_mut().find(|(kind, _)| *kind == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        }
+    }
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect();
+    lines.push(format!("Cells: {}", counts.join(", ")));
+    let [p_min, p_max] = sim.grid.pressure_range;
+    let [s_min, s_max] = sim.grid.speed_range;
+    lines.push(format!(
+        "Pressure from {} to {}, speed from {} to {}",
+        p_min, p_max, s_min, s_max
+    ));
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Convert between file formats, for the `convert` subcommand
+pub fn convert(args: &ConvertArgs) -> Result<(), CommandError> {
+    let input = Path::new(&args.input);
+    let sim = match args.from {
+        InputKind::Simulation if args.repair_geometry => load_repaired_simulation(input)?,
+        InputKind::Simulation => load_simulation(input)?,
+        InputKind::Grid => SimulationBuilder::from(&args.simulation)
+            .grid(load_grid(input, args.repair_geometry)?)
+            .build()?,
+    };
+    let output = Path::new(&args.output);
+    match args.to {
+        OutputKind::Simulation => save_simulation(&sim, output),
+        OutputKind::Grid => save_grid(&sim.grid, output),
+        OutputKind::Ascii => write_file(output, |writer| {
+            Ok(writer.write_all(grid_to_ascii(&sim.grid).as_bytes())?)
+        }),
+    }
+}
+
+/// Draw a saved simulation to a PNG file, for the `render` subcommand
+pub fn render(args: &RenderArgs) -> Result<(), CommandError> {
+    let sim = load_simulation(Path::new(&args.file))?;
+    let [w, h] = sim.size;
+    let mut image = Image::gen_image_color(w as u16, h as u16, colors::WHITE);
+    let mut scratch = Array::zeros(sim.size);
+    let scale = ColorScale {
+        center_zero: args.center_zero,
+        ..Default::default()
+    };
+    render_simulation(&sim, &mut image, w, h, args.color_type, scale, &mut scratch);
+
+    // Blow every cell up into a square of `args.scale` pixels.
+    let factor = args.scale as usize;
+    let mut pixels = Vec::with_capacity(w * h * factor * factor * 4);
+    for y in 0..h * factor {
+        for x in 0..w * factor {
+            let offset = 4 * ((y / factor) * w + x / factor);
+            pixels.extend_from_slice(&image.bytes[offset..offset + 4]);
+        }
+    }
+    image::save_buffer_with_format(
+        &args.out,
+        &pixels,
+        (w * factor) as u32,
+        (h * factor) as u32,
+        image::ColorType::Rgba8,
+        image::ImageFormat::Png,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_formats() {
+        assert_eq!(file_format(Path::new("sim.json.gz")), Format::Json);
+        assert_eq!(
+            file_format(Path::new("sim.msgpack.gz")),
+            Format::MessagePack
+        );
+        assert_eq!(file_format(Path::new("sim.mpk")), Format::MessagePack);
+        assert!(is_gzip(Path::new("sim.json.GZ")));
+        assert!(!is_gzip(Path::new("sim.json")));
+    }
+
+    #[test]
+    fn ascii_grid() {
+        let sim =
+            load_simulation(Path::new("src/test_data/simple_simulation.json")).unwrap();
+        let ascii = grid_to_ascii(&sim.grid);
+        assert_eq!(ascii, ".....\n".repeat(7));
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let sim =
+            load_simulation(Path::new("src/test_data/simple_simulation.json")).unwrap();
+        for name in ["sim.json.gz", "sim.msgpack.gz"] {
+            let path = std::env::temp_dir().join(format!(
+                "stroemung-{}-{}",
+                std::process::id(),
+                name
+            ));
+            save_simulation(&sim, &path).unwrap();
+            let loaded = load_simulation(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.grid.size, sim.grid.size);
+            assert_eq!(loaded.grid.cell_type, sim.grid.cell_type);
+        }
+    }
+}
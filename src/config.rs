@@ -0,0 +1,455 @@
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::args::{Args, Omega, WallType};
+use crate::math::Real;
+use crate::simulation::{
+    NormReset, PressureSolver, SorOrdering, SorStoppingCriterion, TimeScheme,
+};
+use crate::types::{GridIndex, Velocity};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read the config file `{0}`: {1}")]
+    IoError(String, std::io::Error),
+    #[error("Invalid config file `{0}`: {1}")]
+    ParseError(String, toml::de::Error),
+}
+
+/// How a field of `Args` is stored in a `Config`, where every setting is
+/// optional
+trait Setting {
+    type Value;
+
+    fn get(&self) -> Option<Self::Value>;
+    fn set(&mut self, value: Self::Value);
+}
+
+macro_rules! settings {
+    ($($type:ty),* $(,)?) => {
+        $(impl Setting for $type {
+            type Value = $type;
+
+            fn get(&self) -> Option<$type> {
+                Some(self.clone())
+            }
+
+            fn set(&mut self, value: $type) {
+                *self = value;
+            }
+        })*
+    };
+}
+
+settings!(
+    bool,
+    u8,
+    u32,
+    i32,
+    usize,
+    Real,
+    String,
+    Vec<GridIndex>,
+    Omega,
+    WallType,
+    SorStoppingCriterion,
+    NormReset,
+    SorOrdering,
+    PressureSolver,
+    TimeScheme,
+);
+
+// Flags that are unset by default are left out of a config file when unset.
+impl<T: Clone> Setting for Option<T> {
+    type Value = T;
+
+    fn get(&self) -> Option<T> {
+        self.clone()
+    }
+
+    fn set(&mut self, value: T) {
+        *self = Some(value);
+    }
+}
+
+/// A setting that is written the same way as on the command line
+trait FlagValue: Sized {
+    fn parse_flag(s: &str) -> Result<Self, String>;
+    fn to_flag(&self) -> String;
+}
+
+macro_rules! value_enums {
+    ($($type:ty),*) => {
+        $(impl FlagValue for $type {
+            fn parse_flag(s: &str) -> Result<Self, String> {
+                <$type as ValueEnum>::from_str(s, false)
+            }
+
+            fn to_flag(&self) -> String {
+                self.to_possible_value()
+                    .map(|value| value.get_name().to_owned())
+                    .unwrap_or_default()
+            }
+        })*
+    };
+}
+
+value_enums!(WallType, SorOrdering, PressureSolver, TimeScheme);
+
+macro_rules! parsed_values {
+    ($($type:ty),*) => {
+        $(impl FlagValue for $type {
+            fn parse_flag(s: &str) -> Result<Self, String> {
+                s.parse()
+            }
+
+            fn to_flag(&self) -> String {
+                self.to_string()
+            }
+        })*
+    };
+}
+
+parsed_values!(SorStoppingCriterion, NormReset);
+
+/// Serde for the `FlagValue` settings of a `Config`
+mod flag {
+    use super::FlagValue;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: FlagValue, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_some(&value.to_flag()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T: FlagValue, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| T::parse_flag(&s).map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+// The types are those of the settings, which are the types of the fields of
+// `Args` without the `Option` of the flags that are unset by default. The
+// settings in the `simulation` block are those of `Args::simulation`.
+macro_rules! config {
+    (
+        simulation {
+            $($(#[$simulation_meta:meta])* $simulation_field:ident: $simulation_type:ty,)*
+        }
+        $($(#[$meta:meta])* $field:ident: $type:ty,)*
+    ) => {
+        /// Settings from a `--config` file, named like the command-line flags
+        /// without the leading `--`
+        ///
+        /// Every setting is optional. `resolve` fills in the missing ones from
+        /// the command line.
+        #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+        #[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+        pub struct Config {
+            $(
+                $(#[$simulation_meta])*
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub $simulation_field: Option<$simulation_type>,
+            )*
+            $(
+                $(#[$meta])*
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub $field: Option<$type>,
+            )*
+        }
+
+        impl Config {
+            /// All settings of `args`
+            pub fn from_args(args: &Args) -> Config {
+                Config {
+                    $($simulation_field: args.simulation.$simulation_field.get(),)*
+                    $($field: args.$field.get(),)*
+                }
+            }
+
+            /// The settings of `args` that were given on the command line, as
+            /// opposed to left at their defaults
+            ///
+            /// # Arguments
+            /// * `args` - The parsed command line
+            /// * `matches` - The matches `args` was parsed from
+            pub fn from_matches(args: &Args, matches: &ArgMatches) -> Config {
+                let given = |id: &str| {
+                    matches.value_source(id) == Some(ValueSource::CommandLine)
+                };
+                Config {
+                    $($simulation_field: args
+                        .simulation
+                        .$simulation_field
+                        .get()
+                        .filter(|_| given(stringify!($simulation_field))),)*
+                    $($field: args.$field.get().filter(|_| given(stringify!($field))),)*
+                }
+            }
+
+            /// The settings of `self`, and those of `fallback` where `self`
+            /// has none
+            pub fn or(self, fallback: Config) -> Config {
+                Config {
+                    $($simulation_field: self
+                        .$simulation_field
+                        .or(fallback.$simulation_field),)*
+                    $($field: self.$field.or(fallback.$field),)*
+                }
+            }
+
+            /// Overwrite the fields of `args` with the settings of `self`
+            pub fn apply(self, args: &mut Args) {
+                $(if let Some(value) = self.$simulation_field {
+                    args.simulation.$simulation_field.set(value);
+                })*
+                $(if let Some(value) = self.$field {
+                    args.$field.set(value);
+                })*
+            }
+        }
+    };
+}
+
+config! {
+    simulation {
+        x_cells: usize,
+        y_cells: usize,
+        x_cell_width: Real,
+        y_cell_height: Real,
+        delta_t: Real,
+        gamma: Real,
+        auto_gamma: bool,
+        reynolds: Real,
+        density: Real,
+        inflow_u: Real,
+        inflow_v: Real,
+        initial_velocity: Velocity,
+        obstacle_x: usize,
+        obstacle_y: usize,
+        obstacle_radius: Real,
+        #[serde(with = "flag")]
+        top_wall: WallType,
+        #[serde(with = "flag")]
+        bottom_wall: WallType,
+        gx: Real,
+        gy: Real,
+        sor_epsilon: Real,
+        sor_max_iterations: u32,
+        sor_divergence_limit: u32,
+        #[serde(with = "flag")]
+        sor_stopping_criterion: SorStoppingCriterion,
+        sor_rel_epsilon: Real,
+        #[serde(with = "flag")]
+        norm_reset: NormReset,
+        sor_stagnation_factor: Real,
+        omega: Omega,
+        omega_ramp: bool,
+        #[serde(with = "flag")]
+        sor_ordering: SorOrdering,
+        #[serde(with = "flag")]
+        pressure_solver: PressureSolver,
+        #[serde(with = "flag")]
+        time_scheme: TimeScheme,
+    }
+    sim_file: String,
+    repair_geometry: bool,
+    save_file: String,
+    snapshots: usize,
+    stats_history: usize,
+    snapshot_interval: u32,
+    window_width: i32,
+    window_height: i32,
+    headless: bool,
+    ticks: u32,
+    probes: Vec<GridIndex>,
+    probe_csv: String,
+    time_average: String,
+    timing_interval: u32,
+    profile: bool,
+    quiet: bool,
+    verbose: u8,
+}
+
+impl Config {
+    pub fn from_toml(toml: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    pub fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::IoError(path.to_owned(), err))?;
+        Config::from_toml(&toml)
+            .map_err(|err| ConfigError::ParseError(path.to_owned(), err))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("every setting can be written as TOML")
+    }
+}
+
+/// Combine the command line with a config file: flags given on the command
+/// line take precedence over the file, which takes precedence over the
+/// defaults of the flags
+///
+/// # Arguments
+/// * `args` - The parsed command line
+/// * `explicit` - The settings that were given on the command line, see
+///   `Config::from_matches`
+/// * `config` - The settings from the config file
+pub fn resolve(mut args: Args, explicit: &Config, mut config: Config) -> Args {
+    // A flag also overrides the file's setting of the flag it conflicts with,
+    // which would otherwise win over it.
+    if explicit.sor_stopping_criterion.is_some() || explicit.sor_rel_epsilon.is_some() {
+        config.sor_stopping_criterion = None;
+        config.sor_rel_epsilon = None;
+    }
+    if explicit.quiet.is_some() || explicit.verbose.is_some() {
+        config.quiet = None;
+        config.verbose = None;
+    }
+    explicit.clone().or(config).apply(&mut args);
+    args
+}
+
+/// Apply the `--config` file, if there is one
+///
+/// # Arguments
+/// * `args` - The parsed command line
+/// * `matches` - The matches `args` was parsed from
+pub fn apply_config_file(args: Args, matches: &ArgMatches) -> Result<Args, ConfigError> {
+    match &args.config {
+        Some(path) => {
+            let config = Config::from_file(path)?;
+            let explicit = Config::from_matches(&args, matches);
+            Ok(resolve(args, &explicit, config))
+        }
+        None => Ok(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    fn parse(args: &[&str]) -> (Args, Config) {
+        let matches = Args::command().get_matches_from([&["stroemung"], args].concat());
+        let args = Args::from_arg_matches(&matches).unwrap();
+        let explicit = Config::from_matches(&args, &matches);
+        (args, explicit)
+    }
+
+    #[test]
+    fn explicit_flags() {
+        let (_, explicit) = parse(&["--x-cells", "100", "--omega", "auto", "-vv"]);
+        assert_eq!(
+            explicit,
+            Config {
+                x_cells: Some(100),
+                omega: Some(Omega::Auto),
+                verbose: Some(2),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        let config = Config::from_toml(
+            r#"
+            x-cells = 50
+            y-cells = 30
+            omega = "auto"
+            top-wall = "freeslip"
+            norm-reset = "every:5"
+            probes = [[10, 5]]
+            "#,
+        )
+        .unwrap();
+        let (args, explicit) = parse(&["--x-cells", "70", "--reynolds", "400"]);
+        let args = resolve(args, &explicit, config);
+        // The command line beats the file...
+        assert_eq!(args.simulation.x_cells, 70);
+        assert_eq!(args.simulation.reynolds, 400.0);
+        // ...which beats the defaults.
+        assert_eq!(args.simulation.y_cells, 30);
+        assert_eq!(args.simulation.omega, Omega::Auto);
+        assert_eq!(args.simulation.top_wall, WallType::FreeSlip);
+        assert_eq!(args.simulation.norm_reset, NormReset::EveryNTicks(5));
+        assert_eq!(args.probes, vec![(10, 5)]);
+        assert_eq!(
+            args.simulation.delta_t,
+            Args::parse_from(["stroemung"]).simulation.delta_t
+        );
+    }
+
+    #[test]
+    fn conflicting_flags() {
+        let config = Config::from_toml("sor-rel-epsilon = 0.01\nquiet = true").unwrap();
+        let (args, explicit) =
+            parse(&["--sor-stopping-criterion", "absolute:0.1", "--verbose"]);
+        let args = resolve(args, &explicit, config);
+        assert_eq!(
+            args.simulation.sor_stopping_criterion,
+            SorStoppingCriterion::Absolute { epsilon: 0.1 }
+        );
+        assert_eq!(args.simulation.sor_rel_epsilon, None);
+        assert!(!args.quiet);
+        assert_eq!(args.verbose, 1);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let err = Config::from_toml("x-cels = 50").unwrap_err();
+        assert!(
+            err.to_string().contains("unknown field `x-cels`"),
+            "{}",
+            err
+        );
+        assert!(Config::from_toml("x-cells = \"many\"").is_err());
+        assert!(Config::from_toml("omega = \"fast\"").is_err());
+        assert!(Config::from_toml("top-wall = \"glass\"").is_err());
+        assert!(Config::from_toml("norm-reset = \"sometimes\"").is_err());
+    }
+
+    #[test]
+    fn dump_round_trip() {
+        let (args, _) = parse(&[
+            "--x-cells",
+            "64",
+            "--omega",
+            "1.85",
+            "--sor-stopping-criterion",
+            "relative:0.001",
+            "--pressure-solver",
+            "jacobi",
+            "--initial-velocity",
+            "0.5,-0.25",
+            "--probe",
+            "3,4",
+            "--probe",
+            "5,6",
+        ]);
+        let dumped = Config::from_args(&args).to_toml();
+        let (defaults, explicit) = parse(&[]);
+        let loaded = resolve(defaults, &explicit, Config::from_toml(&dumped).unwrap());
+        assert_eq!(Config::from_args(&loaded), Config::from_args(&args));
+        assert!(
+            dumped.contains("pressure-solver = \"jacobi\""),
+            "{}",
+            dumped
+        );
+        assert!(!dumped.contains("obstacle-y"), "{}", dumped);
+    }
+}
@@ -3,18 +3,21 @@ pub mod presets;
 use std::collections::BTreeSet;
 use std::fmt;
 use std::io::Read;
+use std::ops::Range;
 
 use serde::Deserialize;
 use serde::Serialize;
 
 use serde_json::Error as SerdeError;
 
-use ndarray::Zip;
+use ndarray::{s, Axis, Slice, Zip};
 use thiserror::Error;
 
 use crate::cell::{BoundaryCell, Cell};
 use crate::math::Real;
-use crate::types::{BoundaryIndex, GridArray, GridIndex, GridSize};
+use crate::types::{
+    BoundaryIndex, CellPhysicalSize, GridArray, GridIndex, GridSize, Velocity,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EdgeType {
@@ -54,14 +57,133 @@ pub enum SimulationGridError {
     DeserializationError(#[from] SerdeError),
     #[error("A cell `{0}` at `{1}` was not a BoundaryCell as expected.")]
     BoundaryListIncorrectError(String, String),
-    #[error("A cell `{0}` at `{1}` has fluid on opposing sides.")]
-    BoundaryTooThinError(String, String),
+    #[error("Boundary cells have fluid on opposing sides:{}", list_invalid(.0))]
+    InvalidBoundaries(Vec<(GridIndex, InvalidReason)>),
+    #[error("Column `{0}` of the channel is completely blocked.")]
+    ChannelBlockedError(usize),
+    #[error("The `{0}` array has shape `{1:?}`, but the grid size is `{2:?}`.")]
+    ShapeMismatchError(&'static str, Vec<usize>, GridSize),
+    #[error("The patch from `{0:?}` to `{1:?}` is outside of the `{2:?}` grid.")]
+    PatchOutOfBoundsError(GridIndex, GridIndex, GridSize),
+    #[error("Cell `{0:?}` is not in the interior of the `{1:?}` grid.")]
+    NotInteriorError(GridIndex, GridSize),
+    #[error("Cell `{0:?}` is outside of the `{1:?}` grid.")]
+    OutOfBoundsError(GridIndex, GridSize),
+    #[error("The obstacles don't fit in the `{0:?}` grid with fluid all around them.")]
+    ObstaclesDontFitError(GridSize),
+    #[error("The obstacles are `{0}` cells thick, but must be at least `{1}`.")]
+    ObstacleTooThinError(Real, Real),
+    #[error("Could not read the grid: `{0}`")]
+    IoError(#[from] std::io::Error),
+    #[error("Line `{0}` of the NaSt2D flag file is invalid: {1}")]
+    Nast2dFormatError(usize, String),
 }
 
-#[derive(Debug, Default)]
+/// The bit of a NaSt2D flag that marks a fluid cell (`C_F`). The lower four
+/// bits say which sides of an obstacle cell face fluid, which the boundary
+/// list works out again.
+const NAST2D_FLUID: u32 = 0x10;
+
+/// Why a boundary cell can't be simulated
+///
+/// A boundary cell only sets the velocity of one face in each direction, so
+/// it can't be between two fluid cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidReason {
+    FluidLeftAndRight,
+    FluidAboveAndBelow,
+    FluidAllAround,
+}
+
+impl fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            InvalidReason::FluidLeftAndRight => "fluid to the left and right",
+            InvalidReason::FluidAboveAndBelow => "fluid above and below",
+            InvalidReason::FluidAllAround => "fluid on all sides",
+        };
+        f.write_str(message)
+    }
+}
+
+/// The number of invalid cells `SimulationGridError::InvalidBoundaries` lists
+/// before only counting the rest
+const MAX_LISTED_INVALID_CELLS: usize = 20;
+
+fn list_invalid(cells: &[(GridIndex, InvalidReason)]) -> String {
+    let mut list: String = cells
+        .iter()
+        .take(MAX_LISTED_INVALID_CELLS)
+        .map(|(idx, reason)| format!("\n  {:?}: {}", idx, reason))
+        .collect();
+    if cells.len() > MAX_LISTED_INVALID_CELLS {
+        list += &format!("\n  and {} more", cells.len() - MAX_LISTED_INVALID_CELLS);
+    }
+    list
+}
+
+/// How many cells of each kind a grid has and where its openings are, see
+/// `SimulationGrid::census`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GridCensus {
+    pub fluid: usize,
+    pub no_slip: usize,
+    pub symmetry: usize,
+    pub inflow: usize,
+    pub outflow: usize,
+    pub convective_outflow: usize,
+    /// The top left and bottom right corners of each connected region of
+    /// inflow cells
+    pub inflow_regions: Vec<(GridIndex, GridIndex)>,
+    /// The top left and bottom right corners of each connected region of
+    /// outflow and convective outflow cells
+    pub outflow_regions: Vec<(GridIndex, GridIndex)>,
+    /// The number of connected groups of no-slip cells inside the outer ring
+    pub obstacles: usize,
+}
+
+impl GridCensus {
+    /// The area of the fluid cells, for cells of `cell_size`
+    pub fn fluid_area(&self, cell_size: CellPhysicalSize) -> Real {
+        self.fluid as Real * cell_size[0] * cell_size[1]
+    }
+}
+
+impl fmt::Display for GridCensus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Cells: {} fluid, {} no-slip, {} symmetry, {} inflow, {} outflow, {} \
+             convective outflow",
+            self.fluid,
+            self.no_slip,
+            self.symmetry,
+            self.inflow,
+            self.outflow,
+            self.convective_outflow
+        )?;
+        for (name, regions) in [
+            ("Inflow", &self.inflow_regions),
+            ("Outflow", &self.outflow_regions),
+        ] {
+            let regions: Vec<String> = regions
+                .iter()
+                .map(|(first, last)| format!("{:?} to {:?}", first, last))
+                .collect();
+            writeln!(f, "{} regions: {}", name, regions.join(", "))?;
+        }
+        writeln!(f, "Obstacles: {}", self.obstacles)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct BoundaryList {
     boundaries: BTreeSet<BoundaryIndex>,
     pub sorted_boundary_list: Vec<(GridIndex, Option<EdgeType>)>,
+    /// Inflow and outflow cells surrounded by fluid. These are sources and
+    /// sinks rather than walls, so they aren't in `sorted_boundary_list` and
+    /// their faces are computed like those of fluid cells.
+    pub sources: Vec<GridIndex>,
     pub fluid_cells: Real,
     // This is scratch space so the vector doesn't keep getting reallocated
     // between simulation steps
@@ -78,6 +200,12 @@ impl std::fmt::Display for BoundaryList {
         for elem in &self.sorted_boundary_list {
             writeln!(f, "  {:?}", elem)?;
         }
+        if !self.sources.is_empty() {
+            writeln!(f, "Sources:")?;
+            for elem in &self.sources {
+                writeln!(f, "  {:?}", elem)?;
+            }
+        }
         Ok(())
     }
 }
@@ -109,7 +237,7 @@ impl From<SimulationGrid> for UnfinalizedSimulationGrid {
 // This must be the same as UnfinalizedSimulationGrid, except for boundaries.
 // We have two types to make sure we never deserialize without forgetting to
 // generate the boundary list.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SimulationGrid {
     pub size: GridSize,
     pub pressure: GridArray<Real>,
@@ -122,29 +250,16 @@ pub struct SimulationGrid {
     pub pressure_range: [Real; 2],
     #[serde(skip)]
     pub speed_range: [Real; 2],
+    /// The largest absolute `u` and `v` of the fluid cells
+    #[serde(skip)]
+    pub max_velocity: [Real; 2],
 }
 
 impl TryFrom<UnfinalizedSimulationGrid> for SimulationGrid {
     type Error = SimulationGridError;
 
     fn try_from(item: UnfinalizedSimulationGrid) -> Result<Self, Self::Error> {
-        // Will be nicer once https://github.com/rust-lang/rust/issues/86555
-        // is in stable.
-        let mut grid = SimulationGrid {
-            size: item.size,
-            pressure: item.pressure,
-            u: item.u,
-            v: item.v,
-            cell_type: item.cell_type,
-            boundaries: BoundaryList {
-                boundaries: Default::default(),
-                sorted_boundary_list: Default::default(),
-                u_v_restore: Vec::new(),
-                fluid_cells: 0.0,
-            },
-            pressure_range: [0.0, 0.0],
-            speed_range: [0.0, 0.0],
-        };
+        let mut grid = SimulationGrid::unvalidated(item)?;
         grid.rebuild_boundary_list()?;
         grid.calculate_pressure_range();
         grid.calculate_speed_range();
@@ -152,6 +267,107 @@ impl TryFrom<UnfinalizedSimulationGrid> for SimulationGrid {
     }
 }
 
+impl UnfinalizedSimulationGrid {
+    /// Thicken the boundary walls that are one cell thick, see
+    /// `SimulationGrid::repair_thin_boundaries`, and return the number of
+    /// cells added
+    ///
+    /// This is for grids from files that wouldn't load otherwise. It fails
+    /// only if the arrays don't match the size of the grid.
+    pub fn repair_thin_boundaries(&mut self) -> Result<usize, SimulationGridError> {
+        let mut grid = SimulationGrid::unvalidated(UnfinalizedSimulationGrid {
+            size: self.size,
+            pressure: self.pressure.clone(),
+            u: self.u.clone(),
+            v: self.v.clone(),
+            cell_type: self.cell_type.clone(),
+        })?;
+        let added = grid.repair_thin_boundaries();
+        *self = grid.into();
+        Ok(added)
+    }
+}
+
+// Reverse the order of the elements of `array` within `slice` along `axis`
+fn reverse_slice<T: Clone>(array: &mut GridArray<T>, axis: Axis, slice: Slice) {
+    let mut view = array.slice_axis_mut(axis, slice);
+    let reversed = view.slice_axis(axis, Slice::new(0, None, -1)).to_owned();
+    view.assign(&reversed);
+}
+
+/// Where two simulations or grids differ, as found by `approx_eq`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDifference {
+    pub field: &'static str,
+    /// The cell that differs, for fields with a value per cell
+    pub index: Option<GridIndex>,
+    /// The values on both sides, formatted with `Debug`
+    pub values: (String, String),
+}
+
+impl FieldDifference {
+    pub fn new(
+        field: &'static str,
+        index: Option<GridIndex>,
+        left: impl fmt::Debug,
+        right: impl fmt::Debug,
+    ) -> Self {
+        FieldDifference {
+            field,
+            index,
+            values: (format!("{:?}", left), format!("{:?}", right)),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` differs", self.field)?;
+        if let Some(index) = self.index {
+            write!(f, " at {:?}", index)?;
+        }
+        write!(f, ": {} != {}", self.values.0, self.values.1)
+    }
+}
+
+/// Whether `left` and `right` are within `tolerance` of each other. Unlike
+/// comparing the difference directly, equal infinities count as close.
+pub(crate) fn is_close(left: Real, right: Real, tolerance: Real) -> bool {
+    left == right || (left - right).abs() <= tolerance
+}
+
+// The cell where `left` and `right` differ the most, if it's by more than
+// `tolerance`. NaNs count as the largest possible difference.
+fn worst_difference(
+    field: &'static str,
+    left: &GridArray<Real>,
+    right: &GridArray<Real>,
+    tolerance: Real,
+) -> Option<FieldDifference> {
+    let mut worst: Option<(GridIndex, Real)> = None;
+    for ((idx, a), b) in left.indexed_iter().zip(right) {
+        if is_close(*a, *b, tolerance) {
+            continue;
+        }
+        let difference = match (a - b).abs() {
+            d if d.is_nan() => Real::INFINITY,
+            d => d,
+        };
+        if worst.is_none_or(|(_, largest)| difference > largest) {
+            worst = Some((idx, difference));
+        }
+    }
+    worst.map(|(idx, _)| FieldDifference::new(field, Some(idx), left[idx], right[idx]))
+}
+
+impl PartialEq for SimulationGrid {
+    /// Compare the fields that describe the state of the flow. The boundary
+    /// list and the ranges are derived from them and left out.
+    fn eq(&self, other: &Self) -> bool {
+        self.approx_eq(other, 0.0).is_none()
+    }
+}
+
 impl std::fmt::Display for SimulationGrid {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Simulation grid {}x{}", self.size[0], self.size[1],)?;
@@ -164,6 +380,96 @@ impl std::fmt::Display for SimulationGrid {
 }
 
 impl SimulationGrid {
+    /// Find where this grid differs from `other`
+    ///
+    /// The size and the cell types have to match exactly, and the pressure
+    /// and velocities within `tolerance`. Returns `None` if the grids match,
+    /// and otherwise the first field that doesn't, at the cell where it
+    /// differs the most.
+    pub fn approx_eq(&self, other: &Self, tolerance: Real) -> Option<FieldDifference> {
+        if self.size != other.size {
+            return Some(FieldDifference::new("size", None, self.size, other.size));
+        }
+        let mut cell_types = self.cell_type.indexed_iter().zip(&other.cell_type);
+        if let Some(((idx, a), b)) = cell_types.find(|((_, a), b)| a != b) {
+            return Some(FieldDifference::new("cell_type", Some(idx), a, b));
+        }
+        [
+            ("pressure", &self.pressure, &other.pressure),
+            ("u", &self.u, &other.u),
+            ("v", &self.v, &other.v),
+        ]
+        .into_iter()
+        .find_map(|(field, left, right)| worst_difference(field, left, right, tolerance))
+    }
+
+    // Check the shapes of the arrays and build the grid, without a boundary
+    // list or ranges
+    fn unvalidated(item: UnfinalizedSimulationGrid) -> Result<Self, SimulationGridError> {
+        for (name, shape) in [
+            ("pressure", item.pressure.shape()),
+            ("u", item.u.shape()),
+            ("v", item.v.shape()),
+            ("cell_type", item.cell_type.shape()),
+        ] {
+            if shape != item.size {
+                return Err(SimulationGridError::ShapeMismatchError(
+                    name,
+                    shape.to_vec(),
+                    item.size,
+                ));
+            }
+        }
+
+        // Will be nicer once https://github.com/rust-lang/rust/issues/86555
+        // is in stable.
+        Ok(SimulationGrid {
+            size: item.size,
+            pressure: item.pressure,
+            u: item.u,
+            v: item.v,
+            cell_type: item.cell_type,
+            boundaries: BoundaryList {
+                boundaries: Default::default(),
+                sorted_boundary_list: Default::default(),
+                sources: Vec::new(),
+                u_v_restore: Vec::new(),
+                fluid_cells: 0.0,
+            },
+            pressure_range: [0.0, 0.0],
+            speed_range: [0.0, 0.0],
+            max_velocity: [0.0, 0.0],
+        })
+    }
+
+    /// Build a grid from its fields, e.g. to set up custom initial conditions
+    ///
+    /// Returns an error if any of the arrays doesn't have the shape `size`, or
+    /// if the cell types don't form a valid boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The number of cells in the x and y directions
+    /// * `pressure` - The pressure in each cell
+    /// * `u` - The horizontal velocity on the east face of each cell
+    /// * `v` - The vertical velocity on the south face of each cell
+    /// * `cell_type` - Whether each cell is fluid or a boundary
+    pub fn from_fields(
+        size: GridSize,
+        pressure: GridArray<Real>,
+        u: GridArray<Real>,
+        v: GridArray<Real>,
+        cell_type: GridArray<Cell>,
+    ) -> Result<Self, SimulationGridError> {
+        SimulationGrid::try_from(UnfinalizedSimulationGrid {
+            size,
+            pressure,
+            u,
+            v,
+            cell_type,
+        })
+    }
+
     fn neighbors(&self, idx: GridIndex) -> [Option<(GridIndex, Cell)>; 4] {
         // Note that we use the convention that 0,0 is the upper-left corner
         // instead of the bottom left as in the book. This means that "north"
@@ -199,10 +505,310 @@ impl SimulationGrid {
         [north, south, east, west]
     }
 
+    /// The velocity at the center of every cell, as `u` and `v` arrays
+    ///
+    /// See `velocity_at_index` for how the velocity is interpolated.
+    pub fn cell_centered_velocity(&self) -> (GridArray<Real>, GridArray<Real>) {
+        let u = GridArray::from_shape_fn(self.size, |idx| self.velocity_at_index(idx)[0]);
+        let v = GridArray::from_shape_fn(self.size, |idx| self.velocity_at_index(idx)[1]);
+        (u, v)
+    }
+
+    /// The velocity at the center of the cell at `idx`
+    ///
+    /// `u` and `v` are stored on the east and south faces of the cells, so
+    /// the velocity at the center is the average of the two opposite faces.
+    /// The outer ring of cells only has one face inside the domain in each
+    /// direction, so they use the value of that face instead. Panics if `idx`
+    /// is outside of the grid.
+    pub fn velocity_at_index(&self, (x, y): GridIndex) -> Velocity {
+        let [w, h] = self.size;
+        let u = match x {
+            0 => self.u[(0, y)],
+            _ if x == w - 1 => self.u[(x - 1, y)],
+            _ => (self.u[(x - 1, y)] + self.u[(x, y)]) / 2.0,
+        };
+        let v = match y {
+            0 => self.v[(x, 0)],
+            _ if y == h - 1 => self.v[(x, y - 1)],
+            _ => (self.v[(x, y - 1)] + self.v[(x, y)]) / 2.0,
+        };
+        [u, v]
+    }
+
+    /// The physical position of the center of the cell at `idx`, measured
+    /// from the top left corner of the grid
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The cell, which doesn't have to be inside the grid
+    /// * `cell_size` - The physical width and height of a cell
+    pub fn cell_center(
+        &self,
+        (x, y): GridIndex,
+        cell_size: CellPhysicalSize,
+    ) -> (Real, Real) {
+        (
+            (x as Real + 0.5) * cell_size[0],
+            (y as Real + 0.5) * cell_size[1],
+        )
+    }
+
+    /// The pressure of the cell at `idx`. Panics if `idx` is outside of the
+    /// grid.
+    pub fn pressure_at_index(&self, idx: GridIndex) -> Real {
+        self.pressure[idx]
+    }
+
+    /// The type of the cell at `idx`. Panics if `idx` is outside of the grid.
+    pub fn cell_at(&self, idx: GridIndex) -> Cell {
+        self.cell_type[idx]
+    }
+
+    /// Change the type of the cell at `idx`, like `set_cells`
+    pub fn set_cell(
+        &mut self,
+        idx: GridIndex,
+        cell: Cell,
+    ) -> Result<(), SimulationGridError> {
+        self.set_cells([idx], cell)
+    }
+
+    /// Change the type of `cells` to `cell` and update the boundary list
+    ///
+    /// The velocities and pressure of the changed cells start at zero. If the
+    /// new cells would create an invalid boundary, e.g. one that's only one
+    /// cell thick, the grid is left as it was and the error is returned. The
+    /// outer ring of the grid can become other kinds of boundary, but not
+    /// fluid.
+    ///
+    /// # Arguments
+    ///
+    /// * `cells` - The cells to change. Cells that already are `cell` are
+    ///   left alone.
+    /// * `cell` - The new type of the cells
+    pub fn set_cells(
+        &mut self,
+        cells: impl IntoIterator<Item = GridIndex>,
+        cell: Cell,
+    ) -> Result<(), SimulationGridError> {
+        let [w, h] = self.size;
+        let mut changed = Vec::new();
+        for idx @ (x, y) in cells {
+            if x >= w || y >= h {
+                return Err(SimulationGridError::OutOfBoundsError(idx, self.size));
+            }
+            let outer = x == 0 || y == 0 || x == w - 1 || y == h - 1;
+            if outer && cell == Cell::Fluid {
+                return Err(SimulationGridError::NotInteriorError(idx, self.size));
+            }
+            if self.cell_type[idx] != cell && !changed.contains(&idx) {
+                changed.push(idx);
+            }
+        }
+
+        // Keep the old values to restore them if the boundary is invalid.
+        let backup: Vec<(Real, Real, Real, Cell)> = changed
+            .iter()
+            .map(|&idx| {
+                (
+                    self.u[idx],
+                    self.v[idx],
+                    self.pressure[idx],
+                    self.cell_type[idx],
+                )
+            })
+            .collect();
+        for &idx in &changed {
+            self.u[idx] = 0.0;
+            self.v[idx] = 0.0;
+            self.pressure[idx] = 0.0;
+            self.cell_type[idx] = cell;
+        }
+
+        // Only the changed cells need their part of the boundary list
+        // updated, which leaves the list as it was on an error.
+        let result = self.update_boundary_region(&changed);
+        if result.is_err() {
+            for (&idx, &(u, v, pressure, old)) in changed.iter().zip(&backup) {
+                self.u[idx] = u;
+                self.v[idx] = v;
+                self.pressure[idx] = pressure;
+                self.cell_type[idx] = old;
+            }
+        }
+        result
+    }
+
+    /// Set the velocity of every fluid cell to `velocity`
+    ///
+    /// Boundary cells are left alone, `set_boundary_u_and_v` takes care of
+    /// them at the start of each tick.
+    pub fn set_uniform_velocity(&mut self, velocity: Velocity) {
+        Zip::from(&mut self.u)
+            .and(&mut self.v)
+            .and(&self.cell_type)
+            .for_each(|u, v, cell_type| {
+                if let Cell::Fluid = cell_type {
+                    *u = velocity[0];
+                    *v = velocity[1];
+                }
+            });
+        self.calculate_speed_range();
+    }
+
+    /// The distinct velocities of the inflow cells, with how many cells have
+    /// each
+    ///
+    /// Velocities are compared exactly. The groups are in the order their
+    /// first cell appears in the grid.
+    pub fn inflow_groups(&self) -> Vec<(Velocity, usize)> {
+        let mut groups: Vec<(Velocity, usize)> = Vec::new();
+        for cell in &self.cell_type {
+            let Cell::Boundary(BoundaryCell::Inflow { velocity }) = *cell else {
+                continue;
+            };
+            match groups.iter_mut().find(|(group, _)| *group == velocity) {
+                Some((_, count)) => *count += 1,
+                None => groups.push((velocity, 1)),
+            }
+        }
+        groups
+    }
+
+    /// Change the velocity of every inflow cell with velocity `old` to `new`,
+    /// returning how many cells changed
+    ///
+    /// The cells stay inflow cells, so the boundary list doesn't need to be
+    /// rebuilt. The new velocity takes effect at the start of the next tick.
+    pub fn set_inflow_velocity(&mut self, old: Velocity, new: Velocity) -> usize {
+        let mut changed = 0;
+        for cell in &mut self.cell_type {
+            if let Cell::Boundary(BoundaryCell::Inflow { velocity }) = cell {
+                if *velocity == old {
+                    *velocity = new;
+                    changed += 1;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Reflect the interior of the grid left to right
+    ///
+    /// The outer boundary cells and the velocities through them are left
+    /// alone, so e.g. the inflow stays on the left. Horizontal velocities
+    /// change sign.
+    pub fn mirror_x(&mut self) -> Result<(), SimulationGridError> {
+        self.mirror(Axis(0))
+    }
+
+    /// Reflect the interior of the grid top to bottom
+    ///
+    /// Like `mirror_x`, the outer boundary is left alone. Vertical velocities
+    /// change sign.
+    pub fn mirror_y(&mut self) -> Result<(), SimulationGridError> {
+        self.mirror(Axis(1))
+    }
+
+    fn mirror(&mut self, axis: Axis) -> Result<(), SimulationGridError> {
+        let n = self.size[axis.index()];
+        // Cell-centered values of the interior cells
+        let cells = Slice::from(1..n - 1);
+        // Velocities on the faces between two interior cells. The faces on
+        // the outer boundary stay put.
+        let faces = Slice::from(1..n - 2);
+
+        reverse_slice(&mut self.cell_type, axis, cells);
+        reverse_slice(&mut self.pressure, axis, cells);
+        let (normal, tangential) = match axis.index() {
+            0 => (&mut self.u, &mut self.v),
+            _ => (&mut self.v, &mut self.u),
+        };
+        reverse_slice(tangential, axis, cells);
+        reverse_slice(normal, axis, faces);
+        normal
+            .slice_axis_mut(axis, faces)
+            .map_inplace(|velocity| *velocity = -*velocity);
+
+        self.rebuild_boundary_list()?;
+        self.calculate_pressure_range();
+        self.calculate_speed_range();
+        Ok(())
+    }
+
+    /// Crop the grid to a window, e.g. to look closer at the wake behind an
+    /// obstacle or to start a finer simulation of it
+    ///
+    /// The cells in the window become the interior of the new grid, and the
+    /// cells just outside of it its outer ring. Fluid cells in the ring are
+    /// turned into outflow cells so the new grid is a valid domain of its
+    /// own, and every other cell keeps its type. The pressure and velocities
+    /// are copied, and the boundary list is built for the new grid.
+    ///
+    /// Cropping to the whole interior gives back the same grid. Returns an
+    /// error if a range is empty or reaches into the outer ring of this grid,
+    /// or if the cropped cells don't form a valid boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * `x_range` - The columns of the window
+    /// * `y_range` - The rows of the window
+    pub fn subgrid(
+        &self,
+        x_range: Range<usize>,
+        y_range: Range<usize>,
+    ) -> Result<SimulationGrid, SimulationGridError> {
+        let [w, h] = self.size;
+        let inside = |range: &Range<usize>, n: usize| {
+            range.start >= 1 && range.start < range.end && range.end < n
+        };
+        if !inside(&x_range, w) || !inside(&y_range, h) {
+            return Err(SimulationGridError::PatchOutOfBoundsError(
+                (x_range.start, y_range.start),
+                (x_range.end.saturating_sub(1), y_range.end.saturating_sub(1)),
+                self.size,
+            ));
+        }
+
+        let window = s![
+            x_range.start - 1..x_range.end + 1,
+            y_range.start - 1..y_range.end + 1
+        ];
+        let mut cell_type = self.cell_type.slice(window).to_owned();
+        let size = [x_range.len() + 2, y_range.len() + 2];
+        for ((x, y), cell) in cell_type.indexed_iter_mut() {
+            let on_ring = x == 0 || y == 0 || x == size[0] - 1 || y == size[1] - 1;
+            if on_ring && *cell == Cell::Fluid {
+                *cell = Cell::Boundary(BoundaryCell::Outflow);
+            }
+        }
+        SimulationGrid::from_fields(
+            size,
+            self.pressure.slice(window).to_owned(),
+            self.u.slice(window).to_owned(),
+            self.v.slice(window).to_owned(),
+            cell_type,
+        )
+    }
+
+    // Whether the cell at `idx` is an inflow or outflow cell with fluid on all
+    // four sides
+    fn is_source(&self, idx: GridIndex) -> bool {
+        matches!(
+            self.cell_type[idx],
+            Cell::Boundary(BoundaryCell::Inflow { .. } | BoundaryCell::Outflow)
+        ) && self
+            .neighbors(idx)
+            .iter()
+            .all(|neighbor| matches!(neighbor, Some((_, Cell::Fluid))))
+    }
+
     pub fn rebuild_boundary_list(&mut self) -> Result<(), SimulationGridError> {
         let mut fluid_cells = 0;
         self.boundaries.boundaries.clear();
-        self.boundaries.u_v_restore = Vec::new();
+        // Keep the allocation around, the capacity is reserved below.
+        self.boundaries.u_v_restore.clear();
         // Run a for_each with the value and indices. See
         // https://github.com/rust-ndarray/ndarray/issues/1093 for details.
         Zip::indexed(self.cell_type.view()).for_each(|idx, val| {
@@ -215,25 +821,300 @@ impl SimulationGrid {
             }
         });
 
-        let get_neighbors = |idx: BoundaryIndex| {
-            let new_idx: GridIndex = (idx.0, idx.1);
-            let edge_type = self.calculate_edges(new_idx)?;
-            Ok::<((usize, usize), std::option::Option<EdgeType>), SimulationGridError>((
-                new_idx, edge_type,
-            ))
-        };
-        let result: Result<Vec<_>, _> = self
+        // Pull out the sources and sinks before calculating edges, since they
+        // have fluid on every side.
+        let sources: Vec<GridIndex> = self
             .boundaries
             .boundaries
             .iter()
-            .copied()
-            .map(get_neighbors)
+            .map(|idx| (idx.0, idx.1))
+            .filter(|idx| self.is_source(*idx))
             .collect();
-        self.boundaries.sorted_boundary_list = result?;
+        for idx in &sources {
+            self.boundaries
+                .boundaries
+                .remove(&BoundaryIndex(idx.0, idx.1));
+        }
+        self.boundaries.sources = sources;
+
+        // Check every cell rather than stopping at the first invalid one, so
+        // that all of them can be reported together.
+        let mut sorted_boundary_list =
+            Vec::with_capacity(self.boundaries.boundaries.len());
+        let mut invalid = Vec::new();
+        for idx in &self.boundaries.boundaries {
+            let idx: GridIndex = (idx.0, idx.1);
+            match self.calculate_edges(idx) {
+                Ok(edge_type) => sorted_boundary_list.push((idx, edge_type)),
+                Err(reason) => invalid.push((idx, reason)),
+            }
+        }
+        if !invalid.is_empty() {
+            return Err(SimulationGridError::InvalidBoundaries(invalid));
+        }
+        self.boundaries.sorted_boundary_list = sorted_boundary_list;
         self.boundaries.fluid_cells = fluid_cells as Real;
+        // Each boundary cell stashes at most two entries per tick.
+        self.boundaries
+            .u_v_restore
+            .reserve(2 * self.boundaries.sorted_boundary_list.len());
+        Ok(())
+    }
+
+    /// Update the boundary list after the cells in `affected` changed type
+    ///
+    /// Only the affected cells and their neighbors are recomputed, which is
+    /// much faster than `rebuild_boundary_list` for small edits of a large
+    /// grid and gives the same result, as long as no other cells changed
+    /// since the list was last built. Cells outside of the grid are ignored.
+    /// On an error the boundary list is left as it was.
+    pub fn update_boundary_region(
+        &mut self,
+        affected: &[GridIndex],
+    ) -> Result<(), SimulationGridError> {
+        // The edges of a cell depend on its neighbors, so they change too.
+        let mut region = BTreeSet::new();
+        for &idx in affected {
+            if idx.0 >= self.size[0] || idx.1 >= self.size[1] {
+                continue;
+            }
+            region.insert(BoundaryIndex(idx.0, idx.1));
+            for (neighbor, _) in self.neighbors(idx).into_iter().flatten() {
+                region.insert(BoundaryIndex(neighbor.0, neighbor.1));
+            }
+        }
+
+        // Calculate all of the edges before touching the list, so that an
+        // invalid boundary doesn't leave it half updated. Walls get
+        // `Some(edge)`, fluid cells and sources `None`.
+        let mut updates = Vec::with_capacity(region.len());
+        let mut invalid = Vec::new();
+        for idx in region {
+            let grid_idx = (idx.0, idx.1);
+            let is_source = self.is_source(grid_idx);
+            let wall = match self.cell_type[grid_idx] {
+                Cell::Boundary(_) if !is_source => match self.calculate_edges(grid_idx) {
+                    Ok(edge) => Some(edge),
+                    Err(reason) => {
+                        invalid.push((grid_idx, reason));
+                        continue;
+                    }
+                },
+                _ => None,
+            };
+            updates.push((idx, wall, is_source));
+        }
+        if !invalid.is_empty() {
+            return Err(SimulationGridError::InvalidBoundaries(invalid));
+        }
+
+        let list = &mut self.boundaries;
+        let key = |(idx, _): &(GridIndex, Option<EdgeType>)| BoundaryIndex(idx.0, idx.1);
+        for (idx, wall, is_source) in updates {
+            let grid_idx = (idx.0, idx.1);
+            let position = list.sorted_boundary_list.binary_search_by_key(&idx, key);
+            match (wall, position) {
+                (Some(edge), Ok(i)) => list.sorted_boundary_list[i].1 = edge,
+                (Some(edge), Err(i)) => {
+                    list.sorted_boundary_list.insert(i, (grid_idx, edge))
+                }
+                (None, Ok(i)) => {
+                    list.sorted_boundary_list.remove(i);
+                }
+                (None, Err(_)) => {}
+            }
+            if wall.is_some() {
+                list.boundaries.insert(idx);
+            } else {
+                list.boundaries.remove(&idx);
+            }
+
+            let position = list
+                .sources
+                .binary_search_by_key(&idx, |idx| BoundaryIndex(idx.0, idx.1));
+            match (is_source, position) {
+                (true, Err(i)) => list.sources.insert(i, grid_idx),
+                (false, Ok(i)) => {
+                    list.sources.remove(i);
+                }
+                _ => {}
+            }
+        }
+
+        // Every cell that isn't fluid is either a wall or a source.
+        let cells = self.size[0] * self.size[1];
+        list.fluid_cells = (cells - list.boundaries.len() - list.sources.len()) as Real;
+        list.u_v_restore.clear();
+        list.u_v_restore
+            .reserve(2 * list.sorted_boundary_list.len());
         Ok(())
     }
 
+    /// Thicken the boundary walls that are one cell thick instead of
+    /// rejecting them, and return the number of cells added
+    ///
+    /// Every boundary cell with fluid on opposing sides turns the fluid cell
+    /// to its south (for fluid above and below) or to its east (otherwise)
+    /// into a cell of its own type, with zero velocity and pressure. That can
+    /// leave new cells with fluid on opposing sides, so this repeats until
+    /// there are none. Each pass turns at least one fluid cell into a
+    /// boundary, so it always ends, if need be with no fluid left. The
+    /// boundary list and the ranges are rebuilt at the end.
+    pub fn repair_thin_boundaries(&mut self) -> usize {
+        let mut added = 0;
+        loop {
+            let invalid: Vec<(GridIndex, InvalidReason)> = self
+                .cell_type
+                .indexed_iter()
+                .filter(|&(idx, cell)| {
+                    matches!(cell, Cell::Boundary(_)) && !self.is_source(idx)
+                })
+                .filter_map(|(idx, _)| Some((idx, self.calculate_edges(idx).err()?)))
+                .collect();
+            let mut changed = false;
+            for ((x, y), reason) in invalid {
+                let neighbor = match reason {
+                    InvalidReason::FluidAboveAndBelow => (x, y + 1),
+                    InvalidReason::FluidLeftAndRight | InvalidReason::FluidAllAround => {
+                        (x + 1, y)
+                    }
+                };
+                // An earlier cell of this pass may have taken it already.
+                if self.cell_type[neighbor] == Cell::Fluid {
+                    self.cell_type[neighbor] = self.cell_type[(x, y)];
+                    self.u[neighbor] = 0.0;
+                    self.v[neighbor] = 0.0;
+                    self.pressure[neighbor] = 0.0;
+                    added += 1;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        self.rebuild_boundary_list()
+            .expect("repairing leaves no thin boundaries");
+        self.calculate_pressure_range();
+        self.calculate_speed_range();
+        added
+    }
+
+    /// Find the regions of fluid that can't exchange mass with the rest of
+    /// the domain
+    ///
+    /// Fluid cells are connected through their north, south, east and west
+    /// neighbors. A region is isolated if none of its cells is next to an
+    /// inflow or outflow cell, so the amount of fluid in it can never change.
+    /// The cells of each region are sorted.
+    pub fn find_isolated_fluid_regions(&self) -> Vec<Vec<GridIndex>> {
+        let mut visited = GridArray::from_elem(self.size, false);
+        let mut regions = Vec::new();
+        for (start, cell) in self.cell_type.indexed_iter() {
+            if *cell != Cell::Fluid || visited[start] {
+                continue;
+            }
+
+            let mut region = Vec::new();
+            let mut open = false;
+            visited[start] = true;
+            let mut stack = vec![start];
+            while let Some(idx) = stack.pop() {
+                region.push(idx);
+                for (neighbor, cell) in self.neighbors(idx).into_iter().flatten() {
+                    match cell {
+                        Cell::Fluid if !visited[neighbor] => {
+                            visited[neighbor] = true;
+                            stack.push(neighbor);
+                        }
+                        Cell::Boundary(
+                            BoundaryCell::Inflow { .. }
+                            | BoundaryCell::Outflow
+                            | BoundaryCell::ConvectiveOutflow { .. },
+                        ) => open = true,
+                        _ => {}
+                    }
+                }
+            }
+            if !open {
+                region.sort();
+                regions.push(region);
+            }
+        }
+        regions
+    }
+
+    /// Count the cells of each kind and find the inflow and outflow regions
+    /// and the obstacles
+    ///
+    /// Cells are connected through their north, south, east and west
+    /// neighbors, like for `find_isolated_fluid_regions`.
+    pub fn census(&self) -> GridCensus {
+        let mut census = GridCensus::default();
+        for cell in &self.cell_type {
+            let count = match cell {
+                Cell::Fluid => &mut census.fluid,
+                Cell::Boundary(BoundaryCell::NoSlip) => &mut census.no_slip,
+                Cell::Boundary(BoundaryCell::Symmetry) => &mut census.symmetry,
+                Cell::Boundary(BoundaryCell::Inflow { .. }) => &mut census.inflow,
+                Cell::Boundary(BoundaryCell::Outflow) => &mut census.outflow,
+                Cell::Boundary(BoundaryCell::ConvectiveOutflow { .. }) => {
+                    &mut census.convective_outflow
+                }
+            };
+            *count += 1;
+        }
+        census.inflow_regions = self.connected_regions(|_, cell| {
+            matches!(cell, Cell::Boundary(BoundaryCell::Inflow { .. }))
+        });
+        census.outflow_regions = self.connected_regions(|_, cell| {
+            matches!(
+                cell,
+                Cell::Boundary(
+                    BoundaryCell::Outflow | BoundaryCell::ConvectiveOutflow { .. }
+                )
+            )
+        });
+        let [w, h] = self.size;
+        census.obstacles = self
+            .connected_regions(|(x, y), cell| {
+                let interior = (1..w - 1).contains(&x) && (1..h - 1).contains(&y);
+                interior && cell == Cell::Boundary(BoundaryCell::NoSlip)
+            })
+            .len();
+        census
+    }
+
+    // The top left and bottom right corners of each connected region of the
+    // cells for which `member` is true, ordered by their first cell
+    fn connected_regions(
+        &self,
+        member: impl Fn(GridIndex, Cell) -> bool,
+    ) -> Vec<(GridIndex, GridIndex)> {
+        let mut visited = GridArray::from_elem(self.size, false);
+        let mut regions = Vec::new();
+        for (start, &cell) in self.cell_type.indexed_iter() {
+            if visited[start] || !member(start, cell) {
+                continue;
+            }
+            let (mut first, mut last) = (start, start);
+            visited[start] = true;
+            let mut stack = vec![start];
+            while let Some(idx) = stack.pop() {
+                first = (first.0.min(idx.0), first.1.min(idx.1));
+                last = (last.0.max(idx.0), last.1.max(idx.1));
+                for (neighbor, cell) in self.neighbors(idx).into_iter().flatten() {
+                    if !visited[neighbor] && member(neighbor, cell) {
+                        visited[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+            regions.push((first, last));
+        }
+        regions
+    }
+
     pub fn calculate_pressure_range(&mut self) {
         let (min, max) = Zip::from(&self.pressure).and(&self.cell_type).fold(
             (Real::MAX, 0.0),
@@ -250,27 +1131,34 @@ impl SimulationGrid {
         self.pressure_range = [min, max];
     }
 
+    /// Calculate `speed_range` and `max_velocity`
     pub fn calculate_speed_range(&mut self) {
-        let (min, max) = Zip::from(&self.u).and(&self.v).and(&self.cell_type).fold(
-            (Real::MAX, 0.0),
-            |acc, u, v, cell_type| {
+        let (min, max, max_u, max_v) = Zip::from(&self.u)
+            .and(&self.v)
+            .and(&self.cell_type)
+            .fold((Real::MAX, 0.0, 0.0, 0.0), |acc, u, v, cell_type| {
                 // if statement in inner loop :(
                 if let Cell::Fluid = cell_type {
-                    let (min, max) = acc;
+                    let (min, max, max_u, max_v) = acc;
                     let speed_squared = u.powi(2) + v.powi(2);
-                    (Real::min(min, speed_squared), Real::max(max, speed_squared))
+                    (
+                        Real::min(min, speed_squared),
+                        Real::max(max, speed_squared),
+                        Real::max(max_u, u.abs()),
+                        Real::max(max_v, v.abs()),
+                    )
                 } else {
                     acc
                 }
-            },
-        );
+            });
         self.speed_range = [min.sqrt(), max.sqrt()];
+        self.max_velocity = [max_u, max_v];
     }
 
     fn calculate_edges(
         &self,
         cell_idx: GridIndex,
-    ) -> Result<Option<EdgeType>, SimulationGridError> {
+    ) -> Result<Option<EdgeType>, InvalidReason> {
         let [north_neighbor, south_neighbor, east_neighbor, west_neighbor] =
             self.neighbors(cell_idx);
 
@@ -324,10 +1212,9 @@ impl SimulationGrid {
                 south_neighbor: down,
                 west_neighbor: left,
             })),
-            _ => Err(SimulationGridError::BoundaryTooThinError(
-                self.cell_type[cell_idx].to_string(),
-                format!("{:?}", cell_idx),
-            )),
+            (Some(_), Some(_), Some(_), Some(_)) => Err(InvalidReason::FluidAllAround),
+            (Some(_), Some(_), _, _) => Err(InvalidReason::FluidLeftAndRight),
+            _ => Err(InvalidReason::FluidAboveAndBelow),
         }
     }
 
@@ -340,6 +1227,78 @@ impl SimulationGrid {
         }
     }
 
+    /// Read the cell types of a grid from a NaSt2D flag file
+    ///
+    /// The file has one line per row of cells, from the top down, with one
+    /// integer flag per cell separated by whitespace. Flags with the `C_F` bit
+    /// (16) set are fluid, flags from 0 to 15 are obstacles and become no-slip
+    /// walls. Empty lines are skipped. The flow starts at rest, and the outer
+    /// ring must be all obstacles, which can be turned into inflows or
+    /// outflows afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The flag file
+    /// * `size` - The size of the grid, including the outer ring
+    pub fn from_nast2d<R: Read>(
+        mut reader: R,
+        size: GridSize,
+    ) -> Result<SimulationGrid, SimulationGridError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let [w, h] = size;
+        let mut cell_type =
+            GridArray::from_elem(size, Cell::Boundary(BoundaryCell::NoSlip));
+        let rows = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let mut y = 0;
+        for (line_index, line) in rows {
+            let line_number = line_index + 1;
+            let invalid = |reason: String| {
+                SimulationGridError::Nast2dFormatError(line_number, reason)
+            };
+            if y >= h {
+                return Err(invalid(format!("the grid only has {} rows", h)));
+            }
+            let flags: Vec<&str> = line.split_whitespace().collect();
+            if flags.len() != w {
+                return Err(invalid(format!(
+                    "expected {} flags, got {}",
+                    w,
+                    flags.len()
+                )));
+            }
+            for (x, flag) in flags.into_iter().enumerate() {
+                let fluid = match flag.parse::<u32>() {
+                    Ok(flag) if flag < 2 * NAST2D_FLUID => flag & NAST2D_FLUID != 0,
+                    _ => return Err(invalid(format!("`{}` is not a flag", flag))),
+                };
+                if fluid {
+                    if x == 0 || y == 0 || x == w - 1 || y == h - 1 {
+                        return Err(SimulationGridError::NotInteriorError((x, y), size));
+                    }
+                    cell_type[(x, y)] = Cell::Fluid;
+                }
+            }
+            y += 1;
+        }
+        if y < h {
+            return Err(SimulationGridError::Nast2dFormatError(
+                text.lines().count(),
+                format!("expected {} rows, got {}", h, y),
+            ));
+        }
+        SimulationGrid::from_fields(
+            size,
+            GridArray::zeros(size),
+            GridArray::zeros(size),
+            GridArray::zeros(size),
+            cell_type,
+        )
+    }
+
     pub fn copy_pressure_to_boundaries(&mut self) -> Result<(), SimulationGridError> {
         for (boundary_idx, maybe_edge) in &self.boundaries.sorted_boundary_list {
             // Don't do anything if we're not on a boundary.
@@ -411,7 +1370,11 @@ impl SimulationGrid {
         Ok(())
     }
 
-    pub fn set_boundary_u_and_v(&mut self) -> Result<(), SimulationGridError> {
+    pub fn set_boundary_u_and_v(
+        &mut self,
+        delt: Real,
+        cell_size: CellPhysicalSize,
+    ) -> Result<(), SimulationGridError> {
         // We're going to copy u and v back into the vector in the loop
         self.boundaries.u_v_restore.clear();
 
@@ -435,110 +1398,75 @@ impl SimulationGrid {
             // u and v edges.
             match self.cell_type[*boundary_idx] {
                 Cell::Boundary(BoundaryCell::NoSlip) => {
-                    let boundary_u = 0.0;
-                    let boundary_v = 0.0;
-
+                    set_wall_u_and_v(&mut self.u, &mut self.v, boundary_idx, edge, -1.0);
+                }
+                Cell::Boundary(BoundaryCell::Symmetry) => {
+                    set_wall_u_and_v(&mut self.u, &mut self.v, boundary_idx, edge, 1.0);
+                }
+                Cell::Boundary(BoundaryCell::Outflow) => {
+                    set_outflow_u_and_v(&mut self.u, &mut self.v, boundary_idx, edge);
+                }
+                Cell::Boundary(BoundaryCell::ConvectiveOutflow { speed }) => {
+                    // Advect the outflow values out of the domain at the
+                    // characteristic speed, u_new = u - delt * speed *
+                    // (u - u_upstream) / delx. Unlike the zero-gradient
+                    // condition, this lets disturbances leave the domain
+                    // instead of reflecting them back in.
+                    let courant_x = delt * speed / cell_size[0];
+                    let courant_y = delt * speed / cell_size[1];
                     match edge {
+                        EdgeType::West { west_neighbor } => {
+                            // The boundary is on the east side of the fluid,
+                            // so the outflow face is the u of the fluid cell.
+                            let upstream =
+                                (west_neighbor.0.saturating_sub(1), west_neighbor.1);
+                            self.u[*west_neighbor] -=
+                                courant_x * (self.u[*west_neighbor] - self.u[upstream]);
+                            self.v[*boundary_idx] -= courant_x
+                                * (self.v[*boundary_idx] - self.v[*west_neighbor]);
+                        }
+                        EdgeType::East { east_neighbor } => {
+                            self.u[*boundary_idx] -= courant_x
+                                * (self.u[*boundary_idx] - self.u[*east_neighbor]);
+                            self.v[*boundary_idx] -= courant_x
+                                * (self.v[*boundary_idx] - self.v[*east_neighbor]);
+                        }
                         EdgeType::North { north_neighbor } => {
-                            self.u[*boundary_idx] = -self.u[*north_neighbor];
-                            self.v[*north_neighbor] = boundary_v;
-                        }
-                        EdgeType::NorthEast {
-                            north_neighbor,
-                            east_neighbor,
-                        } => {
-                            self.u[*boundary_idx] = boundary_u;
-                            self.v[*north_neighbor] = boundary_v;
-                            self.v[*boundary_idx] = -self.v[*east_neighbor];
-                        }
-                        EdgeType::East { east_neighbor } => {
-                            self.u[*boundary_idx] = boundary_u;
-                            self.v[*boundary_idx] = -self.v[*east_neighbor];
-                        }
-                        EdgeType::SouthEast { .. } => {
-                            self.u[*boundary_idx] = boundary_u;
-                            self.v[*boundary_idx] = boundary_v;
+                            // The boundary is on the south side of the fluid,
+                            // so the outflow face is the v of the fluid cell.
+                            let upstream =
+                                (north_neighbor.0, north_neighbor.1.saturating_sub(1));
+                            self.v[*north_neighbor] -=
+                                courant_y * (self.v[*north_neighbor] - self.v[upstream]);
+                            self.u[*boundary_idx] -= courant_y
+                                * (self.u[*boundary_idx] - self.u[*north_neighbor]);
                         }
                         EdgeType::South { south_neighbor } => {
-                            self.u[*boundary_idx] = -self.u[*south_neighbor];
-                            self.v[*boundary_idx] = boundary_v;
+                            self.v[*boundary_idx] -= courant_y
+                                * (self.v[*boundary_idx] - self.v[*south_neighbor]);
+                            self.u[*boundary_idx] -= courant_y
+                                * (self.u[*boundary_idx] - self.u[*south_neighbor]);
                         }
-                        EdgeType::SouthWest {
-                            south_neighbor,
-                            west_neighbor,
-                        } => {
-                            self.u[*west_neighbor] = boundary_u;
-                            self.u[*boundary_idx] = -self.u[*south_neighbor];
-                            self.v[*boundary_idx] = boundary_v;
-                        }
-                        EdgeType::West { west_neighbor } => {
-                            self.u[*west_neighbor] = boundary_u;
-                            self.v[*boundary_idx] = -self.v[*west_neighbor];
-                        }
-                        EdgeType::NorthWest {
-                            north_neighbor,
-                            west_neighbor,
-                        } => {
-                            self.u[*west_neighbor] = boundary_u;
-                            self.u[*boundary_idx] = -self.u[*north_neighbor];
-                            self.v[*north_neighbor] = boundary_v;
-                            self.v[*boundary_idx] = -self.v[*west_neighbor];
-                        }
-                    };
-                }
-                Cell::Boundary(BoundaryCell::Outflow) => {
-                    match edge {
-                        EdgeType::North { north_neighbor } => {
-                            self.u[*boundary_idx] = self.u[*north_neighbor];
-                            self.v[*boundary_idx] = self.v[*north_neighbor];
-                        }
-                        EdgeType::NorthEast {
-                            north_neighbor,
-                            east_neighbor,
-                        } => {
-                            self.u[*boundary_idx] = self.u[*north_neighbor];
-                            self.v[*boundary_idx] = self.v[*east_neighbor];
-                        }
-                        EdgeType::East { east_neighbor } => {
-                            self.u[*boundary_idx] = self.u[*east_neighbor];
-                            self.v[*boundary_idx] = self.v[*east_neighbor];
-                        }
-                        EdgeType::SouthEast {
-                            south_neighbor,
-                            east_neighbor,
-                        } => {
-                            self.u[*boundary_idx] = self.u[*east_neighbor];
-                            self.v[*boundary_idx] = self.v[*south_neighbor];
-                        }
-                        EdgeType::South { south_neighbor } => {
-                            self.u[*boundary_idx] = self.u[*south_neighbor];
-                            self.v[*boundary_idx] = self.v[*south_neighbor];
-                        }
-                        EdgeType::SouthWest {
-                            south_neighbor,
-                            west_neighbor,
-                        } => {
-                            self.u[*boundary_idx] = self.u[*west_neighbor];
-                            self.v[*boundary_idx] = self.v[*south_neighbor];
-                        }
-                        EdgeType::West { west_neighbor } => {
-                            self.u[*boundary_idx] = self.u[*west_neighbor];
-                            self.v[*boundary_idx] = self.v[*west_neighbor];
-                        }
-                        EdgeType::NorthWest {
-                            north_neighbor,
-                            west_neighbor,
-                        } => {
-                            self.u[*boundary_idx] = self.u[*north_neighbor];
-                            self.v[*boundary_idx] = self.v[*west_neighbor];
-                        }
-                    };
+                        // There's no single outflow direction in corners, so
+                        // fall back to the zero-gradient condition.
+                        _ => set_outflow_u_and_v(
+                            &mut self.u,
+                            &mut self.v,
+                            boundary_idx,
+                            edge,
+                        ),
+                    }
                 }
                 Cell::Boundary(BoundaryCell::Inflow { velocity }) => {
                     let [boundary_u, boundary_v] = velocity;
+                    // The velocity normal to the boundary is set directly.
+                    // The tangential velocity lives half a cell away from the
+                    // boundary, so it's set so that the average with the
+                    // fluid cell is the inflow velocity.
                     match edge {
                         EdgeType::North { north_neighbor } => {
-                            self.u[*boundary_idx] = -self.u[*north_neighbor];
+                            self.u[*boundary_idx] =
+                                2.0 * boundary_u - self.u[*north_neighbor];
                             self.v[*north_neighbor] = boundary_v;
                         }
                         EdgeType::NorthEast {
@@ -547,18 +1475,21 @@ impl SimulationGrid {
                         } => {
                             self.u[*boundary_idx] = boundary_u;
                             self.v[*north_neighbor] = boundary_v;
-                            self.v[*boundary_idx] = -self.v[*east_neighbor];
+                            self.v[*boundary_idx] =
+                                2.0 * boundary_v - self.v[*east_neighbor];
                         }
                         EdgeType::East { east_neighbor } => {
                             self.u[*boundary_idx] = boundary_u;
-                            self.v[*boundary_idx] = -self.v[*east_neighbor];
+                            self.v[*boundary_idx] =
+                                2.0 * boundary_v - self.v[*east_neighbor];
                         }
                         EdgeType::SouthEast { .. } => {
                             self.u[*boundary_idx] = boundary_u;
                             self.v[*boundary_idx] = boundary_v;
                         }
                         EdgeType::South { south_neighbor } => {
-                            self.u[*boundary_idx] = -self.u[*south_neighbor];
+                            self.u[*boundary_idx] =
+                                2.0 * boundary_u - self.u[*south_neighbor];
                             self.v[*boundary_idx] = boundary_v;
                         }
                         EdgeType::SouthWest {
@@ -566,21 +1497,25 @@ impl SimulationGrid {
                             west_neighbor,
                         } => {
                             self.u[*west_neighbor] = boundary_u;
-                            self.u[*boundary_idx] = -self.u[*south_neighbor];
+                            self.u[*boundary_idx] =
+                                2.0 * boundary_u - self.u[*south_neighbor];
                             self.v[*boundary_idx] = boundary_v;
                         }
                         EdgeType::West { west_neighbor } => {
                             self.u[*west_neighbor] = boundary_u;
-                            self.v[*boundary_idx] = -self.v[*west_neighbor];
+                            self.v[*boundary_idx] =
+                                2.0 * boundary_v - self.v[*west_neighbor];
                         }
                         EdgeType::NorthWest {
                             north_neighbor,
                             west_neighbor,
                         } => {
                             self.u[*west_neighbor] = boundary_u;
-                            self.u[*boundary_idx] = -self.u[*north_neighbor];
+                            self.u[*boundary_idx] =
+                                2.0 * boundary_u - self.u[*north_neighbor];
                             self.v[*north_neighbor] = boundary_v;
-                            self.v[*boundary_idx] = -self.v[*west_neighbor];
+                            self.v[*boundary_idx] =
+                                2.0 * boundary_v - self.v[*west_neighbor];
                         }
                     };
                 }
@@ -598,49 +1533,45 @@ impl SimulationGrid {
                 Some(self.v[*boundary_idx]),
             ));
 
-            // Stash u and v values for later restoration
+            // Stash the velocities through the north and west edges, which
+            // are stored in the fluid neighbors, for later restoration.
+            // Outflow velocities are left to the pressure correction, so that
+            // the flow leaving the domain balances the flow entering it.
+            if let Cell::Boundary(
+                BoundaryCell::Outflow | BoundaryCell::ConvectiveOutflow { .. },
+            ) = self.cell_type[*boundary_idx]
+            {
+                continue;
+            }
             match edge {
-                EdgeType::North { north_neighbor } => {
+                EdgeType::North { north_neighbor }
+                | EdgeType::NorthEast { north_neighbor, .. } => {
                     self.boundaries.u_v_restore.push((
-                        *boundary_idx,
+                        *north_neighbor,
                         None,
                         Some(self.v[*north_neighbor]),
                     ));
                 }
-                EdgeType::NorthEast {
-                    north_neighbor,
-                    east_neighbor: _,
-                } => {
+                EdgeType::West { west_neighbor }
+                | EdgeType::SouthWest { west_neighbor, .. } => {
                     self.boundaries.u_v_restore.push((
-                        *boundary_idx,
+                        *west_neighbor,
+                        Some(self.u[*west_neighbor]),
                         None,
-                        Some(self.v[*north_neighbor]),
                     ));
                 }
-                EdgeType::SouthWest {
-                    south_neighbor: _,
+                EdgeType::NorthWest {
+                    north_neighbor,
                     west_neighbor,
                 } => {
                     self.boundaries.u_v_restore.push((
-                        *boundary_idx,
+                        *west_neighbor,
                         Some(self.u[*west_neighbor]),
                         None,
                     ));
-                }
-                EdgeType::West { west_neighbor } => {
                     self.boundaries.u_v_restore.push((
-                        *boundary_idx,
-                        Some(self.u[*west_neighbor]),
+                        *north_neighbor,
                         None,
-                    ));
-                }
-                EdgeType::NorthWest {
-                    north_neighbor,
-                    west_neighbor,
-                } => {
-                    self.boundaries.u_v_restore.push((
-                        *boundary_idx,
-                        Some(self.u[*west_neighbor]),
                         Some(self.v[*north_neighbor]),
                     ));
                 }
@@ -651,9 +1582,192 @@ impl SimulationGrid {
     }
 }
 
+/// Convert the region of cells connected to `start` to `target`
+///
+/// The region is every cell of the same type as `start` that can be reached
+/// from it through north, south, east and west neighbors. The outer ring of
+/// the grid is never changed, so regions that reach it are clipped there.
+/// Converted cells have their velocities and pressure set to zero. If the
+/// result isn't a valid boundary, the grid is left as it was and the error is
+/// returned.
+///
+/// Returns the number of cells that were changed.
+///
+/// # Arguments
+///
+/// * `grid` - The grid to fill
+/// * `start` - An interior cell of the region to fill
+/// * `target` - The type to convert the region to
+pub fn flood_fill(
+    grid: &mut SimulationGrid,
+    start: GridIndex,
+    target: Cell,
+) -> Result<usize, SimulationGridError> {
+    let [x_size, y_size] = grid.size;
+    let interior = |(x, y): GridIndex| x > 0 && y > 0 && x < x_size - 1 && y < y_size - 1;
+    if !interior(start) {
+        return Err(SimulationGridError::NotInteriorError(start, grid.size));
+    }
+    let source = grid.cell_type[start];
+    if source == target {
+        return Ok(0);
+    }
+
+    // An explicit stack instead of recursion, so large regions can't
+    // overflow the call stack.
+    let mut backup: Vec<(GridIndex, Real, Real, Real)> = Vec::new();
+    let mut stack = vec![start];
+    while let Some(idx) = stack.pop() {
+        if !interior(idx) || grid.cell_type[idx] != source {
+            continue;
+        }
+        backup.push((idx, grid.u[idx], grid.v[idx], grid.pressure[idx]));
+        grid.cell_type[idx] = target;
+        grid.u[idx] = 0.0;
+        grid.v[idx] = 0.0;
+        grid.pressure[idx] = 0.0;
+
+        let (x, y) = idx;
+        stack.extend([(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]);
+    }
+
+    if let Err(err) = grid.rebuild_boundary_list() {
+        for (idx, u, v, pressure) in backup {
+            grid.cell_type[idx] = source;
+            grid.u[idx] = u;
+            grid.v[idx] = v;
+            grid.pressure[idx] = pressure;
+        }
+        grid.rebuild_boundary_list()?;
+        return Err(err);
+    }
+    Ok(backup.len())
+}
+
+/// Apply a wall condition: the normal velocity is zero, and the tangential
+/// velocity in the boundary cell is the fluid neighbor's multiplied by
+/// `tangential_sign`. A sign of -1 averages to zero on the wall (no-slip),
+/// while +1 gives a zero gradient across the wall (free-slip or symmetry).
+fn set_wall_u_and_v(
+    u: &mut GridArray<Real>,
+    v: &mut GridArray<Real>,
+    boundary_idx: &GridIndex,
+    edge: &EdgeType,
+    tangential_sign: Real,
+) {
+    let boundary_u = 0.0;
+    let boundary_v = 0.0;
+
+    match edge {
+        EdgeType::North { north_neighbor } => {
+            u[*boundary_idx] = tangential_sign * u[*north_neighbor];
+            v[*north_neighbor] = boundary_v;
+        }
+        EdgeType::NorthEast {
+            north_neighbor,
+            east_neighbor,
+        } => {
+            u[*boundary_idx] = boundary_u;
+            v[*north_neighbor] = boundary_v;
+            v[*boundary_idx] = tangential_sign * v[*east_neighbor];
+        }
+        EdgeType::East { east_neighbor } => {
+            u[*boundary_idx] = boundary_u;
+            v[*boundary_idx] = tangential_sign * v[*east_neighbor];
+        }
+        EdgeType::SouthEast { .. } => {
+            u[*boundary_idx] = boundary_u;
+            v[*boundary_idx] = boundary_v;
+        }
+        EdgeType::South { south_neighbor } => {
+            u[*boundary_idx] = tangential_sign * u[*south_neighbor];
+            v[*boundary_idx] = boundary_v;
+        }
+        EdgeType::SouthWest {
+            south_neighbor,
+            west_neighbor,
+        } => {
+            u[*west_neighbor] = boundary_u;
+            u[*boundary_idx] = tangential_sign * u[*south_neighbor];
+            v[*boundary_idx] = boundary_v;
+        }
+        EdgeType::West { west_neighbor } => {
+            u[*west_neighbor] = boundary_u;
+            v[*boundary_idx] = tangential_sign * v[*west_neighbor];
+        }
+        EdgeType::NorthWest {
+            north_neighbor,
+            west_neighbor,
+        } => {
+            u[*west_neighbor] = boundary_u;
+            u[*boundary_idx] = tangential_sign * u[*north_neighbor];
+            v[*north_neighbor] = boundary_v;
+            v[*boundary_idx] = tangential_sign * v[*west_neighbor];
+        }
+    };
+}
+
+/// Apply the zero-gradient outflow condition, copying the fluid neighbors' u
+/// and v into the boundary cell.
+fn set_outflow_u_and_v(
+    u: &mut GridArray<Real>,
+    v: &mut GridArray<Real>,
+    boundary_idx: &GridIndex,
+    edge: &EdgeType,
+) {
+    match edge {
+        EdgeType::North { north_neighbor } => {
+            u[*boundary_idx] = u[*north_neighbor];
+            v[*boundary_idx] = v[*north_neighbor];
+        }
+        EdgeType::NorthEast {
+            north_neighbor,
+            east_neighbor,
+        } => {
+            u[*boundary_idx] = u[*north_neighbor];
+            v[*boundary_idx] = v[*east_neighbor];
+        }
+        EdgeType::East { east_neighbor } => {
+            u[*boundary_idx] = u[*east_neighbor];
+            v[*boundary_idx] = v[*east_neighbor];
+        }
+        EdgeType::SouthEast {
+            south_neighbor,
+            east_neighbor,
+        } => {
+            u[*boundary_idx] = u[*east_neighbor];
+            v[*boundary_idx] = v[*south_neighbor];
+        }
+        EdgeType::South { south_neighbor } => {
+            u[*boundary_idx] = u[*south_neighbor];
+            v[*boundary_idx] = v[*south_neighbor];
+        }
+        EdgeType::SouthWest {
+            south_neighbor,
+            west_neighbor,
+        } => {
+            u[*boundary_idx] = u[*west_neighbor];
+            v[*boundary_idx] = v[*south_neighbor];
+        }
+        EdgeType::West { west_neighbor } => {
+            u[*boundary_idx] = u[*west_neighbor];
+            v[*boundary_idx] = v[*west_neighbor];
+        }
+        EdgeType::NorthWest {
+            north_neighbor,
+            west_neighbor,
+        } => {
+            u[*boundary_idx] = u[*north_neighbor];
+            v[*boundary_idx] = v[*west_neighbor];
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::grid::presets::Walls;
+    use crate::math::{assert_close, precision_snapshots};
     use ndarray::Array;
     use std::fs::File;
     use std::io::BufReader;
@@ -680,6 +1794,123 @@ mod tests {
         assert_eq!(grid.cell_type.shape(), size);
     }
 
+    #[test]
+    fn from_fields() {
+        let size = [5, 4];
+        let fields = |shape: GridSize| {
+            (
+                Array::zeros(size),
+                Array::from_elem(size, 1.0),
+                Array::zeros(shape),
+                Array::from_elem(size, Cell::Fluid),
+            )
+        };
+
+        let (pressure, u, v, cell_type) = fields(size);
+        let grid = SimulationGrid::from_fields(size, pressure, u, v, cell_type).unwrap();
+        assert_eq!(grid.size, size);
+        assert_eq!(grid.u[(2, 3)], 1.0);
+        assert_eq!(grid.boundaries.fluid_cells, 20.0);
+
+        let (pressure, u, v, cell_type) = fields([4, 5]);
+        let grid = SimulationGrid::from_fields(size, pressure, u, v, cell_type);
+        assert!(matches!(
+            grid,
+            Err(SimulationGridError::ShapeMismatchError("v", _, [5, 4]))
+        ));
+    }
+
+    #[test]
+    fn subgrid() {
+        use crate::cell::{BoundaryCell, Cell};
+
+        let size = [40, 16];
+        let mut grid =
+            presets::obstacle(size, [1.0, 0.0], (10, 8), 3.0, Walls::default()).unwrap();
+        grid.u[(20, 5)] = 1.5;
+        grid.pressure[(15, 8)] = 7.0;
+
+        // The wake behind the obstacle, between the walls
+        let wake = grid.subgrid(15..35, 1..15).unwrap();
+        assert_eq!(wake.size, [22, 16]);
+        assert_eq!(wake.u[(6, 5)], 1.5);
+        assert_eq!(wake.pressure[(1, 8)], 7.0);
+        let outflow = Cell::Boundary(BoundaryCell::Outflow);
+        assert!((1..15).all(|y| wake.cell_type[(0, y)] == outflow));
+        assert!((1..15).all(|y| wake.cell_type[(21, y)] == outflow));
+        // The walls of the channel are still walls.
+        assert_eq!(wake.cell_type.row(10), grid.cell_type.row(24));
+
+        // A window around the obstacle itself, away from the walls
+        let middle = grid.subgrid(5..16, 3..14).unwrap();
+        assert_eq!(middle.size, [13, 13]);
+        assert!(middle.cell_type.row(0).iter().all(|cell| *cell == outflow));
+        assert_eq!(
+            middle.cell_type[(6, 6)],
+            Cell::Boundary(BoundaryCell::NoSlip)
+        );
+
+        let whole = grid.subgrid(1..39, 1..15).unwrap();
+        assert_eq!(whole.approx_eq(&grid, 0.0), None);
+
+        for (x_range, y_range) in [(0..10, 1..15), (1..40, 1..15), (5..5, 1..15)] {
+            assert!(matches!(
+                grid.subgrid(x_range, y_range),
+                Err(SimulationGridError::PatchOutOfBoundsError(..))
+            ));
+        }
+    }
+
+    #[test]
+    fn mirror() {
+        use crate::cell::{BoundaryCell, Cell};
+
+        let size = [8, 7];
+        let inflow = [1.0, 0.0];
+        // A 2x2 obstacle, with velocities and a pressure that can be told apart
+        // from their reflections.
+        let grid_with_obstacle = |x0: usize, y0: usize| {
+            let mut grid = presets::simple_inflow(size, inflow, Walls::default());
+            for idx in [(x0, y0), (x0 + 1, y0), (x0, y0 + 1), (x0 + 1, y0 + 1)] {
+                grid.cell_type[idx] = Cell::Boundary(BoundaryCell::NoSlip);
+            }
+            grid.rebuild_boundary_list().unwrap();
+            grid
+        };
+
+        let mut grid = grid_with_obstacle(2, 2);
+        grid.u[(1, 5)] = 1.5;
+        grid.v[(2, 1)] = 0.5;
+        grid.pressure[(1, 1)] = 7.0;
+        grid.u[(0, 3)] = inflow[0];
+        grid.mirror_x().unwrap();
+        assert_eq!(grid.cell_type, grid_with_obstacle(4, 2).cell_type);
+        assert_eq!(grid.u[(5, 5)], -1.5);
+        assert_eq!(grid.v[(5, 1)], 0.5);
+        assert_eq!(grid.pressure[(6, 1)], 7.0);
+        // The inflow is still on the left.
+        assert_eq!(grid.u[(0, 3)], inflow[0]);
+        assert_eq!(
+            grid.cell_type[(0, 3)],
+            Cell::Boundary(BoundaryCell::Inflow { velocity: inflow })
+        );
+
+        let mut grid = grid_with_obstacle(2, 2);
+        grid.u[(1, 5)] = 1.5;
+        grid.v[(5, 1)] = 0.5;
+        grid.pressure[(1, 1)] = 7.0;
+        grid.mirror_y().unwrap();
+        assert_eq!(grid.cell_type, grid_with_obstacle(2, 3).cell_type);
+        assert_eq!(grid.u[(1, 1)], 1.5);
+        assert_eq!(grid.v[(5, 4)], -0.5);
+        assert_eq!(grid.pressure[(1, 5)], 7.0);
+
+        // Mirroring twice gets back to the start.
+        grid.mirror_y().unwrap();
+        assert_eq!(grid.cell_type, grid_with_obstacle(2, 2).cell_type);
+        assert_eq!(grid.v[(5, 1)], 0.5);
+    }
+
     #[test]
     fn thin_boundary() {
         use crate::cell::{BoundaryCell, Cell};
@@ -700,10 +1931,124 @@ mod tests {
             }
             let grid = SimulationGrid::try_from(unfinalized);
             assert!(grid.is_err());
-            assert!(format!("{:?}", grid).contains("BoundaryTooThinError"));
+            assert!(format!("{:?}", grid).contains("InvalidBoundaries"));
         }
     }
 
+    #[test]
+    fn invalid_boundaries() {
+        use crate::cell::{BoundaryCell, Cell};
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        let size = [10, 8];
+        let mut cell_type = Array::from_elem(size, Cell::Fluid);
+        for x in 0..size[0] {
+            cell_type[(x, 0)] = wall;
+            cell_type[(x, size[1] - 1)] = wall;
+        }
+        for y in 0..size[1] {
+            cell_type[(0, y)] = wall;
+            cell_type[(size[0] - 1, y)] = wall;
+        }
+        // Three walls one cell thick, sticking out of the top, bottom and left
+        // sides, and a valid 2x2 obstacle.
+        for idx in [(3, 1), (6, 6), (1, 4), (5, 3), (6, 3), (5, 4), (6, 4)] {
+            cell_type[idx] = wall;
+        }
+        let unfinalized = UnfinalizedSimulationGrid {
+            size,
+            pressure: Array::zeros(size),
+            u: Array::zeros(size),
+            v: Array::zeros(size),
+            cell_type,
+        };
+        let Err(SimulationGridError::InvalidBoundaries(invalid)) =
+            SimulationGrid::try_from(unfinalized)
+        else {
+            panic!("the thin walls weren't rejected");
+        };
+        assert_eq!(
+            invalid,
+            vec![
+                ((1, 4), InvalidReason::FluidAboveAndBelow),
+                ((3, 1), InvalidReason::FluidLeftAndRight),
+                ((6, 6), InvalidReason::FluidLeftAndRight),
+            ]
+        );
+
+        let many = SimulationGridError::InvalidBoundaries(
+            (0..25)
+                .map(|y| ((1, y), InvalidReason::FluidAllAround))
+                .collect(),
+        );
+        let message = many.to_string();
+        assert_eq!(message.lines().count(), 22);
+        assert!(
+            message.contains("(1, 19): fluid on all sides"),
+            "{}",
+            message
+        );
+        assert!(message.ends_with("and 5 more"), "{}", message);
+    }
+
+    // A `size` grid of fluid with no-slip walls all around and at `walls`
+    fn walled_grid(size: GridSize, walls: &[GridIndex]) -> UnfinalizedSimulationGrid {
+        use crate::cell::{BoundaryCell, Cell};
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        let [w, h] = size;
+        let cell_type = Array::from_shape_fn(size, |idx @ (x, y)| {
+            if x == 0 || y == 0 || x == w - 1 || y == h - 1 || walls.contains(&idx) {
+                wall
+            } else {
+                Cell::Fluid
+            }
+        });
+        UnfinalizedSimulationGrid {
+            size,
+            pressure: Array::ones(size),
+            u: Array::ones(size),
+            v: Array::ones(size),
+            cell_type,
+        }
+    }
+
+    #[test]
+    fn repair_thin_boundaries() {
+        use crate::cell::{BoundaryCell, Cell};
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        let size = [10, 8];
+
+        // A wall one cell thick hanging down from the top gets a second column.
+        let thin: Vec<GridIndex> = (1..=4).map(|y| (4, y)).collect();
+        let mut unfinalized = walled_grid(size, &thin);
+        assert_eq!(unfinalized.repair_thin_boundaries().unwrap(), 4);
+        let grid = SimulationGrid::try_from(unfinalized).unwrap();
+        for y in 1..=4 {
+            assert_eq!(grid.cell_type[(4, y)], wall);
+            assert_eq!(grid.cell_type[(5, y)], wall);
+            assert_eq!(grid.u[(5, y)], 0.0);
+            assert_eq!(grid.pressure[(5, y)], 0.0);
+        }
+        assert_eq!(grid.cell_type[(4, 5)], Cell::Fluid);
+        assert_eq!(grid.cell_type[(6, 1)], Cell::Fluid);
+
+        // A valid grid is left alone.
+        let mut valid = grid.clone();
+        assert_eq!(valid.repair_thin_boundaries(), 0);
+        assert!(valid.approx_eq(&grid, 0.0).is_none());
+
+        // A checkerboard is as bad as it gets, but the repair still ends with
+        // a valid grid.
+        let checkerboard: Vec<GridIndex> = (1..size[0] - 1)
+            .flat_map(|x| (1..size[1] - 1).map(move |y| (x, y)))
+            .filter(|(x, y)| (x + y) % 2 == 0)
+            .collect();
+        let mut grid =
+            SimulationGrid::unvalidated(walled_grid(size, &checkerboard)).unwrap();
+        assert!(grid.repair_thin_boundaries() > 0);
+        let mut rebuilt = grid.clone();
+        rebuilt.rebuild_boundary_list().unwrap();
+    }
+
     #[test]
     fn rebuild_boundary_list() {
         use crate::cell::{BoundaryCell, Cell};
@@ -800,6 +2145,114 @@ mod tests {
         }
     }
 
+    #[test]
+    fn u_v_restore_capacity() {
+        let mut grid =
+            presets::obstacle([30, 12], [1.0, 0.0], (10, 6), 3.0, Walls::default())
+                .unwrap();
+        let capacity = grid.boundaries.u_v_restore.capacity();
+        assert!(capacity >= grid.boundaries.sorted_boundary_list.len());
+        for _ in 0..5 {
+            grid.set_boundary_u_and_v(0.005, [0.1, 0.1]).unwrap();
+            assert!(grid.boundaries.u_v_restore.len() <= capacity);
+            assert_eq!(grid.boundaries.u_v_restore.capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn inflow_edges() {
+        // A 4x4 block of inflow cells in a box has every kind of edge.
+        let size = [10, 10];
+        let velocity = [0.5, -0.25];
+        let mut cell_type = Array::from_elem(size, Cell::Fluid);
+        for i in 0..10 {
+            for idx in [(i, 0), (i, 9), (0, i), (9, i)] {
+                cell_type[idx] = Cell::Boundary(BoundaryCell::NoSlip);
+            }
+        }
+        for x in 3..7 {
+            for y in 3..7 {
+                cell_type[(x, y)] = Cell::Boundary(BoundaryCell::Inflow { velocity });
+            }
+        }
+        let mut grid = SimulationGrid::from_fields(
+            size,
+            Array::zeros(size),
+            Array::from_shape_fn(size, |(x, y)| (x + 2 * y) as Real / 10.0),
+            Array::from_shape_fn(size, |(x, y)| (3 * x + y) as Real / 10.0),
+            cell_type,
+        )
+        .unwrap();
+        grid.set_boundary_u_and_v(0.005, [0.1, 0.1]).unwrap();
+
+        let [boundary_u, boundary_v] = velocity;
+        let mut edges_seen = 0;
+        for &(idx, edge) in &grid.boundaries.sorted_boundary_list {
+            if grid.cell_type[idx] != (Cell::Boundary(BoundaryCell::Inflow { velocity }))
+            {
+                continue;
+            }
+            let Some(edge) = edge else {
+                continue;
+            };
+            edges_seen += 1;
+            // The velocity through each edge is the inflow velocity, and so is
+            // the average of the ghost velocity along each edge with its
+            // fluid neighbor.
+            let along_u = |neighbor: GridIndex| (grid.u[idx] + grid.u[neighbor]) / 2.0;
+            let along_v = |neighbor: GridIndex| (grid.v[idx] + grid.v[neighbor]) / 2.0;
+            match edge {
+                EdgeType::North { north_neighbor } => {
+                    assert_eq!(grid.v[north_neighbor], boundary_v);
+                    assert!((along_u(north_neighbor) - boundary_u).abs() < 1e-12);
+                }
+                EdgeType::NorthEast {
+                    north_neighbor,
+                    east_neighbor,
+                } => {
+                    assert_eq!(grid.v[north_neighbor], boundary_v);
+                    assert_eq!(grid.u[idx], boundary_u);
+                    assert!((along_v(east_neighbor) - boundary_v).abs() < 1e-12);
+                }
+                EdgeType::East { east_neighbor } => {
+                    assert_eq!(grid.u[idx], boundary_u);
+                    assert!((along_v(east_neighbor) - boundary_v).abs() < 1e-12);
+                }
+                EdgeType::SouthEast { .. } => {
+                    assert_eq!(grid.u[idx], boundary_u);
+                    assert_eq!(grid.v[idx], boundary_v);
+                }
+                EdgeType::South { south_neighbor } => {
+                    assert_eq!(grid.v[idx], boundary_v);
+                    assert!((along_u(south_neighbor) - boundary_u).abs() < 1e-12);
+                }
+                EdgeType::SouthWest {
+                    south_neighbor,
+                    west_neighbor,
+                } => {
+                    assert_eq!(grid.v[idx], boundary_v);
+                    assert_eq!(grid.u[west_neighbor], boundary_u);
+                    assert!((along_u(south_neighbor) - boundary_u).abs() < 1e-12);
+                }
+                EdgeType::West { west_neighbor } => {
+                    assert_eq!(grid.u[west_neighbor], boundary_u);
+                    assert!((along_v(west_neighbor) - boundary_v).abs() < 1e-12);
+                }
+                EdgeType::NorthWest {
+                    north_neighbor,
+                    west_neighbor,
+                } => {
+                    assert_eq!(grid.v[north_neighbor], boundary_v);
+                    assert_eq!(grid.u[west_neighbor], boundary_u);
+                    assert!((along_u(north_neighbor) - boundary_u).abs() < 1e-12);
+                    assert!((along_v(west_neighbor) - boundary_v).abs() < 1e-12);
+                }
+            }
+        }
+        // Four corners and two cells along each side.
+        assert_eq!(edges_seen, 12);
+    }
+
     #[test]
     fn deserialize() {
         let test_filename = test_data_directory().join("simple_grid.json");
@@ -812,6 +2265,7 @@ mod tests {
 
     #[test]
     fn deserialize_boundaries() {
+        let _snapshots = precision_snapshots();
         let test_filename = test_data_directory().join("small_grid_with_boundaries.json");
         let result = SimulationGrid::from_reader(BufReader::new(
             File::open(test_filename).unwrap(),
@@ -822,10 +2276,328 @@ mod tests {
         insta::assert_snapshot!(result.boundaries);
     }
 
+    #[test]
+    fn from_nast2d() {
+        use crate::cell::{BoundaryCell, Cell};
+        let test_filename = test_data_directory().join("obstacle_flags.nast2d");
+        let size = [8, 6];
+        let grid = SimulationGrid::from_nast2d(File::open(&test_filename).unwrap(), size)
+            .unwrap();
+        let obstacle = [(3, 2), (4, 2), (3, 3), (4, 3)];
+        for ((x, y), &cell) in grid.cell_type.indexed_iter() {
+            let outer = x == 0 || y == 0 || x == 7 || y == 5;
+            if outer || obstacle.contains(&(x, y)) {
+                assert_eq!(cell, Cell::Boundary(BoundaryCell::NoSlip));
+            } else {
+                assert_eq!(cell, Cell::Fluid);
+            }
+        }
+        assert_eq!(grid.boundaries.fluid_cells, 20.0);
+        assert_eq!(
+            grid.boundaries.sorted_boundary_list[..4],
+            [
+                ((0, 0), None),
+                (
+                    (0, 1),
+                    Some(EdgeType::East {
+                        east_neighbor: (1, 1)
+                    })
+                ),
+                (
+                    (0, 2),
+                    Some(EdgeType::East {
+                        east_neighbor: (1, 2)
+                    })
+                ),
+                (
+                    (0, 3),
+                    Some(EdgeType::East {
+                        east_neighbor: (1, 3)
+                    })
+                ),
+            ]
+        );
+
+        let flags = std::fs::read_to_string(&test_filename).unwrap();
+        assert!(matches!(
+            SimulationGrid::from_nast2d(flags.as_bytes(), [8, 7]),
+            Err(SimulationGridError::Nast2dFormatError(6, _))
+        ));
+        assert!(matches!(
+            SimulationGrid::from_nast2d(flags.as_bytes(), [9, 6]),
+            Err(SimulationGridError::Nast2dFormatError(1, _))
+        ));
+        let bad_flag = flags.replacen("16", "32", 1);
+        assert!(matches!(
+            SimulationGrid::from_nast2d(bad_flag.as_bytes(), size),
+            Err(SimulationGridError::Nast2dFormatError(2, _))
+        ));
+        let fluid_ring = flags.replacen("0 0", "0 16", 1);
+        assert!(matches!(
+            SimulationGrid::from_nast2d(fluid_ring.as_bytes(), size),
+            Err(SimulationGridError::NotInteriorError((1, 0), _))
+        ));
+    }
+
     #[test]
     fn serialize() {
         let size = [2, 3];
         let grid = presets::empty(size);
         insta::assert_json_snapshot!(grid);
     }
+
+    #[test]
+    fn cell_centered_velocity() {
+        let size = [7, 5];
+        // A linear field, as a function of the position in cells
+        let field_u = |x: Real, y: Real| 1.0 + 0.5 * x - 0.25 * y;
+        let field_v = |x: Real, y: Real| -2.0 + 0.125 * x + 0.75 * y;
+        let mut grid = presets::empty(size);
+        // The east face of cell (x, y) is at (x + 1, y + 0.5), the south face
+        // at (x + 0.5, y + 1).
+        grid.u = Array::from_shape_fn(size, |(x, y)| {
+            field_u(x as Real + 1.0, y as Real + 0.5)
+        });
+        grid.v = Array::from_shape_fn(size, |(x, y)| {
+            field_v(x as Real + 0.5, y as Real + 1.0)
+        });
+
+        let (u, v) = grid.cell_centered_velocity();
+        for ((x, y), u) in u.indexed_iter() {
+            let (cx, cy) = (x as Real + 0.5, y as Real + 0.5);
+            let expected = match x {
+                0 => field_u(1.0, cy),
+                6 => field_u(6.0, cy),
+                _ => field_u(cx, cy),
+            };
+            assert!((u - expected).abs() < 1e-12, "u at {:?}", (x, y));
+        }
+        for ((x, y), v) in v.indexed_iter() {
+            let (cx, cy) = (x as Real + 0.5, y as Real + 0.5);
+            let expected = match y {
+                0 => field_v(cx, 1.0),
+                4 => field_v(cx, 4.0),
+                _ => field_v(cx, cy),
+            };
+            assert!((v - expected).abs() < 1e-12, "v at {:?}", (x, y));
+        }
+    }
+
+    #[test]
+    fn cell_center() {
+        let grid = presets::empty([10, 5]);
+        let cell_size = [0.1, 0.2];
+        assert_close!(grid.cell_center((0, 0), cell_size).0, 0.05);
+        assert_close!(grid.cell_center((0, 0), cell_size).1, 0.1);
+        let (x, y) = grid.cell_center((9, 4), cell_size);
+        assert_close!(x, 0.95);
+        assert_close!(y, 0.9);
+    }
+
+    #[test]
+    fn cell_accessors() {
+        let mut grid = presets::simple_inflow([10, 8], [1.0, 0.0], Walls::default());
+        grid.u[(3, 4)] = 2.0;
+        grid.u[(4, 4)] = 4.0;
+        grid.v[(4, 3)] = -1.0;
+        grid.pressure[(4, 4)] = 0.5;
+        assert_eq!(grid.velocity_at_index((4, 4)), [3.0, -0.5]);
+        let (u, v) = grid.cell_centered_velocity();
+        assert_eq!([u[(4, 4)], v[(4, 4)]], grid.velocity_at_index((4, 4)));
+        assert_eq!(grid.pressure_at_index((4, 4)), 0.5);
+        assert_eq!(
+            grid.cell_at((0, 4)),
+            Cell::Boundary(BoundaryCell::Inflow {
+                velocity: [1.0, 0.0]
+            })
+        );
+
+        // A 2x2 obstacle is fine, and its cells start at rest.
+        let obstacle = [(4, 4), (5, 4), (4, 5), (5, 5)];
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        grid.set_cells(obstacle, wall).unwrap();
+        assert_eq!(grid.cell_at((4, 4)), wall);
+        assert_eq!(grid.velocity_at_index((5, 5)), [0.0, 0.0]);
+        assert_eq!(grid.pressure_at_index((4, 4)), 0.0);
+        let boundaries = grid.boundaries.sorted_boundary_list.clone();
+        let mut rebuilt = grid.clone();
+        rebuilt.rebuild_boundary_list().unwrap();
+        assert_eq!(rebuilt.boundaries.sorted_boundary_list, boundaries);
+
+        // A single cell is too thin, so nothing changes.
+        grid.u[(2, 2)] = 0.25;
+        assert!(matches!(
+            grid.set_cell((2, 2), wall),
+            Err(SimulationGridError::InvalidBoundaries(..))
+        ));
+        assert_eq!(grid.cell_at((2, 2)), Cell::Fluid);
+        assert_eq!(grid.u[(2, 2)], 0.25);
+        assert_eq!(grid.boundaries.sorted_boundary_list, boundaries);
+
+        assert!(matches!(
+            grid.set_cell((0, 3), Cell::Fluid),
+            Err(SimulationGridError::NotInteriorError((0, 3), _))
+        ));
+        assert!(matches!(
+            grid.set_cell((10, 3), wall),
+            Err(SimulationGridError::OutOfBoundsError((10, 3), _))
+        ));
+        grid.set_cells(obstacle, Cell::Fluid).unwrap();
+        rebuilt.set_cells(obstacle, Cell::Fluid).unwrap();
+        rebuilt.rebuild_boundary_list().unwrap();
+        assert_eq!(
+            grid.boundaries.sorted_boundary_list,
+            rebuilt.boundaries.sorted_boundary_list
+        );
+    }
+
+    #[test]
+    fn inflow_groups() {
+        let mut grid = presets::multi_inflow(
+            [12, 10],
+            &[
+                ((0, 1), (0, 2), [1.0, 0.0]),
+                ((0, 3), (0, 5), [0.5, -0.5]),
+                ((0, 6), (0, 8), [1.0, 0.0]),
+            ],
+            Walls::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            grid.inflow_groups(),
+            vec![([1.0, 0.0], 5), ([0.5, -0.5], 3)]
+        );
+
+        let boundaries = grid.boundaries.sorted_boundary_list.clone();
+        assert_eq!(grid.set_inflow_velocity([0.5, -0.5], [2.0, 0.25]), 3);
+        for y in 1..=8 {
+            let expected = if (3..=5).contains(&y) {
+                [2.0, 0.25]
+            } else {
+                [1.0, 0.0]
+            };
+            assert_eq!(
+                grid.cell_type[(0, y)],
+                Cell::Boundary(BoundaryCell::Inflow { velocity: expected })
+            );
+        }
+        assert_eq!(grid.boundaries.sorted_boundary_list, boundaries);
+        // Nothing has the old velocity any more.
+        assert_eq!(grid.set_inflow_velocity([0.5, -0.5], [0.0, 0.0]), 0);
+        assert_eq!(
+            grid.inflow_groups(),
+            vec![([1.0, 0.0], 5), ([2.0, 0.25], 3)]
+        );
+    }
+
+    #[test]
+    fn isolated_fluid_regions() {
+        use crate::cell::{BoundaryCell, Cell};
+        let size = [12, 10];
+        let mut grid = presets::simple_inflow(size, [1.0, 0.0], Walls::default());
+        assert!(grid.find_isolated_fluid_regions().is_empty());
+
+        // Wall off cells 4..=7 by 4..=5 with walls two cells thick.
+        let mut pocket = Vec::new();
+        for x in 2..=9 {
+            for y in 2..=7 {
+                if [2, 3, 8, 9].contains(&x) || [2, 3, 6, 7].contains(&y) {
+                    grid.cell_type[(x, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+                } else {
+                    pocket.push((x, y));
+                }
+            }
+        }
+        grid.rebuild_boundary_list().unwrap();
+        assert_eq!(grid.find_isolated_fluid_regions(), vec![pocket]);
+    }
+
+    #[test]
+    fn flood_fill() {
+        use crate::cell::{BoundaryCell, Cell};
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        let boxed = |size: GridSize, walls: &[GridIndex]| {
+            let mut cell_type = Array::from_elem(size, Cell::Fluid);
+            for x in 0..size[0] {
+                for y in 0..size[1] {
+                    if x == 0 || y == 0 || x == size[0] - 1 || y == size[1] - 1 {
+                        cell_type[(x, y)] = wall;
+                    }
+                }
+            }
+            for idx in walls {
+                cell_type[*idx] = wall;
+            }
+            cell_type
+        };
+
+        // A rectangle outline with walls two cells thick around cells 4..=7
+        // by 4..=5
+        let size = [12, 10];
+        let mut outline = Vec::new();
+        for x in 2..=9 {
+            for y in 2..=7 {
+                if [2, 3, 8, 9].contains(&x) || [2, 3, 6, 7].contains(&y) {
+                    outline.push((x, y));
+                }
+            }
+        }
+        let mut grid = SimulationGrid::from_fields(
+            size,
+            Array::ones(size),
+            Array::ones(size),
+            Array::ones(size),
+            boxed(size, &outline),
+        )
+        .unwrap();
+
+        assert_eq!(super::flood_fill(&mut grid, (5, 4), wall).unwrap(), 8);
+        for x in 4..=7 {
+            for y in 4..=5 {
+                assert_eq!(grid.cell_type[(x, y)], wall);
+                assert_eq!(grid.u[(x, y)], 0.0);
+            }
+        }
+        assert_eq!(grid.cell_type[(1, 1)], Cell::Fluid);
+        assert_eq!(super::flood_fill(&mut grid, (5, 4), wall).unwrap(), 0);
+
+        // Carving the filled rectangle back out reaches the walls around the
+        // grid, but stops short of them.
+        let carved = super::flood_fill(&mut grid, (5, 4), Cell::Fluid).unwrap();
+        assert_eq!(carved, 8 * 6);
+        assert_eq!(grid.boundaries.fluid_cells, (10 * 8) as Real);
+        for x in 0..size[0] {
+            assert_eq!(grid.cell_type[(x, 0)], wall);
+            assert_eq!(grid.cell_type[(x, size[1] - 1)], wall);
+        }
+        assert!(matches!(
+            super::flood_fill(&mut grid, (0, 4), Cell::Fluid),
+            Err(SimulationGridError::NotInteriorError((0, 4), _))
+        ));
+
+        // Carving the block next to the inflow cell would leave it with fluid
+        // on both sides, so nothing changes.
+        let size = [8, 6];
+        let mut cell_type = boxed(size, &[(4, 1), (5, 1), (4, 2), (5, 2)]);
+        let inflow = Cell::Boundary(BoundaryCell::Inflow {
+            velocity: [1.0, 0.0],
+        });
+        cell_type[(3, 1)] = inflow;
+        let mut grid = SimulationGrid::from_fields(
+            size,
+            Array::ones(size),
+            Array::ones(size),
+            Array::ones(size),
+            cell_type,
+        )
+        .unwrap();
+        let boundaries = grid.boundaries.sorted_boundary_list.clone();
+        assert!(matches!(
+            super::flood_fill(&mut grid, (4, 2), Cell::Fluid),
+            Err(SimulationGridError::InvalidBoundaries(..))
+        ));
+        assert_eq!(grid.cell_type[(4, 2)], wall);
+        assert_eq!(grid.pressure[(4, 2)], 1.0);
+        assert_eq!(grid.boundaries.sorted_boundary_list, boundaries);
+    }
 }
@@ -1,8 +1,52 @@
 use crate::cell::{BoundaryCell, Cell};
-use crate::grid::{SimulationGrid, UnfinalizedSimulationGrid};
-use crate::math::Real;
-use crate::types::GridSize;
+use crate::grid::{SimulationGrid, SimulationGridError, UnfinalizedSimulationGrid};
+use crate::math::{consts, Real};
+use crate::types::{GridIndex, GridSize, Velocity};
 use ndarray::{Array, Ix2};
+use strum_macros::EnumString;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PresetError {
+    #[error("An error occurred while parsing the Preset enum: `{0}`")]
+    PresetParsingError(String),
+}
+
+/// The ready-made grids the UI offers, built by `SimulationBuilder::grid`
+#[derive(Debug, Copy, Clone, PartialEq, EnumString, strum_macros::VariantNames)]
+pub enum Preset {
+    Obstacle,
+    #[strum(serialize = "Empty")]
+    Inflow,
+    /// No-slip walls on the top and bottom, inflow on the left and outflow on
+    /// the right, for checking developing Poiseuille flow
+    Channel,
+    /// A box with a jet of fluid entering through the bottom wall
+    Jet,
+    /// A channel with two rows of three cylinders
+    Cylinders,
+    /// A channel with a NACA 2412 airfoil at an angle of attack of 8 degrees
+    Airfoil,
+}
+
+impl TryFrom<usize> for Preset {
+    type Error = PresetError;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Preset::Obstacle),
+            1 => Ok(Preset::Inflow),
+            2 => Ok(Preset::Channel),
+            3 => Ok(Preset::Jet),
+            4 => Ok(Preset::Cylinders),
+            5 => Ok(Preset::Airfoil),
+            _ => Err(PresetError::PresetParsingError(format!(
+                "{:?} does not match to a known Preset",
+                value
+            ))),
+        }
+    }
+}
 
 /// Generate an empty simulation grid
 pub fn empty(size: GridSize) -> SimulationGrid {
@@ -16,18 +60,98 @@ pub fn empty(size: GridSize) -> SimulationGrid {
     .unwrap()
 }
 
-pub fn simple_inflow(size: GridSize) -> SimulationGrid {
+/// The boundary types of the top and bottom walls of a channel, including the
+/// corners of the grid
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Walls {
+    pub top: BoundaryCell,
+    pub bottom: BoundaryCell,
+}
+
+impl Walls {
+    /// Free-slip walls on the top and bottom, for an obstacle in open flow
+    ///
+    /// The fluid slides along the walls without a boundary layer, so a
+    /// uniform inflow into an empty channel stays uniform. Outflow walls
+    /// would let the fluid cross them, but then nothing holds the vertical
+    /// velocity in place and the flow drifts out through one of them.
+    pub fn free_stream() -> Walls {
+        Walls {
+            top: BoundaryCell::Symmetry,
+            bottom: BoundaryCell::Symmetry,
+        }
+    }
+}
+
+impl Default for Walls {
+    /// No-slip walls on the top and bottom
+    fn default() -> Self {
+        Walls {
+            top: BoundaryCell::NoSlip,
+            bottom: BoundaryCell::NoSlip,
+        }
+    }
+}
+
+/// Generate the cells of a channel: `walls` on the top and bottom, an inflow
+/// boundary on the left and an outflow boundary on the right.
+fn channel_cells(size: GridSize, inflow: Velocity, walls: Walls) -> Array<Cell, Ix2> {
     let mut cell_array = Array::from_elem(size, Cell::Fluid);
     for x in 0..size[0] {
-        cell_array[(x, 0)] = Cell::Boundary(BoundaryCell::NoSlip);
-        cell_array[(x, size[1] - 1)] = Cell::Boundary(BoundaryCell::NoSlip);
+        cell_array[(x, 0)] = Cell::Boundary(walls.top);
+        cell_array[(x, size[1] - 1)] = Cell::Boundary(walls.bottom);
     }
     for y in 1..(size[1] - 1) {
-        cell_array[(0, y)] = Cell::Boundary(BoundaryCell::Inflow {
-            velocity: [1.0, 0.0],
-        });
+        cell_array[(0, y)] = Cell::Boundary(BoundaryCell::Inflow { velocity: inflow });
         cell_array[(size[0] - 1, y)] = Cell::Boundary(BoundaryCell::Outflow);
     }
+    cell_array
+}
+
+/// Generate an empty channel with fluid entering from the left at `inflow`
+pub fn simple_inflow(size: GridSize, inflow: Velocity, walls: Walls) -> SimulationGrid {
+    SimulationGrid::try_from(UnfinalizedSimulationGrid {
+        size,
+        pressure: Array::zeros(size),
+        u: Array::zeros(size),
+        v: Array::zeros(size),
+        cell_type: channel_cells(size, inflow, walls),
+    })
+    .unwrap()
+}
+
+/// Generate a channel whose left wall is a no-slip wall except for inflow patches
+///
+/// Each patch is a rectangle of cells, given by two opposite corners, whose
+/// cells become inflow boundaries with their own velocity. Later patches
+/// overwrite earlier ones where they overlap. Returns an error if a patch is
+/// outside of the grid.
+///
+/// # Arguments
+///
+/// * `size` - The size of the grid
+/// * `patches` - The corners and inflow velocity of each patch
+/// * `walls` - The boundary types of the top and bottom walls
+pub fn multi_inflow(
+    size: GridSize,
+    patches: &[(GridIndex, GridIndex, Velocity)],
+    walls: Walls,
+) -> Result<SimulationGrid, SimulationGridError> {
+    let mut cell_array = channel_cells(size, [0.0, 0.0], walls);
+    for y in 1..(size[1] - 1) {
+        cell_array[(0, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+    }
+
+    for &(start, end, velocity) in patches {
+        if start.0.max(end.0) >= size[0] || start.1.max(end.1) >= size[1] {
+            return Err(SimulationGridError::PatchOutOfBoundsError(start, end, size));
+        }
+        for x in start.0.min(end.0)..=start.0.max(end.0) {
+            for y in start.1.min(end.1)..=start.1.max(end.1) {
+                cell_array[(x, y)] = Cell::Boundary(BoundaryCell::Inflow { velocity });
+            }
+        }
+    }
 
     SimulationGrid::try_from(UnfinalizedSimulationGrid {
         size,
@@ -36,45 +160,339 @@ pub fn simple_inflow(size: GridSize) -> SimulationGrid {
         v: Array::zeros(size),
         cell_type: cell_array,
     })
+}
+
+/// Generate a box with a jet of fluid entering upward through the bottom wall
+/// and leaving through the top
+///
+/// Returns an error if the jet isn't within the bottom wall, excluding the
+/// corners.
+///
+/// # Arguments
+///
+/// * `size` - The size of the grid
+/// * `jet` - The first and last column of the jet
+/// * `speed` - The speed of the fluid entering the box
+pub fn bottom_jet(
+    size: GridSize,
+    jet: (usize, usize),
+    speed: Real,
+) -> Result<SimulationGrid, SimulationGridError> {
+    let bottom = size[1] - 1;
+    if jet.0 == 0 || jet.0 > jet.1 || jet.1 >= size[0] - 1 {
+        return Err(SimulationGridError::PatchOutOfBoundsError(
+            (jet.0, bottom),
+            (jet.1, bottom),
+            size,
+        ));
+    }
+
+    let mut cell_array = Array::from_elem(size, Cell::Fluid);
+    for y in 0..size[1] {
+        cell_array[(0, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+        cell_array[(size[0] - 1, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+    }
+    for x in 1..(size[0] - 1) {
+        cell_array[(x, 0)] = Cell::Boundary(BoundaryCell::Outflow);
+        cell_array[(x, bottom)] = Cell::Boundary(BoundaryCell::NoSlip);
+    }
+    // Screen y grows downward, so upward velocities are negative.
+    for x in jet.0..=jet.1 {
+        cell_array[(x, bottom)] = Cell::Boundary(BoundaryCell::Inflow {
+            velocity: [0.0, -speed],
+        });
+    }
+
+    SimulationGrid::try_from(UnfinalizedSimulationGrid {
+        size,
+        pressure: Array::zeros(size),
+        u: Array::zeros(size),
+        v: Array::zeros(size),
+        cell_type: cell_array,
+    })
+}
+
+/// The cell size that makes the interior of a `size` grid span [0, π] in both
+/// directions, as `taylor_green` expects
+pub fn taylor_green_cell_size(size: GridSize) -> [Real; 2] {
+    size.map(|cells| consts::PI / (cells - 2) as Real)
+}
+
+/// The fraction of its initial velocity a Taylor-Green vortex has left at
+/// `time`, `exp(-2 t / Re)`
+///
+/// The kinetic energy decays with the square of this.
+pub fn taylor_green_decay(time: Real, reynolds: Real) -> Real {
+    (-2.0 * time / reynolds).exp()
+}
+
+/// Generate one cell of the Taylor-Green vortex, a flow with a known
+/// analytic solution for checking the accuracy of the solver
+///
+/// The velocity is `u = sin(x) cos(y)`, `v = -cos(x) sin(y)` and the pressure
+/// `p = -(cos(2x) + cos(2y)) / 4` for a density of 1, with the interior of
+/// the grid spanning [0, π] in both directions when the simulation uses
+/// `taylor_green_cell_size`. The flow never crosses the edges of that square,
+/// and it is mirror symmetric about them, so free-slip walls around it give
+/// the same flow as the usual periodic domain. The velocity then decays by
+/// `taylor_green_decay`.
+pub fn taylor_green(size: GridSize) -> SimulationGrid {
+    let [delx, dely] = taylor_green_cell_size(size);
+    // The physical position of a point `x` cells from the top left corner of
+    // the grid, with 0 at the inner edge of the walls
+    let position = |x: Real, y: Real| (delx * (x - 1.0), dely * (y - 1.0));
+    // u[(i, j)] is on the east face of cell (i, j) and v[(i, j)] on its south
+    // face.
+    let u = Array::from_shape_fn(size, |(i, j)| {
+        let (x, y) = position(i as Real + 1.0, j as Real + 0.5);
+        x.sin() * y.cos()
+    });
+    let v = Array::from_shape_fn(size, |(i, j)| {
+        let (x, y) = position(i as Real + 0.5, j as Real + 1.0);
+        -x.cos() * y.sin()
+    });
+    let pressure = Array::from_shape_fn(size, |(i, j)| {
+        let (x, y) = position(i as Real + 0.5, j as Real + 0.5);
+        -((2.0 * x).cos() + (2.0 * y).cos()) / 4.0
+    });
+    let cell_type = Array::from_shape_fn(size, |(i, j)| {
+        if i == 0 || j == 0 || i == size[0] - 1 || j == size[1] - 1 {
+            Cell::Boundary(BoundaryCell::Symmetry)
+        } else {
+            Cell::Fluid
+        }
+    });
+    SimulationGrid::try_from(UnfinalizedSimulationGrid {
+        size,
+        pressure,
+        u,
+        v,
+        cell_type,
+    })
     .unwrap()
 }
 
-fn draw_circle(cell_array: &mut Array<Cell, Ix2>, x: usize, y: usize, radius: Real) {
+/// An obstacle that can be drawn into a channel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shape {
+    /// A circle centered on cell (`x`, `y`) with radius `r`, in cells
+    Circle { x: usize, y: usize, r: Real },
+    /// A rectangle spanning cells `x0..=x1` and `y0..=y1`
+    Rect {
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    },
+}
+
+impl Shape {
+    /// The interior cells of a `size` grid covered by the shape
+    ///
+    /// Cells on the outer ring of the grid are never included, so drawing a
+    /// shape can't replace the boundary of the grid.
+    pub fn cells(&self, size: GridSize) -> Vec<GridIndex> {
+        // The inclusive range of interior cells within `reach` of `center`
+        let clamp = |center: usize, reach: usize, len: usize| {
+            center.saturating_sub(reach).max(1)
+                ..=center.saturating_add(reach).min(len - 2)
+        };
+        match *self {
+            Shape::Circle { x, y, r } => {
+                let reach = r.max(0.0).ceil() as usize;
+                let mut cells = Vec::new();
+                for xi in clamp(x, reach, size[0]) {
+                    let x_dist = xi as i32 - x as i32;
+                    for yi in clamp(y, reach, size[1]) {
+                        let y_dist = yi as i32 - y as i32;
+                        let distance =
+                            ((x_dist * x_dist + y_dist * y_dist) as Real).sqrt();
+                        if distance < r {
+                            cells.push((xi, yi));
+                        }
+                    }
+                }
+                cells
+            }
+            Shape::Rect { x0, y0, x1, y1 } => {
+                let xs = x0.max(1)..=x1.min(size[0] - 2);
+                xs.flat_map(|xi| {
+                    (y0.max(1)..=y1.min(size[1] - 2)).map(move |yi| (xi, yi))
+                })
+                .collect()
+            }
+        }
+    }
+}
+
+// Fail if any column of the channel's interior is completely filled with
+// boundary cells, since no fluid could flow past it.
+fn check_channel_open(cell_array: &Array<Cell, Ix2>) -> Result<(), SimulationGridError> {
     let (x_size, y_size) = cell_array.dim();
-    for xi in (x.saturating_sub(radius as usize))..(x.saturating_add(radius as usize)) {
-        if xi >= x_size {
-            continue;
+    for x in 1..(x_size - 1) {
+        if (1..(y_size - 1)).all(|y| cell_array[(x, y)] != Cell::Fluid) {
+            return Err(SimulationGridError::ChannelBlockedError(x));
         }
-        let x_dist = xi as i32 - x as i32;
-        for yi in (y.saturating_sub(radius as usize))..(y.saturating_add(radius as usize))
-        {
-            if yi >= y_size {
-                continue;
-            }
-            let y_dist = yi as i32 - y as i32;
-            let distance = ((x_dist * x_dist + y_dist * y_dist) as f64).sqrt();
+    }
+    Ok(())
+}
+
+/// Generate a channel with a circular obstacle
+///
+/// The obstacle is clipped to the interior of the channel. Returns an error if
+/// the obstacle is so large that it blocks the whole channel.
+///
+/// # Arguments
+///
+/// * `size` - The size of the grid
+/// * `inflow` - The velocity of the fluid entering from the left
+/// * `center` - The cell at the center of the obstacle
+/// * `radius` - The radius of the obstacle, in cells
+/// * `walls` - The boundary types of the top and bottom walls
+pub fn obstacle(
+    size: GridSize,
+    inflow: Velocity,
+    center: GridIndex,
+    radius: Real,
+    walls: Walls,
+) -> Result<SimulationGrid, SimulationGridError> {
+    obstacle_array(
+        size,
+        inflow,
+        &[Shape::Circle {
+            x: center.0,
+            y: center.1,
+            r: radius,
+        }],
+        walls,
+    )
+}
+
+/// Generate a channel containing several obstacles
+///
+/// The obstacles are clipped to the interior of the channel. Returns an error
+/// if they block the whole channel, or if they overlap or touch in a way that
+/// leaves a boundary with fluid on opposing sides.
+///
+/// # Arguments
+///
+/// * `size` - The size of the grid
+/// * `inflow` - The velocity of the fluid entering from the left
+/// * `shapes` - The obstacles to draw
+/// * `walls` - The boundary types of the top and bottom walls
+pub fn obstacle_array(
+    size: GridSize,
+    inflow: Velocity,
+    shapes: &[Shape],
+    walls: Walls,
+) -> Result<SimulationGrid, SimulationGridError> {
+    let mut cell_array = channel_cells(size, inflow, walls);
+
+    for shape in shapes {
+        for idx in shape.cells(size) {
+            cell_array[idx] = Cell::Boundary(BoundaryCell::NoSlip);
+        }
+    }
+    check_channel_open(&cell_array)?;
+
+    SimulationGrid::try_from(UnfinalizedSimulationGrid {
+        size,
+        pressure: Array::zeros(size),
+        u: Array::zeros(size),
+        v: Array::zeros(size),
+        cell_type: cell_array,
+    })
+}
 
-            if distance < radius {
-                cell_array[(xi, yi)] = Cell::Boundary(BoundaryCell::NoSlip);
+// Turn the obstacle cells that have fluid on opposing sides back into fluid,
+// until there are none left. Rasterized curves leave single cells sticking
+// out, which aren't valid boundaries.
+fn remove_thin_cells(cell_array: &mut Array<Cell, Ix2>) {
+    let (x_size, y_size) = cell_array.dim();
+    loop {
+        let mut removed = false;
+        for x in 1..(x_size - 1) {
+            for y in 1..(y_size - 1) {
+                let fluid = |idx: GridIndex| cell_array[idx] == Cell::Fluid;
+                let thin = (fluid((x - 1, y)) && fluid((x + 1, y)))
+                    || (fluid((x, y - 1)) && fluid((x, y + 1)));
+                if cell_array[(x, y)] != Cell::Fluid && thin {
+                    cell_array[(x, y)] = Cell::Fluid;
+                    removed = true;
+                }
             }
         }
+        if !removed {
+            return;
+        }
     }
 }
 
-pub fn obstacle(size: GridSize) -> SimulationGrid {
-    let mut cell_array = Array::from_elem(size, Cell::Fluid);
-    for x in 0..size[0] {
-        cell_array[(x, 0)] = Cell::Boundary(BoundaryCell::NoSlip);
-        cell_array[(x, size[1] - 1)] = Cell::Boundary(BoundaryCell::NoSlip);
+// The smallest radius whose circle covers at least a 3x3 block of cells
+const MIN_CYLINDER_RADIUS: Real = 1.5;
+
+/// Generate a channel with a regular array of circular obstacles
+///
+/// The rows are spread evenly over the height of the channel, and the
+/// columns start a quarter of the way along it with the same spacing. Returns
+/// an error if the cylinders are less than three cells across, or if they
+/// don't fit in the channel with at least one cell of fluid between them and
+/// around them.
+///
+/// # Arguments
+///
+/// * `size` - The size of the grid
+/// * `inflow` - The velocity of the fluid entering from the left
+/// * `rows` - The number of cylinders across the channel
+/// * `cols` - The number of cylinders along the channel
+/// * `radius` - The radius of each cylinder, in cells
+/// * `walls` - The boundary types of the top and bottom walls
+pub fn cylinder_array(
+    size: GridSize,
+    inflow: Velocity,
+    rows: usize,
+    cols: usize,
+    radius: Real,
+    walls: Walls,
+) -> Result<SimulationGrid, SimulationGridError> {
+    if radius < MIN_CYLINDER_RADIUS {
+        return Err(SimulationGridError::ObstacleTooThinError(
+            2.0 * radius,
+            2.0 * MIN_CYLINDER_RADIUS,
+        ));
     }
-    for y in 1..(size[1] - 1) {
-        cell_array[(0, y)] = Cell::Boundary(BoundaryCell::Inflow {
-            velocity: [1.0, 0.0],
-        });
-        cell_array[(size[0] - 1, y)] = Cell::Boundary(BoundaryCell::Outflow);
+    let spacing = (size[1] - 2) as Real / rows.max(1) as Real;
+    let first_x = size[0] as Real / 4.0;
+    let last_x = first_x + spacing * cols.saturating_sub(1) as Real;
+    // How far the cells of a circle reach from where it should be centered.
+    // The circle covers the cells less than `radius` from its center, which
+    // is rounded to the nearest cell.
+    let reach = radius.ceil() - 0.5;
+    let too_close = spacing < 2.0 * reach + 2.0;
+    let outside = first_x - reach < 2.0 || last_x + reach > (size[0] - 3) as Real;
+    if too_close || outside {
+        return Err(SimulationGridError::ObstaclesDontFitError(size));
     }
 
-    draw_circle(&mut cell_array, 20, size[1] / 2, 5.0);
+    let mut shapes = Vec::with_capacity(rows * cols);
+    for row in 0..rows {
+        let y = 1.0 + spacing * (row as Real + 0.5);
+        for col in 0..cols {
+            let x = first_x + spacing * col as Real;
+            shapes.push(Shape::Circle {
+                x: x.round() as usize,
+                y: y.round() as usize,
+                r: radius,
+            });
+        }
+    }
+    let mut cell_array = channel_cells(size, inflow, walls);
+    for shape in &shapes {
+        for idx in shape.cells(size) {
+            cell_array[idx] = Cell::Boundary(BoundaryCell::NoSlip);
+        }
+    }
+    remove_thin_cells(&mut cell_array);
 
     SimulationGrid::try_from(UnfinalizedSimulationGrid {
         size,
@@ -83,5 +501,439 @@ pub fn obstacle(size: GridSize) -> SimulationGrid {
         v: Array::zeros(size),
         cell_type: cell_array,
     })
-    .unwrap()
+}
+
+/// The shape of a NACA four-digit airfoil, as fractions of the chord
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NacaProfile {
+    /// The largest distance between the camber line and the chord
+    pub camber: Real,
+    /// How far along the chord the camber is largest
+    pub camber_position: Real,
+    /// The largest thickness
+    pub thickness: Real,
+}
+
+impl NacaProfile {
+    /// The profile with the four digits `digits`, e.g. 2412 or 12 for NACA
+    /// 0012
+    ///
+    /// Returns `None` for numbers with more than four digits, profiles with
+    /// no thickness and cambered profiles with the camber at the leading edge.
+    pub fn from_digits(digits: u16) -> Option<NacaProfile> {
+        let camber = digits / 1000;
+        let camber_position = digits / 100 % 10;
+        let thickness = digits % 100;
+        if digits > 9999 || thickness == 0 || (camber > 0 && camber_position == 0) {
+            return None;
+        }
+        Some(NacaProfile {
+            camber: camber as Real / 100.0,
+            camber_position: camber_position as Real / 10.0,
+            thickness: thickness as Real / 100.0,
+        })
+    }
+
+    /// Whether the point `x` along the chord and `y` above it is inside the
+    /// airfoil, both as fractions of the chord
+    pub fn contains(&self, x: Real, y: Real) -> bool {
+        if !(0.0..=1.0).contains(&x) {
+            return false;
+        }
+        let half_thickness = 5.0
+            * self.thickness
+            * (0.2969 * x.sqrt() - 0.1260 * x - 0.3516 * x.powi(2) + 0.2843 * x.powi(3)
+                - 0.1015 * x.powi(4));
+        let (m, p) = (self.camber, self.camber_position);
+        let camber = if m == 0.0 {
+            0.0
+        } else if x < p {
+            m / p.powi(2) * (2.0 * p * x - x.powi(2))
+        } else {
+            m / (1.0 - p).powi(2) * ((1.0 - 2.0 * p) + 2.0 * p * x - x.powi(2))
+        };
+        (y - camber).abs() <= half_thickness
+    }
+}
+
+// The thinnest airfoil that still covers two cells where it's thickest
+const MIN_AIRFOIL_THICKNESS: Real = 2.0;
+
+/// Generate a channel with an airfoil in it
+///
+/// The leading edge is a quarter of the way along the channel, in the middle
+/// of its height. Cells whose centers are inside the profile become no-slip
+/// boundaries, apart from single cells sticking out of it, e.g. at a sharp
+/// trailing edge. Returns an error if the airfoil is less than two cells thick
+/// or reaches outside of the channel.
+///
+/// # Arguments
+///
+/// * `size` - The size of the grid
+/// * `inflow` - The velocity of the fluid entering from the left
+/// * `profile` - The shape of the airfoil
+/// * `chord` - The distance from the leading to the trailing edge, in cells
+/// * `angle_of_attack` - How far the airfoil is tilted nose up, in degrees
+/// * `walls` - The boundary types of the top and bottom walls
+pub fn naca_airfoil(
+    size: GridSize,
+    inflow: Velocity,
+    profile: NacaProfile,
+    chord: Real,
+    angle_of_attack: Real,
+    walls: Walls,
+) -> Result<SimulationGrid, SimulationGridError> {
+    let thickness = chord * profile.thickness;
+    if thickness < MIN_AIRFOIL_THICKNESS {
+        return Err(SimulationGridError::ObstacleTooThinError(
+            thickness,
+            MIN_AIRFOIL_THICKNESS,
+        ));
+    }
+
+    let leading_edge = (size[0] as Real / 4.0, size[1] as Real / 2.0);
+    let (sin, cos) = angle_of_attack.to_radians().sin_cos();
+    let mut cell_array = channel_cells(size, inflow, walls);
+    for ((x, y), cell) in cell_array.indexed_iter_mut() {
+        // The center of the cell relative to the leading edge, with y up
+        let dx = x as Real + 0.5 - leading_edge.0;
+        let dy = leading_edge.1 - (y as Real + 0.5);
+        // Turned back by the angle of attack, so the chord is along x
+        let along = (dx * cos - dy * sin) / chord;
+        let above = (dx * sin + dy * cos) / chord;
+        if !profile.contains(along, above) {
+            continue;
+        }
+        if x == 0 || y == 0 || x == size[0] - 1 || y == size[1] - 1 {
+            return Err(SimulationGridError::ObstaclesDontFitError(size));
+        }
+        *cell = Cell::Boundary(BoundaryCell::NoSlip);
+    }
+    remove_thin_cells(&mut cell_array);
+    check_channel_open(&cell_array)?;
+
+    SimulationGrid::try_from(UnfinalizedSimulationGrid {
+        size,
+        pressure: Array::zeros(size),
+        u: Array::zeros(size),
+        v: Array::zeros(size),
+        cell_type: cell_array,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_types() {
+        let size = [12, 8];
+        let types = [
+            BoundaryCell::NoSlip,
+            BoundaryCell::Symmetry,
+            BoundaryCell::Outflow,
+        ];
+        for top in types {
+            for bottom in types {
+                let walls = Walls { top, bottom };
+                for grid in [
+                    simple_inflow(size, [1.0, 0.0], walls),
+                    obstacle(size, [1.0, 0.0], (5, 4), 2.0, walls).unwrap(),
+                ] {
+                    for x in 0..size[0] {
+                        assert_eq!(grid.cell_type[(x, 0)], Cell::Boundary(top));
+                        assert_eq!(
+                            grid.cell_type[(x, size[1] - 1)],
+                            Cell::Boundary(bottom)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn angled_inflow() {
+        let size = [10, 6];
+        let inflow = [0.8, 0.6];
+        for grid in [
+            simple_inflow(size, inflow, Walls::default()),
+            obstacle(size, inflow, (5, 3), 2.0, Walls::default()).unwrap(),
+        ] {
+            for y in 1..(size[1] - 1) {
+                assert_eq!(
+                    grid.cell_type[(0, y)],
+                    Cell::Boundary(BoundaryCell::Inflow { velocity: inflow })
+                );
+            }
+            // The corners belong to the walls, not the inflow.
+            assert_eq!(grid.cell_type[(0, 0)], Cell::Boundary(BoundaryCell::NoSlip));
+            assert_eq!(
+                grid.cell_type[(0, size[1] - 1)],
+                Cell::Boundary(BoundaryCell::NoSlip)
+            );
+        }
+    }
+
+    #[test]
+    fn multi_inflow_patches() {
+        let size = [12, 10];
+        let upward = [1.0, -0.5];
+        let downward = [1.0, 0.5];
+        let mut grid = multi_inflow(
+            size,
+            &[((0, 1), (0, 4), upward), ((0, 5), (0, 8), downward)],
+            Walls::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            grid.cell_type[(0, 4)],
+            Cell::Boundary(BoundaryCell::Inflow { velocity: upward })
+        );
+        assert_eq!(
+            grid.cell_type[(0, 5)],
+            Cell::Boundary(BoundaryCell::Inflow { velocity: downward })
+        );
+        assert_eq!(grid.cell_type[(0, 0)], Cell::Boundary(BoundaryCell::NoSlip));
+
+        grid.v.fill(0.25);
+        grid.set_boundary_u_and_v(0.005, [0.1, 0.2]).unwrap();
+        // The vertical velocity at the wall, halfway between the ghost value
+        // and the first fluid cell, is the velocity of each patch, right up
+        // to the cells on either side of the edge the patches share. The last
+        // face of the lower patch is on the bottom wall, so it's skipped.
+        for (range, velocity) in [(1..5, upward), (5..8, downward)] {
+            for y in range {
+                assert_eq!(grid.u[(0, y)], velocity[0]);
+                assert_eq!((grid.v[(0, y)] + grid.v[(1, y)]) / 2.0, velocity[1]);
+            }
+        }
+
+        assert!(matches!(
+            multi_inflow(size, &[((0, 1), (0, 10), upward)], Walls::default()),
+            Err(SimulationGridError::PatchOutOfBoundsError(..))
+        ));
+    }
+
+    #[test]
+    fn bottom_jet_cells() {
+        let size = [12, 8];
+        let grid = bottom_jet(size, (5, 6), 2.0).unwrap();
+        for x in 1..11 {
+            assert_eq!(
+                grid.cell_type[(x, 0)],
+                Cell::Boundary(BoundaryCell::Outflow)
+            );
+            let expected = if (5..=6).contains(&x) {
+                Cell::Boundary(BoundaryCell::Inflow {
+                    velocity: [0.0, -2.0],
+                })
+            } else {
+                Cell::Boundary(BoundaryCell::NoSlip)
+            };
+            assert_eq!(grid.cell_type[(x, 7)], expected);
+        }
+
+        for jet in [(0, 3), (5, 11), (6, 5)] {
+            assert!(matches!(
+                bottom_jet(size, jet, 2.0),
+                Err(SimulationGridError::PatchOutOfBoundsError(..))
+            ));
+        }
+    }
+
+    #[test]
+    fn obstacle_radius() {
+        let size = [40, 12];
+        let center = (20, 6);
+
+        let mut last_fluid_cells = simple_inflow(size, [1.0, 0.0], Walls::default())
+            .boundaries
+            .fluid_cells;
+        // A radius of 2 covers the same cells as 1.5, since the cells 2 away
+        // from the center aren't inside the circle.
+        for radius in [1.5, 2.5, 3.0, 4.0, 5.0] {
+            let grid =
+                obstacle(size, [1.0, 0.0], center, radius, Walls::default()).unwrap();
+            assert!(grid.boundaries.fluid_cells < last_fluid_cells);
+            last_fluid_cells = grid.boundaries.fluid_cells;
+        }
+
+        // A radius of 6 covers every interior cell of the center column.
+        let blocked = obstacle(size, [1.0, 0.0], center, 6.0, Walls::default());
+        assert!(matches!(
+            blocked,
+            Err(SimulationGridError::ChannelBlockedError(_))
+        ));
+    }
+
+    #[test]
+    fn obstacle_symmetric() {
+        let size = [40, 12];
+        let (x, y) = (20, 6);
+        for radius in [1.5, 2.5, 3.7] {
+            let grid =
+                obstacle(size, [1.0, 0.0], (x, y), radius, Walls::default()).unwrap();
+            for dx in 0..=4 {
+                for dy in 0..=4 {
+                    let cells = [
+                        (x + dx, y + dy),
+                        (x - dx, y + dy),
+                        (x + dx, y - dy),
+                        (x - dx, y - dy),
+                    ]
+                    .map(|idx| grid.cell_type[idx]);
+                    assert!(
+                        cells.iter().all(|&cell| cell == cells[0]),
+                        "radius {radius} isn't symmetric at offset ({dx}, {dy})"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn obstacle_array_shapes() {
+        let size = [30, 12];
+        // A circle with a radius of 2 covers a 3x3 square of cells.
+        let grid = obstacle_array(
+            size,
+            [1.0, 0.0],
+            &[
+                Shape::Circle { x: 8, y: 6, r: 2.0 },
+                Shape::Circle {
+                    x: 20,
+                    y: 6,
+                    r: 2.0,
+                },
+            ],
+            Walls::default(),
+        )
+        .unwrap();
+        let channel_boundaries = 2 * size[0] + 2 * (size[1] - 2);
+        assert_eq!(
+            grid.boundaries.sorted_boundary_list.len(),
+            channel_boundaries + 2 * 9
+        );
+        assert_eq!(
+            grid.boundaries.fluid_cells,
+            (size[0] * size[1] - channel_boundaries - 2 * 9) as Real
+        );
+
+        // Joining two circles with a one-cell bridge leaves a boundary with
+        // fluid above and below it.
+        let touching = obstacle_array(
+            size,
+            [1.0, 0.0],
+            &[
+                Shape::Circle { x: 8, y: 6, r: 2.0 },
+                Shape::Circle {
+                    x: 12,
+                    y: 6,
+                    r: 2.0,
+                },
+                Shape::Rect {
+                    x0: 10,
+                    y0: 6,
+                    x1: 10,
+                    y1: 6,
+                },
+            ],
+            Walls::default(),
+        );
+        assert!(matches!(
+            touching,
+            Err(SimulationGridError::InvalidBoundaries(..))
+        ));
+    }
+
+    // The number of no-slip cells inside the outer ring of `grid`
+    fn obstacle_cells(grid: &SimulationGrid) -> usize {
+        let [w, h] = grid.size;
+        grid.cell_type
+            .indexed_iter()
+            .filter(|&((x, y), cell)| {
+                (1..w - 1).contains(&x)
+                    && (1..h - 1).contains(&y)
+                    && *cell == Cell::Boundary(BoundaryCell::NoSlip)
+            })
+            .count()
+    }
+
+    #[test]
+    fn cylinder_array_cells() {
+        let size = [60, 26];
+        let grid = cylinder_array(size, [1.0, 0.0], 3, 2, 3.0, Walls::default()).unwrap();
+        let expected = 6.0 * consts::PI * 3.0 * 3.0;
+        let cells = obstacle_cells(&grid) as Real;
+        assert!(
+            (cells - expected).abs() < 0.15 * expected,
+            "{} cells",
+            cells
+        );
+        // Every cylinder has fluid all around it, also next to the walls.
+        assert!((1..size[0] - 1).all(|x| grid.cell_type[(x, 1)] == Cell::Fluid));
+
+        assert!(matches!(
+            cylinder_array(size, [1.0, 0.0], 3, 2, 1.0, Walls::default()),
+            Err(SimulationGridError::ObstacleTooThinError(..))
+        ));
+        for (rows, cols) in [(4, 2), (1, 6)] {
+            assert!(matches!(
+                cylinder_array(size, [1.0, 0.0], rows, cols, 3.0, Walls::default()),
+                Err(SimulationGridError::ObstaclesDontFitError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn naca_profiles() {
+        assert_eq!(
+            NacaProfile::from_digits(2412),
+            Some(NacaProfile {
+                camber: 0.02,
+                camber_position: 0.4,
+                thickness: 0.12,
+            })
+        );
+        let symmetric = NacaProfile::from_digits(12).unwrap();
+        assert_eq!(symmetric.camber, 0.0);
+        assert!(symmetric.contains(0.3, 0.05));
+        assert!(!symmetric.contains(0.3, 0.07));
+        assert!(!symmetric.contains(1.1, 0.0));
+        for digits in [2012, 2400, 10012] {
+            assert_eq!(NacaProfile::from_digits(digits), None);
+        }
+    }
+
+    #[test]
+    fn naca_airfoil_cells() {
+        let size = [120, 40];
+        let symmetric = NacaProfile::from_digits(12).unwrap();
+        // The area of a symmetric four-digit airfoil is about 0.685 t c².
+        let expected = 0.685 * 0.12 * 40.0 * 40.0;
+        for (profile, angle) in [
+            (symmetric, 0.0),
+            (NacaProfile::from_digits(2412).unwrap(), 10.0),
+        ] {
+            let grid =
+                naca_airfoil(size, [1.0, 0.0], profile, 40.0, angle, Walls::default())
+                    .unwrap();
+            let cells = obstacle_cells(&grid) as Real;
+            assert!(
+                (cells - expected).abs() < 0.15 * expected,
+                "{} cells",
+                cells
+            );
+        }
+
+        assert!(matches!(
+            naca_airfoil(size, [1.0, 0.0], symmetric, 10.0, 0.0, Walls::default()),
+            Err(SimulationGridError::ObstacleTooThinError(..))
+        ));
+        assert!(matches!(
+            naca_airfoil(size, [1.0, 0.0], symmetric, 100.0, 0.0, Walls::default()),
+            Err(SimulationGridError::ObstaclesDontFitError(_))
+        ));
+    }
 }
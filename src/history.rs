@@ -0,0 +1,622 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cell::Cell;
+use crate::grid::{SimulationGrid, SimulationGridError};
+use crate::math::Real;
+use crate::simulation::Simulation;
+use crate::types::{GridArray, GridIndex};
+
+/// The number of snapshots a `SnapshotHistory` keeps by default
+pub const DEFAULT_SNAPSHOTS: usize = 5;
+
+/// The number of edits an `EditHistory` can undo by default
+pub const DEFAULT_UNDO_STEPS: usize = 100;
+
+/// The number of ticks between snapshots by default
+pub const DEFAULT_SNAPSHOT_INTERVAL: u32 = 50;
+
+/// The number of `TickStats` a simulation keeps by default
+pub const DEFAULT_STATS_ENTRIES: usize = 1000;
+
+/// Caps on how much history is kept for a simulation, so that long runs on
+/// large grids don't use up all memory
+///
+/// Once a history is full, its oldest entries are dropped first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryBudget {
+    /// The most snapshots a `SnapshotHistory` of the simulation keeps. Each
+    /// one holds a copy of the pressure and velocities.
+    pub max_snapshots: usize,
+    /// The most entries `Simulation::stats_history` keeps
+    pub max_stats: usize,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget {
+            max_snapshots: DEFAULT_SNAPSHOTS,
+            max_stats: DEFAULT_STATS_ENTRIES,
+        }
+    }
+}
+
+/// The state of a simulation at one point in time
+///
+/// Only the fields that change every tick are copied. The cell types are
+/// left out, so the history has to be cleared whenever they change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub pressure: GridArray<Real>,
+    pub u: GridArray<Real>,
+    pub v: GridArray<Real>,
+    pub time: Real,
+    pub iterations: u32,
+}
+
+impl Snapshot {
+    pub fn new(simulation: &Simulation) -> Self {
+        Snapshot {
+            pressure: simulation.grid.pressure.clone(),
+            u: simulation.grid.u.clone(),
+            v: simulation.grid.v.clone(),
+            time: simulation.time,
+            iterations: simulation.iterations,
+        }
+    }
+
+    // Whether every value is finite, i.e. the simulation hadn't blown up yet
+    fn is_finite(&self) -> bool {
+        [&self.pressure, &self.u, &self.v]
+            .iter()
+            .all(|field| field.iter().all(|value| value.is_finite()))
+    }
+}
+
+/// The last few healthy states of a simulation, to roll back to when it
+/// diverges
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotHistory {
+    capacity: usize,
+    interval: u32,
+    ticks_since_snapshot: u32,
+    /// The snapshots, oldest first
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Default for SnapshotHistory {
+    fn default() -> Self {
+        SnapshotHistory::new(DEFAULT_SNAPSHOTS, DEFAULT_SNAPSHOT_INTERVAL)
+    }
+}
+
+impl SnapshotHistory {
+    /// Create an empty history
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The most snapshots to keep. Older snapshots are dropped
+    ///   first, and 0 disables the history.
+    /// * `interval` - Take a snapshot every this many ticks
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        SnapshotHistory {
+            capacity,
+            interval: interval.max(1),
+            ticks_since_snapshot: 0,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Change the most snapshots to keep, dropping the oldest ones that no
+    /// longer fit
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.snapshots.len().saturating_sub(capacity);
+        self.snapshots.drain(..excess);
+    }
+
+    /// Call after every tick to take a snapshot every `interval` ticks
+    ///
+    /// Snapshots with values that aren't finite are skipped, so everything in
+    /// the history is safe to restore.
+    pub fn record(&mut self, simulation: &Simulation) {
+        self.ticks_since_snapshot += 1;
+        if self.capacity == 0 || self.ticks_since_snapshot < self.interval {
+            return;
+        }
+        self.ticks_since_snapshot = 0;
+        let snapshot = Snapshot::new(simulation);
+        if !snapshot.is_finite() {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Forget all snapshots, e.g. after the cell types changed
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.ticks_since_snapshot = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// The snapshots, oldest first
+    pub fn snapshots(&self) -> &VecDeque<Snapshot> {
+        &self.snapshots
+    }
+
+    /// Put `simulation` back into the state of the most recent snapshot
+    ///
+    /// The snapshot is removed from the history, so if the simulation
+    /// diverges again, the next restore goes further back. Returns the time
+    /// of the snapshot, or `None` if there's no snapshot of a grid this size.
+    pub fn restore(&mut self, simulation: &mut Simulation) -> Option<Real> {
+        let snapshot = self.snapshots.pop_back()?;
+        if snapshot.pressure.dim() != simulation.grid.pressure.dim() {
+            self.clear();
+            return None;
+        }
+        let grid = &mut simulation.grid;
+        grid.pressure = snapshot.pressure;
+        grid.u = snapshot.u;
+        grid.v = snapshot.v;
+        grid.calculate_pressure_range();
+        grid.calculate_speed_range();
+        simulation.time = snapshot.time;
+        simulation.iterations = snapshot.iterations;
+        self.ticks_since_snapshot = 0;
+        Some(snapshot.time)
+    }
+}
+
+/// The type and values of one cell
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct CellState {
+    cell: Cell,
+    u: Real,
+    v: Real,
+    pressure: Real,
+}
+
+impl CellState {
+    fn new(grid: &SimulationGrid, idx: GridIndex) -> Self {
+        CellState {
+            cell: grid.cell_type[idx],
+            u: grid.u[idx],
+            v: grid.v[idx],
+            pressure: grid.pressure[idx],
+        }
+    }
+
+    fn apply(&self, grid: &mut SimulationGrid, idx: GridIndex) {
+        grid.cell_type[idx] = self.cell;
+        grid.u[idx] = self.u;
+        grid.v[idx] = self.v;
+        grid.pressure[idx] = self.pressure;
+    }
+}
+
+/// The cells an edit changed, with their states before and after it
+#[derive(Debug, Clone, PartialEq)]
+struct GridEdit {
+    cells: Vec<(GridIndex, CellState, CellState)>,
+}
+
+impl GridEdit {
+    // Put the changed cells back the way they were before the edit, or after
+    // it if `forward`. The rest of the grid, including the flow the
+    // simulation computed since, stays as it is. If that gives an invalid
+    // boundary, the grid is left as it was and the error is returned.
+    fn apply(
+        &self,
+        grid: &mut SimulationGrid,
+        forward: bool,
+    ) -> Result<(), SimulationGridError> {
+        let current: Vec<CellState> = self
+            .cells
+            .iter()
+            .map(|(idx, _, _)| CellState::new(grid, *idx))
+            .collect();
+        for (idx, before, after) in &self.cells {
+            let state = if forward { after } else { before };
+            state.apply(grid, *idx);
+        }
+        if let Err(err) = grid.rebuild_boundary_list() {
+            for ((idx, _, _), state) in self.cells.iter().zip(&current) {
+                state.apply(grid, *idx);
+            }
+            grid.rebuild_boundary_list()?;
+            return Err(err);
+        }
+        grid.calculate_pressure_range();
+        grid.calculate_speed_range();
+        Ok(())
+    }
+}
+
+/// The grid before the edit that is being made
+#[derive(Debug, Clone, PartialEq)]
+struct PendingEdit {
+    cell_type: GridArray<Cell>,
+    u: GridArray<Real>,
+    v: GridArray<Real>,
+    pressure: GridArray<Real>,
+}
+
+/// Undo and redo stacks of the edits made to the cell types of a grid
+///
+/// Call `begin_edit` before changing the grid and `end_edit` once the edit is
+/// done. Everything in between, e.g. all the squares of one drag of the
+/// mouse, is a single step to undo. Only the cells whose type changed are
+/// kept, with their velocities and pressure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditHistory {
+    capacity: usize,
+    /// The edits to undo, oldest first
+    undo: VecDeque<GridEdit>,
+    /// The undone edits, most recently undone last
+    redo: Vec<GridEdit>,
+    pending: Option<PendingEdit>,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        EditHistory::new(DEFAULT_UNDO_STEPS)
+    }
+}
+
+impl EditHistory {
+    /// Create an empty history that can undo up to `capacity` edits
+    pub fn new(capacity: usize) -> Self {
+        EditHistory {
+            capacity,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Remember `grid` as it is before an edit, unless an edit was already
+    /// begun
+    pub fn begin_edit(&mut self, grid: &SimulationGrid) {
+        if self.pending.is_none() {
+            self.pending = Some(PendingEdit {
+                cell_type: grid.cell_type.clone(),
+                u: grid.u.clone(),
+                v: grid.v.clone(),
+                pressure: grid.pressure.clone(),
+            });
+        }
+    }
+
+    /// Finish the edit begun with `begin_edit`, if there is one
+    ///
+    /// Edits that didn't change any cell types aren't kept. Any other edit
+    /// can't be redone after, so the redo stack is cleared.
+    pub fn end_edit(&mut self, grid: &SimulationGrid) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        if pending.cell_type.dim() != grid.cell_type.dim() {
+            return;
+        }
+        let mut cells = Vec::new();
+        for (idx, &cell) in pending.cell_type.indexed_iter() {
+            if cell != grid.cell_type[idx] {
+                let before = CellState {
+                    cell,
+                    u: pending.u[idx],
+                    v: pending.v[idx],
+                    pressure: pending.pressure[idx],
+                };
+                cells.push((idx, before, CellState::new(grid, idx)));
+            }
+        }
+        if cells.is_empty() || self.capacity == 0 {
+            return;
+        }
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(GridEdit { cells });
+        self.redo.clear();
+    }
+
+    /// Undo the most recent edit of `grid`
+    ///
+    /// Returns whether there was an edit to undo.
+    pub fn undo(
+        &mut self,
+        grid: &mut SimulationGrid,
+    ) -> Result<bool, SimulationGridError> {
+        let Some(edit) = self.undo.pop_back() else {
+            return Ok(false);
+        };
+        if let Err(err) = edit.apply(grid, false) {
+            self.undo.push_back(edit);
+            return Err(err);
+        }
+        self.redo.push(edit);
+        Ok(true)
+    }
+
+    /// Redo the most recently undone edit of `grid`
+    ///
+    /// Returns whether there was an edit to redo.
+    pub fn redo(
+        &mut self,
+        grid: &mut SimulationGrid,
+    ) -> Result<bool, SimulationGridError> {
+        let Some(edit) = self.redo.pop() else {
+            return Ok(false);
+        };
+        if let Err(err) = edit.apply(grid, true) {
+            self.redo.push(edit);
+            return Err(err);
+        }
+        self.undo.push_back(edit);
+        Ok(true)
+    }
+
+    /// Forget all edits, e.g. when the grid is replaced
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.pending = None;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::SimulationBuilder;
+    use crate::cell::BoundaryCell;
+    use crate::grid::presets::{self, Walls};
+    use crate::grid::SimulationGrid;
+    use crate::simulation::TickStats;
+
+    fn simulation(grid: SimulationGrid) -> Simulation {
+        SimulationBuilder::new().grid(grid).build().unwrap()
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut sim = simulation(presets::empty([6, 5]));
+        let mut history = SnapshotHistory::new(3, 2);
+        let snapshot_ticks = |history: &SnapshotHistory| -> Vec<u32> {
+            history.snapshots().iter().map(|s| s.iterations).collect()
+        };
+
+        // A snapshot every other tick, dropping the oldest beyond 3.
+        for iterations in 1..=9 {
+            sim.iterations = iterations;
+            history.record(&sim);
+        }
+        assert_eq!(snapshot_ticks(&history), vec![4, 6, 8]);
+
+        // Blown up states aren't kept.
+        sim.grid.u[(2, 2)] = Real::NAN;
+        for iterations in 10..=11 {
+            sim.iterations = iterations;
+            history.record(&sim);
+        }
+        assert_eq!(snapshot_ticks(&history), vec![4, 6, 8]);
+
+        history.clear();
+        assert!(history.is_empty());
+        let mut disabled = SnapshotHistory::new(0, 1);
+        disabled.record(&sim);
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn restore_and_continue() {
+        let mut sim = simulation(presets::simple_inflow(
+            [20, 10],
+            [1.0, 0.0],
+            Walls::default(),
+        ));
+        let mut history = SnapshotHistory::new(2, 5);
+        for _ in 0..10 {
+            sim.run_simulation_tick().unwrap();
+            history.record(&sim);
+        }
+        let healthy = Snapshot::new(&sim);
+
+        // Blow up the simulation.
+        sim.grid.u.fill(Real::NAN);
+        sim.grid.pressure.fill(Real::INFINITY);
+        sim.time += 1.0;
+        assert!(history
+            .restore(&mut sim)
+            .is_some_and(|time| time == healthy.time));
+        assert_eq!(Snapshot::new(&sim), healthy);
+        assert_eq!(history.len(), 1);
+
+        // The restored simulation goes on exactly like the original would.
+        let mut original = simulation(presets::simple_inflow(
+            [20, 10],
+            [1.0, 0.0],
+            Walls::default(),
+        ));
+        for _ in 0..10 {
+            original.run_simulation_tick().unwrap();
+        }
+        for _ in 0..5 {
+            sim.run_simulation_tick().unwrap();
+            original.run_simulation_tick().unwrap();
+        }
+        assert_eq!(Snapshot::new(&sim), Snapshot::new(&original));
+        assert!(sim.grid.u.iter().all(|u| u.is_finite()));
+
+        // Snapshots of another grid size can't be restored.
+        let mut other = simulation(presets::empty([6, 5]));
+        assert_eq!(history.restore(&mut other), None);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut sim = simulation(presets::empty([6, 5]));
+        sim.memory_budget = MemoryBudget {
+            max_snapshots: 2,
+            max_stats: 3,
+        };
+
+        // The stats of the last 3 ticks are kept, oldest first.
+        let stats: Vec<TickStats> =
+            (0..5).map(|_| sim.run_simulation_tick().unwrap()).collect();
+        let kept: Vec<TickStats> = sim.stats_history().iter().copied().collect();
+        assert_eq!(kept, stats[2..]);
+
+        let mut history = SnapshotHistory::new(sim.memory_budget.max_snapshots, 1);
+        for iterations in 1..=4 {
+            sim.iterations = iterations;
+            history.record(&sim);
+        }
+        let snapshot_ticks: Vec<u32> =
+            history.snapshots().iter().map(|s| s.iterations).collect();
+        assert_eq!(snapshot_ticks, vec![3, 4]);
+
+        // Shrinking the budget drops the oldest entries.
+        sim.memory_budget.max_stats = 1;
+        let last = sim.run_simulation_tick().unwrap();
+        assert_eq!(sim.stats_history().iter().collect::<Vec<_>>(), vec![&last]);
+        history.set_capacity(1);
+        assert_eq!(history.snapshots()[0].iterations, 4);
+        sim.memory_budget.max_stats = 0;
+        sim.run_simulation_tick().unwrap();
+        assert!(sim.stats_history().is_empty());
+    }
+
+    #[test]
+    fn undo_and_redo_edits() {
+        let mut sim = simulation(presets::simple_inflow(
+            [20, 10],
+            [1.0, 0.0],
+            Walls::default(),
+        ));
+        for _ in 0..5 {
+            sim.run_simulation_tick().unwrap();
+        }
+        let original = sim.grid.clone();
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        let mut edits = EditHistory::default();
+        assert!(!edits.undo(&mut sim.grid).unwrap());
+
+        // One drag draws two squares, which are undone together.
+        edits.begin_edit(&sim.grid);
+        sim.grid
+            .set_cells([(5, 4), (6, 4), (5, 5), (6, 5)], wall)
+            .unwrap();
+        edits.begin_edit(&sim.grid);
+        sim.grid
+            .set_cells([(7, 4), (8, 4), (7, 5), (8, 5)], wall)
+            .unwrap();
+        edits.end_edit(&sim.grid);
+        let drawn = sim.grid.clone();
+        // Clicking a cell that is already a wall changes nothing.
+        edits.begin_edit(&sim.grid);
+        sim.grid.set_cells([(5, 4)], wall).unwrap();
+        edits.end_edit(&sim.grid);
+
+        assert!(edits.undo(&mut sim.grid).unwrap());
+        assert_eq!(sim.grid.cell_type, original.cell_type);
+        assert_eq!(sim.grid.u, original.u);
+        assert_eq!(sim.grid.pressure, original.pressure);
+        assert_eq!(
+            sim.grid.boundaries.sorted_boundary_list,
+            original.boundaries.sorted_boundary_list
+        );
+        assert!(!edits.can_undo());
+
+        assert!(edits.redo(&mut sim.grid).unwrap());
+        assert_eq!(sim.grid.cell_type, drawn.cell_type);
+        assert_eq!(
+            sim.grid.boundaries.sorted_boundary_list,
+            drawn.boundaries.sorted_boundary_list
+        );
+        assert!(!edits.redo(&mut sim.grid).unwrap());
+
+        // A new edit after an undo can't be followed by the undone redo.
+        edits.undo(&mut sim.grid).unwrap();
+        edits.begin_edit(&sim.grid);
+        sim.grid
+            .set_cells([(10, 2), (11, 2), (10, 3), (11, 3)], wall)
+            .unwrap();
+        edits.end_edit(&sim.grid);
+        assert!(!edits.can_redo());
+        sim.run_simulation_tick().unwrap();
+    }
+
+    #[test]
+    fn undo_to_invalid_boundary() {
+        let mut grid = presets::empty([10, 8]);
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        grid.set_cells([(3, 3), (4, 3), (3, 4), (4, 4)], wall)
+            .unwrap();
+        let mut edits = EditHistory::default();
+        edits.begin_edit(&grid);
+        grid.set_cells([(5, 3), (5, 4)], wall).unwrap();
+        edits.end_edit(&grid);
+        // Without the edit, only the column the history doesn't know about
+        // would be left, which is one cell thick.
+        grid.set_cells([(3, 3), (3, 4)], Cell::Fluid).unwrap();
+        let before_undo = grid.clone();
+
+        assert!(matches!(
+            edits.undo(&mut grid),
+            Err(SimulationGridError::InvalidBoundaries(_))
+        ));
+        assert_eq!(grid, before_undo);
+        assert_eq!(
+            grid.boundaries.sorted_boundary_list,
+            before_undo.boundaries.sorted_boundary_list
+        );
+        assert!(edits.can_undo());
+        assert!(!edits.can_redo());
+    }
+
+    #[test]
+    fn undo_capacity() {
+        let mut grid = presets::empty([10, 8]);
+        let mut edits = EditHistory::new(2);
+        for x in [2, 4, 6] {
+            edits.begin_edit(&grid);
+            grid.set_cells(
+                [(x, 3), (x + 1, 3), (x, 4), (x + 1, 4)],
+                Cell::Boundary(BoundaryCell::NoSlip),
+            )
+            .unwrap();
+            edits.end_edit(&grid);
+        }
+        assert!(edits.undo(&mut grid).unwrap());
+        assert!(edits.undo(&mut grid).unwrap());
+        assert!(!edits.undo(&mut grid).unwrap());
+        // The oldest edit is still in place.
+        assert_eq!(grid.cell_type[(2, 3)], Cell::Boundary(BoundaryCell::NoSlip));
+        assert_eq!(grid.cell_type[(4, 3)], Cell::Fluid);
+
+        edits.clear();
+        assert!(!edits.can_redo());
+    }
+}
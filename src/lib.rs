@@ -1,163 +1,411 @@
+// Values are converted from `Real` to `f32` for drawing, which is a no-op when
+// `Real` is `f32`.
+#![cfg_attr(feature = "single-precision", allow(clippy::unnecessary_cast))]
+
 pub mod args;
+pub mod averaging;
+pub mod builder;
 pub mod cell;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod commands;
+pub mod config;
 pub mod grid;
+pub mod history;
 pub mod math;
+pub mod particles;
+pub mod probe;
+pub mod scheduler;
 pub mod simulation;
+pub mod time;
 pub mod types;
 pub mod ui_state;
+pub mod units;
 pub mod visualization;
 
-use crate::ui_state::{initialize_state, MouseState, Preset};
+use crate::ui_state::{
+    handle_keyboard, initialize_state, slider_range, MouseState, Preset, Reset, UiState,
+    KEY_BINDINGS,
+};
+use crate::visualization::draw_boundary_outlines;
 use crate::visualization::render_simulation;
-use crate::visualization::ColorType;
+use crate::visualization::{
+    draw_axes, draw_help_overlay, draw_phase_bar, draw_probe_markers, draw_probe_plots,
+    draw_shape_preview, draw_tracers, highlight_cells, GridView, AXIS_MARGIN,
+    WARNING_COLOR,
+};
+use crate::visualization::{ColorScale, ColorType};
+#[cfg(not(target_arch = "wasm32"))]
 use std::fs::File;
-use std::io::BufReader;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufWriter;
 use std::path::Path;
 
 use args::Args;
+use averaging::FieldAverager;
+use builder::SimulationBuilder;
 use cell::{BoundaryCell, Cell};
-use grid::{presets, SimulationGrid, UnfinalizedSimulationGrid};
+use grid::{SimulationGrid, SimulationGridError};
+use history::SnapshotHistory;
 use math::Real;
-use simulation::{Simulation, UnfinalizedSimulation};
-use strum::VariantNames;
-use types::GridIndex;
+use probe::{ProbeError, ProbeSet};
+use scheduler::FrameTicks;
+use simulation::{
+    ConvergenceStatus, Parameter, PhaseTimings, Simulation, SimulationError, TickStats,
+};
+use strum::{IntoEnumIterator, VariantNames};
+use types::{GridArray, GridIndex};
 
+use log::{error, info, warn};
 use macroquad::prelude::*;
 
 use macroquad::ui::{hash, root_ui};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum HeadlessError {
+    #[error(transparent)]
+    SimulationError(#[from] SimulationError),
+    #[error(transparent)]
+    ProbeError(#[from] ProbeError),
+    #[error("Could not write the probe history or the averaged field: `{0}`")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not set up the simulation: {0}")]
+    SetupError(Box<dyn std::error::Error>),
+}
 
-pub fn window_conf() -> Conf {
+pub fn window_conf(args: &Args) -> Conf {
     Conf {
         window_title: "Stroemung".to_owned(),
+        window_width: args.window_width,
+        window_height: args.window_height,
         ..Default::default()
     }
 }
 
+// Space kept free for the control panel on the right of the window
+const CONTROL_PANEL_WIDTH: f32 = 220.0;
+// Space kept free below the grid for the text readouts and the probe plots
+const HUD_HEIGHT: f32 = 150.0;
+const PROBE_PLOT_HEIGHT: f32 = 170.0;
+// Warn that SOR isn't converging once this many ticks in a row stopped at
+// max_iterations. The first few ticks of a new flow often do.
+const SOR_CAP_WARNING_TICKS: u32 = 10;
+
+// How long the cells that made an edit invalid stay highlighted, in seconds
+const INVALID_CELLS_SECONDS: f32 = 1.5;
+
+// Set `cells` to `cell_type`, or leave the grid as it was if that would
+// create an invalid boundary. The outer ring of the grid can be given other
+// boundary types, e.g. to add an inflow, but never becomes fluid.
+fn stamp_cells(
+    grid: &mut SimulationGrid,
+    cell_type: Cell,
+    cells: impl IntoIterator<Item = GridIndex>,
+) -> Result<(), SimulationGridError> {
+    let [w, h] = grid.size;
+    // Shapes may reach past the grid, and the brush past the outer ring.
+    let cells = cells.into_iter().filter(|&(x, y)| {
+        if x >= w || y >= h {
+            return false;
+        }
+        let outer = x == 0 || y == 0 || x == w - 1 || y == h - 1;
+        !(outer && cell_type == Cell::Fluid)
+    });
+    grid.set_cells(cells, cell_type)
+}
+
 // Draw a 2x2 square since the simulation doesn't support boundary cells that
-// have fluid cells on opposite sides.
-fn draw_cells(grid: &mut SimulationGrid, cell_type: Cell, m_x: usize, m_y: usize) {
-    let mut backup: Vec<(GridIndex, Real, Real, Real, Cell)> = Vec::new();
-    let mut modified = false;
-
-    for (x, y) in [
-        (m_x, m_y),
-        (m_x + 1, m_y),
-        (m_x, m_y + 1),
-        (m_x + 1, m_y + 1),
-    ] {
-        // Don't touch outer boundary cells
-        if (x > 0) && (x < grid.size[0] - 1) && (y > 0) && (y < grid.size[1] - 1) {
-            let idx = (x, y);
-            if grid.cell_type[idx] != cell_type {
-                // Backup the values so we can restore them in the event that
-                // this creates an invalid boundary.
-                backup.push((
-                    idx,
-                    grid.u[idx],
-                    grid.v[idx],
-                    grid.pressure[idx],
-                    grid.cell_type[idx],
-                ));
-                grid.u[idx] = 0.0;
-                grid.v[idx] = 0.0;
-                grid.pressure[idx] = 0.0;
-                grid.cell_type[idx] = cell_type;
-                modified = true;
+// have fluid cells on opposite sides. Parts of the square past the far edge
+// of the grid are left out.
+fn draw_cells(
+    grid: &mut SimulationGrid,
+    cell_type: Cell,
+    m_x: usize,
+    m_y: usize,
+) -> Result<(), SimulationGridError> {
+    let square = [(0, 0), (1, 0), (0, 1), (1, 1)]
+        .into_iter()
+        .filter_map(|(dx, dy)| Some((m_x.checked_add(dx)?, m_y.checked_add(dy)?)));
+    stamp_cells(grid, cell_type, square)
+}
+
+// Highlight the cells that made an edit invalid. Dragging over cells that
+// can't change is common, so nothing is logged; the grid stays valid either
+// way.
+fn show_invalid_cells(ui_state: &mut UiState, result: Result<(), SimulationGridError>) {
+    if let Err(SimulationGridError::InvalidBoundaries(invalid)) = result {
+        ui_state.invalid_cells = invalid.into_iter().map(|(idx, _)| idx).collect();
+        ui_state.invalid_cells_time = INVALID_CELLS_SECONDS;
+    }
+}
+
+// The simulation from `--sim-file`, or else a new one from `preset`. There
+// are no files in the browser, so it always starts from `preset` there. Some
+// presets don't fit on small grids, e.g. the cylinders need 14 cells down.
+fn get_sim(
+    args: &Args,
+    preset: Preset,
+) -> Result<Simulation, Box<dyn std::error::Error>> {
+    let sim = match &args.sim_file {
+        #[cfg(not(target_arch = "wasm32"))]
+        Some(filename) => load_sim(Path::new(&filename), args.repair_geometry)?,
+        _ => SimulationBuilder::from(&args.simulation)
+            .grid((preset, [args.simulation.x_cells, args.simulation.y_cells]))
+            .build()?,
+    };
+    Ok(override_memory_budget(args, sim))
+}
+
+// Apply `--snapshots` and `--stats-history` to a new or loaded simulation
+fn override_memory_budget(args: &Args, mut sim: Simulation) -> Simulation {
+    if let Some(max_snapshots) = args.snapshots {
+        sim.memory_budget.max_snapshots = max_snapshots;
+    }
+    if let Some(max_stats) = args.stats_history {
+        sim.memory_budget.max_stats = max_stats;
+    }
+    sim
+}
+
+// Load the simulation from `path`, in the format that matches its extension.
+// With `repair`, walls that are one cell thick are thickened instead of
+// rejected.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_sim(path: &Path, repair: bool) -> Result<Simulation, Box<dyn std::error::Error>> {
+    Ok(if repair {
+        commands::load_repaired_simulation(path)?
+    } else {
+        commands::load_simulation(path)?
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn load_sim(
+    _path: &Path,
+    _repair: bool,
+) -> Result<Simulation, Box<dyn std::error::Error>> {
+    Err("there are no files in the browser".into())
+}
+
+// Save the simulation to `path`, in the format that matches its extension
+#[cfg(not(target_arch = "wasm32"))]
+fn save_sim(sim: &Simulation, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(commands::save_simulation(sim, path)?)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn save_sim(_sim: &Simulation, _path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    Err("there are no files in the browser".into())
+}
+
+// Create a probe for each `--probe` flag
+fn get_probes(args: &Args, sim: &Simulation) -> Result<ProbeSet, ProbeError> {
+    let mut probes = ProbeSet::default();
+    for location in &args.probes {
+        probes.add(*location, sim.size)?;
+    }
+    Ok(probes)
+}
+
+// A progress bar on standard error for a headless run of `ticks` ticks. It's
+// hidden when standard error isn't a terminal.
+#[cfg(feature = "progress")]
+fn progress_bar(ticks: u32) -> indicatif::ProgressBar {
+    let progress = indicatif::ProgressBar::new(ticks.into());
+    progress.set_style(
+        indicatif::ProgressStyle::with_template(
+            "{bar:40} tick {pos}/{len} [{elapsed_precise} < {eta}] {msg}",
+        )
+        .expect("the progress bar template is valid"),
+    );
+    progress
+}
+
+/// Run the simulation for `args.ticks` ticks without a window
+///
+/// This isn't available in the browser, which has no files to write the
+/// results to and no clock outside of the window.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_headless(args: &Args) -> Result<(), HeadlessError> {
+    let mut sim = get_sim(args, Preset::Obstacle).map_err(HeadlessError::SetupError)?;
+    let mut probes = get_probes(args, &sim)?;
+
+    sim.log_parameters();
+
+    let mut averager = args
+        .time_average
+        .as_ref()
+        .map(|_| FieldAverager::new(sim.size, true));
+
+    let mut stats = TickStats::default();
+    let mut capped_ticks = 0;
+    let mut interval_timings = PhaseTimings::default();
+    #[cfg(feature = "progress")]
+    let progress = (!args.quiet).then(|| progress_bar(args.ticks));
+    // Keep the progress bar from drawing over the line.
+    let log_line = |line: &str| {
+        #[cfg(feature = "progress")]
+        if let Some(progress) = &progress {
+            progress.suspend(|| info!("{}", line));
+            return;
+        }
+        info!("{}", line);
+    };
+    for tick in 1..=args.ticks {
+        stats = sim.run_simulation_tick()?;
+        #[cfg(feature = "progress")]
+        if let Some(progress) = &progress {
+            progress.set_position(tick.into());
+            progress.set_message(format!(
+                "time {:.3}, residual {}",
+                sim.time, stats.residual
+            ));
+        }
+        if stats.convergence == ConvergenceStatus::MaxIterationsReached {
+            capped_ticks += 1;
+        }
+        probes.record(&sim);
+        if let Some(averager) = &mut averager {
+            averager.accumulate(&sim);
+        }
+
+        if let Some(interval) = args.timing_interval.filter(|&n| n > 0) {
+            interval_timings += stats.timings;
+            if tick % interval == 0 {
+                let line = format!("Tick {}: {}", tick, interval_timings / interval);
+                interval_timings = PhaseTimings::default();
+                log_line(&line);
             }
         }
     }
 
-    if modified && grid.rebuild_boundary_list().is_err() {
-        for (idx, u, v, pressure, cell) in backup {
-            grid.u[idx] = u;
-            grid.v[idx] = v;
-            grid.pressure[idx] = pressure;
-            grid.cell_type[idx] = cell;
+    #[cfg(feature = "progress")]
+    if let Some(progress) = &progress {
+        progress.finish_and_clear();
+    }
+
+    info!("Ran {} ticks, simulated time {:.3}", args.ticks, sim.time);
+    info!(
+        "Last tick: {} SOR iterations, residual {}, CFL {:.2}",
+        stats.sor_iterations, stats.residual, stats.cfl
+    );
+    if capped_ticks > 0 {
+        warn!(
+            "SOR stopped at --sor-max-iterations without converging in {} of {} \
+             ticks",
+            capped_ticks, args.ticks
+        );
+    }
+
+    if args.profile {
+        #[cfg(feature = "profiling")]
+        for line in sim.timings.to_string().lines() {
+            log_line(line);
         }
+        #[cfg(not(feature = "profiling"))]
+        warn!("--profile requires building with `--features profiling`");
     }
-}
 
-fn get_sim(args: &Args, preset: Preset) -> Simulation {
-    match &args.sim_file {
-        Some(filename) => {
-            let file = File::open(Path::new(&filename)).unwrap();
-            Simulation::from_reader(BufReader::new(file)).unwrap()
-        }
-        _ => {
-            let size = [args.x_cells, args.y_cells];
-            let grid: UnfinalizedSimulationGrid = match preset {
-                Preset::Obstacle => presets::obstacle(size).into(),
-                Preset::Inflow => presets::simple_inflow(size).into(),
-            };
-            Simulation::try_from(UnfinalizedSimulation {
-                size,
-                cell_size: [args.x_cell_width, args.y_cell_height],
-                delt: args.delta_t,
-                gamma: args.gamma,
-                reynolds: args.reynolds,
-                sor_absolute_epsilon: args.sor_epsilon,
-                max_iterations: args.sor_max_iterations,
-                initial_norm_squared: None,
-                iterations: 0,
-                time: 0.0,
-                omega: args.omega,
-                grid,
-            })
-            .unwrap()
+    if !probes.is_empty() {
+        match &args.probe_csv {
+            Some(filename) => {
+                probes.write_csv(BufWriter::new(File::create(Path::new(filename))?))?
+            }
+            None => probes.write_csv(std::io::stdout().lock())?,
         }
     }
+    if let (Some(averager), Some(filename)) = (&averager, &args.time_average) {
+        averager.write_csv(BufWriter::new(File::create(Path::new(filename))?))?;
+        info!(
+            "Wrote the fields averaged over {} ticks to {}",
+            averager.samples(),
+            filename
+        );
+    }
+
+    Ok(())
 }
 
 pub async fn run(args: Args) {
-    println!("Exécute des simulations...");
-
-    let mut sim = get_sim(&args, Preset::Obstacle);
-
-    println!("Grid size {} x {}", sim.size[0], sim.size[1]);
-
-    let [w, h] = sim.size;
+    let mut sim = match get_sim(&args, Preset::Obstacle) {
+        Ok(sim) => sim,
+        Err(err) => {
+            error!("Can't set up the simulation: {}", err);
+            std::process::exit(1);
+        }
+    };
+    sim.log_parameters();
 
-    let scaling = 4;
+    let [mut w, mut h] = sim.size;
 
     let background_color = Color::from_hex(0xfdf6e3);
 
     let mut image = Image::gen_image_color(w as u16, h as u16, background_color);
 
-    let texture = Texture2D::from_image(&image);
+    let mut texture = Texture2D::from_image(&image);
 
     let mut preset_index = 0;
+    // The preset the simulation was last started from
+    let mut sim_preset = Preset::Obstacle;
+    let mut perturb_seed = 0;
 
     let mut ui_state = initialize_state();
+    ui_state.inflow_velocity = [args.simulation.inflow_u, args.simulation.inflow_v];
+    ui_state.history =
+        SnapshotHistory::new(sim.memory_budget.max_snapshots, args.snapshot_interval);
+    ui_state.probes = get_probes(&args, &sim).unwrap_or_else(|err| {
+        warn!("Ignoring probes: {}", err);
+        ProbeSet::default()
+    });
+
+    let mut stats = TickStats::default();
+    // Ticks in a row where SOR stopped at max_iterations
+    let mut capped_ticks = 0;
+
+    let mut render_scratch: GridArray<Real> = ndarray::Array::zeros(sim.size);
+
+    let controls_id = hash!();
 
     loop {
         let (mouse_x, mouse_y) = mouse_position();
+        // Drawing tools erase to fluid instead while shift is held.
+        let erase = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
 
         clear_background(background_color);
 
+        // Recomputed every frame to follow changes to the window size.
+        // The margins leave room for the axes.
+        let view = GridView::fit(
+            sim.size,
+            Rect::new(
+                AXIS_MARGIN,
+                0.0,
+                screen_width() - CONTROL_PANEL_WIDTH - AXIS_MARGIN,
+                screen_height() - AXIS_MARGIN - HUD_HEIGHT - PROBE_PLOT_HEIGHT,
+            ),
+        );
+        let hud_y = view.y + view.height() + AXIS_MARGIN;
+
+        let controls_position =
+            Vec2::new(screen_width() - CONTROL_PANEL_WIDTH + 10., 20.);
+        root_ui().move_window(controls_id, controls_position);
         root_ui().window(
-            hash!(),
-            Vec2::new(20., (h * scaling) as f32 + 105.),
-            Vec2::new(200., 280.),
+            controls_id,
+            controls_position,
+            Vec2::new(200., 725.),
             |ui| {
-                ui.group(hash!(), vec2(190.0, 275.0), |ui| {
-                    ui.label(None, "Controls");
+                ui.group(hash!(), vec2(190.0, 720.0), |ui| {
+                    ui.label(None, "Controls (H: shortcuts)");
 
                     if ui.button(None, "Run / Pause") {
                         ui_state.keep_running = !ui_state.keep_running;
                     }
                     ui.group(hash!(), vec2(50.0, 50.0), |ui| {
                         if ui.button(None, "Slower") {
-                            // There's a bad UI interaction that happens if this
-                            // if is collapsed into the ui.button code above,
-                            // I assume it has to do with short-circuiting the
-                            // && somehow.
-                            #[allow(clippy::collapsible_if)]
-                            if ui_state.speed_multiplier > 1 {
-                                ui_state.speed_multiplier -= 1;
-                            }
+                            ui_state.scheduler.slower();
                         }
                         if ui.button(None, "Faster") {
-                            ui_state.speed_multiplier += 1;
+                            ui_state.scheduler.faster();
                         }
                     });
 
@@ -171,74 +419,429 @@ pub async fn run(args: Args) {
                     if ui.button(None, "Visualize Pressure") {
                         ui_state.color_type = ColorType::Pressure;
                     }
-                    if ui.button(None, "Reset Simulation") {
-                        ui_state.reset = true;
+                    if ui.button(None, "Visualize Residual") {
+                        ui_state.color_type = ColorType::Residual;
+                    }
+                    if ui.button(None, "Visualize Streamlines") {
+                        ui_state.color_type = ColorType::StreamFunction;
+                    }
+                    if ui.button(None, "Center Pressure on Zero") {
+                        ui_state.center_zero = !ui_state.center_zero;
+                    }
+                    if ui.button(None, "Outline Boundaries") {
+                        ui_state.show_boundary_outlines =
+                            !ui_state.show_boundary_outlines;
+                    }
+                    if ui.button(None, "Show Axes") {
+                        ui_state.show_axes = !ui_state.show_axes;
+                    }
+                    if ui.button(None, "Reset Flow (keep geometry)") {
+                        ui_state.reset = Some(Reset::Flow);
                     }
                     ui.combo_box(hash!(), "Preset", Preset::VARIANTS, &mut preset_index);
                     let desired_preset = Preset::try_from(preset_index).unwrap();
                     if ui_state.preset != desired_preset {
-                        ui_state.reset = true;
+                        ui_state.reset = Some(Reset::Everything);
                     }
                     ui_state.preset = desired_preset;
-                    if ui.button(None, "Reset Simulation") {
-                        ui_state.reset = true;
+                    if ui.button(None, "Reset Everything") {
+                        ui_state.reset = Some(Reset::Everything);
                     }
+                    ui.group(hash!(), vec2(90.0, 50.0), |ui| {
+                        if ui.button(None, "Save Grid") {
+                            ui_state.save = true;
+                        }
+                        if ui.button(None, "Load Grid") {
+                            ui_state.load = true;
+                        }
+                    });
                     if ui.button(None, "Mouse Inspects") {
                         ui_state.mouse_state = MouseState::Inspection;
                     }
+                    if ui.button(None, "Clear Probes") {
+                        ui_state.probes.clear();
+                    }
+                    if ui.button(None, "Perturb") {
+                        // A few percent of the inflow speed is enough to
+                        // break the symmetry. Each press uses the next seed,
+                        // so repeated presses don't add the same noise.
+                        let inflow = [args.simulation.inflow_u, args.simulation.inflow_v];
+                        let amplitude = 0.05 * inflow[0].hypot(inflow[1]);
+                        sim.perturb_velocity(amplitude, perturb_seed);
+                        perturb_seed += 1;
+                    }
+                    if ui.button(None, "Seed Tracers") {
+                        ui_state.tracers.seed(&sim.grid, ((0, 0), (w - 1, h - 1)));
+                    }
                     if ui.button(None, "Mouse Draws Boundaries") {
                         ui_state.mouse_state = MouseState::Boundary;
                     }
                     if ui.button(None, "Mouse Draws Fluid") {
                         ui_state.mouse_state = MouseState::Fluid;
                     }
+                    if ui.button(None, "Mouse Draws Rectangles") {
+                        ui_state.mouse_state = MouseState::RectTool;
+                    }
+                    if ui.button(None, "Mouse Draws Circles") {
+                        ui_state.mouse_state = MouseState::CircleTool;
+                    }
+                    if ui.button(None, "Mouse Fills Regions") {
+                        ui_state.mouse_state = MouseState::Fill;
+                    }
+                    if ui.button(None, "Mouse Draws Inflow") {
+                        ui_state.mouse_state = MouseState::Inflow;
+                    }
+                    if ui.button(None, "Mouse Draws Outflow") {
+                        ui_state.mouse_state = MouseState::Outflow;
+                    }
+                    // The widgets only edit f32s.
+                    let mut inflow = ui_state.inflow_velocity.map(|value| value as f32);
+                    ui.drag(hash!(), "Inflow u", (-10.0, 10.0), &mut inflow[0]);
+                    ui.drag(hash!(), "Inflow v", (-10.0, 10.0), &mut inflow[1]);
+                    ui_state.inflow_velocity = inflow.map(Real::from);
+                    ui.group(hash!(), vec2(90.0, 50.0), |ui| {
+                        // Mirroring a valid grid keeps it valid, so errors
+                        // can only come from bugs.
+                        if ui.button(None, "Mirror X") {
+                            ui_state.edits.begin_edit(&sim.grid);
+                            sim.grid.mirror_x().unwrap();
+                            ui_state.edits.end_edit(&sim.grid);
+                            ui_state.history.clear();
+                            ui_state.isolated_regions = None;
+                        }
+                        if ui.button(None, "Mirror Y") {
+                            ui_state.edits.begin_edit(&sim.grid);
+                            sim.grid.mirror_y().unwrap();
+                            ui_state.edits.end_edit(&sim.grid);
+                            ui_state.history.clear();
+                            ui_state.isolated_regions = None;
+                        }
+                    });
+                    ui.tree_node(hash!(), "Inflow Velocities", |ui| {
+                        for (i, (velocity, count)) in
+                            sim.grid.inflow_groups().into_iter().enumerate()
+                        {
+                            ui.label(None, &format!("{} cells", count));
+                            let old = velocity.map(|value| value as f32);
+                            let mut new = old;
+                            ui.drag(
+                                hash!("inflow", i, 0),
+                                "u",
+                                (-10.0, 10.0),
+                                &mut new[0],
+                            );
+                            ui.drag(
+                                hash!("inflow", i, 1),
+                                "v",
+                                (-10.0, 10.0),
+                                &mut new[1],
+                            );
+                            // Only write back edits, the round trip through
+                            // f32 would round the velocity.
+                            if new != old {
+                                sim.grid
+                                    .set_inflow_velocity(velocity, new.map(Real::from));
+                            }
+                        }
+                    });
+                    ui.tree_node(hash!(), "Parameters", |ui| {
+                        for parameter in Parameter::iter() {
+                            let old = sim.parameter(parameter) as f32;
+                            let mut new = old;
+                            ui.slider(
+                                hash!("parameter", parameter.name()),
+                                parameter.name(),
+                                slider_range(parameter),
+                                &mut new,
+                            );
+                            // Like the inflow velocities, only write back
+                            // edits. The change takes effect on the next tick.
+                            if new != old {
+                                match sim.set_parameter(parameter, Real::from(new)) {
+                                    Ok(()) => ui_state.parameter_error = None,
+                                    Err(err) => {
+                                        warn!("{}", err);
+                                        ui_state.parameter_error = Some(err.to_string());
+                                    }
+                                }
+                            }
+                        }
+                        // The sliders round to two decimals.
+                        ui.label(None, &format!("delt {}", sim.delt));
+                        if let Some(err) = &ui_state.parameter_error {
+                            ui.label(None, err);
+                        }
+                    });
+                    ui.tree_node(hash!(), "Color Ranges", |ui| {
+                        let ranges = [
+                            (
+                                "Pressure",
+                                &mut ui_state.fixed_pressure_range,
+                                sim.grid.pressure_range,
+                            ),
+                            (
+                                "Speed",
+                                &mut ui_state.fixed_speed_range,
+                                sim.grid.speed_range,
+                            ),
+                        ];
+                        for (i, (name, fixed, current)) in ranges.into_iter().enumerate()
+                        {
+                            let toggle = match fixed {
+                                Some(_) => format!("Unlock {} Range", name),
+                                None => format!("Lock {} Range", name),
+                            };
+                            if ui.button(None, toggle.as_str()) {
+                                *fixed = match fixed {
+                                    Some(_) => None,
+                                    None => Some(current),
+                                };
+                            }
+                            if let Some(range) = fixed {
+                                let old = range.map(|value| value as f32);
+                                let mut new = old;
+                                ui.drag(hash!("range", i, 0), "min", None, &mut new[0]);
+                                ui.drag(hash!("range", i, 1), "max", None, &mut new[1]);
+                                // Like the inflow velocities, only write back
+                                // edits that leave a valid range.
+                                if new != old && new[0] < new[1] {
+                                    *range = new.map(Real::from);
+                                }
+                            }
+                        }
+                    });
                 });
             },
         );
 
-        if ui_state.reset {
-            sim = get_sim(&args, ui_state.preset);
-            ui_state.reset = false;
-        }
+        handle_keyboard(&mut ui_state);
 
-        // Set to 1 in case the user asked to run one iteration.
-        let mut speed_multiplier = 1;
+        if ui_state.undo || ui_state.redo {
+            // Finish a drag that is still going, so it's undone as a whole.
+            ui_state.edits.end_edit(&sim.grid);
+            let result = if ui_state.undo {
+                ui_state.edits.undo(&mut sim.grid)
+            } else {
+                ui_state.edits.redo(&mut sim.grid)
+            };
+            match result {
+                // Snapshots don't include the cell types.
+                Ok(true) => {
+                    ui_state.history.clear();
+                    ui_state.isolated_regions = None;
+                }
+                Ok(false) => {}
+                Err(err) => error!("Can't undo or redo the edit: {}", err),
+            }
+            ui_state.undo = false;
+            ui_state.redo = false;
+        }
 
-        if ui_state.keep_running {
-            ui_state.run = true;
-            // Set to the multiplier in case the user asked to keep running the
-            // simulation.
-            speed_multiplier = ui_state.speed_multiplier;
+        if ui_state.save {
+            match save_sim(&sim, Path::new(&args.save_file)) {
+                Ok(()) => info!("Saved the simulation to {}", args.save_file),
+                Err(err) => error!("Can't save the simulation: {}", err),
+            }
+            ui_state.save = false;
         }
 
-        if ui_state.run {
-            for _ in 0..speed_multiplier {
-                sim.run_simulation_tick().unwrap();
+        let reset = ui_state.reset.take();
+        if reset == Some(Reset::Flow) {
+            sim.reset_fields();
+        }
+        let new_sim = if reset == Some(Reset::Everything) {
+            match get_sim(&args, ui_state.preset) {
+                Ok(new_sim) => {
+                    sim_preset = ui_state.preset;
+                    Some(new_sim)
+                }
+                // Keep running the current simulation, and show the preset it
+                // came from in the menu again.
+                Err(err) => {
+                    error!("Can't start the {:?} preset: {}", ui_state.preset, err);
+                    ui_state.preset = sim_preset;
+                    preset_index = sim_preset as usize;
+                    None
+                }
+            }
+        } else if ui_state.load {
+            match load_sim(Path::new(&args.save_file), args.repair_geometry) {
+                Ok(loaded) => {
+                    info!("Loaded the simulation from {}", args.save_file);
+                    Some(override_memory_budget(&args, loaded))
+                }
+                Err(err) => {
+                    error!("Can't load the simulation: {}", err);
+                    None
+                }
             }
-            ui_state.run = false;
+        } else {
+            None
+        };
+        ui_state.load = false;
+        let started_over = new_sim.is_some() || reset == Some(Reset::Flow);
+        if let Some(new_sim) = new_sim {
+            sim = new_sim;
+            // A loaded simulation can have a different size.
+            if sim.size != [w, h] {
+                [w, h] = sim.size;
+                image = Image::gen_image_color(w as u16, h as u16, background_color);
+                texture = Texture2D::from_image(&image);
+            }
+            ui_state
+                .history
+                .set_capacity(sim.memory_budget.max_snapshots);
+            ui_state.edits.clear();
+            ui_state.invalid_cells.clear();
+        }
+        if started_over {
+            stats = TickStats::default();
+            capped_ticks = 0;
+            ui_state.probes = get_probes(&args, &sim).unwrap_or_default();
+            ui_state.tracers.clear();
+            ui_state.history.clear();
+            ui_state.rollback_message = None;
+            ui_state.isolated_regions = None;
         }
 
-        render_simulation(&sim, &mut image, w, h, ui_state.color_type);
+        let (keep_running, run_once) = (ui_state.keep_running, ui_state.run);
+        if keep_running || run_once {
+            ui_state.rollback_message = None;
+        }
+        let mut frame_timings = PhaseTimings::default();
+        // Run one tick, returning the simulated time it covered or None if
+        // the simulation had to stop.
+        let mut tick = || match sim.run_simulation_tick() {
+            Ok(tick_stats) => {
+                stats = tick_stats;
+                frame_timings += stats.timings;
+                capped_ticks = match stats.convergence {
+                    ConvergenceStatus::MaxIterationsReached => capped_ticks + 1,
+                    _ => 0,
+                };
+                ui_state.probes.record(&sim);
+                ui_state.tracers.advance(&sim, sim.delt);
+                ui_state.history.record(&sim);
+                Some(sim.delt)
+            }
+            Err(err) => {
+                error!("Stopping the simulation: {}", err);
+                ui_state.keep_running = false;
+                // Go back to the last healthy state, so the user can change
+                // the settings and carry on instead of starting over.
+                let stopped_at = sim.time;
+                if let Some(restored) = ui_state.history.restore(&mut sim) {
+                    stats = TickStats::default();
+                    capped_ticks = 0;
+                    ui_state.rollback_message = Some(format!(
+                        "Paused at time {:.3} ({}), rolled back to time {:.3}",
+                        stopped_at, err, restored
+                    ));
+                }
+                None
+            }
+        };
+        let frame = if keep_running {
+            ui_state
+                .scheduler
+                .run_frame(get_frame_time() as f64, &mut tick)
+        } else if run_once {
+            // The user asked to run one iteration.
+            match tick() {
+                Some(simulated) => FrameTicks {
+                    ticks: 1,
+                    simulated,
+                },
+                None => FrameTicks::default(),
+            }
+        } else {
+            // Don't catch up on the time spent paused.
+            ui_state.scheduler.reset();
+            FrameTicks::default()
+        };
+        ui_state.run = false;
+        ui_state.performance.push(
+            get_frame_time(),
+            frame.ticks,
+            frame.simulated,
+            frame_timings,
+        );
+
+        render_simulation(
+            &sim,
+            &mut image,
+            w,
+            h,
+            ui_state.color_type,
+            ColorScale {
+                center_zero: ui_state.center_zero,
+                pressure_range: ui_state.fixed_pressure_range,
+                speed_range: ui_state.fixed_speed_range,
+            },
+            &mut render_scratch,
+        );
+        if ui_state.invalid_cells_time > 0.0 {
+            highlight_cells(&mut image, w, h, &ui_state.invalid_cells, WARNING_COLOR);
+            ui_state.invalid_cells_time -= get_frame_time();
+        }
 
         texture.update(&image);
         draw_texture_ex(
             &texture,
-            0.,
-            0.,
+            view.x,
+            view.y,
             background_color,
             DrawTextureParams {
-                dest_size: Some(vec2((w * scaling) as f32, (h * scaling) as f32)),
+                dest_size: Some(vec2(view.width(), view.height())),
                 ..Default::default()
             },
         );
+        if ui_state.show_boundary_outlines {
+            draw_boundary_outlines(&sim, &view);
+        }
+        if ui_state.show_axes {
+            draw_axes(&sim, &view);
+        }
+        draw_tracers(&ui_state.tracers, &view);
+        draw_probe_markers(&ui_state.probes, &view);
+        let mut warnings = Vec::new();
+        // Fluid that's walled off from every inflow and outflow can't move
+        // in or out, which is usually a drawing mistake. Finding the regions
+        // walks the whole grid, so they're only counted again after the cells
+        // change.
+        let isolated_regions = *ui_state
+            .isolated_regions
+            .get_or_insert_with(|| sim.grid.find_isolated_fluid_regions().len());
+        if isolated_regions > 0 {
+            warnings.push(format!(
+                "Warning: {} fluid region(s) can't reach an inflow or outflow",
+                isolated_regions
+            ));
+        }
+        if let Some(message) = &ui_state.rollback_message {
+            warnings.push(message.clone());
+        }
+        if capped_ticks >= SOR_CAP_WARNING_TICKS {
+            warnings.push(format!(
+                "Warning: SOR hasn't converged for {} ticks, try a smaller delt or \
+                 another omega",
+                capped_ticks
+            ));
+        }
+        for (i, warning) in warnings.iter().enumerate() {
+            draw_text(
+                warning,
+                view.x + 10.0,
+                view.y + 25.0 + 25.0 * i as f32,
+                24.0,
+                RED,
+            );
+        }
 
-        let m_x = (mouse_x / (scaling as f32)) as usize;
-        let m_y = (mouse_y / (scaling as f32)) as usize;
-
-        if (m_x < w) && (m_y < h) {
-            let inspect_cell_pressure = sim.grid.pressure[(m_x, m_y)];
-            let inspect_cell_speed =
-                (sim.grid.u[(m_x, m_y)].powi(2) + sim.grid.v[(m_x, m_y)].powi(2)).sqrt();
+        if let Some((m_x, m_y)) = view.cell_at(mouse_x, mouse_y) {
+            let inspect_cell_pressure = sim.grid.pressure_at_index((m_x, m_y));
+            let [u, v] = sim.grid.velocity_at_index((m_x, m_y));
+            let inspect_cell_speed = u.hypot(v);
             draw_text(
                 &format!(
                     "x: {:?}, y: {:?}, press: {:.2?}, speed: {:.2?}",
@@ -246,36 +849,424 @@ pub async fn run(args: Args) {
                 )
                 .to_string(),
                 20.0,
-                (h * scaling) as f32 + 35.0,
+                hud_y + 35.0,
                 30.0,
                 DARKGREEN,
             );
 
+            if ui_state.mouse_state == MouseState::Inspection
+                && is_mouse_button_pressed(MouseButton::Left)
+            {
+                if let Err(err) = ui_state.probes.add((m_x, m_y), sim.size) {
+                    warn!("Can't add a probe: {}", err);
+                }
+            }
+
+            if ui_state
+                .mouse_state
+                .drag_shape((m_x, m_y), (m_x, m_y))
+                .is_some()
+                && is_mouse_button_pressed(MouseButton::Left)
+            {
+                ui_state.drag_anchor = Some((m_x, m_y));
+            }
+
+            if ui_state.mouse_state == MouseState::Fill
+                && is_mouse_button_pressed(MouseButton::Left)
+            {
+                let target = if erase {
+                    Cell::Fluid
+                } else {
+                    Cell::Boundary(BoundaryCell::NoSlip)
+                };
+                ui_state.edits.begin_edit(&sim.grid);
+                let filled = grid::flood_fill(&mut sim.grid, (m_x, m_y), target);
+                ui_state.edits.end_edit(&sim.grid);
+                match filled {
+                    Ok(filled) => {
+                        info!("Filled {} cells", filled);
+                        ui_state.history.clear();
+                        ui_state.isolated_regions = None;
+                    }
+                    Err(err) => {
+                        warn!("Can't fill the region: {}", err);
+                        show_invalid_cells(&mut ui_state, Err(err));
+                    }
+                }
+            }
+
             if is_mouse_button_down(MouseButton::Left) {
-                match ui_state.mouse_state {
-                    MouseState::Boundary => draw_cells(
-                        &mut sim.grid,
-                        Cell::Boundary(BoundaryCell::NoSlip),
-                        m_x,
-                        m_y,
-                    ),
-                    MouseState::Fluid => draw_cells(&mut sim.grid, Cell::Fluid, m_x, m_y),
-                    _ => {}
+                if matches!(
+                    ui_state.mouse_state,
+                    MouseState::Boundary
+                        | MouseState::Fluid
+                        | MouseState::Inflow
+                        | MouseState::Outflow
+                ) {
+                    // Snapshots don't include the cell types.
+                    ui_state.history.clear();
+                    // The whole drag is one edit, finished when the button
+                    // is released.
+                    ui_state.edits.begin_edit(&sim.grid);
+                }
+                let cell_type = match ui_state.mouse_state {
+                    MouseState::Boundary => Some(Cell::Boundary(BoundaryCell::NoSlip)),
+                    MouseState::Fluid => Some(Cell::Fluid),
+                    MouseState::Inflow => Some(Cell::Boundary(BoundaryCell::Inflow {
+                        velocity: ui_state.inflow_velocity,
+                    })),
+                    MouseState::Outflow => Some(Cell::Boundary(BoundaryCell::Outflow)),
+                    _ => None,
+                };
+                if let Some(cell_type) = cell_type {
+                    let drawn = draw_cells(&mut sim.grid, cell_type, m_x, m_y);
+                    if drawn.is_ok() {
+                        ui_state.isolated_regions = None;
+                    }
+                    show_invalid_cells(&mut ui_state, drawn);
                 }
             }
         }
+        // Shape tools preview the shape while dragging and draw it when the
+        // button is released.
+        if let Some(anchor) = ui_state.drag_anchor {
+            let cursor = view.nearest_cell(mouse_x, mouse_y);
+            if let Some(shape) = ui_state.mouse_state.drag_shape(anchor, cursor) {
+                let cells = shape.cells(sim.size);
+                if is_mouse_button_released(MouseButton::Left) {
+                    let cell_type = if erase {
+                        Cell::Fluid
+                    } else {
+                        Cell::Boundary(BoundaryCell::NoSlip)
+                    };
+                    ui_state.edits.begin_edit(&sim.grid);
+                    let stamped = stamp_cells(&mut sim.grid, cell_type, cells);
+                    ui_state.edits.end_edit(&sim.grid);
+                    match &stamped {
+                        Ok(()) => ui_state.isolated_regions = None,
+                        Err(err) => warn!("Can't draw the shape: {}", err),
+                    }
+                    show_invalid_cells(&mut ui_state, stamped);
+                    ui_state.history.clear();
+                } else {
+                    draw_shape_preview(&cells, &view, erase);
+                }
+            }
+            if !is_mouse_button_down(MouseButton::Left) {
+                ui_state.drag_anchor = None;
+            }
+        }
+        if !is_mouse_button_down(MouseButton::Left) {
+            ui_state.edits.end_edit(&sim.grid);
+        }
         draw_text(
             &format!(
-                "time: {:.2?}, iter: {:?}, speedup: {:?}",
-                sim.time, sim.iterations, ui_state.speed_multiplier
+                "time: {:.2?}, iter: {:?}, speed: {:.2} of {} sim s/s",
+                sim.time,
+                sim.iterations,
+                ui_state.performance.simulation_rate(),
+                ui_state.scheduler.rate
             )
             .to_string(),
             20.0,
-            (h * scaling) as f32 + 65.0,
+            hud_y + 65.0,
             30.0,
             DARKGREEN,
         );
+        draw_text(
+            &format!(
+                "SOR iter: {:?}, residual {}, net mass flux {:.1e}",
+                stats.sor_iterations,
+                stats.residual,
+                sim.net_mass_flux()
+            ),
+            20.0,
+            hud_y + 95.0,
+            30.0,
+            DARKGREEN,
+        );
+        let tick_timings = ui_state.performance.average_tick_timings();
+        let performance_text = format!(
+            "fps: {:.0}, ticks/s: {:.0}, ticks/frame: {:.1}, tick: {:.2?}, ",
+            ui_state.performance.frames_per_second(),
+            ui_state.performance.ticks_per_second(),
+            ui_state.performance.ticks_per_frame(),
+            tick_timings.total
+        );
+        let performance_dimensions =
+            draw_text(&performance_text, 20.0, hud_y + 125.0, 30.0, DARKGREEN);
+        // The simulation becomes unstable above a CFL number of 1.
+        draw_text(
+            &format!("CFL: {:.2}", stats.cfl),
+            20.0 + performance_dimensions.width,
+            hud_y + 125.0,
+            30.0,
+            if stats.cfl > 1.0 { RED } else { DARKGREEN },
+        );
+        draw_probe_plots(
+            &ui_state.probes,
+            Rect::new(20.0, hud_y + HUD_HEIGHT, 400.0, 160.0),
+        );
+        draw_phase_bar(
+            &tick_timings,
+            Rect::new(440.0, hud_y + HUD_HEIGHT, 560.0, 40.0),
+        );
+        if ui_state.show_help {
+            draw_help_overlay(&KEY_BINDINGS, 20.0, 20.0);
+        }
 
         next_frame().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::presets;
+    use clap::Parser;
+
+    #[test]
+    fn stamp_inflow_and_outflow() {
+        let size = [8, 6];
+        let velocity = [1.5, 0.5];
+        let inflow = Cell::Boundary(BoundaryCell::Inflow { velocity });
+        let mut grid = presets::empty(size);
+        grid.u.fill(0.25);
+        grid.v.fill(0.25);
+
+        stamp_cells(&mut grid, inflow, (1..size[1] - 1).map(|y| (0, y))).unwrap();
+        for y in 1..size[1] - 1 {
+            assert_eq!(grid.cell_type[(0, y)], inflow);
+        }
+        grid.set_boundary_u_and_v(0.005, [0.1, 0.2]).unwrap();
+        for y in 1..size[1] - 1 {
+            assert_eq!(grid.u[(0, y)], velocity[0]);
+        }
+        // The tangential velocity averages to the inflow's between the corners.
+        for y in 2..size[1] - 2 {
+            assert_eq!((grid.v[(0, y)] + grid.v[(1, y)]) / 2.0, velocity[1]);
+        }
+
+        // Outflow can go right next to inflow.
+        let outflow = Cell::Boundary(BoundaryCell::Outflow);
+        draw_cells(&mut grid, outflow, 0, 3).unwrap();
+        assert_eq!(grid.cell_type[(0, 3)], outflow);
+        assert_eq!(grid.cell_type[(1, 4)], outflow);
+        assert_eq!(grid.cell_type[(0, 2)], inflow);
+
+        // A row of inflow cells one cell thick has fluid above and below, so
+        // it's rejected.
+        let before = grid.cell_type.clone();
+        let Err(SimulationGridError::InvalidBoundaries(invalid)) =
+            stamp_cells(&mut grid, inflow, (3..=5).map(|x| (x, 2)))
+        else {
+            panic!("the thin row of inflow cells wasn't rejected");
+        };
+        let invalid: Vec<GridIndex> = invalid.into_iter().map(|(idx, _)| idx).collect();
+        assert_eq!(invalid, vec![(3, 2), (4, 2), (5, 2)]);
+        assert_eq!(grid.cell_type, before);
+        // The outer ring never becomes fluid.
+        draw_cells(&mut grid, Cell::Fluid, 0, 1).unwrap();
+        assert_eq!(grid.cell_type[(0, 1)], inflow);
+    }
+
+    #[test]
+    fn draw_cells_at_corners() {
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        let inflow = Cell::Boundary(BoundaryCell::Inflow {
+            velocity: [1.0, 0.0],
+        });
+        // A grid with walls all around and fluid inside, if there's room
+        let walled = |size: crate::types::GridSize| {
+            let [w, h] = size;
+            let mut grid = presets::empty(size);
+            let ring = (0..w)
+                .flat_map(|x| [(x, 0), (x, h - 1)])
+                .chain((0..h).flat_map(|y| [(0, y), (w - 1, y)]));
+            grid.set_cells(ring, wall).unwrap();
+            grid
+        };
+        for size in [[2, 2], [3, 3]] {
+            let [w, h] = size;
+            let corners = [(0, 0), (w - 1, 0), (0, h - 1), (w - 1, h - 1)];
+            for (x, y) in corners.into_iter().chain([(usize::MAX, usize::MAX)]) {
+                for cell_type in [wall, inflow, Cell::Fluid] {
+                    let mut grid = walled(size);
+                    let before = grid.cell_type.clone();
+                    let _ = draw_cells(&mut grid, cell_type, x, y);
+                    for ((cx, cy), &cell) in grid.cell_type.indexed_iter() {
+                        let outer = cx == 0 || cy == 0 || cx == w - 1 || cy == h - 1;
+                        if outer {
+                            assert_ne!(cell, Cell::Fluid);
+                        }
+                        let in_square = (x..=x.saturating_add(1)).contains(&cx)
+                            && (y..=y.saturating_add(1)).contains(&cy);
+                        if !in_square {
+                            assert_eq!(cell, before[(cx, cy)]);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The far corner of a 3x3 grid only covers the corner cell.
+        let mut grid = walled([3, 3]);
+        draw_cells(&mut grid, inflow, 2, 2).unwrap();
+        assert_eq!(grid.cell_type[(2, 2)], inflow);
+        assert_eq!(grid.cell_type[(1, 1)], Cell::Fluid);
+    }
+
+    #[test]
+    fn channel_preset() {
+        let preset = Preset::try_from(2).unwrap();
+        assert_eq!(preset, Preset::Channel);
+        assert_eq!(Preset::VARIANTS[2], "Channel");
+        assert_eq!(Preset::try_from(3).unwrap(), Preset::Jet);
+        // Every preset in the menu can be picked.
+        let last = Preset::VARIANTS.len() - 1;
+        assert_eq!(Preset::try_from(last).unwrap(), Preset::Airfoil);
+        assert!(Preset::try_from(last + 1).is_err());
+
+        let args = Args::parse_from(["stroemung", "--x-cells", "20", "--y-cells", "8"]);
+        let mut sim = get_sim(&args, preset).unwrap();
+        let [w, h] = sim.size;
+        for x in 0..w {
+            assert_eq!(
+                sim.grid.cell_type[(x, 0)],
+                Cell::Boundary(BoundaryCell::NoSlip)
+            );
+            assert_eq!(
+                sim.grid.cell_type[(x, h - 1)],
+                Cell::Boundary(BoundaryCell::NoSlip)
+            );
+        }
+        for y in 1..(h - 1) {
+            assert_eq!(
+                sim.grid.cell_type[(0, y)],
+                Cell::Boundary(BoundaryCell::Inflow {
+                    velocity: [1.0, 0.0]
+                })
+            );
+            assert_eq!(
+                sim.grid.cell_type[(w - 1, y)],
+                Cell::Boundary(BoundaryCell::Outflow)
+            );
+            for x in 1..(w - 1) {
+                assert_eq!(sim.grid.cell_type[(x, y)], Cell::Fluid);
+            }
+        }
+
+        for _ in 0..20 {
+            sim.run_simulation_tick().unwrap();
+        }
+    }
+
+    #[test]
+    fn preset_too_small() {
+        // The cylinders need 14 cells down and the airfoil 67 across.
+        let args = Args::parse_from(["stroemung", "--x-cells", "60", "--y-cells", "13"]);
+        assert!(get_sim(&args, Preset::Obstacle).is_ok());
+        assert!(get_sim(&args, Preset::Cylinders).is_err());
+        assert!(get_sim(&args, Preset::Airfoil).is_err());
+    }
+
+    #[test]
+    fn save_and_load_edited_grid() {
+        let args = Args::parse_from(["stroemung", "--x-cells", "40", "--y-cells", "16"]);
+        let mut sim = get_sim(&args, Preset::Channel).unwrap();
+        draw_cells(&mut sim.grid, Cell::Boundary(BoundaryCell::NoSlip), 12, 7).unwrap();
+        stamp_cells(
+            &mut sim.grid,
+            Cell::Boundary(BoundaryCell::Outflow),
+            (5..9).map(|x| (x, 0)),
+        )
+        .unwrap();
+        assert_eq!(
+            sim.grid.cell_type[(12, 7)],
+            Cell::Boundary(BoundaryCell::NoSlip)
+        );
+        assert_eq!(
+            sim.grid.cell_type[(5, 0)],
+            Cell::Boundary(BoundaryCell::Outflow)
+        );
+        for _ in 0..3 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        for extension in ["json", "msgpack"] {
+            let path = std::env::temp_dir().join(format!(
+                "stroemung-edited-grid-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            save_sim(&sim, &path).unwrap();
+            let loaded = load_sim(&path, false).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(loaded.grid.cell_type, sim.grid.cell_type);
+            assert_eq!(
+                loaded.grid.boundaries.sorted_boundary_list,
+                sim.grid.boundaries.sorted_boundary_list
+            );
+            assert_eq!(loaded.iterations, 3);
+        }
+        assert!(load_sim(Path::new("does_not_exist.json"), false).is_err());
+    }
+
+    #[cfg(feature = "progress")]
+    #[test]
+    fn headless_progress() {
+        for quiet in [None, Some("--quiet")] {
+            let args = Args::parse_from(
+                [
+                    "stroemung",
+                    "--headless",
+                    "--ticks",
+                    "5",
+                    "--x-cells",
+                    "40",
+                    "--y-cells",
+                    "16",
+                    "--timing-interval",
+                    "2",
+                ]
+                .into_iter()
+                .chain(quiet),
+            );
+            run_headless(&args).unwrap();
+        }
+    }
+
+    #[test]
+    fn outer_wall_types() {
+        let args = Args::parse_from([
+            "stroemung",
+            "--x-cells",
+            "40",
+            "--y-cells",
+            "16",
+            "--top-wall",
+            "outflow",
+            "--bottom-wall",
+            "freeslip",
+        ]);
+        for preset in [Preset::Channel, Preset::Obstacle] {
+            let mut sim = get_sim(&args, preset).unwrap();
+            let [w, h] = sim.size;
+            for x in 0..w {
+                assert_eq!(
+                    sim.grid.cell_type[(x, 0)],
+                    Cell::Boundary(BoundaryCell::Outflow)
+                );
+                assert_eq!(
+                    sim.grid.cell_type[(x, h - 1)],
+                    Cell::Boundary(BoundaryCell::Symmetry)
+                );
+            }
+            for _ in 0..20 {
+                sim.run_simulation_tick().unwrap();
+            }
+            assert!(sim.grid.u.iter().chain(&sim.grid.v).all(|x| x.is_finite()));
+        }
+    }
+}
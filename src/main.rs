@@ -1,8 +1,96 @@
+#[cfg(target_arch = "wasm32")]
 use clap::Parser;
+#[cfg(not(target_arch = "wasm32"))]
+use clap::{error::ErrorKind, ArgMatches, CommandFactory, FromArgMatches};
+#[cfg(not(target_arch = "wasm32"))]
+use log::LevelFilter;
+use stroemung::args::Args;
+#[cfg(not(target_arch = "wasm32"))]
+use stroemung::args::{Cli, Command, InputKind};
+#[cfg(not(target_arch = "wasm32"))]
+use stroemung::commands;
+#[cfg(not(target_arch = "wasm32"))]
+use stroemung::config::{apply_config_file, Config};
 use stroemung::window_conf;
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let args = stroemung::args::Args::parse();
-    stroemung::run(args).await;
+#[cfg(not(target_arch = "wasm32"))]
+fn init_logger(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn exit_on_error<E: std::fmt::Display>(result: Result<(), E>) {
+    if let Err(err) = result {
+        log::error!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+// Run the simulation in a window, or without one for `simulate` and
+// `--headless`
+#[cfg(not(target_arch = "wasm32"))]
+fn run(args: Args, matches: &ArgMatches, headless: bool) {
+    let args = apply_config_file(args, matches)
+        .unwrap_or_else(|err| Cli::command().error(ErrorKind::Io, err).exit());
+    if let Err(err) = args.validate() {
+        Cli::command().error(ErrorKind::ValueValidation, err).exit();
+    }
+    if args.dump_config {
+        print!("{}", Config::from_args(&args).to_toml());
+        return;
+    }
+    init_logger(args.log_level());
+    if args.sim_file.is_none() {
+        for warning in args.simulation.stability_warnings() {
+            log::warn!("{}", warning);
+        }
+    }
+    if headless || args.headless {
+        exit_on_error(stroemung::run_headless(&args));
+    } else {
+        macroquad::Window::from_config(window_conf(&args), stroemung::run(args));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    // The matches of the subcommand, which say which flags were given
+    let command_matches = matches
+        .subcommand()
+        .map_or(&matches, |(_, matches)| matches);
+    match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => run(args, command_matches, false),
+        Command::Simulate(args) => run(args, command_matches, true),
+        Command::Info(args) => {
+            init_logger(LevelFilter::Info);
+            exit_on_error(commands::info(&args).map(|info| print!("{}", info)));
+        }
+        Command::Convert(args) => {
+            // The simulation settings are only used for grids.
+            if args.from == InputKind::Grid {
+                if let Err(err) = args.simulation.validate() {
+                    Cli::command().error(ErrorKind::ValueValidation, err).exit();
+                }
+            }
+            init_logger(LevelFilter::Info);
+            exit_on_error(commands::convert(&args));
+        }
+        Command::Render(args) => {
+            init_logger(LevelFilter::Info);
+            exit_on_error(commands::render(&args));
+        }
+    }
+}
+
+// There are no command-line arguments in the browser, so it always runs with
+// the defaults.
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    let args = Args::parse_from(["stroemung"]);
+    macroquad::Window::from_config(window_conf(&args), stroemung::run(args));
 }
@@ -1,6 +1,60 @@
 use ndarray::ArrayView2;
 
+/// The floating point type of the simulation: `f64`, or `f32` with the
+/// `single-precision` feature
+#[cfg(not(feature = "single-precision"))]
 pub type Real = f64;
+#[cfg(feature = "single-precision")]
+pub type Real = f32;
+
+/// Mathematical constants of type `Real`
+#[cfg(feature = "single-precision")]
+pub use std::f32::consts;
+/// Mathematical constants of type `Real`
+#[cfg(not(feature = "single-precision"))]
+pub use std::f64::consts;
+
+/// The relative error tests allow between results that would be equal with
+/// exact arithmetic
+#[cfg(test)]
+pub(crate) const TEST_TOLERANCE: Real = if cfg!(feature = "single-precision") {
+    1e-5
+} else {
+    1e-12
+};
+
+/// Assert that two `Real`s are equal to within `TEST_TOLERANCE` of the larger
+/// one, or of 1 for values smaller than that
+#[cfg(test)]
+macro_rules! assert_close {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::math::assert_close!($left, $right, $crate::math::TEST_TOLERANCE)
+    };
+    ($left:expr, $right:expr, $tolerance:expr $(,)?) => {{
+        let (left, right): ($crate::math::Real, $crate::math::Real) = ($left, $right);
+        let scale = left.abs().max(right.abs()).max(1.0);
+        assert!(
+            (left - right).abs() <= $tolerance * scale,
+            "{} != {} to within {}",
+            left,
+            right,
+            $tolerance
+        );
+    }};
+}
+#[cfg(test)]
+pub(crate) use assert_close;
+
+/// Keep separate insta snapshots of the results for single precision, which
+/// differ in the last digits, until the returned guard is dropped
+#[cfg(test)]
+pub(crate) fn precision_snapshots() -> insta::internals::SettingsBindDropGuard {
+    let mut settings = insta::Settings::clone_current();
+    if cfg!(feature = "single-precision") {
+        settings.set_snapshot_suffix("single_precision");
+    }
+    settings.bind_to_scope()
+}
 
 /// Calculate du^2/dx (the derivative of u^2 over x)
 ///
@@ -151,6 +205,9 @@ pub fn dv2dy(v_view: ArrayView2<Real>, dely: Real, gamma: Real) -> Real {
 
 /// Calculate the discrete Laplacian using a five-point stencil
 ///
+/// This is `(e_e - 2e + e_w) / delx^2 + (e_n - 2e + e_s) / dely^2`, so the
+/// cells don't have to be square.
+///
 /// # Arguments
 ///
 /// * `view` - A 3x3-element ArrayView2 representing the stencil of the array
@@ -158,7 +215,7 @@ pub fn dv2dy(v_view: ArrayView2<Real>, dely: Real, gamma: Real) -> Real {
 ///   view would represent u[(i-1) to (i+1), (j-1) to (j+1)] or
 ///   v[(i-1) to (i+1), (j-1) to (j+1)] respectively.
 /// * `delx` - "delta x," the physical width of the cell
-/// * `dely` - "delta y," the physical width of the cell
+/// * `dely` - "delta y," the physical height of the cell
 pub fn laplacian(view: ArrayView2<Real>, delx: Real, dely: Real) -> Real {
     // Since the view could be of u or v, we use "e" here to denote "element."
     let e_i_j = view[(1, 1)]; // e[(i, j)] -> e_i_j
@@ -173,6 +230,21 @@ pub fn laplacian(view: ArrayView2<Real>, delx: Real, dely: Real) -> Real {
     d2edx2 + d2edy2
 }
 
+/// Calculate the residual of the discretized Poisson pressure equation
+///
+/// The residual is the discrete Laplacian of the pressure minus the
+/// right-hand side of the pressure equation. It is zero when the pressure
+/// exactly satisfies the equation at this cell.
+///
+/// # Arguments
+///
+/// * `p_view` - A 3x3-element ArrayView2 representing
+///   p[(i-1) to (i+1), (j-1) to (j+1)]. This function only uses the five
+///   values of the stencil centered on index (1, 1), but takes a 3x3
+///   ArrayView2 to be easier to combine with other functions.
+/// * `delx` - "delta x," the physical width of the cell
+/// * `dely` - "delta y," the physical width of the cell
+/// * `rhs` - The right-hand side of the pressure equation at (i, j)
 pub fn residual(p_view: ArrayView2<Real>, delx: Real, dely: Real, rhs: Real) -> Real {
     let p_i_p1_j = p_view[(2, 1)];
     let p_i_j = p_view[(1, 1)];
@@ -184,10 +256,13 @@ pub fn residual(p_view: ArrayView2<Real>, delx: Real, dely: Real, rhs: Real) ->
     let part2 = ((p_i_j_p1 - p_i_j) - (p_i_j - p_i_j_m1)) / dely.powi(2);
     part1 + part2 - rhs
 }
+
 #[cfg(test)]
+// The expected values are written with the precision of f64.
+#[cfg_attr(feature = "single-precision", allow(clippy::excessive_precision))]
 mod tests {
     use super::*;
-    use ndarray::{array, ArrayView2};
+    use ndarray::{array, Array, ArrayView2};
 
     #[test]
     fn test_du2dx() {
@@ -231,7 +306,7 @@ mod tests {
             ),
         ];
         for (u, delx, gamma, expected) in test_cases {
-            assert_eq!(du2dx(ArrayView2::from(&u), delx, gamma), expected);
+            assert_close!(du2dx(ArrayView2::from(&u), delx, gamma), expected);
         }
     }
 
@@ -269,7 +344,7 @@ mod tests {
             ),
         ];
         for (u, v, delx, gamma, expected) in test_cases {
-            assert_eq!(
+            assert_close!(
                 duvdx(ArrayView2::from(&u), ArrayView2::from(&v), delx, gamma),
                 expected
             );
@@ -310,7 +385,7 @@ mod tests {
             ),
         ];
         for (u, v, dely, gamma, expected) in test_cases {
-            assert_eq!(
+            assert_close!(
                 duvdy(ArrayView2::from(&u), ArrayView2::from(&v), dely, gamma),
                 expected
             );
@@ -359,7 +434,7 @@ mod tests {
             ),
         ];
         for (v, dely, gamma, expected) in test_cases {
-            assert_eq!(dv2dy(ArrayView2::from(&v), dely, gamma), expected);
+            assert_close!(dv2dy(ArrayView2::from(&v), dely, gamma), expected);
         }
     }
 
@@ -397,7 +472,62 @@ mod tests {
             ),
         ];
         for (e, delx, dely, expected) in test_cases {
-            assert_eq!(laplacian(ArrayView2::from(&e), delx, dely), expected);
+            assert_close!(laplacian(ArrayView2::from(&e), delx, dely), expected);
+        }
+    }
+
+    #[test]
+    fn laplacian_stretched_cells() {
+        // e = x^2 + y^2 sampled on cells 0.1 wide and 0.4 high, so both second
+        // derivatives are 2.
+        let (delx, dely) = (0.1, 0.4);
+        let e = Array::from_shape_fn((3, 3), |(i, j)| {
+            let (x, y) = (i as Real * delx, j as Real * dely);
+            x.powi(2) + y.powi(2)
+        });
+        assert_close!(laplacian(e.view(), delx, dely), 4.0, 1e-4);
+        // Using delx in both directions, as if the cells were square, would
+        // scale up the y term by (dely / delx)^2.
+        let square = laplacian(e.view(), delx, delx);
+        assert_close!(square, 2.0 + 2.0 * (dely / delx).powi(2), 1e-4);
+        assert!((square - 4.0).abs() > 1.0);
+    }
+
+    #[test]
+    fn test_residual() {
+        // These don't have any particular significance, just some random data.
+        let test_cases = [
+            (
+                array![[1., 4., 1.], [1., 5., 3.], [2., 1., 1.]],
+                1.,
+                1.,
+                0.,
+                -11.,
+            ),
+            (
+                array![[1., 4., 1.], [1., 5., 3.], [2., 1., 1.]],
+                1.,
+                1.,
+                2.,
+                -13.,
+            ),
+            (
+                array![[1., 4., 1.], [1., 5., 3.], [2., 1., 1.]],
+                2.,
+                1.,
+                0.5,
+                -7.75,
+            ),
+            (
+                array![[2., 2., 2.], [2., 2., 2.], [2., 2., 2.]],
+                1.5,
+                0.7,
+                3.,
+                -3.,
+            ),
+        ];
+        for (p, delx, dely, rhs, expected) in test_cases {
+            assert_close!(residual(ArrayView2::from(&p), delx, dely, rhs), expected);
         }
     }
 }
@@ -0,0 +1,130 @@
+use crate::cell::Cell;
+use crate::grid::SimulationGrid;
+use crate::math::Real;
+use crate::probe::sample_velocity;
+use crate::simulation::Simulation;
+use crate::types::GridIndex;
+
+/// Massless particles that are carried along by the flow, to visualize it
+///
+/// Positions are in cells, with (0, 0) at the top left corner of the grid, like
+/// in `probe::sample_velocity`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tracer {
+    pub positions: Vec<(Real, Real)>,
+}
+
+impl Tracer {
+    /// Add a particle at the center of every fluid cell in `region`
+    ///
+    /// # Arguments
+    ///
+    /// * `grid` - The grid to seed the particles in
+    /// * `region` - Opposite corners of the region, inclusive. The parts of
+    ///   the region outside the grid are ignored.
+    pub fn seed(&mut self, grid: &SimulationGrid, region: (GridIndex, GridIndex)) {
+        let ((x0, y0), (x1, y1)) = region;
+        let x_end = x0.max(x1).min(grid.size[0] - 1);
+        let y_end = y0.max(y1).min(grid.size[1] - 1);
+        for x in x0.min(x1)..=x_end {
+            for y in y0.min(y1)..=y_end {
+                if grid.cell_type[(x, y)] == Cell::Fluid {
+                    self.positions.push((x as Real + 0.5, y as Real + 0.5));
+                }
+            }
+        }
+    }
+
+    /// Move every particle along the flow for `dt` with the midpoint method,
+    /// a second order Runge-Kutta scheme
+    ///
+    /// Particles that leave the grid or enter a boundary cell are removed.
+    pub fn advance(&mut self, simulation: &Simulation, dt: Real) {
+        let grid = &simulation.grid;
+        let [delx, dely] = simulation.cell_size();
+        // The velocity in cells per unit of time
+        let velocity = |position: (Real, Real)| {
+            let [u, v] = sample_velocity(grid, position);
+            (u / delx, v / dely)
+        };
+
+        self.positions.retain_mut(|position| {
+            let k1 = velocity(*position);
+            let midpoint = (position.0 + 0.5 * dt * k1.0, position.1 + 0.5 * dt * k1.1);
+            let k2 = velocity(midpoint);
+            *position = (position.0 + dt * k2.0, position.1 + dt * k2.1);
+            in_fluid(grid, *position)
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Remove all of the particles
+    pub fn clear(&mut self) {
+        self.positions.clear();
+    }
+}
+
+// Whether `position` is inside a fluid cell of the grid. NaN positions aren't.
+fn in_fluid(grid: &SimulationGrid, (x, y): (Real, Real)) -> bool {
+    let inside =
+        x >= 0.0 && y >= 0.0 && x < grid.size[0] as Real && y < grid.size[1] as Real;
+    inside && grid.cell_type[(x as usize, y as usize)] == Cell::Fluid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::SimulationBuilder;
+    use crate::grid::presets::{self, Walls};
+
+    use crate::math::assert_close;
+
+    fn simulation(grid: SimulationGrid) -> Simulation {
+        SimulationBuilder::new().grid(grid).build().unwrap()
+    }
+
+    #[test]
+    fn uniform_flow() {
+        let mut grid = presets::empty([10, 8]);
+        grid.u.fill(1.0);
+        grid.v.fill(-0.5);
+        let sim = simulation(grid);
+
+        let mut tracer = Tracer::default();
+        tracer.seed(&sim.grid, ((2, 3), (2, 3)));
+        assert_eq!(tracer.positions, vec![(2.5, 3.5)]);
+        for step in 1..=5 {
+            tracer.advance(&sim, 0.01);
+            // 0.01 in x and -0.005 in y per step, in cells of 0.1 by 0.2
+            let (x, y) = tracer.positions[0];
+            assert_close!(x, 2.5 + 0.1 * step as Real);
+            assert_close!(y, 3.5 - 0.025 * step as Real);
+        }
+    }
+
+    #[test]
+    fn remove_particles_leaving_fluid() {
+        let size = [10, 6];
+        let mut grid = presets::simple_inflow(size, [1.0, 0.0], Walls::default());
+        grid.v.fill(-2.0);
+        let sim = simulation(grid);
+
+        let mut tracer = Tracer::default();
+        // Boundary cells and cells outside the grid aren't seeded.
+        tracer.seed(&sim.grid, ((3, 0), (4, 20)));
+        assert_eq!(tracer.len(), 2 * (size[1] - 2));
+        // Moving up by a cell pushes the top row into the wall.
+        tracer.advance(&sim, 0.1);
+        assert_eq!(tracer.len(), 2 * (size[1] - 3));
+        assert!(tracer.positions.iter().all(|&(_, y)| y > 1.0));
+        tracer.clear();
+        assert!(tracer.is_empty());
+    }
+}
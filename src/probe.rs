@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use thiserror::Error;
+
+use crate::grid::SimulationGrid;
+use crate::math::Real;
+use crate::simulation::Simulation;
+use crate::types::{GridArray, GridIndex, GridSize, Velocity};
+
+/// The most probes a `ProbeSet` can hold
+pub const MAX_PROBES: usize = 8;
+
+/// The number of samples each probe keeps by default
+pub const DEFAULT_HISTORY_LENGTH: usize = 1000;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ProbeError {
+    #[error("Can't add more than {MAX_PROBES} probes.")]
+    TooManyProbes,
+    #[error("Probe location `{0:?}` is outside of the `{1:?}` grid.")]
+    OutOfBounds(GridIndex, GridSize),
+}
+
+/// The values of a cell at one point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeSample {
+    pub time: Real,
+    pub pressure: Real,
+    pub speed: Real,
+}
+
+/// A cell whose values are recorded every tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    pub location: GridIndex,
+    /// The most recent samples, oldest first
+    pub history: VecDeque<ProbeSample>,
+}
+
+/// A set of probes that record the values of cells over time
+///
+/// Each probe keeps a bounded history, dropping the oldest samples once it's
+/// full.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeSet {
+    probes: Vec<Probe>,
+    history_length: usize,
+}
+
+impl Default for ProbeSet {
+    fn default() -> Self {
+        ProbeSet::new(DEFAULT_HISTORY_LENGTH)
+    }
+}
+
+impl ProbeSet {
+    /// Create an empty set of probes that keep `history_length` samples each
+    pub fn new(history_length: usize) -> Self {
+        ProbeSet {
+            probes: Vec::with_capacity(MAX_PROBES),
+            history_length,
+        }
+    }
+
+    pub fn probes(&self) -> &[Probe] {
+        &self.probes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    /// Add a probe at `location`, unless there already is one there
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - The cell to record
+    /// * `size` - The size of the grid, to check that `location` is inside it
+    pub fn add(&mut self, location: GridIndex, size: GridSize) -> Result<(), ProbeError> {
+        if location.0 >= size[0] || location.1 >= size[1] {
+            return Err(ProbeError::OutOfBounds(location, size));
+        }
+        if self.probes.iter().any(|probe| probe.location == location) {
+            return Ok(());
+        }
+        if self.probes.len() >= MAX_PROBES {
+            return Err(ProbeError::TooManyProbes);
+        }
+        self.probes.push(Probe {
+            location,
+            history: VecDeque::with_capacity(self.history_length),
+        });
+        Ok(())
+    }
+
+    /// Remove all of the probes
+    pub fn clear(&mut self) {
+        self.probes.clear();
+    }
+
+    /// Record the current values of every probed cell
+    pub fn record(&mut self, simulation: &Simulation) {
+        for probe in &mut self.probes {
+            if probe.history.len() >= self.history_length {
+                probe.history.pop_front();
+            }
+            let grid = &simulation.grid;
+            probe.history.push_back(ProbeSample {
+                time: simulation.time,
+                pressure: grid.pressure[probe.location],
+                speed: (grid.u[probe.location].powi(2) + grid.v[probe.location].powi(2))
+                    .sqrt(),
+            });
+        }
+    }
+
+    /// Write the history of every probe as CSV, one row per sample
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "probe,x,y,time,pressure,speed")?;
+        for (index, probe) in self.probes.iter().enumerate() {
+            for sample in &probe.history {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{}",
+                    index,
+                    probe.location.0,
+                    probe.location.1,
+                    sample.time,
+                    sample.pressure,
+                    sample.speed
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The velocity at `position`, interpolated bilinearly from the faces around it
+///
+/// `position` is in cells, with (0, 0) at the top left corner of the grid, so
+/// the center of cell (i, j) is at (i + 0.5, j + 0.5). Positions outside the
+/// grid get the velocities at its edge.
+pub fn sample_velocity(grid: &SimulationGrid, position: (Real, Real)) -> Velocity {
+    // u[(i, j)] is on the east face of cell (i, j), at (i + 1, j + 0.5), and
+    // v[(i, j)] is on its south face, at (i + 0.5, j + 1).
+    [
+        interpolate(&grid.u, position.0 - 1.0, position.1 - 0.5),
+        interpolate(&grid.v, position.0 - 0.5, position.1 - 1.0),
+    ]
+}
+
+// Bilinearly interpolate `values` at the fractional index (`x`, `y`), clamped
+// to the array.
+fn interpolate(values: &GridArray<Real>, x: Real, y: Real) -> Real {
+    let (x_len, y_len) = values.dim();
+    // The lower index along an axis and how far past it `coordinate` is
+    let lower = |coordinate: Real, len: usize| {
+        let coordinate = coordinate.clamp(0.0, (len - 1) as Real);
+        let index = (coordinate.floor() as usize).min(len.saturating_sub(2));
+        (index, coordinate - index as Real)
+    };
+    let (i, fx) = lower(x, x_len);
+    let (j, fy) = lower(y, y_len);
+    let (i1, j1) = ((i + 1).min(x_len - 1), (j + 1).min(y_len - 1));
+
+    (1.0 - fx) * (1.0 - fy) * values[(i, j)]
+        + fx * (1.0 - fy) * values[(i1, j)]
+        + (1.0 - fx) * fy * values[(i, j1)]
+        + fx * fy * values[(i1, j1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::SimulationBuilder;
+    use crate::grid::presets::{self, Walls};
+
+    use ndarray::Array;
+
+    #[test]
+    fn add_probes() {
+        let size = [10, 4];
+        let mut probes = ProbeSet::default();
+        for x in 0..MAX_PROBES {
+            probes.add((x, 1), size).unwrap();
+        }
+        // Probing the same cell twice is ignored.
+        probes.add((0, 1), size).unwrap();
+        assert_eq!(probes.probes().len(), MAX_PROBES);
+
+        assert_eq!(probes.add((0, 2), size), Err(ProbeError::TooManyProbes));
+        probes.clear();
+        assert!(probes.is_empty());
+        assert_eq!(
+            probes.add((10, 2), size),
+            Err(ProbeError::OutOfBounds((10, 2), size))
+        );
+    }
+
+    #[test]
+    fn record_history() {
+        let size = [6, 5];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .build()
+            .unwrap();
+
+        let mut probes = ProbeSet::new(3);
+        probes.add((2, 2), size).unwrap();
+        probes.add((4, 1), size).unwrap();
+        sim.grid.u[(2, 2)] = 3.0;
+        sim.grid.v[(2, 2)] = 4.0;
+        sim.grid.pressure[(4, 1)] = 2.5;
+        probes.record(&sim);
+        assert_eq!(
+            probes.probes()[0].history[0],
+            ProbeSample {
+                time: 0.0,
+                pressure: 0.0,
+                speed: 5.0
+            }
+        );
+        assert_eq!(probes.probes()[1].history[0].pressure, 2.5);
+
+        // Only the last 3 samples are kept.
+        for _ in 0..4 {
+            sim.run_simulation_tick().unwrap();
+            probes.record(&sim);
+        }
+        let history = &probes.probes()[0].history;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].time, sim.time);
+        assert!(history[0].time < history[1].time);
+
+        let mut csv = Vec::new();
+        probes.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + 2 * 3);
+        assert_eq!(lines[0], "probe,x,y,time,pressure,speed");
+        assert!(lines[4].starts_with("1,4,1,"));
+    }
+
+    #[test]
+    fn sample_staggered_velocity() {
+        let size = [6, 5];
+        let mut grid = presets::empty(size);
+        // Linear fields are reproduced exactly.
+        grid.u = Array::from_shape_fn(size, |(i, j)| 2.0 * i as Real + j as Real);
+        grid.v = Array::from_shape_fn(size, |(i, j)| i as Real - 3.0 * j as Real);
+
+        // The east face of cell (2, 1) and the south face of cell (3, 2)
+        assert_eq!(sample_velocity(&grid, (3.0, 1.5))[0], grid.u[(2, 1)]);
+        assert_eq!(sample_velocity(&grid, (3.5, 3.0))[1], grid.v[(3, 2)]);
+        // Halfway between faces
+        let [u, v] = sample_velocity(&grid, (2.25, 2.0));
+        assert!((u - (2.0 * 1.25 + 1.5)).abs() < 1e-12);
+        assert!((v - (1.75 - 3.0)).abs() < 1e-12);
+        // Outside the grid, the edge values are used.
+        assert_eq!(
+            sample_velocity(&grid, (-4.0, -4.0)),
+            [grid.u[(0, 0)], grid.v[(0, 0)]]
+        );
+    }
+}
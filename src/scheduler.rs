@@ -0,0 +1,230 @@
+use crate::math::Real;
+use crate::time::Instant;
+
+// The slowest rate `TickScheduler::slower` goes down to
+const MIN_RATE: Real = 1.0 / 64.0;
+// At most this many wall-clock seconds of simulated time are carried over to
+// later frames. Without a limit, a grid that is too large to keep up would
+// build up a backlog that keeps it running flat out long after slowing down.
+const MAX_CARRY: f64 = 0.25;
+
+/// A source of wall-clock time
+pub trait Clock {
+    /// Seconds since an arbitrary starting point
+    fn now(&self) -> f64;
+}
+
+/// The system's monotonic clock
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+}
+
+/// What `TickScheduler::run_frame` did in a frame
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameTicks {
+    pub ticks: u32,
+    /// The simulated time the ticks covered
+    pub simulated: Real,
+}
+
+/// Decides how many ticks to run each frame, so that the simulation advances
+/// at a steady rate of simulated time per wall-clock second, independent of
+/// the frame rate
+///
+/// Each frame runs ticks until the simulated time owed for the frame is
+/// covered or `budget` seconds of wall-clock time are used up, whichever
+/// comes first. Time that's still owed is carried over to the next frame, so
+/// the rate evens out as long as the simulation can keep up.
+#[derive(Debug)]
+pub struct TickScheduler<C: Clock = SystemClock> {
+    clock: C,
+    /// The target rate, in simulated seconds per wall-clock second
+    pub rate: Real,
+    /// Wall-clock seconds each frame may spend running ticks
+    pub budget: f64,
+    // Simulated seconds still owed from earlier frames. Negative if the last
+    // tick went past the target.
+    owed: Real,
+}
+
+impl Default for TickScheduler {
+    fn default() -> Self {
+        TickScheduler::new(SystemClock::default(), 4.0, 0.012)
+    }
+}
+
+impl<C: Clock> TickScheduler<C> {
+    pub fn new(clock: C, rate: Real, budget: f64) -> Self {
+        TickScheduler {
+            clock,
+            rate,
+            budget,
+            owed: 0.0,
+        }
+    }
+
+    /// Double the target rate
+    pub fn faster(&mut self) {
+        self.rate *= 2.0;
+    }
+
+    /// Halve the target rate, down to a minimum
+    pub fn slower(&mut self) {
+        self.rate = (self.rate / 2.0).max(MIN_RATE);
+    }
+
+    /// Forget the time owed from earlier frames, e.g. after pausing
+    pub fn reset(&mut self) {
+        self.owed = 0.0;
+    }
+
+    /// Run the ticks for a frame
+    ///
+    /// At least one tick runs if any simulated time is owed, even if it takes
+    /// longer than the whole budget, so the simulation always makes progress.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame_time` - Wall-clock seconds since the last frame
+    /// * `tick` - Runs one tick and returns the simulated time it covered, or
+    ///   `None` if the simulation can't go on. The rest of the frame is
+    ///   skipped in that case and nothing is carried over.
+    pub fn run_frame(
+        &mut self,
+        frame_time: f64,
+        mut tick: impl FnMut() -> Option<Real>,
+    ) -> FrameTicks {
+        self.owed += self.rate * frame_time as Real;
+        let start = self.clock.now();
+        let mut frame = FrameTicks::default();
+        while self.owed > 0.0 && self.clock.now() - start < self.budget {
+            let Some(simulated) = tick() else {
+                self.owed = 0.0;
+                break;
+            };
+            self.owed -= simulated;
+            frame.ticks += 1;
+            frame.simulated += simulated;
+        }
+        self.owed = self.owed.min(self.rate * MAX_CARRY as Real);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::assert_close;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // A clock that only moves when the test moves it
+    #[derive(Debug, Clone, Default)]
+    struct FakeClock(Rc<Cell<f64>>);
+
+    impl FakeClock {
+        fn advance(&self, seconds: f64) {
+            self.0.set(self.0.get() + seconds);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> f64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn meet_the_target_rate() {
+        let clock = FakeClock::default();
+        let mut scheduler = TickScheduler::new(clock.clone(), 0.5, 0.012);
+        // Ticks that cover 3/512 s and take 1 ms each. A frame of 1/64 s owes
+        // 4/512 s, so it takes 4/3 ticks on average. The powers of two keep
+        // the arithmetic exact.
+        let mut tick = || {
+            clock.advance(0.001);
+            Some(3.0 / 512.0)
+        };
+        let ticks: Vec<u32> = (0..6)
+            .map(|_| scheduler.run_frame(1.0 / 64.0, &mut tick).ticks)
+            .collect();
+        assert_eq!(ticks, vec![2, 1, 1, 2, 1, 1]);
+
+        scheduler.faster();
+        assert_eq!(scheduler.rate, 1.0);
+        assert_eq!(scheduler.run_frame(1.0 / 64.0, &mut tick).ticks, 3);
+    }
+
+    #[test]
+    fn carry_over_when_out_of_budget() {
+        let clock = FakeClock::default();
+        let mut scheduler = TickScheduler::new(clock.clone(), 2.0, 0.012);
+        // Each tick takes 5 ms of the 12 ms budget, so only 3 fit in a frame
+        // even though 0.1 s of frame time owes 40 ticks.
+        let mut tick = || {
+            clock.advance(0.005);
+            Some(0.005)
+        };
+        let frame = scheduler.run_frame(0.1, &mut tick);
+        assert_eq!(frame.ticks, 3);
+        assert_close!(frame.simulated, 0.015);
+        // The rest is made up over the next frames.
+        assert_eq!(scheduler.run_frame(0.0, &mut tick).ticks, 3);
+        assert_close!(scheduler.owed, 0.2 - 0.03);
+
+        // A long frame doesn't build up more than MAX_CARRY of backlog.
+        scheduler.run_frame(10.0, &mut tick);
+        assert_eq!(scheduler.owed, 2.0 * MAX_CARRY as Real);
+        scheduler.reset();
+        assert_eq!(scheduler.run_frame(0.0, &mut tick).ticks, 0);
+    }
+
+    #[test]
+    fn tick_longer_than_budget() {
+        let clock = FakeClock::default();
+        let mut scheduler = TickScheduler::new(clock.clone(), 1.0, 0.012);
+        let mut tick = || {
+            clock.advance(0.05);
+            Some(0.005)
+        };
+        // Each frame still runs one tick.
+        for _ in 0..3 {
+            assert_eq!(scheduler.run_frame(1.0 / 60.0, &mut tick).ticks, 1);
+        }
+    }
+
+    #[test]
+    fn stop_on_failed_tick() {
+        let clock = FakeClock::default();
+        let mut scheduler = TickScheduler::new(clock.clone(), 1.0, 0.012);
+        let mut ticks = 0;
+        let frame = scheduler.run_frame(0.1, || {
+            ticks += 1;
+            (ticks < 3).then_some(0.005)
+        });
+        assert_eq!(frame.ticks, 2);
+        assert_eq!(scheduler.owed, 0.0);
+
+        scheduler.slower();
+        assert_eq!(scheduler.rate, 0.5);
+        for _ in 0..20 {
+            scheduler.slower();
+        }
+        assert_eq!(scheduler.rate, MIN_RATE);
+    }
+}
@@ -1,10 +1,17 @@
+use std::collections::VecDeque;
 use std::fmt;
-use std::io::Read;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
 
-use crate::cell::Cell;
-use crate::math::Real;
+use crate::averaging::FieldAccumulator;
+use crate::cell::{BoundaryCell, Cell};
+use crate::math::{consts, Real};
 use crate::math::{du2dx, duvdx, duvdy, dv2dy, laplacian, residual};
 
+use log::{debug, info, warn};
+
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -13,18 +20,270 @@ use serde_json::Error as SerdeError;
 use thiserror::Error;
 
 use crate::grid::{
-    EdgeType, SimulationGrid, SimulationGridError, UnfinalizedSimulationGrid,
+    is_close, EdgeType, FieldDifference, SimulationGrid, SimulationGridError,
+    UnfinalizedSimulationGrid,
 };
-use crate::types::{CellPhysicalSize, GridArray, GridSize};
+use crate::history::MemoryBudget;
+use crate::time::Instant;
+use crate::types::{CellPhysicalSize, GridArray, GridIndex, GridSize, Velocity};
 
 use ndarray::{s, Array, ArrayView2, Zip};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Store the time taken to evaluate `$body` in `$timings.$field`
+macro_rules! timed {
+    ($timings:expr, $field:ident, $body:expr) => {{
+        let start = Instant::now();
+        let result = $body;
+        $timings.$field = start.elapsed();
+        result
+    }};
+}
 
 #[derive(Error, Debug)]
 pub enum SimulationError {
     #[error("An error occurred while deserializing: `{0}`")]
     DeserializationError(#[from] SerdeError),
+    #[error("An error occurred while serializing: `{0}`")]
+    SerializationError(SerdeError),
+    #[error("An error occurred while deserializing MessagePack: `{0}`")]
+    MessagePackDecodeError(#[from] rmp_serde::decode::Error),
+    #[error("An error occurred while serializing MessagePack: `{0}`")]
+    MessagePackEncodeError(#[from] rmp_serde::encode::Error),
     #[error("An error occurred with the SimulationGrid: `{0}`")]
     GridError(#[from] SimulationGridError),
+    #[error("Invalid value `{value}` for parameter `{field}`: {reason}")]
+    InvalidParameter {
+        field: &'static str,
+        value: Real,
+        reason: &'static str,
+    },
+    #[error(
+        "SOR diverged at iteration `{iteration}` with a squared norm of `{norm_squared}`"
+    )]
+    SorDiverged { iteration: u32, norm_squared: Real },
+    #[error(
+        "Forced cell `{0:?}` must be a fluid cell inside the outer ring of the grid"
+    )]
+    InvalidForcedCell(GridIndex),
+    #[error("Could not read or write the checkpoint: `{0}`")]
+    IoError(#[from] std::io::Error),
+}
+
+// The norm can rise for a while without SOR being unstable, e.g. when the
+// pressure equation has no exact solution and the norm levels off above zero.
+// SOR is only considered to be diverging when the norm grows by at least this
+// factor on every iteration.
+const SOR_DIVERGENCE_GROWTH: Real = 1.1;
+// With `omega_ramp`, omega starts stepping up once the norm has decreased on
+// this many iterations in a row, and reaches the configured value after
+// OMEGA_RAMP_STEPS more decreasing iterations.
+const OMEGA_RAMP_START: u32 = 3;
+const OMEGA_RAMP_STEPS: u32 = 4;
+
+fn default_sor_divergence_limit() -> u32 {
+    10
+}
+
+fn default_density() -> Real {
+    1.0
+}
+
+/// The order in which SOR visits the fluid cells during each iteration
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize, clap::ValueEnum,
+)]
+pub enum SorOrdering {
+    /// Column by column, from the top left to the bottom right
+    #[default]
+    Lexicographic,
+    /// All cells where `(x + y)` is even first, then the remaining cells
+    RedBlack,
+}
+
+/// How the pressure equation is solved
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize, clap::ValueEnum,
+)]
+pub enum PressureSolver {
+    /// Successive over-relaxation, which updates the pressure in place
+    #[default]
+    Sor,
+    /// Jacobi iteration, which computes every cell from the pressure of the
+    /// previous iteration. It ignores `omega` and `sor_ordering` and needs
+    /// many more iterations than SOR, but the result doesn't depend on the
+    /// order of the cells, so it can be spread over threads with the
+    /// `parallel` feature and still give the same result.
+    Jacobi,
+}
+
+/// How the velocity is advanced in time
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize, clap::ValueEnum,
+)]
+pub enum TimeScheme {
+    /// Solve for the pressure and subtract its gradient from F and G, which
+    /// keeps the flow divergence-free
+    #[default]
+    ProjectionSor,
+    /// Skip the pressure solve and advance the velocity with forward Euler,
+    /// `u = F` and `v = G`. Nothing enforces incompressibility, so the flow
+    /// soon becomes unstable. This is meant for showing why the projection
+    /// is needed, not for real simulations.
+    ExplicitEuler,
+}
+
+/// A parameter of a simulation that can be changed while it runs, see
+/// `Simulation::set_parameter`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, strum_macros::EnumIter)]
+pub enum Parameter {
+    Reynolds,
+    Omega,
+    Delt,
+    Gamma,
+}
+
+impl Parameter {
+    /// The name of the parameter, as in `SimulationError::InvalidParameter`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Parameter::Reynolds => "reynolds",
+            Parameter::Omega => "omega",
+            Parameter::Delt => "delt",
+            Parameter::Gamma => "gamma",
+        }
+    }
+
+    /// Check that `value` is valid for this parameter
+    ///
+    /// NaN is never valid, since every comparison with it is false.
+    pub fn check(&self, value: Real) -> Result<(), SimulationError> {
+        let (valid, reason) = match self {
+            Parameter::Reynolds | Parameter::Delt => {
+                (value > 0.0, "must be greater than 0")
+            }
+            Parameter::Omega => (
+                value > 0.0 && value < 2.0,
+                "must be between 0 and 2 (exclusive)",
+            ),
+            Parameter::Gamma => (
+                (0.0..=1.0).contains(&value),
+                "must be between 0 and 1 (inclusive)",
+            ),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(SimulationError::InvalidParameter {
+                field: self.name(),
+                value,
+                reason,
+            })
+        }
+    }
+}
+
+/// Scales the velocity of every inflow cell linearly from zero up to `target`,
+/// see `Simulation::set_inflow_ramp`
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InflowRamp {
+    pub target: Velocity,
+    /// How many ticks it takes to reach `target`
+    pub ticks: u32,
+    /// The number of ticks that had run when the ramp started
+    pub start: u32,
+}
+
+impl InflowRamp {
+    /// The inflow velocity during the tick that starts after `iterations`
+    /// ticks have run
+    ///
+    /// The first tick of the ramp runs at `target / ticks`, and tick number
+    /// `ticks` and every tick after it at `target`.
+    pub fn velocity(&self, iterations: u32) -> Velocity {
+        let tick = iterations.saturating_sub(self.start) + 1;
+        if tick >= self.ticks {
+            return self.target;
+        }
+        let fraction = tick as Real / self.ticks as Real;
+        self.target.map(|component| component * fraction)
+    }
+
+    /// Whether the tick that starts after `iterations` ticks have run is the
+    /// last one that needs scaling
+    fn is_finished(&self, iterations: u32) -> bool {
+        iterations.saturating_sub(self.start) + 1 >= self.ticks
+    }
+}
+
+/// When SOR accepts the pressure as converged
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SorStoppingCriterion {
+    /// Stop once the norm is below `sor_absolute_epsilon` or below the norm
+    /// from the very first tick of the simulation
+    #[default]
+    AbsoluteOrImproved,
+    /// Stop once the norm is below `epsilon`
+    Absolute { epsilon: Real },
+    /// Stop once the norm is below `factor` times the norm at the start of the
+    /// current tick
+    Relative { factor: Real },
+}
+
+/// When the cached `initial_norm_squared` used by the `AbsoluteOrImproved`
+/// stopping criterion is recomputed from the current pressure
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NormReset {
+    /// Keep the norm from the start of the simulation
+    #[default]
+    Never,
+    /// Recompute the norm during every tick
+    ///
+    /// The norm after the first SOR iteration of the tick becomes the one to
+    /// improve on, so SOR stops as soon as the norm drops below it. This is
+    /// much cheaper than `Never` but far less accurate, and can let the flow
+    /// become unstable.
+    EveryTick,
+    /// Recompute the norm during every `n`th tick, like `EveryTick`
+    EveryNTicks(u32),
+}
+
+impl NormReset {
+    /// Whether the norm should be recomputed during the tick with the given
+    /// number
+    pub fn is_due(&self, tick: u32) -> bool {
+        match *self {
+            NormReset::Never => false,
+            NormReset::EveryTick => true,
+            NormReset::EveryNTicks(n) => tick.is_multiple_of(n),
+        }
+    }
+}
+
+/// The file formats a simulation can be saved in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// Pretty-printed JSON, which is easy to read and edit by hand
+    Json,
+    /// MessagePack, which is much smaller and faster to parse for large grids
+    MessagePack,
+}
+
+impl Format {
+    /// Pick the format from the extension of `path`: MessagePack for `.msgpack`
+    /// and `.mpk`, JSON for anything else
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension)
+                if extension.eq_ignore_ascii_case("msgpack")
+                    || extension.eq_ignore_ascii_case("mpk") =>
+            {
+                Format::MessagePack
+            }
+            _ => Format::Json,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -33,26 +292,323 @@ pub struct UnfinalizedSimulation {
     pub cell_size: CellPhysicalSize,
     pub delt: Real,
     pub gamma: Real,
+    /// Set `gamma` to `recommended_gamma` at the start of every tick. Defaults
+    /// to off so older files still load.
+    #[serde(default)]
+    pub auto_gamma: bool,
     pub reynolds: Real,
+    /// The density of the fluid. Defaults to 1 so older files still load.
+    #[serde(default = "default_density")]
+    pub density: Real,
+    /// External force per unit volume (e.g. gravity) in the x and y
+    /// directions. Defaults to no force so older files still load.
+    #[serde(default)]
+    pub body_force: [Real; 2],
     pub initial_norm_squared: Option<Real>,
+    /// Defaults to `Never` so older files still load.
+    #[serde(default)]
+    pub norm_reset: NormReset,
     pub sor_absolute_epsilon: Real,
     pub max_iterations: u32,
+    /// SOR gives up with `SimulationError::SorDiverged` once the norm has grown
+    /// by more than 10% on this many consecutive iterations.
+    #[serde(default = "default_sor_divergence_limit")]
+    pub sor_divergence_limit: u32,
+    /// SOR stops early once an iteration reduces the norm by less than this
+    /// fraction of its previous value. Defaults to 0, which never stops early.
+    #[serde(default)]
+    pub sor_stagnation_factor: Real,
+    /// Defaults to `AbsoluteOrImproved` so older files still load.
+    #[serde(default)]
+    pub sor_stopping_criterion: SorStoppingCriterion,
     pub iterations: u32,
     pub time: Real,
     pub omega: Real,
+    /// Start each SOR solve with omega = 1 and only ramp up to `omega` once
+    /// the norm is decreasing. Defaults to off so older files still load.
+    #[serde(default)]
+    pub omega_ramp: bool,
+    /// Fluid cells whose u and v are held at a fixed velocity instead of
+    /// being solved for, e.g. to inject fluid inside the domain. Defaults to
+    /// none so older files still load.
+    #[serde(default)]
+    pub forced_cells: Vec<(GridIndex, Velocity)>,
+    /// Defaults to lexicographic ordering so older files still load.
+    #[serde(default)]
+    pub sor_ordering: SorOrdering,
+    /// Defaults to SOR so older files still load.
+    #[serde(default)]
+    pub pressure_solver: PressureSolver,
+    /// Defaults to the pressure projection so older files still load.
+    #[serde(default)]
+    pub time_scheme: TimeScheme,
+    /// Caps how much history is kept. Defaults to `MemoryBudget::default()`
+    /// so older files still load.
+    #[serde(default)]
+    pub memory_budget: MemoryBudget,
+    /// Defaults to no ramp so older files still load.
+    #[serde(default)]
+    pub inflow_ramp: Option<InflowRamp>,
     pub grid: UnfinalizedSimulationGrid,
 }
 
+impl UnfinalizedSimulation {
+    /// Check that the simulation parameters can't produce NaNs or infinite
+    /// loops once the simulation starts running.
+    ///
+    /// Comparisons are written so that NaN parameters are rejected as well.
+    #[allow(clippy::neg_cmp_op_on_partial_ord)]
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |field, value, reason| {
+            Err(SimulationError::InvalidParameter {
+                field,
+                value,
+                reason,
+            })
+        };
+
+        if !(self.cell_size[0] > 0.0) {
+            return invalid("cell_size[0]", self.cell_size[0], "must be greater than 0");
+        }
+        if !(self.cell_size[1] > 0.0) {
+            return invalid("cell_size[1]", self.cell_size[1], "must be greater than 0");
+        }
+        Parameter::Delt.check(self.delt)?;
+        Parameter::Reynolds.check(self.reynolds)?;
+        if !(self.density > 0.0) {
+            return invalid("density", self.density, "must be greater than 0");
+        }
+        Parameter::Gamma.check(self.gamma)?;
+        Parameter::Omega.check(self.omega)?;
+        if self.max_iterations == 0 {
+            return invalid(
+                "max_iterations",
+                self.max_iterations as Real,
+                "must be at least 1",
+            );
+        }
+        if self.sor_divergence_limit == 0 {
+            return invalid(
+                "sor_divergence_limit",
+                self.sor_divergence_limit as Real,
+                "must be at least 1",
+            );
+        }
+        if !(self.sor_stagnation_factor >= 0.0 && self.sor_stagnation_factor < 1.0) {
+            return invalid(
+                "sor_stagnation_factor",
+                self.sor_stagnation_factor,
+                "must be between 0 (inclusive) and 1 (exclusive)",
+            );
+        }
+        if self.norm_reset == NormReset::EveryNTicks(0) {
+            return invalid("norm_reset", 0.0, "must reset every 1 or more ticks");
+        }
+        match self.sor_stopping_criterion {
+            SorStoppingCriterion::AbsoluteOrImproved => {}
+            SorStoppingCriterion::Absolute { epsilon } => {
+                if !(epsilon > 0.0) {
+                    return invalid(
+                        "sor_stopping_criterion.epsilon",
+                        epsilon,
+                        "must be greater than 0",
+                    );
+                }
+            }
+            SorStoppingCriterion::Relative { factor } => {
+                if !(factor > 0.0 && factor < 1.0) {
+                    return invalid(
+                        "sor_stopping_criterion.factor",
+                        factor,
+                        "must be between 0 and 1 (exclusive)",
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The residual of the pressure equation over the fluid cells
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ResidualReport {
+    /// The mean of the squared residuals, which SOR uses as its norm
+    pub l2_squared: Real,
+    /// The largest absolute residual
+    pub l_inf: Real,
+    /// The cell where the largest absolute residual occurs
+    pub l_inf_location: GridIndex,
+}
+
+impl std::fmt::Display for ResidualReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "L2 {:.3e}, max {:.3e} at ({}, {})",
+            self.l2_squared.sqrt(),
+            self.l_inf,
+            self.l_inf_location.0,
+            self.l_inf_location.1
+        )
+    }
+}
+
+/// Why SOR stopped iterating
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ConvergenceStatus {
+    /// The residual met the stopping criterion
+    #[default]
+    Converged,
+    /// An iteration improved the residual by less than the stagnation factor
+    Stagnated,
+    /// SOR ran `max_iterations` without meeting the stopping criterion, so
+    /// the pressure may be far from a solution
+    MaxIterationsReached,
+}
+
+/// What happened during a simulation tick
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct TickStats {
+    pub sor_iterations: u32,
+    pub convergence: ConvergenceStatus,
+    /// The residual once SOR stopped
+    pub residual: ResidualReport,
+    /// The CFL number of the velocities at the end of the tick
+    pub cfl: Real,
+    /// How long each stage of the tick took
+    pub timings: PhaseTimings,
+}
+
+/// Wall-clock time spent in each stage of `run_simulation_tick`
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct PhaseTimings {
+    pub set_boundary_u_and_v: Duration,
+    pub calculate_f_and_g: Duration,
+    pub calculate_rhs: Duration,
+    pub solve_sor: Duration,
+    pub set_u_and_v: Duration,
+    /// The whole tick, including the bookkeeping between the stages
+    pub total: Duration,
+}
+
+impl PhaseTimings {
+    /// The stages with their names, in the order they run
+    pub fn phases(&self) -> [(&'static str, Duration); 5] {
+        [
+            ("set_boundary_u_and_v", self.set_boundary_u_and_v),
+            ("calculate_f_and_g", self.calculate_f_and_g),
+            ("calculate_rhs", self.calculate_rhs),
+            ("solve_sor", self.solve_sor),
+            ("set_u_and_v", self.set_u_and_v),
+        ]
+    }
+}
+
+impl std::ops::AddAssign for PhaseTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.set_boundary_u_and_v += other.set_boundary_u_and_v;
+        self.calculate_f_and_g += other.calculate_f_and_g;
+        self.calculate_rhs += other.calculate_rhs;
+        self.solve_sor += other.solve_sor;
+        self.set_u_and_v += other.set_u_and_v;
+        self.total += other.total;
+    }
+}
+
+impl std::ops::Div<u32> for PhaseTimings {
+    type Output = Self;
+
+    fn div(self, ticks: u32) -> Self {
+        PhaseTimings {
+            set_boundary_u_and_v: self.set_boundary_u_and_v / ticks,
+            calculate_f_and_g: self.calculate_f_and_g / ticks,
+            calculate_rhs: self.calculate_rhs / ticks,
+            solve_sor: self.solve_sor / ticks,
+            set_u_and_v: self.set_u_and_v / ticks,
+            total: self.total / ticks,
+        }
+    }
+}
+
+impl std::fmt::Display for PhaseTimings {
+    /// The total time and the share of each stage, on one line
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3?}", self.total)?;
+        let total = self.total.as_secs_f64().max(1e-12);
+        for (i, (name, duration)) in self.phases().into_iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            let percent = 100.0 * duration.as_secs_f64() / total;
+            write!(f, "{}{} {:.1}%", separator, name, percent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Total wall-clock time spent in each stage of `run_simulation_tick`
+#[cfg(feature = "profiling")]
+#[derive(Debug, Default, Clone)]
+pub struct TickTimings {
+    pub ticks: u32,
+    pub sum: PhaseTimings,
+}
+
+#[cfg(feature = "profiling")]
+impl TickTimings {
+    // Add the stages of one more tick
+    fn record(&mut self, timings: PhaseTimings) {
+        self.ticks += 1;
+        self.sum += timings;
+    }
+}
+
+#[cfg(feature = "profiling")]
+impl std::fmt::Display for TickTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Time spent over {} ticks: {}", self.ticks, self.sum)?;
+        if self.ticks > 0 {
+            write!(f, "\nAverage tick: {}", self.sum / self.ticks)?;
+        }
+        Ok(())
+    }
+}
+
+/// Coefficients of the SOR update, which only depend on omega and the cell size
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SorCoefficients {
+    pub delx2: Real,
+    pub dely2: Real,
+    pub one_minus_w: Real,
+    pub middle: Real,
+}
+
+impl SorCoefficients {
+    pub fn new(omega: Real, cell_size: CellPhysicalSize) -> SorCoefficients {
+        let delx2 = cell_size[0].powi(2);
+        let dely2 = cell_size[1].powi(2);
+        SorCoefficients {
+            delx2,
+            dely2,
+            one_minus_w: 1.0 - omega,
+            middle: omega / ((2.0 / delx2) + (2.0 / dely2)),
+        }
+    }
+}
+
 // This must be the same as UnfinalizedSimulation, except the type
 // of grid and without the calculated values. We have two types to make sure
 // we never deserialize without forgetting to generate the boundary list.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Simulation {
     pub size: GridSize,
-    pub cell_size: CellPhysicalSize,
+    // Private so that changes go through set_cell_size, which keeps
+    // sor_coefficients up to date.
+    cell_size: CellPhysicalSize,
     pub delt: Real,
     pub gamma: Real,
+    pub auto_gamma: bool,
     pub reynolds: Real,
+    pub density: Real,
+    pub body_force: [Real; 2],
     #[serde(skip)]
     pub f: GridArray<Real>,
     #[serde(skip)]
@@ -60,18 +616,50 @@ pub struct Simulation {
     #[serde(skip)]
     pub rhs: GridArray<Real>,
     pub initial_norm_squared: Option<Real>,
+    pub norm_reset: NormReset,
     pub sor_absolute_epsilon: Real,
     pub max_iterations: u32,
+    pub sor_divergence_limit: u32,
+    pub sor_stagnation_factor: Real,
+    pub sor_stopping_criterion: SorStoppingCriterion,
     pub iterations: u32,
     pub time: Real,
-    pub omega: Real,
+    // Private for the same reason as cell_size.
+    omega: Real,
+    #[serde(skip)]
+    sor_coefficients: SorCoefficients,
+    pub omega_ramp: bool,
+    pub forced_cells: Vec<(GridIndex, Velocity)>,
+    pub sor_ordering: SorOrdering,
+    pub pressure_solver: PressureSolver,
+    pub time_scheme: TimeScheme,
+    pub memory_budget: MemoryBudget,
+    // The stats of the most recent ticks, oldest first, up to
+    // memory_budget.max_stats of them
+    #[serde(skip)]
+    stats_history: VecDeque<TickStats>,
+    // The pressure of the next Jacobi iteration. Only allocated once Jacobi is
+    // used.
+    #[serde(skip)]
+    pressure_buffer: GridArray<Real>,
+    // Private so that it's only set through set_inflow_ramp, which also stops
+    // the inflow.
+    inflow_ramp: Option<InflowRamp>,
+    // Analysis rather than state of the flow, so it isn't saved
+    #[serde(skip)]
+    field_accumulator: Option<FieldAccumulator>,
     pub grid: SimulationGrid,
+    #[cfg(feature = "profiling")]
+    #[serde(skip)]
+    pub timings: TickTimings,
 }
 
 impl TryFrom<UnfinalizedSimulation> for Simulation {
     type Error = SimulationError;
 
     fn try_from(item: UnfinalizedSimulation) -> Result<Self, Self::Error> {
+        item.validate()?;
+
         // Will be nicer once https://github.com/rust-lang/rust/issues/86555
         // is in stable.
         let mut sim = Simulation {
@@ -79,18 +667,46 @@ impl TryFrom<UnfinalizedSimulation> for Simulation {
             cell_size: item.cell_size,
             delt: item.delt,
             gamma: item.gamma,
+            auto_gamma: item.auto_gamma,
             reynolds: item.reynolds,
+            density: item.density,
+            body_force: item.body_force,
             f: Array::zeros(item.size),
             g: Array::zeros(item.size),
             rhs: Array::zeros(item.size),
             initial_norm_squared: item.initial_norm_squared,
+            norm_reset: item.norm_reset,
             sor_absolute_epsilon: item.sor_absolute_epsilon,
             max_iterations: item.max_iterations,
+            sor_divergence_limit: item.sor_divergence_limit,
+            sor_stagnation_factor: item.sor_stagnation_factor,
+            sor_stopping_criterion: item.sor_stopping_criterion,
             iterations: item.iterations,
             time: item.time,
             omega: item.omega,
+            sor_coefficients: SorCoefficients::new(item.omega, item.cell_size),
+            omega_ramp: item.omega_ramp,
+            forced_cells: item.forced_cells,
+            sor_ordering: item.sor_ordering,
+            pressure_solver: item.pressure_solver,
+            time_scheme: item.time_scheme,
+            memory_budget: item.memory_budget,
+            stats_history: VecDeque::new(),
+            pressure_buffer: Array::zeros((0, 0)),
+            inflow_ramp: item.inflow_ramp,
+            field_accumulator: None,
             grid: item.grid.try_into()?,
+            #[cfg(feature = "profiling")]
+            timings: TickTimings::default(),
         };
+        let [width, height] = sim.size;
+        for &(idx @ (x, y), _) in &sim.forced_cells {
+            let inside = (1..width - 1).contains(&x) && (1..height - 1).contains(&y);
+            if !inside || sim.grid.cell_type[idx] != Cell::Fluid {
+                return Err(SimulationError::InvalidForcedCell(idx));
+            }
+        }
+        sim.apply_forced_cells();
         sim.calculate_f_and_g();
         sim.calculate_rhs();
         sim.get_initial_norm_squared();
@@ -98,6 +714,14 @@ impl TryFrom<UnfinalizedSimulation> for Simulation {
     }
 }
 
+impl PartialEq for Simulation {
+    /// Compare the parameters and the state of the flow. Arrays that are
+    /// recomputed every tick, like F, G and the RHS, are left out.
+    fn eq(&self, other: &Self) -> bool {
+        self.approx_eq(other, 0.0).is_none()
+    }
+}
+
 impl std::fmt::Display for Simulation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(
@@ -108,17 +732,473 @@ impl std::fmt::Display for Simulation {
         writeln!(f, "Time step delta:{}", self.delt)?;
         writeln!(f, "Gamma:{}", self.gamma)?;
         writeln!(f, "Reynolds number:{}", self.reynolds)?;
+        writeln!(f, "Density:{}", self.density)?;
+        writeln!(f, "SOR omega:{}", self.omega)?;
+        writeln!(
+            f,
+            "Body force:[{}, {}]",
+            self.body_force[0], self.body_force[1]
+        )?;
+        let census = self.grid.census();
+        write!(f, "{}", census)?;
+        writeln!(f, "Fluid area:{}", census.fluid_area(self.cell_size))?;
         writeln!(f, "{}", self.grid)?;
         Ok(())
     }
 }
 
 impl Simulation {
+    /// Find where this simulation differs from `other`
+    ///
+    /// Real-valued parameters, the time and the pressure and velocities have
+    /// to be within `tolerance`, and everything else has to match exactly.
+    /// Returns `None` if the simulations match, and otherwise the first field
+    /// that doesn't. See `SimulationGrid::approx_eq` for the grid.
+    pub fn approx_eq(&self, other: &Self, tolerance: Real) -> Option<FieldDifference> {
+        macro_rules! exact {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != other.$field {
+                        return Some(FieldDifference::new(
+                            stringify!($field),
+                            None,
+                            &self.$field,
+                            &other.$field,
+                        ));
+                    }
+                )*
+            };
+        }
+        exact!(
+            size,
+            auto_gamma,
+            norm_reset,
+            max_iterations,
+            sor_divergence_limit,
+            sor_stopping_criterion,
+            iterations,
+            omega_ramp,
+            forced_cells,
+            sor_ordering,
+            pressure_solver,
+            time_scheme,
+            memory_budget,
+            inflow_ramp
+        );
+
+        let initial_norms = [self.initial_norm_squared, other.initial_norm_squared];
+        if let [Some(left), Some(right)] = initial_norms {
+            if !is_close(left, right, tolerance) {
+                exact!(initial_norm_squared);
+            }
+        } else {
+            exact!(initial_norm_squared);
+        }
+
+        let scalars = [
+            ("cell_size[0]", self.cell_size[0], other.cell_size[0]),
+            ("cell_size[1]", self.cell_size[1], other.cell_size[1]),
+            ("delt", self.delt, other.delt),
+            ("gamma", self.gamma, other.gamma),
+            ("reynolds", self.reynolds, other.reynolds),
+            ("density", self.density, other.density),
+            ("body_force[0]", self.body_force[0], other.body_force[0]),
+            ("body_force[1]", self.body_force[1], other.body_force[1]),
+            (
+                "sor_absolute_epsilon",
+                self.sor_absolute_epsilon,
+                other.sor_absolute_epsilon,
+            ),
+            (
+                "sor_stagnation_factor",
+                self.sor_stagnation_factor,
+                other.sor_stagnation_factor,
+            ),
+            ("omega", self.omega, other.omega),
+            ("time", self.time, other.time),
+        ];
+        for (field, left, right) in scalars {
+            if !is_close(left, right, tolerance) {
+                return Some(FieldDifference::new(field, None, left, right));
+            }
+        }
+
+        self.grid.approx_eq(&other.grid, tolerance)
+    }
+
     pub fn from_reader<R: Read>(reader: R) -> Result<Simulation, SimulationError> {
-        let unfinalized: UnfinalizedSimulation = serde_json::from_reader(reader)?;
+        Simulation::from_reader_format(reader, Format::Json)
+    }
+
+    /// Parse and validate a simulation saved with `to_writer_format` in
+    /// `format`
+    pub fn from_reader_format<R: Read>(
+        reader: R,
+        format: Format,
+    ) -> Result<Simulation, SimulationError> {
+        let unfinalized: UnfinalizedSimulation = match format {
+            Format::Json => serde_json::from_reader(reader)?,
+            Format::MessagePack => rmp_serde::from_read(reader)?,
+        };
+        Simulation::try_from(unfinalized)
+    }
+
+    /// Save the simulation in `format`, like `to_json_pretty`
+    ///
+    /// MessagePack keeps the field names, so files stay loadable when fields
+    /// are added.
+    pub fn to_writer_format<W: Write>(
+        &self,
+        mut writer: W,
+        format: Format,
+    ) -> Result<(), SimulationError> {
+        match format {
+            Format::Json => serde_json::to_writer_pretty(writer, self)
+                .map_err(SimulationError::SerializationError)?,
+            Format::MessagePack => rmp_serde::encode::write_named(&mut writer, self)?,
+        }
+        Ok(())
+    }
+
+    /// Parse and validate a simulation saved with `to_json_pretty`
+    pub fn from_json_str(json: &str) -> Result<Simulation, SimulationError> {
+        let unfinalized: UnfinalizedSimulation = serde_json::from_str(json)?;
         Simulation::try_from(unfinalized)
     }
 
+    /// Save the simulation, including its solver settings and progress, so
+    /// that it can be resumed with `from_json_str`
+    pub fn to_json_pretty(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a Simulation is always serializable")
+    }
+
+    /// Save everything needed to resume the simulation exactly where it is to
+    /// `path`, in the format that matches its extension, see
+    /// `Format::from_path`
+    ///
+    /// Besides the grid and the solver settings, this keeps the number of
+    /// ticks, the time, the norm the SOR stopping criterion compares against
+    /// and any running inflow ramp, so ticking the loaded simulation gives the
+    /// same flow as ticking this one. F, G and the RHS are recomputed on load.
+    /// The stats history and the timings start over.
+    pub fn save_checkpoint(&self, path: &Path) -> Result<(), SimulationError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.to_writer_format(&mut writer, Format::from_path(path))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Resume a simulation saved with `save_checkpoint`
+    pub fn load_checkpoint(path: &Path) -> Result<Simulation, SimulationError> {
+        let reader = BufReader::new(File::open(path)?);
+        Simulation::from_reader_format(reader, Format::from_path(path))
+    }
+
+    pub fn cell_size(&self) -> CellPhysicalSize {
+        self.cell_size
+    }
+
+    /// The physical width and height of the whole grid, including the outer
+    /// ring of cells
+    pub fn domain_extent(&self) -> (Real, Real) {
+        (
+            self.size[0] as Real * self.cell_size[0],
+            self.size[1] as Real * self.cell_size[1],
+        )
+    }
+
+    /// Change the physical size of a cell. Unlike construction, this does not
+    /// validate the new value.
+    pub fn set_cell_size(&mut self, cell_size: CellPhysicalSize) {
+        self.cell_size = cell_size;
+        self.sor_coefficients = SorCoefficients::new(self.omega, self.cell_size);
+    }
+
+    pub fn omega(&self) -> Real {
+        self.omega
+    }
+
+    /// Change the SOR relaxation factor. Unlike construction, this does not
+    /// validate the new value.
+    pub fn set_omega(&mut self, omega: Real) {
+        self.omega = omega;
+        self.sor_coefficients = SorCoefficients::new(self.omega, self.cell_size);
+    }
+
+    /// The current value of `parameter`
+    pub fn parameter(&self, parameter: Parameter) -> Real {
+        match parameter {
+            Parameter::Reynolds => self.reynolds,
+            Parameter::Omega => self.omega,
+            Parameter::Delt => self.delt,
+            Parameter::Gamma => self.gamma,
+        }
+    }
+
+    /// Change `parameter` to `value`, starting with the next tick
+    ///
+    /// Invalid values are rejected with `SimulationError::InvalidParameter`
+    /// and leave the simulation unchanged. Setting gamma turns off
+    /// `auto_gamma`, which would overwrite it on the next tick.
+    pub fn set_parameter(
+        &mut self,
+        parameter: Parameter,
+        value: Real,
+    ) -> Result<(), SimulationError> {
+        parameter.check(value)?;
+        match parameter {
+            Parameter::Reynolds => self.reynolds = value,
+            Parameter::Omega => self.set_omega(value),
+            Parameter::Delt => self.delt = value,
+            Parameter::Gamma => {
+                self.gamma = value;
+                self.auto_gamma = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calculate the Reynolds number `U * L / nu` of a physical flow
+    ///
+    /// See `units::UnitSystem` for converting the results back to physical
+    /// units.
+    ///
+    /// # Arguments
+    ///
+    /// * `length` - The characteristic length of the flow, in m
+    /// * `velocity` - The characteristic velocity of the flow, in m/s
+    /// * `viscosity` - The kinematic viscosity of the fluid, in m²/s
+    pub fn reynolds_from(length: Real, velocity: Real, viscosity: Real) -> Real {
+        velocity * length / viscosity
+    }
+
+    /// Calculate the CFL number, `max(u_max * delt / delx, v_max * delt / dely)`
+    ///
+    /// The velocities are from the last time `grid.calculate_speed_range` was
+    /// called, which happens at the end of each tick. The simulation becomes
+    /// unstable when the CFL number is above 1.
+    pub fn cfl_number(&self) -> Real {
+        let [max_u, max_v] = self.grid.max_velocity;
+        Real::max(
+            max_u * self.delt / self.cell_size[0],
+            max_v * self.delt / self.cell_size[1],
+        )
+    }
+
+    /// The physical x, pressure, u and v at the centers of the cells of row
+    /// `y`, from left to right
+    ///
+    /// The velocities are interpolated to the cell centers like
+    /// `SimulationGrid::velocity_at_index`, and the positions are measured like
+    /// `SimulationGrid::cell_center`. Panics if `y` is outside of the grid.
+    pub fn profile_x(&self, y: usize) -> Vec<(Real, Real, Real, Real)> {
+        (0..self.size[0])
+            .map(|x| {
+                let (physical_x, _) = self.grid.cell_center((x, y), self.cell_size);
+                self.profile_entry(physical_x, (x, y))
+            })
+            .collect()
+    }
+
+    /// The physical y, pressure, u and v at the centers of the cells of column
+    /// `x`, from top to bottom
+    ///
+    /// See `profile_x`. Panics if `x` is outside of the grid.
+    pub fn profile_y(&self, x: usize) -> Vec<(Real, Real, Real, Real)> {
+        (0..self.size[1])
+            .map(|y| {
+                let (_, physical_y) = self.grid.cell_center((x, y), self.cell_size);
+                self.profile_entry(physical_y, (x, y))
+            })
+            .collect()
+    }
+
+    fn profile_entry(&self, position: Real, idx: GridIndex) -> (Real, Real, Real, Real) {
+        let [u, v] = self.grid.velocity_at_index(idx);
+        (position, self.grid.pressure_at_index(idx), u, v)
+    }
+
+    /// Calculate the kinetic energy of the fluid, per unit of depth
+    ///
+    /// This sums `density * (u² + v²) / 2` over the east and south faces of
+    /// the fluid cells, times the area of a cell.
+    pub fn kinetic_energy(&self) -> Real {
+        let sum = Zip::from(&self.grid.cell_type)
+            .and(&self.grid.u)
+            .and(&self.grid.v)
+            .fold(0.0, |sum, cell, u, v| match cell {
+                Cell::Fluid => sum + u * u + v * v,
+                Cell::Boundary(_) => sum,
+            });
+        0.5 * self.density * sum * self.cell_size[0] * self.cell_size[1]
+    }
+
+    /// Calculate the net rate at which mass leaves the domain through its
+    /// inflow and outflow boundaries, per unit of depth
+    ///
+    /// This sums the density times the velocity through every face between a
+    /// fluid cell and an inflow or outflow cell, times the length of the face.
+    /// Mass entering the domain counts as negative. Interior sources and
+    /// sinks count as well. For an incompressible flow this should be close
+    /// to zero once the pressure has converged.
+    pub fn net_mass_flux(&self) -> Real {
+        let grid = &self.grid;
+        let [delx, dely] = self.cell_size;
+        let mut flux = 0.0;
+        for &(idx, edge) in &grid.boundaries.sorted_boundary_list {
+            let Some(edge) = edge else {
+                continue;
+            };
+            if !matches!(
+                grid.cell_type[idx],
+                Cell::Boundary(
+                    BoundaryCell::Inflow { .. }
+                        | BoundaryCell::Outflow
+                        | BoundaryCell::ConvectiveOutflow { .. }
+                )
+            ) {
+                continue;
+            }
+            // The flow out of the fluid across each face it shares with the
+            // boundary cell. y grows downward, so fluid to the north leaves
+            // through a positive v.
+            let north = |neighbor: GridIndex| grid.v[neighbor] * delx;
+            let south = || -grid.v[idx] * delx;
+            let east = || -grid.u[idx] * dely;
+            let west = |neighbor: GridIndex| grid.u[neighbor] * dely;
+            flux += match edge {
+                EdgeType::North { north_neighbor } => north(north_neighbor),
+                EdgeType::NorthEast { north_neighbor, .. } => {
+                    north(north_neighbor) + east()
+                }
+                EdgeType::East { .. } => east(),
+                EdgeType::SouthEast { .. } => south() + east(),
+                EdgeType::South { .. } => south(),
+                EdgeType::SouthWest { west_neighbor, .. } => {
+                    south() + west(west_neighbor)
+                }
+                EdgeType::West { west_neighbor } => west(west_neighbor),
+                EdgeType::NorthWest {
+                    north_neighbor,
+                    west_neighbor,
+                } => north(north_neighbor) + west(west_neighbor),
+            };
+        }
+        // What leaves a source or sink cell through its faces enters the fluid.
+        for &(x, y) in &grid.boundaries.sources {
+            flux -= (grid.u[(x, y)] - grid.u[(x - 1, y)]) * dely
+                + (grid.v[(x, y)] - grid.v[(x, y - 1)]) * delx;
+        }
+        self.density * flux
+    }
+
+    /// Calculate the stream function `psi` of the flow
+    ///
+    /// `psi[(x, y)]` is at the corner between cells `(x, y)` and
+    /// `(x + 1, y + 1)`, where `u[(x, y)]` and `v[(x, y)]` meet. It's zero at the
+    /// corner of cell `(0, 0)`, is integrated along the first row with `v` and
+    /// then along each column with `u`, so that `u = dpsi/dy` and
+    /// `v = -dpsi/dx`. The velocity across a face between two boundary cells
+    /// counts as zero, which keeps `psi` constant along walls and obstacles.
+    ///
+    /// Since the flow is incompressible, the contours of `psi` are streamlines
+    /// and the difference of `psi` between two points is the flow rate between
+    /// them.
+    pub fn stream_function(&self) -> GridArray<Real> {
+        let mut psi = Array::zeros(self.size);
+        self.stream_function_into(&mut psi);
+        psi
+    }
+
+    /// Like `stream_function`, but writes into `psi` so that it can be reused
+    /// between frames. `psi` must have the same shape as the grid.
+    pub fn stream_function_into(&self, psi: &mut GridArray<Real>) {
+        let [w, h] = self.size;
+        let [delx, dely] = self.cell_size;
+        let grid = &self.grid;
+        let is_fluid =
+            |idx: GridIndex| matches!(grid.cell_type.get(idx), Some(Cell::Fluid));
+
+        psi[(0, 0)] = 0.0;
+        for x in 1..w {
+            let v = if is_fluid((x, 0)) || is_fluid((x, 1)) {
+                grid.v[(x, 0)]
+            } else {
+                0.0
+            };
+            psi[(x, 0)] = psi[(x - 1, 0)] - v * delx;
+        }
+        for x in 0..w {
+            for y in 1..h {
+                let u = if is_fluid((x, y)) || is_fluid((x + 1, y)) {
+                    grid.u[(x, y)]
+                } else {
+                    0.0
+                };
+                psi[(x, y)] = psi[(x, y - 1)] + u * dely;
+            }
+        }
+    }
+
+    pub fn sor_coefficients(&self) -> SorCoefficients {
+        self.sor_coefficients
+    }
+
+    /// Calculate the optimal SOR relaxation factor for the grid
+    ///
+    /// This is `2 / (1 + sqrt(1 - rho^2))`, where `rho` is the spectral radius
+    /// of the Jacobi iteration for the Poisson equation on a rectangle of
+    /// interior cells. Obstacles and the kind of boundary conditions aren't
+    /// taken into account, so this is an estimate for more complex grids.
+    pub fn optimal_omega(&self) -> Real {
+        let interior_x = (self.size[0] - 2) as Real;
+        let interior_y = (self.size[1] - 2) as Real;
+        let inv_delx2 = 1.0 / self.cell_size[0].powi(2);
+        let inv_dely2 = 1.0 / self.cell_size[1].powi(2);
+
+        let rho = ((consts::PI / interior_x).cos() * inv_delx2
+            + (consts::PI / interior_y).cos() * inv_dely2)
+            / (inv_delx2 + inv_dely2);
+
+        2.0 / (1.0 + (1.0 - rho.powi(2)).sqrt())
+    }
+
+    /// Calculate the smallest stable upwind blending factor,
+    /// `max(|u| * delt / delx, |v| * delt / dely)`, clamped to `[0, 1]`
+    ///
+    /// Smaller values of `gamma` use more central differencing, which is more
+    /// accurate but oscillates when `gamma` is below this bound. Like
+    /// `cfl_number`, this uses the velocities at the end of the last tick.
+    pub fn recommended_gamma(&self) -> Real {
+        self.cfl_number().clamp(0.0, 1.0)
+    }
+
+    /// Add seeded pseudo-random noise to the velocities of the fluid cells
+    ///
+    /// Symmetric setups like the flow around the obstacle preset stay
+    /// symmetric forever unless something breaks the symmetry, and a small
+    /// kick is enough to start e.g. vortex shedding. The same `seed` always
+    /// gives the same noise, so runs stay reproducible. Boundary cells are
+    /// left alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `amplitude` - The largest change of each velocity component
+    /// * `seed` - The seed of the random number generator
+    pub fn perturb_velocity(&mut self, amplitude: Real, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Zip::from(&mut self.grid.u)
+            .and(&mut self.grid.v)
+            .and(&self.grid.cell_type)
+            .for_each(|u, v, cell_type| {
+                if let Cell::Fluid = cell_type {
+                    *u += amplitude * (2.0 * rng.random::<Real>() - 1.0);
+                    *v += amplitude * (2.0 * rng.random::<Real>() - 1.0);
+                }
+            });
+        self.grid.calculate_speed_range();
+    }
+
     fn calculate_f_and_g(&mut self) {
         // Ignore outer boundary. This also gives us the correct shape, because
         // everything is computed using 3x3 grids which aren't defined on the
@@ -148,6 +1228,7 @@ impl Simulation {
                     self.delt,
                     self.gamma,
                     self.reynolds,
+                    self.body_force[0] / self.density,
                 );
                 *g = calculate_g(
                     u_view,
@@ -157,6 +1238,7 @@ impl Simulation {
                     self.delt,
                     self.gamma,
                     self.reynolds,
+                    self.body_force[1] / self.density,
                 );
             });
 
@@ -199,251 +1281,2431 @@ impl Simulation {
                 None | Some(_) => {}
             }
         }
+
+        // Forced velocities are fixed like boundary velocities, so the
+        // pressure only has to make the rest of the flow divergence-free.
+        for &(idx, [u, v]) in &self.forced_cells {
+            self.f[idx] = u;
+            self.g[idx] = v;
+        }
     }
 
-    fn calculate_rhs(&mut self) {
-        let mut rhs_view = self.rhs.slice_mut(s![1.., 1..]);
-        Zip::from(&mut rhs_view)
-            .and(self.f.windows((2, 2)))
-            .and(self.g.windows((2, 2)))
-            .for_each(|rhs, f_view, g_view| {
-                *rhs = (((f_view[(1, 1)] - f_view[(0, 1)]) / self.cell_size[0])
-                    + ((g_view[(1, 1)] - g_view[(1, 0)]) / self.cell_size[1]))
-                    / self.delt
-            });
+    /// Start the flow over from rest, keeping the cells and the settings
+    ///
+    /// The pressure and velocities are zeroed, the time and the tick count go
+    /// back to zero and the norm the SOR stopping criterion compares against
+    /// is recomputed, which leaves the simulation as it would be if it had
+    /// just been built from the current cells. Unlike building a new one,
+    /// the boundary list doesn't have to be rebuilt, and cells drawn since
+    /// the start are kept. A running inflow ramp starts over, and so does an
+    /// attached field accumulator, warm-up included.
+    pub fn reset_fields(&mut self) {
+        self.grid.pressure.fill(0.0);
+        self.grid.u.fill(0.0);
+        self.grid.v.fill(0.0);
+        self.grid.calculate_pressure_range();
+        self.grid.calculate_speed_range();
+        self.time = 0.0;
+        self.iterations = 0;
+        self.initial_norm_squared = None;
+        self.stats_history.clear();
+        if let Some(ramp) = &mut self.inflow_ramp {
+            ramp.start = 0;
+        }
+        if let Some(accumulator) = &mut self.field_accumulator {
+            accumulator.restart();
+        }
+        self.apply_forced_cells();
+        self.calculate_f_and_g();
+        self.calculate_rhs();
+        self.get_initial_norm_squared();
     }
 
-    fn calculate_norm_squared(&self) -> Real {
-        #[allow(clippy::reversed_empty_ranges)]
-        let rhses = self.rhs.slice(s![1..-1, 1..-1]);
+    /// Ramp the velocity of every inflow cell from zero up to `target` over
+    /// the next `ramp_ticks` ticks
+    ///
+    /// Starting the inflow at full speed against fluid at rest makes the
+    /// pressure of the first ticks very hard to solve for. The inflow is
+    /// stopped right away, and each tick raises it by `target / ramp_ticks`
+    /// until it reaches `target` on tick number `ramp_ticks`. All inflow cells
+    /// get the same velocity, whatever they had before.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The velocity of the inflow once the ramp is done
+    /// * `ramp_ticks` - How many ticks it takes to reach `target`. With 0 the
+    ///   inflow is at `target` from the next tick on.
+    pub fn set_inflow_ramp(&mut self, target: Velocity, ramp_ticks: u32) {
+        self.set_all_inflow_velocities([0.0, 0.0]);
+        self.inflow_ramp = Some(InflowRamp {
+            target,
+            ticks: ramp_ticks,
+            start: self.iterations,
+        });
+    }
 
-        let sums = Zip::from(self.grid.pressure.windows((3, 3)))
-            .and(rhses)
-            .fold(0.0, |acc, p_view, rhs| {
-                acc + residual(p_view, self.cell_size[0], self.cell_size[1], *rhs).powi(2)
-            });
+    /// The inflow ramp that is still running, if any
+    pub fn inflow_ramp(&self) -> Option<InflowRamp> {
+        self.inflow_ramp
+    }
 
-        sums / self.grid.boundaries.fluid_cells
+    /// Start averaging the velocity and pressure at the end of every tick
+    /// after the next `warm_up_ticks`, replacing the averages so far
+    pub fn attach_field_accumulator(&mut self, warm_up_ticks: u32) {
+        self.field_accumulator = Some(FieldAccumulator::new(self.size, warm_up_ticks));
     }
 
-    fn get_initial_norm_squared(&mut self) -> Real {
-        if let Some(norm) = self.initial_norm_squared {
-            return norm;
-        }
+    /// Stop averaging and return the averages
+    pub fn detach_field_accumulator(&mut self) -> Option<FieldAccumulator> {
+        self.field_accumulator.take()
+    }
+
+    /// The averages of the fields, if an accumulator is attached
+    pub fn field_accumulator(&self) -> Option<&FieldAccumulator> {
+        self.field_accumulator.as_ref()
+    }
+
+    fn set_all_inflow_velocities(&mut self, new: Velocity) {
+        for cell in &mut self.grid.cell_type {
+            if let Cell::Boundary(BoundaryCell::Inflow { velocity }) = cell {
+                *velocity = new;
+            }
+        }
+    }
+
+    // Set the inflow velocity for the coming tick, and forget the ramp once it
+    // has reached its target so the inflow can be changed by hand again.
+    fn apply_inflow_ramp(&mut self) {
+        let Some(ramp) = self.inflow_ramp else {
+            return;
+        };
+        self.set_all_inflow_velocities(ramp.velocity(self.iterations));
+        if ramp.is_finished(self.iterations) {
+            self.inflow_ramp = None;
+        }
+    }
+
+    // Set the velocities of the forced cells
+    fn apply_forced_cells(&mut self) {
+        for &(idx, [u, v]) in &self.forced_cells {
+            self.grid.u[idx] = u;
+            self.grid.v[idx] = v;
+        }
+    }
+
+    // The pressure is scaled by the density, so that subtracting its gradient
+    // divided by the density in set_u_and_v keeps the flow divergence-free.
+    fn calculate_rhs(&mut self) {
+        let mut rhs_view = self.rhs.slice_mut(s![1.., 1..]);
+        Zip::from(&mut rhs_view)
+            .and(self.f.windows((2, 2)))
+            .and(self.g.windows((2, 2)))
+            .for_each(|rhs, f_view, g_view| {
+                *rhs = self.density
+                    * (((f_view[(1, 1)] - f_view[(0, 1)]) / self.cell_size[0])
+                        + ((g_view[(1, 1)] - g_view[(1, 0)]) / self.cell_size[1]))
+                    / self.delt
+            });
+
+        // A source pushes fluid out through all four of its faces at the speed
+        // of its inflow velocity, so the velocity field should have that
+        // divergence there instead of none.
+        for &idx in &self.grid.boundaries.sources {
+            if let Cell::Boundary(BoundaryCell::Inflow { velocity }) =
+                self.grid.cell_type[idx]
+            {
+                let speed = velocity[0].hypot(velocity[1]);
+                let divergence =
+                    2.0 * speed * (1.0 / self.cell_size[0] + 1.0 / self.cell_size[1]);
+                self.rhs[idx] -= self.density * divergence / self.delt;
+            }
+        }
+    }
+
+    fn calculate_residual(&self) -> ResidualReport {
+        #[allow(clippy::reversed_empty_ranges)]
+        let rhses = self.rhs.slice(s![1..-1, 1..-1]);
+        #[allow(clippy::reversed_empty_ranges)]
+        let cell_types = self.grid.cell_type.slice(s![1..-1, 1..-1]);
+
+        // Only fluid cells take part in the pressure equation. Obstacle cells
+        // that aren't next to fluid never have their pressure updated, so
+        // their residual would grow as the fluid pressure drifts.
+        let (sums, l_inf, l_inf_location) =
+            Zip::indexed(self.grid.pressure.windows((3, 3)))
+                .and(rhses)
+                .and(cell_types)
+                .fold((0.0, 0.0, (0, 0)), |acc, (x, y), p_view, rhs, cell_type| {
+                    match cell_type {
+                        Cell::Fluid => {
+                            let residual = residual(
+                                p_view,
+                                self.cell_size[0],
+                                self.cell_size[1],
+                                *rhs,
+                            );
+                            let (sums, l_inf, l_inf_location) = acc;
+                            // The windows start one cell up and to the left of
+                            // the cell they're centered on.
+                            if residual.abs() > l_inf {
+                                (sums + residual.powi(2), residual.abs(), (x + 1, y + 1))
+                            } else {
+                                (sums + residual.powi(2), l_inf, l_inf_location)
+                            }
+                        }
+                        _ => acc,
+                    }
+                });
+
+        ResidualReport {
+            l2_squared: sums / self.grid.boundaries.fluid_cells,
+            l_inf,
+            l_inf_location,
+        }
+    }
+
+    /// Calculate the residual of the pressure equation in every fluid cell,
+    /// with zeros everywhere else
+    pub fn residual_field(&self) -> GridArray<Real> {
+        let mut field = Array::zeros(self.size);
+        self.residual_field_into(&mut field);
+        field
+    }
+
+    /// Like `residual_field`, but writes into `field` so that it can be reused
+    /// between frames. `field` must have the same shape as the grid.
+    pub fn residual_field_into(&self, field: &mut GridArray<Real>) {
+        field.fill(0.0);
+        #[allow(clippy::reversed_empty_ranges)]
+        let interior = field.slice_mut(s![1..-1, 1..-1]);
+        #[allow(clippy::reversed_empty_ranges)]
+        let rhses = self.rhs.slice(s![1..-1, 1..-1]);
+        #[allow(clippy::reversed_empty_ranges)]
+        let cell_types = self.grid.cell_type.slice(s![1..-1, 1..-1]);
+
+        Zip::from(interior)
+            .and(self.grid.pressure.windows((3, 3)))
+            .and(rhses)
+            .and(cell_types)
+            .for_each(|out, p_view, rhs, cell_type| {
+                if let Cell::Fluid = cell_type {
+                    *out = residual(p_view, self.cell_size[0], self.cell_size[1], *rhs);
+                }
+            });
+    }
+
+    fn calculate_norm_squared(&self) -> Real {
+        self.calculate_residual().l2_squared
+    }
+
+    fn get_initial_norm_squared(&mut self) -> Real {
+        if let Some(norm) = self.initial_norm_squared {
+            return norm;
+        }
+
+        let norm = self.calculate_norm_squared();
+        self.initial_norm_squared = Some(norm);
+        norm
+    }
+
+    // The new pressure of the cell at `idx` after one SOR step
+    #[inline]
+    fn sor_update(&self, coefficients: &SorCoefficients, (x, y): GridIndex) -> Real {
+        let SorCoefficients {
+            delx2,
+            dely2,
+            one_minus_w,
+            middle,
+        } = *coefficients;
+
+        // Note that we're modifying in place, so "minus one" values have been
+        // computed for the next step already.
+        let p_i_j = self.grid.pressure[(x, y)];
+        let p_i_m1_j = self.grid.pressure[(x - 1, y)];
+        let p_i_p1_j = self.grid.pressure[(x + 1, y)];
+        let p_i_j_m1 = self.grid.pressure[(x, y - 1)];
+        let p_i_j_p1 = self.grid.pressure[(x, y + 1)];
+        let rhs = self.rhs[(x, y)];
+
+        (one_minus_w * p_i_j)
+            + middle
+                * (((p_i_p1_j + p_i_m1_j) / delx2) + ((p_i_j_p1 + p_i_j_m1) / dely2)
+                    - rhs)
+    }
+
+    // Update the pressure of every fluid cell and source in place, in the
+    // order given by sor_ordering
+    fn sor_iteration(&mut self, coefficients: &SorCoefficients) {
+        // Each pass only updates the cells with a matching `(x + y) % 2`, or
+        // every cell if there's no parity.
+        let passes: &[Option<usize>] = match self.sor_ordering {
+            SorOrdering::Lexicographic => &[None],
+            SorOrdering::RedBlack => &[Some(0), Some(1)],
+        };
+        for parity in passes {
+            // indexing instead of iterators :(
+            for x in 1..self.size[0] - 1 {
+                // indexing instead of iterators :(
+                for y in 1..self.size[1] - 1 {
+                    if parity.is_some_and(|parity| (x + y) % 2 != parity) {
+                        continue;
+                    }
+                    // if statement in inner loop :(
+                    if let Cell::Fluid = self.grid.cell_type[(x, y)] {
+                        self.grid.pressure[(x, y)] =
+                            self.sor_update(coefficients, (x, y));
+                    }
+                }
+            }
+        }
+        // Sources take part in the pressure equation like fluid cells.
+        // Sinks hold the pressure at zero, which is what lets the fluid
+        // from the sources leave the domain.
+        for &idx in &self.grid.boundaries.sources {
+            self.grid.pressure[idx] = match self.grid.cell_type[idx] {
+                Cell::Boundary(BoundaryCell::Inflow { .. }) => {
+                    self.sor_update(coefficients, idx)
+                }
+                _ => 0.0,
+            };
+        }
+    }
+
+    // Update the pressure of every fluid cell and source from the pressure of
+    // the previous iteration only, using pressure_buffer for the new pressure
+    fn jacobi_iteration(&mut self, coefficients: &SorCoefficients) {
+        let SorCoefficients {
+            delx2,
+            dely2,
+            middle,
+            ..
+        } = *coefficients;
+
+        // Cells that aren't updated keep their pressure.
+        self.pressure_buffer.assign(&self.grid.pressure);
+        #[allow(clippy::reversed_empty_ranges)]
+        let next = self.pressure_buffer.slice_mut(s![1..-1, 1..-1]);
+        #[allow(clippy::reversed_empty_ranges)]
+        let rhses = self.rhs.slice(s![1..-1, 1..-1]);
+        #[allow(clippy::reversed_empty_ranges)]
+        let cell_types = self.grid.cell_type.slice(s![1..-1, 1..-1]);
+
+        let cells = Zip::from(next)
+            .and(self.grid.pressure.windows((3, 3)))
+            .and(rhses)
+            .and(cell_types);
+        let update =
+            |next: &mut Real, p_view: ArrayView2<Real>, rhs: &Real, cell: &Cell| {
+                if let Cell::Fluid = cell {
+                    *next = middle
+                        * (((p_view[(2, 1)] + p_view[(0, 1)]) / delx2)
+                            + ((p_view[(1, 2)] + p_view[(1, 0)]) / dely2)
+                            - rhs);
+                }
+            };
+        #[cfg(feature = "parallel")]
+        cells.par_for_each(update);
+        #[cfg(not(feature = "parallel"))]
+        cells.for_each(update);
+
+        for &idx in &self.grid.boundaries.sources {
+            self.pressure_buffer[idx] = match self.grid.cell_type[idx] {
+                Cell::Boundary(BoundaryCell::Inflow { .. }) => {
+                    self.sor_update(coefficients, idx)
+                }
+                _ => 0.0,
+            };
+        }
+        std::mem::swap(&mut self.grid.pressure, &mut self.pressure_buffer);
+    }
+
+    fn solve_sor(&mut self) -> Result<TickStats, SimulationError> {
+        let epsilon_squared = self.sor_absolute_epsilon.powi(2);
+        let tick_initial_norm_squared = match self.sor_stopping_criterion {
+            SorStoppingCriterion::Relative { .. } => {
+                self.grid.copy_pressure_to_boundaries()?;
+                self.calculate_norm_squared()
+            }
+            _ => 0.0,
+        };
+        // A pressure that already solves the equation, e.g. for fluid at rest,
+        // has nothing left to reduce by the factor.
+        if tick_initial_norm_squared == 0.0
+            && matches!(
+                self.sor_stopping_criterion,
+                SorStoppingCriterion::Relative { .. }
+            )
+        {
+            return Ok(TickStats {
+                sor_iterations: 0,
+                convergence: ConvergenceStatus::Converged,
+                residual: self.calculate_residual(),
+                ..Default::default()
+            });
+        }
+
+        let mut report = ResidualReport::default();
+        let mut last_norm_squared = Real::INFINITY;
+        let mut growing_iterations = 0;
+
+        // Jacobi diverges with omega > 1, so it always uses omega = 1.
+        let target_omega = match self.pressure_solver {
+            PressureSolver::Sor => self.omega,
+            PressureSolver::Jacobi => 1.0,
+        };
+        // High omegas can make SOR diverge on the first ticks of a new flow,
+        // when the pressure is far from the solution. With omega_ramp, plain
+        // Gauss-Seidel (omega = 1) runs until the norm is decreasing steadily
+        // before stepping up to the configured omega.
+        let mut omega = target_omega;
+        let mut coefficients = match self.pressure_solver {
+            PressureSolver::Sor => self.sor_coefficients,
+            PressureSolver::Jacobi => SorCoefficients::new(omega, self.cell_size),
+        };
+        let mut decreasing_iterations = 0;
+        if self.omega_ramp && target_omega > 1.0 {
+            omega = 1.0;
+            coefficients = SorCoefficients::new(omega, self.cell_size);
+        }
+
+        if self.pressure_solver == PressureSolver::Jacobi
+            && self.pressure_buffer.dim() != self.grid.pressure.dim()
+        {
+            self.pressure_buffer = Array::zeros(self.size);
+        }
+
+        for i in 0..self.max_iterations {
+            self.grid.copy_pressure_to_boundaries()?;
+            match self.pressure_solver {
+                PressureSolver::Sor => self.sor_iteration(&coefficients),
+                PressureSolver::Jacobi => self.jacobi_iteration(&coefficients),
+            }
+
+            report = self.calculate_residual();
+            let norm_squared = report.l2_squared;
+
+            let converged = match self.sor_stopping_criterion {
+                SorStoppingCriterion::AbsoluteOrImproved => {
+                    let initial_norm_squared = self.get_initial_norm_squared();
+                    (norm_squared < initial_norm_squared)
+                        || (norm_squared < epsilon_squared)
+                }
+                SorStoppingCriterion::Absolute { epsilon } => {
+                    norm_squared < epsilon.powi(2)
+                }
+                SorStoppingCriterion::Relative { factor } => {
+                    norm_squared < factor.powi(2) * tick_initial_norm_squared
+                }
+            };
+            if converged {
+                return Ok(TickStats {
+                    sor_iterations: i + 1,
+                    convergence: ConvergenceStatus::Converged,
+                    residual: report,
+                    ..Default::default()
+                });
+            }
+
+            // NaN norms count as growing so they're reported as divergence.
+            #[allow(clippy::neg_cmp_op_on_partial_ord)]
+            if !(norm_squared < SOR_DIVERGENCE_GROWTH * last_norm_squared) {
+                growing_iterations += 1;
+                if growing_iterations >= self.sor_divergence_limit {
+                    warn!(
+                        "Tick {}: SOR diverged at iteration {} with a squared norm of {}",
+                        self.iterations + 1,
+                        i + 1,
+                        norm_squared
+                    );
+                    return Err(SimulationError::SorDiverged {
+                        iteration: i + 1,
+                        norm_squared,
+                    });
+                }
+            } else {
+                growing_iterations = 0;
+                if self.sor_stagnation_factor > 0.0
+                    && last_norm_squared - norm_squared
+                        < self.sor_stagnation_factor * last_norm_squared
+                {
+                    return Ok(TickStats {
+                        sor_iterations: i + 1,
+                        convergence: ConvergenceStatus::Stagnated,
+                        residual: report,
+                        ..Default::default()
+                    });
+                }
+            }
+            if omega < target_omega {
+                if norm_squared < last_norm_squared {
+                    decreasing_iterations += 1;
+                } else {
+                    decreasing_iterations = 0;
+                }
+                if decreasing_iterations >= OMEGA_RAMP_START {
+                    omega = (omega + (target_omega - 1.0) / OMEGA_RAMP_STEPS as Real)
+                        .min(target_omega);
+                    coefficients = SorCoefficients::new(omega, self.cell_size);
+                }
+            }
+            last_norm_squared = norm_squared;
+        }
+        self.grid.calculate_pressure_range();
+        Ok(TickStats {
+            sor_iterations: self.max_iterations,
+            convergence: ConvergenceStatus::MaxIterationsReached,
+            residual: report,
+            ..Default::default()
+        })
+    }
+
+    pub fn set_u_and_v(&mut self) {
+        #[allow(clippy::reversed_empty_ranges)]
+        let mut u_view = self.grid.u.slice_mut(s![0..-1, 0..-1]);
+        #[allow(clippy::reversed_empty_ranges)]
+        let mut v_view = self.grid.v.slice_mut(s![0..-1, 0..-1]);
+
+        if self.time_scheme == TimeScheme::ExplicitEuler {
+            #[allow(clippy::reversed_empty_ranges)]
+            u_view.assign(&self.f.slice(s![0..-1, 0..-1]));
+            #[allow(clippy::reversed_empty_ranges)]
+            v_view.assign(&self.g.slice(s![0..-1, 0..-1]));
+            self.finish_u_and_v();
+            return;
+        }
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let f_view = self.f.slice_mut(s![0..-1, 0..-1]);
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let g_view = self.g.slice_mut(s![0..-1, 0..-1]);
+
+        Zip::from(&mut u_view)
+            .and(&mut v_view)
+            .and(self.grid.pressure.windows((2, 2)))
+            .and(f_view)
+            .and(g_view)
+            .for_each(|u, v, p_view, f, g| {
+                let p_i_j = p_view[(0, 0)];
+                let p_i_p1_j = p_view[(1, 0)];
+                let p_i_j_p1 = p_view[(0, 1)];
+
+                *u = *f
+                    - (self.delt / (self.density * self.cell_size[0]))
+                        * (p_i_p1_j - p_i_j);
+                *v = *g
+                    - (self.delt / (self.density * self.cell_size[1]))
+                        * (p_i_j_p1 - p_i_j);
+            });
+        self.finish_u_and_v();
+    }
+
+    /// Restore the boundary velocities that the update of u and v overwrote
+    /// and hold the forced cells at their velocity
+    fn finish_u_and_v(&mut self) {
+        for (idx, maybe_u, maybe_v) in &self.grid.boundaries.u_v_restore {
+            if let Some(u) = maybe_u {
+                self.grid.u[*idx] = *u;
+            }
+            if let Some(v) = maybe_v {
+                self.grid.v[*idx] = *v;
+            }
+        }
+        self.apply_forced_cells();
+        self.grid.calculate_speed_range();
+    }
+
+    pub fn run_simulation_tick(&mut self) -> Result<TickStats, SimulationError> {
+        let start = Instant::now();
+        let mut timings = PhaseTimings::default();
+        if self.auto_gamma {
+            self.gamma = self.recommended_gamma();
+        }
+        self.apply_inflow_ramp();
+        timed!(
+            timings,
+            set_boundary_u_and_v,
+            self.grid.set_boundary_u_and_v(self.delt, self.cell_size)?
+        );
+        timed!(timings, calculate_f_and_g, self.calculate_f_and_g());
+        let mut stats = match self.time_scheme {
+            TimeScheme::ProjectionSor => {
+                timed!(timings, calculate_rhs, self.calculate_rhs());
+                // solve_sor recomputes the norm after its first iteration.
+                if self.norm_reset.is_due(self.iterations) {
+                    self.initial_norm_squared = None;
+                }
+                timed!(timings, solve_sor, self.solve_sor()?)
+            }
+            TimeScheme::ExplicitEuler => TickStats::default(),
+        };
+        timed!(timings, set_u_and_v, self.set_u_and_v());
+        self.time += self.delt;
+        self.iterations += 1;
+        stats.cfl = self.cfl_number();
+        timings.total = start.elapsed();
+        #[cfg(feature = "profiling")]
+        self.timings.record(timings);
+        stats.timings = timings;
+        debug!(
+            "Tick {}: time {:.4}, {} SOR iterations ({:?}), residual {}, CFL {:.3}",
+            self.iterations,
+            self.time,
+            stats.sor_iterations,
+            stats.convergence,
+            stats.residual,
+            stats.cfl
+        );
+        self.record_stats(stats);
+        if let Some(accumulator) = &mut self.field_accumulator {
+            accumulator.record(&self.grid);
+        }
+        Ok(stats)
+    }
+
+    /// Log the parameters the simulation runs with, once they're all known,
+    /// e.g. after `Omega::Auto` picked the SOR omega
+    pub fn log_parameters(&self) {
+        for line in self.parameter_summary() {
+            info!("{}", line);
+        }
+    }
+
+    /// The parameters of the simulation and of the SOR solver, one line each
+    pub fn parameter_summary(&self) -> [String; 2] {
+        [
+            format!(
+                "{}x{} cells of {}x{}, delt {}, gamma {}{}, Reynolds {}, density {}, \
+                 body force {:?}",
+                self.size[0],
+                self.size[1],
+                self.cell_size[0],
+                self.cell_size[1],
+                self.delt,
+                self.gamma,
+                if self.auto_gamma { " (auto)" } else { "" },
+                self.reynolds,
+                self.density,
+                self.body_force
+            ),
+            format!(
+                "SOR omega {}{}, {:?} ordering, epsilon {}, at most {} iterations, \
+                 {:?} stopping, norm reset {:?}",
+                self.omega,
+                if self.omega_ramp { " (ramped)" } else { "" },
+                self.sor_ordering,
+                self.sor_absolute_epsilon,
+                self.max_iterations,
+                self.sor_stopping_criterion,
+                self.norm_reset
+            ),
+        ]
+    }
+
+    /// The stats of the most recent ticks, oldest first
+    ///
+    /// At most `memory_budget.max_stats` ticks are kept.
+    pub fn stats_history(&self) -> &VecDeque<TickStats> {
+        &self.stats_history
+    }
+
+    // Add `stats` to the history, dropping the oldest entries that are over
+    // the budget
+    fn record_stats(&mut self, stats: TickStats) {
+        let max = self.memory_budget.max_stats;
+        while !self.stats_history.is_empty() && self.stats_history.len() >= max {
+            self.stats_history.pop_front();
+        }
+        if max > 0 {
+            self.stats_history.push_back(stats);
+        }
+    }
+
+    // The stages of `run_simulation_tick`, exposed so the benchmarks in
+    // `benches/` can time them separately. Not part of the public API.
+
+    #[doc(hidden)]
+    pub fn bench_calculate_f_and_g(&mut self) {
+        self.calculate_f_and_g();
+    }
+
+    #[doc(hidden)]
+    pub fn bench_calculate_rhs(&mut self) {
+        self.calculate_rhs();
+    }
+
+    #[doc(hidden)]
+    pub fn bench_solve_sor(&mut self) -> Result<TickStats, SimulationError> {
+        self.solve_sor()
+    }
+}
+
+/// Calculate F (the horizontal non-pressure part of the momentum equation)
+///
+/// # Arguments
+///
+/// * `u_view` - A 3x3-element ArrayView2 representing
+///   u[(i-1) to (i+1), (j-1) to (j+1)].
+/// * `v_view` - A 3x3-element ArrayView2 representing
+///   v[(i-1) to (i+1), (j-1) to (j+1)].
+/// * `delx` - "delta x," the physical width of the cell
+/// * `dely` - "delta y," the physical width of the cell
+/// * `delt` - "delta t," the amount of time per time step
+/// * `gamma` - Greek letter gamma, the upwind discretization parameter
+/// * `reynolds` - The Reynolds number for the simulation
+/// * `gx` - The external body force in the x direction
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_f(
+    u_view: ArrayView2<Real>,
+    v_view: ArrayView2<Real>,
+    delx: Real,
+    dely: Real,
+    delt: Real,
+    gamma: Real,
+    reynolds: Real,
+    gx: Real,
+) -> Real {
+    u_view[(1, 1)]
+        + (delt
+            * ((laplacian(u_view, delx, dely) / reynolds)
+                - du2dx(u_view, delx, gamma)
+                - duvdy(u_view, v_view, dely, gamma)
+                + gx))
+}
+
+/// Calculate G (the vertical non-pressure part of the momentum equation)
+///
+/// # Arguments
+///
+/// * `u_view` - A 3x3-element ArrayView2 representing
+///   u[(i-1) to (i+1), (j-1) to (j+1)].
+/// * `v_view` - A 3x3-element ArrayView2 representing
+///   v[(i-1) to (i+1), (j-1) to (j+1)].
+/// * `delx` - "delta x," the physical width of the cell
+/// * `dely` - "delta y," the physical width of the cell
+/// * `delt` - "delta t," the amount of time per time step
+/// * `gamma` - Greek letter gamma, the upwind discretization parameter
+/// * `reynolds` - The Reynolds number for the simulation
+/// * `gy` - The external body force in the y direction
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_g(
+    u_view: ArrayView2<Real>,
+    v_view: ArrayView2<Real>,
+    delx: Real,
+    dely: Real,
+    delt: Real,
+    gamma: Real,
+    reynolds: Real,
+    gy: Real,
+) -> Real {
+    v_view[(1, 1)]
+        + (delt
+            * ((laplacian(v_view, delx, dely) / reynolds)
+                - duvdx(u_view, v_view, delx, gamma)
+                - dv2dy(v_view, dely, gamma)
+                + gy))
+}
+
+#[cfg(test)]
+// The expected values are written with the precision of f64.
+#[cfg_attr(feature = "single-precision", allow(clippy::excessive_precision))]
+mod tests {
+    use super::*;
+    use ndarray::{array, ArrayView2};
+    use std::cell::RefCell;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::{Path, PathBuf};
+
+    use crate::args::Omega;
+    use crate::builder::SimulationBuilder;
+    use crate::grid::presets::{self, Walls};
+    use crate::grid::SimulationGrid;
+    use crate::math::{assert_close, precision_snapshots};
+    use crate::types::Velocity;
+
+    fn test_data_directory() -> PathBuf {
+        Path::new(file!()).parent().unwrap().join("test_data")
+    }
+
+    #[test]
+    fn deserialize() {
+        let _snapshots = precision_snapshots();
+        for test_filename in [
+            test_data_directory().join("simple_simulation.json"),
+            test_data_directory().join("small_simulation_with_boundaries.json"),
+        ] {
+            let result = Simulation::from_reader(BufReader::new(
+                File::open(test_filename).unwrap(),
+            ))
+            .unwrap();
+            insta::assert_json_snapshot!(result);
+        }
+    }
+
+    #[test]
+    fn serialize() {
+        let size = [5, 7];
+        let cell_size = [1., 2.];
+        let delt = 1.4;
+        let gamma = 0.7;
+        let reynolds = 100.;
+
+        let simulation = SimulationBuilder::new()
+            .grid(presets::empty(size))
+            .cell_size(cell_size)
+            .delt(delt)
+            .gamma(gamma)
+            .reynolds(reynolds)
+            .build()
+            .unwrap();
+
+        insta::assert_json_snapshot!(simulation);
+    }
+
+    #[test]
+    fn checkpoint() {
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(
+                [12, 6],
+                [1.0, 0.0],
+                Walls::default(),
+            ))
+            .build()
+            .unwrap();
+        for _ in 0..5 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        for name in ["checkpoint.json", "checkpoint.msgpack"] {
+            let path = std::env::temp_dir().join(format!(
+                "stroemung-{}-{}",
+                std::process::id(),
+                name
+            ));
+            sim.save_checkpoint(&path).unwrap();
+            let mut resumed = Simulation::load_checkpoint(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(resumed.iterations, 5);
+            assert_eq!(resumed.time, sim.time);
+            assert_eq!(resumed.initial_norm_squared, sim.initial_norm_squared);
+
+            let mut original = sim.clone();
+            for _ in 0..3 {
+                original.run_simulation_tick().unwrap();
+                resumed.run_simulation_tick().unwrap();
+            }
+            assert_eq!(resumed.iterations, 8);
+            assert_eq!(resumed.approx_eq(&original, 0.0), None);
+        }
+
+        let missing = Simulation::load_checkpoint(Path::new("does-not-exist.json"));
+        assert!(matches!(missing, Err(SimulationError::IoError(_))));
+    }
+
+    #[test]
+    fn domain_extent() {
+        let sim = SimulationBuilder::new()
+            .grid(presets::empty([10, 5]))
+            .cell_size([0.1, 0.2])
+            .build()
+            .unwrap();
+        let (width, height) = sim.domain_extent();
+        assert_close!(width, 1.0);
+        assert_close!(height, 1.0);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let size = [10, 6];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .body_force([0.1, 0.2])
+            .sor_stopping_criterion(SorStoppingCriterion::Relative { factor: 0.1 })
+            .sor_ordering(SorOrdering::RedBlack)
+            .build()
+            .unwrap();
+        for _ in 0..20 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        let json = sim.to_json_pretty();
+        let mut reloaded = Simulation::from_json_str(&json).unwrap();
+        assert_eq!(reloaded, sim);
+        assert_eq!(reloaded.to_json_pretty(), json);
+        let mut checkpoint = sim.clone();
+
+        // Everything but the timings is reproducible.
+        let [expected, actual] = [&mut sim, &mut reloaded].map(|sim| TickStats {
+            timings: PhaseTimings::default(),
+            ..sim.run_simulation_tick().unwrap()
+        });
+        assert_eq!(actual, expected);
+        assert_eq!(reloaded.to_json_pretty(), sim.to_json_pretty());
+
+        // Resuming from a checkpoint gives the same flow bit for bit.
+        for _ in 0..10 {
+            sim.run_simulation_tick().unwrap();
+            reloaded.run_simulation_tick().unwrap();
+        }
+        for _ in 0..11 {
+            checkpoint.run_simulation_tick().unwrap();
+        }
+        assert_eq!(reloaded.approx_eq(&sim, 0.0), None);
+        assert_eq!(checkpoint.approx_eq(&sim, 0.0), None);
+
+        // MessagePack holds the same simulation as JSON, in less space.
+        let mut files = Vec::new();
+        for format in [Format::Json, Format::MessagePack] {
+            let mut file = Vec::new();
+            sim.to_writer_format(&mut file, format).unwrap();
+            let reloaded =
+                Simulation::from_reader_format(file.as_slice(), format).unwrap();
+            assert_eq!(reloaded, sim);
+            assert_eq!(reloaded.to_json_pretty(), sim.to_json_pretty());
+            files.push(file);
+        }
+        assert!(files[1].len() < files[0].len() / 2);
+        assert!(
+            Simulation::from_reader_format(files[0].as_slice(), Format::MessagePack)
+                .is_err()
+        );
+        assert_eq!(
+            Format::from_path(Path::new("runs/big.msgpack")),
+            Format::MessagePack
+        );
+        assert_eq!(Format::from_path(Path::new("big.MPK")), Format::MessagePack);
+        assert_eq!(
+            Format::from_path(Path::new("simulation.json")),
+            Format::Json
+        );
+
+        // Parameters are validated on load.
+        let edited = json.replace("\"reynolds\": 100.0", "\"reynolds\": -1.0");
+        assert!(matches!(
+            Simulation::from_json_str(&edited),
+            Err(SimulationError::InvalidParameter {
+                field: "reynolds",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn norm_reset_policy() {
+        let size = [20, 8];
+        let simulation = |norm_reset, sor_stopping_criterion, max_iterations| {
+            SimulationBuilder::new()
+                .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+                .max_iterations(max_iterations)
+                .sor_stopping_criterion(sor_stopping_criterion)
+                .norm_reset(norm_reset)
+                .build()
+                .unwrap()
+        };
+        let improved = SorStoppingCriterion::AbsoluteOrImproved;
+        let mut never = simulation(NormReset::Never, improved, 100);
+        let mut every_tick = simulation(NormReset::EveryTick, improved, 100);
+        let mut reference = simulation(
+            NormReset::Never,
+            SorStoppingCriterion::Absolute { epsilon: 1e-6 },
+            500,
+        );
+
+        for tick in 0..20 {
+            never.run_simulation_tick().unwrap();
+            let iterations = every_tick.run_simulation_tick().unwrap().sor_iterations;
+            reference.run_simulation_tick().unwrap();
+            // The norm after the first iteration of each tick is the one to
+            // improve on, so SOR always runs at least twice.
+            if tick >= 10 {
+                assert!(iterations > 1);
+            }
+        }
+
+        let max_difference = |a: &GridArray<Real>, b: &GridArray<Real>| {
+            Zip::from(a)
+                .and(b)
+                .fold(0.0, |acc: Real, a, b| acc.max((a - b).abs()))
+        };
+        let pressure_gradient = |sim: &Simulation| {
+            &sim.grid.pressure.slice(s![1.., ..]) - &sim.grid.pressure.slice(s![..-1, ..])
+        };
+        assert!(
+            max_difference(&pressure_gradient(&never), &pressure_gradient(&every_tick))
+                > 1.0
+        );
+
+        // Never keeps the norm of the initial, motionless state, which is 0, so
+        // SOR runs until it reaches the absolute epsilon and closely follows
+        // the reference. Resetting every tick stops SOR as soon as the norm
+        // improves and drifts away from it. Single precision can't get as
+        // close to the reference's epsilon.
+        let tolerance = if cfg!(feature = "single-precision") {
+            1e-2
+        } else {
+            1e-4
+        };
+        assert!(max_difference(&never.grid.u, &reference.grid.u) < tolerance);
+        assert!(max_difference(&every_tick.grid.u, &reference.grid.u) > 0.1);
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let size = [4, 3];
+        let unfinalized = || UnfinalizedSimulation {
+            size,
+            cell_size: [0.1, 0.2],
+            delt: 0.005,
+            gamma: 0.9,
+            auto_gamma: false,
+            reynolds: 100.0,
+            density: 1.0,
+            body_force: [0.0, 0.0],
+            sor_absolute_epsilon: 0.001,
+            max_iterations: 100,
+            sor_divergence_limit: 10,
+            sor_stagnation_factor: 0.0,
+            sor_stopping_criterion: SorStoppingCriterion::AbsoluteOrImproved,
+            initial_norm_squared: None,
+            norm_reset: NormReset::Never,
+            iterations: 0,
+            time: 0.0,
+            omega: 1.7,
+            omega_ramp: false,
+            forced_cells: Vec::new(),
+            sor_ordering: SorOrdering::Lexicographic,
+            pressure_solver: PressureSolver::Sor,
+            time_scheme: TimeScheme::ProjectionSor,
+            memory_budget: MemoryBudget::default(),
+            inflow_ramp: None,
+            grid: presets::simple_inflow(size, [1.0, 0.0], Walls::default()).into(),
+        };
+
+        let test_cases: Vec<(&str, UnfinalizedSimulation)> = vec![
+            (
+                "reynolds",
+                UnfinalizedSimulation {
+                    reynolds: 0.0,
+                    density: 1.0,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "reynolds",
+                UnfinalizedSimulation {
+                    reynolds: Real::NAN,
+                    density: 1.0,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "density",
+                UnfinalizedSimulation {
+                    density: 0.0,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "gamma",
+                UnfinalizedSimulation {
+                    gamma: 1.5,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "gamma",
+                UnfinalizedSimulation {
+                    gamma: Real::NAN,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "omega",
+                UnfinalizedSimulation {
+                    omega: 2.5,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "omega",
+                UnfinalizedSimulation {
+                    omega: 0.0,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "cell_size[0]",
+                UnfinalizedSimulation {
+                    cell_size: [0.0, 0.2],
+                    ..unfinalized()
+                },
+            ),
+            (
+                "cell_size[1]",
+                UnfinalizedSimulation {
+                    cell_size: [0.1, -0.2],
+                    ..unfinalized()
+                },
+            ),
+            (
+                "delt",
+                UnfinalizedSimulation {
+                    delt: 0.0,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "max_iterations",
+                UnfinalizedSimulation {
+                    max_iterations: 0,
+                    ..unfinalized()
+                },
+            ),
+            (
+                "norm_reset",
+                UnfinalizedSimulation {
+                    norm_reset: NormReset::EveryNTicks(0),
+                    ..unfinalized()
+                },
+            ),
+        ];
+
+        for (expected_field, unfinalized) in test_cases {
+            match Simulation::try_from(unfinalized) {
+                Err(SimulationError::InvalidParameter { field, .. }) => {
+                    assert_eq!(field, expected_field)
+                }
+                other => {
+                    panic!("expected an invalid {}, got {:?}", expected_field, other)
+                }
+            }
+        }
+
+        assert!(Simulation::try_from(unfinalized()).is_ok());
+    }
+
+    #[test]
+    fn profiles() {
+        let size = [6, 4];
+        let mut grid = presets::empty(size);
+        grid.u = Array::from_shape_fn(size, |(x, y)| (x + 10 * y) as Real);
+        grid.v.fill(2.0);
+        grid.pressure = Array::from_shape_fn(size, |(x, y)| (x * y) as Real);
+        let sim = SimulationBuilder::new()
+            .grid(grid)
+            .cell_size([0.5, 0.25])
+            .build()
+            .unwrap();
+
+        let row = sim.profile_x(2);
+        assert_eq!(row.len(), size[0]);
+        let xs: Vec<Real> = row.iter().map(|entry| entry.0).collect();
+        assert_eq!(xs, vec![0.25, 0.75, 1.25, 1.75, 2.25, 2.75]);
+        // The u of cell 3 is the average of its west and east faces.
+        assert_eq!(row[3], (1.75, 6.0, 22.5, 2.0));
+        // The edges of the grid use their one inner face.
+        assert_eq!(row[0].2, 20.0);
+        assert_eq!(row[5].2, 24.0);
+
+        let column = sim.profile_y(4);
+        assert_eq!(column.len(), size[1]);
+        let ys: Vec<Real> = column.iter().map(|entry| entry.0).collect();
+        assert_eq!(ys, vec![0.125, 0.375, 0.625, 0.875]);
+        assert_eq!(column[1], (0.375, 4.0, 13.5, 2.0));
+    }
+
+    #[test]
+    fn reset_fields() {
+        let simulation = |grid| {
+            SimulationBuilder::new()
+                .grid(grid)
+                .forced_cells(vec![((3, 3), [0.0, 0.5])])
+                .build()
+                .unwrap()
+        };
+        let mut sim = simulation(presets::simple_inflow(
+            [16, 8],
+            [1.0, 0.0],
+            Walls::default(),
+        ));
+        for _ in 0..5 {
+            sim.run_simulation_tick().unwrap();
+        }
+        let obstacle = [(8, 3), (9, 3), (8, 4), (9, 4)];
+        let no_slip = Cell::Boundary(BoundaryCell::NoSlip);
+        sim.grid.set_cells(obstacle, no_slip).unwrap();
+        for _ in 0..5 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        let mut grid = sim.grid.clone();
+        grid.pressure.fill(0.0);
+        grid.u.fill(0.0);
+        grid.v.fill(0.0);
+        let mut fresh = simulation(grid);
+        sim.reset_fields();
+        assert!(obstacle
+            .iter()
+            .all(|&idx| sim.grid.cell_type[idx] == no_slip));
+        assert_eq!(sim.approx_eq(&fresh, 0.0), None);
+        assert!(sim.stats_history().is_empty());
+
+        for _ in 0..3 {
+            sim.run_simulation_tick().unwrap();
+            fresh.run_simulation_tick().unwrap();
+        }
+        assert_eq!(sim.approx_eq(&fresh, 0.0), None);
+    }
+
+    #[test]
+    fn free_stream() {
+        let size = [30, 12];
+        // The largest difference from the uniform inflow in u and v after
+        // `ticks` ticks
+        let run = |walls, initial_velocity, ticks| {
+            let mut sim = SimulationBuilder::new()
+                .grid(presets::simple_inflow(size, [1.0, 0.0], walls))
+                .build()
+                .unwrap();
+            sim.grid.set_uniform_velocity(initial_velocity);
+            for _ in 0..ticks {
+                sim.run_simulation_tick().unwrap();
+            }
+            let mut worst: [Real; 2] = [0.0, 0.0];
+            for x in 1..size[0] - 1 {
+                for y in 1..size[1] - 1 {
+                    worst[0] = worst[0].max((sim.grid.u[(x, y)] - 1.0).abs());
+                    worst[1] = worst[1].max(sim.grid.v[(x, y)].abs());
+                }
+            }
+            worst
+        };
+
+        // Uniform flow stays uniform, without a boundary layer at the walls.
+        let [u, v] = run(Walls::free_stream(), [1.0, 0.0], 40);
+        assert!(u < 1e-5 && v < 1e-5, "{} {}", u, v);
+        let [u, _] = run(Walls::default(), [1.0, 0.0], 40);
+        assert!(u > 0.05, "{}", u);
+
+        // Fluid at rest is pushed up to the speed of the inflow everywhere.
+        let [u, v] = run(Walls::free_stream(), [0.0, 0.0], 40);
+        assert!(u < 0.02 && v < 0.01, "{} {}", u, v);
+    }
+
+    #[test]
+    fn inflow_ramp() {
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(
+                [12, 6],
+                [2.0, 0.0],
+                Walls::default(),
+            ))
+            .build()
+            .unwrap();
+        let inflow = |sim: &Simulation| sim.grid.inflow_groups();
+        let ramp_ticks = 4;
+        sim.set_inflow_ramp([1.0, 0.5], ramp_ticks);
+        assert_eq!(inflow(&sim), vec![([0.0, 0.0], 4)]);
+
+        for tick in 1..=ramp_ticks {
+            sim.run_simulation_tick().unwrap();
+            let fraction = tick as Real / ramp_ticks as Real;
+            assert_eq!(inflow(&sim), vec![([fraction, 0.5 * fraction], 4)]);
+        }
+        assert_eq!(sim.inflow_ramp(), None);
+
+        // Once the ramp is done the inflow can be changed again.
+        sim.grid.set_inflow_velocity([1.0, 0.5], [3.0, 0.0]);
+        sim.run_simulation_tick().unwrap();
+        assert_eq!(inflow(&sim), vec![([3.0, 0.0], 4)]);
+
+        // A ramp started later counts from the tick it started at.
+        sim.set_inflow_ramp([2.0, 0.0], 2);
+        sim.run_simulation_tick().unwrap();
+        assert_eq!(inflow(&sim), vec![([1.0, 0.0], 4)]);
+        sim.run_simulation_tick().unwrap();
+        assert_eq!(inflow(&sim), vec![([2.0, 0.0], 4)]);
+    }
+
+    #[test]
+    fn taylor_green_vortex() {
+        let size = [34, 34];
+        let reynolds = 10.0;
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::taylor_green(size))
+            .cell_size(presets::taylor_green_cell_size(size))
+            .reynolds(reynolds)
+            .gamma(0.0)
+            .delt(0.01)
+            .build()
+            .unwrap();
+        // (sin² x cos² y + cos² x sin² y) / 2 over [0, π]² is π² / 4.
+        let initial = sim.kinetic_energy();
+        let analytic = consts::PI.powi(2) / 4.0;
+        assert!((initial / analytic - 1.0).abs() < 1e-3, "{}", initial);
+
+        for _ in 0..50 {
+            sim.run_simulation_tick().unwrap();
+        }
+        let decay = sim.kinetic_energy() / initial;
+        let expected = presets::taylor_green_decay(sim.time, reynolds).powi(2);
+        assert!(
+            (decay / expected - 1.0).abs() < 0.01,
+            "{} instead of {}",
+            decay,
+            expected
+        );
+    }
+
+    #[test]
+    fn runtime_parameters() {
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(
+                [20, 10],
+                [1.0, 0.0],
+                Walls::default(),
+            ))
+            .auto_gamma(true)
+            .build()
+            .unwrap();
+        sim.run_simulation_tick().unwrap();
+
+        sim.set_parameter(Parameter::Reynolds, 400.0).unwrap();
+        sim.set_parameter(Parameter::Omega, 1.5).unwrap();
+        sim.set_parameter(Parameter::Delt, 0.002).unwrap();
+        sim.set_parameter(Parameter::Gamma, 0.5).unwrap();
+        assert_eq!(sim.reynolds, 400.0);
+        assert_eq!(sim.omega(), 1.5);
+        assert!(!sim.auto_gamma);
+        let time = sim.time;
+        sim.run_simulation_tick().unwrap();
+        assert_close!(sim.time, time + 0.002);
+        assert_eq!(sim.parameter(Parameter::Gamma), 0.5);
+
+        // Invalid values leave the simulation as it was.
+        for (parameter, value) in [
+            (Parameter::Reynolds, 0.0),
+            (Parameter::Omega, 2.0),
+            (Parameter::Delt, -0.001),
+            (Parameter::Gamma, Real::NAN),
+        ] {
+            let old = sim.parameter(parameter);
+            assert!(matches!(
+                sim.set_parameter(parameter, value),
+                Err(SimulationError::InvalidParameter { field, .. })
+                    if field == parameter.name()
+            ));
+            assert_eq!(sim.parameter(parameter), old);
+        }
+        sim.run_simulation_tick().unwrap();
+    }
+
+    #[test]
+    fn load_double_precision() {
+        // Values that single precision has to round
+        let delt: f64 = 0.00123456789012345;
+        let u: f64 = 0.987654321098765;
+        let sim = sor_simulation(SorOrdering::Lexicographic, 100);
+        let mut value = serde_json::to_value(&sim).unwrap();
+        value["delt"] = delt.into();
+        for data in value["grid"]["u"]["data"].as_array_mut().unwrap() {
+            *data = u.into();
+        }
+
+        // Both formats write the values as f64.
+        let json = serde_json::to_vec(&value).unwrap();
+        let msgpack = rmp_serde::to_vec_named(&value).unwrap();
+        for (bytes, format) in [(json, Format::Json), (msgpack, Format::MessagePack)] {
+            let loaded =
+                Simulation::from_reader_format(bytes.as_slice(), format).unwrap();
+            assert_eq!(loaded.delt, delt as Real);
+            assert!(loaded.grid.u.iter().all(|&value| value == u as Real));
+            assert_close!(loaded.grid.pressure[(2, 2)], sim.grid.pressure[(2, 2)]);
+        }
+    }
+
+    #[test]
+    fn approx_eq() {
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+        sim.run_simulation_tick().unwrap();
+        let original = sim.clone();
+        assert_eq!(sim, original);
+
+        // The worst mismatch is reported, and small ones are tolerated.
+        sim.grid.u[(2, 3)] += 1e-9;
+        sim.grid.u[(4, 2)] -= 1e-3;
+        assert_ne!(sim, original);
+        assert_eq!(sim.approx_eq(&original, 1e-6), {
+            let u = original.grid.u[(4, 2)];
+            Some(FieldDifference::new("u", Some((4, 2)), u - 1e-3, u))
+        });
+        assert_eq!(sim.approx_eq(&original, 1e-2), None);
+        sim.grid.pressure[(1, 1)] = Real::NAN;
+        assert_eq!(
+            sim.approx_eq(&original, 1e-2)
+                .map(|difference| difference.field),
+            Some("pressure")
+        );
+
+        // Parameters are compared before the grid.
+        let mut sim = original.clone();
+        sim.reynolds = 250.0;
+        sim.grid.cell_type[(3, 3)] = Cell::Boundary(BoundaryCell::NoSlip);
+        let difference = sim.approx_eq(&original, 1.0).unwrap();
+        assert_eq!(difference.to_string(), "`reynolds` differs: 250.0 != 100.0");
+        sim.reynolds = original.reynolds;
+        assert_eq!(
+            sim.approx_eq(&original, 1.0).unwrap().to_string(),
+            "`cell_type` differs at (3, 3): Boundary(NoSlip) != Fluid"
+        );
+        assert_ne!(sim.grid, original.grid);
+    }
+
+    thread_local! {
+        // The messages logged by the current thread, so that tests running
+        // in parallel don't see each other's
+        static LOG_RECORDS: RefCell<Vec<(log::Level, String)>> =
+            const { RefCell::new(Vec::new()) };
+    }
+
+    struct CapturingLogger;
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOG_RECORDS.with_borrow_mut(|records| {
+                records.push((record.level(), record.args().to_string()))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger;
+
+    // Send everything that's logged to LOG_RECORDS
+    fn capture_logs() {
+        // Only the first test to get here can install the logger.
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+
+    #[test]
+    fn tick_logging() {
+        capture_logs();
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+
+        LOG_RECORDS.with_borrow_mut(Vec::clear);
+        let stats = sim.run_simulation_tick().unwrap();
+        let records = LOG_RECORDS.take();
+        assert_eq!(records.len(), 1);
+        let (level, message) = &records[0];
+        assert_eq!(*level, log::Level::Debug);
+        assert!(message.starts_with(&format!("Tick {}: ", sim.iterations)));
+        assert!(message.contains(&format!("{} SOR iterations", stats.sor_iterations)));
+
+        sim.log_parameters();
+        let records = LOG_RECORDS.take();
+        assert!(records.iter().all(|(level, _)| *level == log::Level::Info));
+        assert!(records[1]
+            .1
+            .starts_with(&format!("SOR omega {}", sim.omega())));
+    }
+
+    #[test]
+    fn divergence_warning() {
+        capture_logs();
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 1000);
+        sim.initial_norm_squared = Some(0.0);
+        // Validation rejects this, but it's the easiest way to make SOR
+        // unstable.
+        sim.set_omega(2.5);
+
+        LOG_RECORDS.with_borrow_mut(Vec::clear);
+        assert!(sim.run_simulation_tick().is_err());
+        let records = LOG_RECORDS.take();
+        assert_eq!(records.len(), 1);
+        let (level, message) = &records[0];
+        assert_eq!(*level, log::Level::Warn);
+        // sor_simulation already ran 50 ticks.
+        assert!(message.starts_with("Tick 51: SOR diverged"), "{}", message);
+    }
+
+    #[test]
+    fn forced_cells() {
+        let size = [20, 10];
+        let forced = (6, 4);
+        let simulation = |forced_cells| {
+            SimulationBuilder::new()
+                .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+                .forced_cells(forced_cells)
+                .build()
+        };
+
+        // A jet pushing down through the south face of the forced cell
+        let mut sim = simulation(vec![(forced, [0.0, 2.0])]).unwrap();
+        let mut free = simulation(Vec::new()).unwrap();
+        for _ in 0..20 {
+            sim.run_simulation_tick().unwrap();
+            free.run_simulation_tick().unwrap();
+            assert_eq!((sim.grid.u[forced], sim.grid.v[forced]), (0.0, 2.0));
+        }
+        // The flow around it reacts to the jet.
+        let below = (forced.0, forced.1 + 1);
+        assert!(sim.grid.v[below] > free.grid.v[below] + 0.1);
+        let west = (forced.0 - 1, forced.1);
+        assert!((sim.grid.u[west] - free.grid.u[west]).abs() > 1e-3);
+
+        // Only fluid cells inside the outer ring can be forced.
+        for idx in [(0, 4), (6, 0), (25, 4)] {
+            assert!(matches!(
+                simulation(vec![(idx, [1.0, 0.0])]),
+                Err(SimulationError::InvalidForcedCell(cell)) if cell == idx
+            ));
+        }
+    }
+
+    #[test]
+    fn hydrostatic_pressure() {
+        use crate::cell::BoundaryCell;
+
+        let size = [6, 10];
+        let mut grid = presets::empty(size);
+        for x in 0..size[0] {
+            grid.cell_type[(x, 0)] = Cell::Boundary(BoundaryCell::NoSlip);
+            grid.cell_type[(x, size[1] - 1)] = Cell::Boundary(BoundaryCell::NoSlip);
+        }
+        for y in 0..size[1] {
+            grid.cell_type[(0, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+            grid.cell_type[(size[0] - 1, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+        }
+        grid.rebuild_boundary_list().unwrap();
+
+        let cell_size = [0.1, 0.1];
+        let gravity = 2.0;
+        let mut sim = SimulationBuilder::new()
+            .grid(grid)
+            .cell_size(cell_size)
+            .body_force([0.0, gravity])
+            .sor_absolute_epsilon(0.00001)
+            .max_iterations(1000)
+            .build()
+            .unwrap();
+        // An initial norm of zero can never be improved upon, forcing SOR to
+        // iterate until it reaches the absolute epsilon.
+        sim.initial_norm_squared = Some(0.0);
+
+        for _ in 0..100 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        // The fluid should come to rest, with the pressure increasing
+        // linearly with depth to balance gravity.
+        for x in 1..size[0] - 1 {
+            for y in 1..size[1] - 1 {
+                assert!(sim.grid.u[(x, y)].abs() < 1e-6);
+                assert!(sim.grid.v[(x, y)].abs() < 1e-6);
+            }
+            for y in 1..size[1] - 2 {
+                let gradient = (sim.grid.pressure[(x, y + 1)]
+                    - sim.grid.pressure[(x, y)])
+                    / cell_size[1];
+                assert!((gradient - gravity).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn convective_outflow_reflection() {
+        use crate::cell::BoundaryCell;
+
+        let size = [40, 12];
+        // Kinetic energy of the transverse flow near the outflow. The inflow
+        // is purely horizontal, so this is mostly made up of disturbances
+        // reflected back into the domain by the outflow boundary.
+        let transverse_energy = |outflow: BoundaryCell| {
+            let mut grid = presets::simple_inflow(size, [1.0, 0.0], Walls::default());
+            for y in 1..size[1] - 1 {
+                grid.cell_type[(size[0] - 1, y)] = Cell::Boundary(outflow);
+            }
+            grid.rebuild_boundary_list().unwrap();
+            let mut sim = SimulationBuilder::new()
+                .grid(grid)
+                .cell_size([0.1, 0.1])
+                .build()
+                .unwrap();
+            for _ in 0..200 {
+                sim.run_simulation_tick().unwrap();
+            }
+            sim.grid
+                .v
+                .slice(s![size[0] - 8..size[0] - 1, 1..size[1] - 1])
+                .fold(0.0, |acc, v| acc + v.powi(2))
+        };
+
+        let zero_gradient = transverse_energy(BoundaryCell::Outflow);
+        let convective =
+            transverse_energy(BoundaryCell::ConvectiveOutflow { speed: 1.0 });
+        assert!(convective < 0.1 * zero_gradient);
+    }
+
+    #[test]
+    fn bottom_jet() {
+        let size = [20, 24];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::bottom_jet(size, (8, 11), 1.0).unwrap())
+            .cell_size([0.1, 0.1])
+            .build()
+            .unwrap();
+        for _ in 0..100 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        let grid = &sim.grid;
+        for x in 8..=11 {
+            assert_eq!(grid.v[(x, size[1] - 2)], -1.0);
+        }
+        // Just above the inflow, the jet is much faster than the fluid next
+        // to it.
+        assert!(grid.v[(9, size[1] - 4)] < -0.5);
+        assert!(grid.v[(2, size[1] - 4)] > -0.1);
+        // Everything that comes in through the jet leaves through the top.
+        let outflow: Real = (1..size[0] - 1).map(|x| grid.v[(x, 0)]).sum();
+        assert!((outflow + 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn interior_source_and_sink() {
+        let size = [21, 21];
+        let (source, sink) = ((6, 10), (14, 10));
+        let mut cell_type = Array::from_elem(size, Cell::Fluid);
+        for i in 0..size[0] {
+            for idx in [(i, 0), (i, size[1] - 1), (0, i), (size[0] - 1, i)] {
+                cell_type[idx] = Cell::Boundary(BoundaryCell::NoSlip);
+            }
+        }
+        cell_type[source] = Cell::Boundary(BoundaryCell::Inflow {
+            velocity: [1.0, 0.0],
+        });
+        cell_type[sink] = Cell::Boundary(BoundaryCell::Outflow);
+        let grid = SimulationGrid::from_fields(
+            size,
+            Array::zeros(size),
+            Array::zeros(size),
+            Array::zeros(size),
+            cell_type,
+        )
+        .unwrap();
+        assert_eq!(grid.boundaries.sources, vec![source, sink]);
+
+        let mut sim = SimulationBuilder::new()
+            .grid(grid)
+            .cell_size([0.1, 0.1])
+            .max_iterations(1000)
+            .sor_stopping_criterion(SorStoppingCriterion::Absolute { epsilon: 0.001 })
+            .build()
+            .unwrap();
+        for _ in 0..20 {
+            sim.run_simulation_tick().unwrap();
+        }
+
+        let grid = &sim.grid;
+        // The fluid flows out of every face of the source and into every
+        // face of the sink...
+        let faces = |(x, y): GridIndex| {
+            [
+                grid.u[(x, y)],
+                -grid.u[(x - 1, y)],
+                grid.v[(x, y)],
+                -grid.v[(x, y - 1)],
+            ]
+        };
+        assert!(faces(source).iter().all(|outflow| *outflow > 0.5));
+        assert!(faces(sink).iter().all(|outflow| *outflow < -0.5));
+        // ...at the rate the source's velocity asks for.
+        let divergence = |idx: GridIndex| {
+            let [east, west, south, north] = faces(idx);
+            (east + west) / sim.cell_size[0] + (south + north) / sim.cell_size[1]
+        };
+        assert!((divergence(source) - 40.0).abs() < 0.1);
+        assert!((divergence(sink) + 40.0).abs() < 0.1);
+        // Everything the source puts in, the sink takes out.
+        assert!(sim.net_mass_flux().abs() < 1e-3);
+        // The pressure peaks at the source, which is what pushes the fluid
+        // away from it.
+        let source_pressure = grid.pressure[source];
+        assert!(grid.pressure.iter().all(|p| *p <= source_pressure));
+        assert_eq!(grid.pressure[sink], 0.0);
+    }
+
+    fn sor_simulation(sor_ordering: SorOrdering, max_iterations: u32) -> Simulation {
+        let size = [8, 6];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .max_iterations(max_iterations)
+            .sor_ordering(sor_ordering)
+            .build()
+            .unwrap();
+        // Let the flow develop so that the pressure equation has a solution,
+        // then start the solve from scratch.
+        for _ in 0..50 {
+            sim.run_simulation_tick().unwrap();
+        }
+        sim.grid.pressure.fill(0.0);
+        sim.grid
+            .set_boundary_u_and_v(sim.delt, sim.cell_size)
+            .unwrap();
+        sim.calculate_f_and_g();
+        sim.calculate_rhs();
+        sim
+    }
+
+    #[test]
+    #[cfg_attr(
+        feature = "single-precision",
+        ignore = "the pressure drifts too far from 0 to reach the epsilon in f32"
+    )]
+    fn sor_ordering_converges() {
+        for sor_ordering in [SorOrdering::Lexicographic, SorOrdering::RedBlack] {
+            let mut sim = sor_simulation(sor_ordering, 1000);
+            // Only stop once the absolute epsilon has been reached.
+            sim.initial_norm_squared = Some(0.0);
+            let stats = sim.solve_sor().unwrap();
+            assert!(
+                stats.sor_iterations < 1000,
+                "{:?} did not converge",
+                sor_ordering
+            );
+            assert!(stats.residual.l2_squared < sim.sor_absolute_epsilon.powi(2));
+        }
+    }
+
+    #[test]
+    fn sor_ordering_pressure() {
+        let _snapshots = precision_snapshots();
+        // Pin down the sweep order of each variant by stopping part of the
+        // way through the solve.
+        for (name, sor_ordering) in [
+            ("lexicographic", SorOrdering::Lexicographic),
+            ("red_black", SorOrdering::RedBlack),
+        ] {
+            let mut sim = sor_simulation(sor_ordering, 5);
+            sim.initial_norm_squared = Some(0.0);
+            assert_eq!(sim.solve_sor().unwrap().sor_iterations, 5);
+            insta::assert_json_snapshot!(name, sim.grid.pressure);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(
+        feature = "single-precision",
+        ignore = "the pressure drifts too far from 0 to reach the epsilon in f32"
+    )]
+    fn jacobi_matches_sor() {
+        let solve = |pressure_solver| {
+            let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+            sim.max_iterations = 10_000;
+            sim.pressure_solver = pressure_solver;
+            sim.sor_stopping_criterion = SorStoppingCriterion::Absolute { epsilon: 1e-5 };
+            let stats = sim.solve_sor().unwrap();
+            assert_eq!(stats.convergence, ConvergenceStatus::Converged);
+            sim.grid.copy_pressure_to_boundaries().unwrap();
+            (sim, stats.sor_iterations)
+        };
+        let (sor, sor_iterations) = solve(PressureSolver::Sor);
+        let (jacobi, jacobi_iterations) = solve(PressureSolver::Jacobi);
+        assert!(
+            jacobi_iterations > sor_iterations,
+            "Jacobi took {} iterations and SOR {}",
+            jacobi_iterations,
+            sor_iterations
+        );
+
+        // The outflow lets the pressure drift by a constant, so compare the
+        // pressure relative to its mean over the fluid cells.
+        let relative = |sim: &Simulation| {
+            let fluid =
+                |(idx, _): &(GridIndex, &Real)| sim.grid.cell_type[*idx] == Cell::Fluid;
+            let pressures = sim.grid.pressure.indexed_iter().filter(fluid);
+            let mean = pressures.clone().map(|(_, p)| p).sum::<Real>()
+                / sim.grid.boundaries.fluid_cells;
+            pressures
+                .map(|(idx, p)| (idx, p - mean))
+                .collect::<Vec<_>>()
+        };
+        for ((idx, left), (_, right)) in relative(&sor).into_iter().zip(relative(&jacobi))
+        {
+            assert!(
+                (left - right).abs() < 1e-4,
+                "{:?}: {} != {}",
+                idx,
+                left,
+                right
+            );
+        }
+    }
+
+    // The L2 norm of the divergence of the velocity over the fluid cells
+    fn divergence_norm(sim: &Simulation) -> Real {
+        let grid = &sim.grid;
+        grid.cell_type
+            .indexed_iter()
+            .filter(|(_, cell)| **cell == Cell::Fluid)
+            .map(|((x, y), _)| {
+                let divergence = (grid.u[(x, y)] - grid.u[(x - 1, y)]) / sim.cell_size[0]
+                    + (grid.v[(x, y)] - grid.v[(x, y - 1)]) / sim.cell_size[1];
+                divergence * divergence
+            })
+            .sum::<Real>()
+            .sqrt()
+    }
+
+    #[test]
+    fn explicit_euler_diverges() {
+        let run = |time_scheme| {
+            let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+            sim.time_scheme = time_scheme;
+            let mut norms = Vec::new();
+            for _ in 0..20 {
+                let stats = sim.run_simulation_tick().unwrap();
+                if time_scheme == TimeScheme::ExplicitEuler {
+                    assert_eq!(stats.sor_iterations, 0);
+                }
+                norms.push(divergence_norm(&sim));
+            }
+            norms
+        };
+        let projection = run(TimeScheme::ProjectionSor);
+        let explicit = run(TimeScheme::ExplicitEuler);
+        // Without the pressure, nothing removes the divergence that F and G
+        // introduce, so it piles up from tick to tick.
+        assert!(explicit.windows(2).all(|pair| pair[1] > pair[0]));
+        assert!(
+            explicit[19] > 1000.0 * projection[19],
+            "explicit {} vs. projection {}",
+            explicit[19],
+            projection[19]
+        );
+        assert!(projection.iter().all(|norm| *norm < 1e-2));
+    }
+
+    #[test]
+    fn relative_criterion_ignores_scale() {
+        // Scaling the RHS scales the solution and every residual by the same
+        // factor, since the pressure starts at 0.
+        let iterations = |sor_stopping_criterion, scale: Real| {
+            let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+            sim.sor_stopping_criterion = sor_stopping_criterion;
+            sim.rhs.mapv_inplace(|rhs| rhs * scale);
+            sim.solve_sor().unwrap().sor_iterations
+        };
+        let relative = SorStoppingCriterion::Relative { factor: 0.01 };
+        let unscaled = iterations(relative, 1.0);
+        assert!(unscaled > 1);
+        for scale in [1e-3, 1e3] {
+            assert_eq!(iterations(relative, scale), unscaled);
+        }
+
+        // An absolute epsilon is met much sooner by a weaker flow.
+        let absolute = SorStoppingCriterion::Absolute { epsilon: 1e-3 };
+        assert!(iterations(absolute, 1e-3) < iterations(absolute, 1e3));
+    }
+
+    // A 5x5 grid with a 3x3 block of fluid, no pressure, and a nonzero RHS in
+    // a single cell.
+    fn single_residual_simulation() -> Simulation {
+        use crate::cell::BoundaryCell;
+
+        let size = [5, 5];
+        let mut cell_type = Array::from_elem(size, Cell::Boundary(BoundaryCell::NoSlip));
+        cell_type.slice_mut(s![1..4, 1..4]).fill(Cell::Fluid);
+        let grid = SimulationGrid::from_fields(
+            size,
+            Array::zeros(size),
+            Array::zeros(size),
+            Array::zeros(size),
+            cell_type,
+        )
+        .unwrap();
+        let mut sim = SimulationBuilder::new().grid(grid).build().unwrap();
 
-        let norm = self.calculate_norm_squared();
-        self.initial_norm_squared = Some(norm);
-        norm
+        sim.rhs.fill(0.0);
+        sim.rhs[(3, 2)] = -3.0;
+        sim
     }
 
-    fn solve_sor(&mut self) -> Result<(u32, Real), SimulationGridError> {
-        let delx2 = self.cell_size[0].powi(2);
-        let dely2 = self.cell_size[1].powi(2);
+    #[test]
+    fn residual_report() {
+        let sim = single_residual_simulation();
+        // With no pressure, the residual is the RHS of the single cell where
+        // it's nonzero.
+        assert_eq!(
+            sim.calculate_residual(),
+            ResidualReport {
+                l2_squared: 9.0 / 9.0,
+                l_inf: 3.0,
+                l_inf_location: (3, 2),
+            }
+        );
+    }
 
-        let one_minus_w = 1.0 - self.omega;
-        let middle = self.omega / ((2.0 / delx2) + (2.0 / dely2));
+    #[test]
+    fn residual_field() {
+        let sim = single_residual_simulation();
+        let mut expected = Array::zeros(sim.size);
+        expected[(3, 2)] = 3.0;
+        assert_eq!(sim.residual_field(), expected);
 
-        let epsilon_squared = self.sor_absolute_epsilon.powi(2);
+        let mut field = Array::from_elem(sim.size, 1.0);
+        sim.residual_field_into(&mut field);
+        assert_eq!(field, expected);
+    }
 
-        let mut norm_squared = 0.0;
+    #[test]
+    fn residual_field_matches_norm() {
+        let mut sim = SimulationBuilder::new()
+            .grid(
+                presets::obstacle([30, 12], [1.0, 0.0], (8, 6), 2.0, Walls::default())
+                    .unwrap(),
+            )
+            .max_iterations(5)
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            sim.run_simulation_tick().unwrap();
+        }
 
-        for i in 0..self.max_iterations {
-            self.grid.copy_pressure_to_boundaries()?;
-            // indexing instead of iterators :(
-            for x in 1..self.size[0] - 1 {
-                // indexing instead of iterators :(
-                for y in 1..self.size[1] - 1 {
-                    // if statement in inner loop :(
-                    if let Cell::Fluid = self.grid.cell_type[(x, y)] {
-                        // Note that we're modifying in place, so "minus one"
-                        // values have been computed for the next step already.
-                        let p_i_j = self.grid.pressure[(x, y)];
-                        let p_i_m1_j = self.grid.pressure[(x - 1, y)];
-                        let p_i_p1_j = self.grid.pressure[(x + 1, y)];
-                        let p_i_j_m1 = self.grid.pressure[(x, y - 1)];
-                        let p_i_j_p1 = self.grid.pressure[(x, y + 1)];
-                        let rhs = self.rhs[(x, y)];
-
-                        self.grid.pressure[(x, y)] = (one_minus_w * p_i_j)
-                            + middle
-                                * (((p_i_p1_j + p_i_m1_j) / delx2)
-                                    + ((p_i_j_p1 + p_i_j_m1) / dely2)
-                                    - rhs)
-                    }
-                }
+        let field = sim.residual_field();
+        // Only fluid cells have a residual.
+        assert!(Zip::from(&field)
+            .and(&sim.grid.cell_type)
+            .all(|residual, cell| *cell == Cell::Fluid || *residual == 0.0));
+        let sum_squared: Real = field.iter().map(|residual| residual.powi(2)).sum();
+        assert!(sum_squared > 0.0);
+        assert_close!(
+            sum_squared,
+            sim.calculate_norm_squared() * sim.grid.boundaries.fluid_cells
+        );
+    }
+
+    #[test]
+    fn stream_function() {
+        let size = [6, 5];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::empty(size))
+            .build()
+            .unwrap();
+        sim.grid.u.fill(2.0);
+
+        // Uniform horizontal flow: psi grows by u * dely from row to row and
+        // is the same along each row.
+        let psi = sim.stream_function();
+        for ((_, y), value) in psi.indexed_iter() {
+            assert_eq!(*value, psi[(0, y)], "psi changes along row {}", y);
+            assert_close!(*value, 2.0 * 0.2 * y as Real);
+        }
+
+        // The velocity between the cells of a wall doesn't count, so psi is
+        // constant along the walls of a channel.
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        sim.grid.cell_type.column_mut(0).fill(wall);
+        sim.grid.cell_type.column_mut(4).fill(wall);
+        let mut psi = Array::from_elem(size, 1.0);
+        sim.stream_function_into(&mut psi);
+        for x in 0..size[0] {
+            assert_eq!(psi[(x, 0)], 0.0);
+            assert_eq!(psi[(x, 3)], psi[(x, 4)]);
+            assert_close!(psi[(x, 4)], 2.0 * 0.2 * 3.0);
+        }
+    }
+
+    #[test]
+    fn density() {
+        let size = [6, 5];
+        let velocity_response = |density| {
+            let mut sim = SimulationBuilder::new()
+                .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+                .density(density)
+                .build()
+                .unwrap();
+            sim.f.fill(0.0);
+            sim.g.fill(0.0);
+            for ((x, y), p) in sim.grid.pressure.indexed_iter_mut() {
+                *p = x as Real + 2.0 * y as Real;
             }
+            sim.set_u_and_v();
+            (sim.grid.u[(2, 2)], sim.grid.v[(2, 2)])
+        };
 
-            let initial_norm_squared = self.get_initial_norm_squared();
-            norm_squared = self.calculate_norm_squared();
+        let (u1, v1) = velocity_response(1.0);
+        let (u2, v2) = velocity_response(2.0);
+        assert_eq!(u1, -0.005 / 0.1);
+        assert_eq!(v1, -2.0 * 0.005 / 0.2);
+        assert_eq!(u2, u1 / 2.0);
+        assert_eq!(v2, v1 / 2.0);
+    }
 
-            if (norm_squared < initial_norm_squared) || (norm_squared < epsilon_squared) {
-                return Ok((i + 1, norm_squared));
+    #[test]
+    fn uniform_initial_velocity() {
+        let size = [40, 12];
+        let inflow = [1.0, 0.0];
+        let first_tick_iterations = |initial_velocity: Option<Velocity>| {
+            let mut grid =
+                presets::obstacle(size, inflow, (12, 6), 3.0, Walls::default()).unwrap();
+            if let Some(velocity) = initial_velocity {
+                grid.set_uniform_velocity(velocity);
             }
+            let mut sim = SimulationBuilder::new().grid(grid).build().unwrap();
+            sim.run_simulation_tick().unwrap().sor_iterations
+        };
+
+        let at_rest = first_tick_iterations(None);
+        let moving = first_tick_iterations(Some(inflow));
+        assert!(moving < at_rest);
+    }
+
+    #[test]
+    fn cfl_number() {
+        let size = [6, 5];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .build()
+            .unwrap();
+
+        sim.grid.u[(2, 2)] = 2.0;
+        sim.grid.v[(3, 2)] = -5.0;
+        sim.grid.calculate_speed_range();
+        assert_eq!(sim.grid.max_velocity, [2.0, 5.0]);
+        // 2 * 0.005 / 0.1 = 0.1 horizontally, 5 * 0.005 / 0.2 = 0.125
+        // vertically.
+        assert_close!(sim.cfl_number(), 0.125);
+
+        let stats = sim.run_simulation_tick().unwrap();
+        assert!(stats.cfl > 0.0);
+        assert_eq!(stats.cfl, sim.cfl_number());
+    }
+
+    #[test]
+    fn net_mass_flux() {
+        let size = [30, 10];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .density(2.0)
+            .build()
+            .unwrap();
+        for _ in 0..200 {
+            sim.run_simulation_tick().unwrap();
         }
-        self.grid.calculate_pressure_range();
-        Ok((self.max_iterations, norm_squared))
+
+        // Density 2 times speed 1 through 8 faces 0.2 long
+        let inflow = 2.0 * 8.0 * 0.2;
+        assert!(sim.net_mass_flux().abs() < 1e-3 * inflow);
+        // Without the outflow, only the inflow is left, and it counts as
+        // negative.
+        for y in 1..size[1] - 1 {
+            sim.grid.u[(size[0] - 2, y)] = 0.0;
+        }
+        assert_close!(sim.net_mass_flux(), -inflow);
     }
 
-    pub fn set_u_and_v(&mut self) {
-        #[allow(clippy::reversed_empty_ranges)]
-        let mut u_view = self.grid.u.slice_mut(s![0..-1, 0..-1]);
-        #[allow(clippy::reversed_empty_ranges)]
-        let mut v_view = self.grid.v.slice_mut(s![0..-1, 0..-1]);
+    #[test]
+    fn recommended_gamma() {
+        let size = [6, 5];
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .auto_gamma(true)
+            .build()
+            .unwrap();
 
-        #[allow(clippy::reversed_empty_ranges)]
-        let f_view = self.f.slice_mut(s![0..-1, 0..-1]);
+        // Faster flow needs more upwinding, up to fully upwind differences.
+        let mut last_gamma = 0.0;
+        for speed in [2.0, 8.0, 15.0, 100.0] {
+            sim.grid.u[(2, 2)] = speed;
+            sim.grid.calculate_speed_range();
+            let gamma = sim.recommended_gamma();
+            assert!(gamma > last_gamma || gamma == 1.0);
+            assert!((0.0..=1.0).contains(&gamma));
+            last_gamma = gamma;
+        }
+        assert_eq!(last_gamma, 1.0);
 
-        #[allow(clippy::reversed_empty_ranges)]
-        let g_view = self.g.slice_mut(s![0..-1, 0..-1]);
+        sim.grid.u.fill(0.0);
+        sim.grid.calculate_speed_range();
+        let expected = sim.recommended_gamma();
+        sim.run_simulation_tick().unwrap();
+        assert_eq!(sim.gamma, expected);
+    }
 
-        Zip::from(&mut u_view)
-            .and(&mut v_view)
-            .and(self.grid.pressure.windows((2, 2)))
-            .and(f_view)
-            .and(g_view)
-            .for_each(|u, v, p_view, f, g| {
-                let p_i_j = p_view[(0, 0)];
-                let p_i_p1_j = p_view[(1, 0)];
-                let p_i_j_p1 = p_view[(0, 1)];
+    #[test]
+    fn census() {
+        let size = [30, 12];
+        let grid =
+            presets::obstacle(size, [1.0, 0.0], (10, 6), 3.0, Walls::default()).unwrap();
+        let obstacle_cells = grid
+            .cell_type
+            .slice(s![1..size[0] - 1, 1..size[1] - 1])
+            .iter()
+            .filter(|&&cell| cell == Cell::Boundary(BoundaryCell::NoSlip))
+            .count();
+        let sim = SimulationBuilder::new().grid(grid).build().unwrap();
+        let census = sim.grid.census();
+        assert_eq!(census.obstacles, 1);
+        assert!(obstacle_cells > 0);
+        // The top and bottom walls are the rest of the no-slip cells.
+        assert_eq!(census.no_slip, obstacle_cells + 2 * size[0]);
+        assert_eq!((census.inflow, census.outflow), (10, 10));
+        assert_eq!(census.fluid, 28 * 10 - obstacle_cells);
+        assert_eq!(census.symmetry + census.convective_outflow, 0);
+        assert_eq!(census.inflow_regions, vec![((0, 1), (0, 10))]);
+        assert_eq!(census.outflow_regions, vec![((29, 1), (29, 10))]);
+        // The cells are 0.1 by 0.2.
+        assert_close!(
+            census.fluid_area(sim.cell_size),
+            census.fluid as Real * 0.02
+        );
 
-                *u = *f - (self.delt / self.cell_size[0]) * (p_i_p1_j - p_i_j);
-                *v = *g - (self.delt / self.cell_size[1]) * (p_i_j_p1 - p_i_j);
-            });
+        let text = sim.to_string();
+        assert!(
+            text.contains("Inflow regions: (0, 1) to (0, 10)\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("Obstacles: 1\n"), "{}", text);
 
-        for (idx, maybe_u, maybe_v) in &self.grid.boundaries.u_v_restore {
-            if let Some(u) = maybe_u {
-                self.grid.u[*idx] = *u;
+        // Two separate obstacles
+        let mut sim = sim;
+        let wall = Cell::Boundary(BoundaryCell::NoSlip);
+        sim.grid
+            .set_cells([(20, 5), (21, 5), (20, 6), (21, 6)], wall)
+            .unwrap();
+        assert_eq!(sim.grid.census().obstacles, 2);
+    }
+
+    #[test]
+    fn perturb_velocity() {
+        let size = [30, 12];
+        let perturbed = |seed| {
+            let mut sim = SimulationBuilder::new()
+                .grid(
+                    presets::obstacle(size, [1.0, 0.0], (10, 6), 3.0, Walls::default())
+                        .unwrap(),
+                )
+                .build()
+                .unwrap();
+            sim.grid.u.fill(0.5);
+            sim.perturb_velocity(0.01, seed);
+            sim
+        };
+
+        let sim = perturbed(7);
+        let grid = &sim.grid;
+        for ((idx, u), v) in grid.u.indexed_iter().zip(&grid.v) {
+            if grid.cell_type[idx] == Cell::Fluid {
+                assert!((u - 0.5).abs() <= 0.01 && *u != 0.5);
+                assert!(v.abs() <= 0.01 && *v != 0.0);
+            } else {
+                assert_eq!((*u, *v), (0.5, 0.0));
             }
-            if let Some(v) = maybe_v {
-                self.grid.v[*idx] = *v;
+        }
+        assert!(grid.speed_range[1] > 0.5);
+
+        let same = perturbed(7);
+        assert_eq!(same.grid.u, grid.u);
+        assert_eq!(same.grid.v, grid.v);
+        let other = perturbed(8);
+        assert_ne!(other.grid.u, grid.u);
+        assert_ne!(other.grid.v, grid.v);
+    }
+
+    #[test]
+    fn sor_divergence() {
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 1000);
+        sim.initial_norm_squared = Some(0.0);
+        // Validation rejects this, but it's the easiest way to make SOR
+        // unstable.
+        sim.set_omega(2.5);
+        match sim.solve_sor() {
+            Err(SimulationError::SorDiverged { iteration, .. }) => {
+                assert!(iteration < 30, "took {} iterations", iteration)
             }
+            other => panic!("expected SOR to diverge, got {:?}", other),
         }
-        self.grid.calculate_speed_range();
     }
 
-    pub fn run_simulation_tick(&mut self) -> Result<(u32, Real), SimulationError> {
-        self.grid.set_boundary_u_and_v()?;
-        self.calculate_f_and_g();
-        self.calculate_rhs();
-        let (sor_iterations, norm_squared) = self.solve_sor()?;
-        self.set_u_and_v();
-        self.time += self.delt;
-        self.iterations += 1;
-        Ok((sor_iterations, norm_squared))
+    #[test]
+    fn sor_coefficients() {
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+        assert_eq!(
+            sim.sor_coefficients(),
+            SorCoefficients::new(1.7, [0.1, 0.2])
+        );
+        sim.set_omega(1.2);
+        assert_eq!(sim.omega(), 1.2);
+        assert_eq!(
+            sim.sor_coefficients(),
+            SorCoefficients::new(1.2, [0.1, 0.2])
+        );
+        assert_eq!(sim.sor_coefficients().one_minus_w, 1.0 - 1.2);
+        sim.set_cell_size([0.3, 0.4]);
+        assert_eq!(sim.cell_size(), [0.3, 0.4]);
+        assert_eq!(
+            sim.sor_coefficients(),
+            SorCoefficients::new(1.2, [0.3, 0.4])
+        );
     }
-}
 
-/// Calculate F (the horizontal non-pressure part of the momentum equation)
-///
-/// # Arguments
-///
-/// * `u_view` - A 3x3-element ArrayView2 representing
-///   u[(i-1) to (i+1), (j-1) to (j+1)].
-/// * `v_view` - A 3x3-element ArrayView2 representing
-///   v[(i-1) to (i+1), (j-1) to (j+1)].
-/// * `delx` - "delta x," the physical width of the cell
-/// * `dely` - "delta y," the physical width of the cell
-/// * `delt` - "delta t," the amount of time per time step
-/// * `gamma` - Greek letter gamma, the upwind discretization parameter
-/// * `reynolds` - The Reynolds number for the simulation
-pub fn calculate_f(
-    u_view: ArrayView2<Real>,
-    v_view: ArrayView2<Real>,
-    delx: Real,
-    dely: Real,
-    delt: Real,
-    gamma: Real,
-    reynolds: Real,
-) -> Real {
-    u_view[(1, 1)]
-        + (delt
-            * ((laplacian(u_view, delx, dely) / reynolds)
-                - du2dx(u_view, delx, gamma)
-                - duvdy(u_view, v_view, dely, gamma)))
-}
+    #[test]
+    fn optimal_omega() {
+        let size = [100, 20];
+        let sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .build()
+            .unwrap();
+        // rho = (cos(pi / 98) / 0.1^2 + cos(pi / 18) / 0.2^2) / (1 / 0.1^2 + 1 / 0.2^2)
+        //     = 0.99655052...
+        assert_close!(sim.optimal_omega(), 1.846742059869);
+        // Neither converges within 1000 iterations in single precision, see
+        // sor_ordering_converges.
+        if cfg!(feature = "single-precision") {
+            return;
+        }
 
-/// Calculate G (the vertical non-pressure part of the momentum equation)
-///
-/// # Arguments
-///
-/// * `u_view` - A 3x3-element ArrayView2 representing
-///   u[(i-1) to (i+1), (j-1) to (j+1)].
-/// * `v_view` - A 3x3-element ArrayView2 representing
-///   v[(i-1) to (i+1), (j-1) to (j+1)].
-/// * `delx` - "delta x," the physical width of the cell
-/// * `dely` - "delta y," the physical width of the cell
-/// * `delt` - "delta t," the amount of time per time step
-/// * `gamma` - Greek letter gamma, the upwind discretization parameter
-/// * `reynolds` - The Reynolds number for the simulation
-pub fn calculate_g(
-    u_view: ArrayView2<Real>,
-    v_view: ArrayView2<Real>,
-    delx: Real,
-    dely: Real,
-    delt: Real,
-    gamma: Real,
-    reynolds: Real,
-) -> Real {
-    v_view[(1, 1)]
-        + (delt
-            * ((laplacian(v_view, delx, dely) / reynolds)
-                - duvdx(u_view, v_view, delx, gamma)
-                - dv2dy(v_view, dely, gamma)))
-}
+        let iterations_to_converge = |auto: bool| {
+            let mut sim = sor_simulation(SorOrdering::Lexicographic, 1000);
+            sim.initial_norm_squared = Some(0.0);
+            let omega = if auto { sim.optimal_omega() } else { 1.0 };
+            sim.set_omega(omega);
+            sim.solve_sor().unwrap().sor_iterations
+        };
+        assert!(iterations_to_converge(true) < iterations_to_converge(false));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use ndarray::{array, ArrayView2};
-    use std::fs::File;
-    use std::io::BufReader;
-    use std::path::{Path, PathBuf};
+    #[test]
+    #[cfg_attr(
+        feature = "single-precision",
+        ignore = "rounding keeps the residual above the absolute epsilon in f32"
+    )]
+    fn sor_relative_stopping() {
+        // Once the flow has developed, the default criterion accepts the
+        // pressure after very few iterations.
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 1000);
+        for _ in 0..50 {
+            sim.run_simulation_tick().unwrap();
+        }
+        let default_iterations = sim.run_simulation_tick().unwrap().sor_iterations;
 
-    use crate::grid::presets;
+        sim.sor_stopping_criterion = SorStoppingCriterion::Relative { factor: 0.01 };
+        let relative_iterations = sim.run_simulation_tick().unwrap().sor_iterations;
+        assert!(relative_iterations > 1);
+        assert!(relative_iterations > default_iterations);
 
-    fn test_data_directory() -> PathBuf {
-        Path::new(file!()).parent().unwrap().join("test_data")
+        sim.sor_stopping_criterion = SorStoppingCriterion::Absolute { epsilon: 1e-4 };
+        let stats = sim.run_simulation_tick().unwrap();
+        assert!(stats.sor_iterations > 1);
+        assert!(stats.sor_iterations < 1000);
+        assert!(stats.residual.l2_squared < 1e-8);
     }
 
     #[test]
-    fn deserialize() {
-        for test_filename in [
-            test_data_directory().join("simple_simulation.json"),
-            test_data_directory().join("small_simulation_with_boundaries.json"),
-        ] {
-            let result = Simulation::from_reader(BufReader::new(
-                File::open(test_filename).unwrap(),
-            ))
+    fn sor_relative_stopping_at_rest() {
+        // Fluid at rest in a closed box has no residual to reduce.
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::empty([10, 8]))
+            .max_iterations(100)
+            .sor_stopping_criterion(SorStoppingCriterion::Relative { factor: 0.01 })
+            .build()
             .unwrap();
-            insta::assert_json_snapshot!(result);
+        for _ in 0..3 {
+            let stats = sim.run_simulation_tick().unwrap();
+            assert!(stats.sor_iterations <= 1, "{:?}", stats);
+            assert_eq!(stats.convergence, ConvergenceStatus::Converged);
         }
     }
 
     #[test]
-    fn serialize() {
-        let size = [5, 7];
-        let cell_size = [1., 2.];
-        let delt = 1.4;
-        let gamma = 1.7;
-        let reynolds = 100.;
+    fn sor_stagnation() {
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 1000);
+        sim.initial_norm_squared = Some(0.0);
+        sim.sor_absolute_epsilon = 0.0;
+        let stats = sim.solve_sor().unwrap();
+        assert_eq!(stats.sor_iterations, 1000);
+        assert_eq!(stats.convergence, ConvergenceStatus::MaxIterationsReached);
 
-        let simulation = Simulation::try_from(UnfinalizedSimulation {
-            size,
-            cell_size,
-            delt,
-            gamma,
-            reynolds,
-            initial_norm_squared: Default::default(),
-            sor_absolute_epsilon: 0.001,
-            max_iterations: 100,
-            iterations: 0,
-            time: 0.0,
-            omega: 1.7,
-            grid: presets::empty(size).into(),
-        })
-        .unwrap();
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 1000);
+        sim.initial_norm_squared = Some(0.0);
+        sim.sor_absolute_epsilon = 0.0;
+        sim.sor_stagnation_factor = 0.5;
+        let stats = sim.solve_sor().unwrap();
+        assert!(stats.sor_iterations < 1000);
+        assert_eq!(stats.convergence, ConvergenceStatus::Stagnated);
+    }
 
-        insta::assert_json_snapshot!(simulation);
+    #[test]
+    fn sor_divergence_with_stagnation() {
+        // A growing norm is divergence, not stagnation, even when stopping
+        // early on stagnation is enabled. With omega = 3, the norm grows on
+        // every iteration.
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 1000);
+        sim.initial_norm_squared = Some(0.0);
+        sim.sor_stagnation_factor = 0.5;
+        sim.set_omega(3.0);
+        match sim.solve_sor() {
+            // The first iteration has no norm to compare against.
+            Err(SimulationError::SorDiverged { iteration, .. }) => {
+                assert_eq!(iteration, sim.sor_divergence_limit + 1)
+            }
+            other => panic!("expected SOR to diverge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn omega_ramp() {
+        use crate::cell::BoundaryCell;
+
+        // Total SOR iterations of the first ticks of random motion in a closed
+        // box, with a high omega
+        let startup_iterations = |omega_ramp| {
+            let size = [24, 12];
+            let mut cell_type =
+                Array::from_elem(size, Cell::Boundary(BoundaryCell::NoSlip));
+            cell_type
+                .slice_mut(s![1..size[0] - 1, 1..size[1] - 1])
+                .fill(Cell::Fluid);
+            let grid = SimulationGrid::from_fields(
+                size,
+                Array::zeros(size),
+                Array::zeros(size),
+                Array::zeros(size),
+                cell_type,
+            )
+            .unwrap();
+            let mut sim = SimulationBuilder::new()
+                .grid(grid)
+                .max_iterations(1000)
+                .sor_stopping_criterion(SorStoppingCriterion::Absolute { epsilon: 0.001 })
+                .omega(Omega::Value(1.9))
+                .omega_ramp(omega_ramp)
+                .build()
+                .unwrap();
+            sim.perturb_velocity(1.0, 7);
+            (0..10)
+                .map(|_| {
+                    let stats = sim.run_simulation_tick().unwrap();
+                    assert_eq!(stats.convergence, ConvergenceStatus::Converged);
+                    stats.sor_iterations
+                })
+                .sum::<u32>()
+        };
+        let constant = startup_iterations(false);
+        let ramped = startup_iterations(true);
+        assert!(ramped < constant, "{} >= {}", ramped, constant);
+    }
+
+    #[test]
+    fn phase_timings() {
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+        let mut timings = PhaseTimings::default();
+        for _ in 0..10 {
+            timings += sim.run_simulation_tick().unwrap().timings;
+        }
+        for (name, duration) in timings.phases() {
+            assert!(duration > Duration::ZERO, "{} wasn't timed", name);
+        }
+        // The stages are timed within the tick.
+        let phases: Duration =
+            timings.phases().iter().map(|(_, duration)| *duration).sum();
+        assert!(phases <= timings.total);
+
+        let average = timings / 10;
+        assert_eq!(average.solve_sor, timings.solve_sor / 10);
+        let line = average.to_string();
+        assert!(line.contains("solve_sor"), "{}", line);
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn tick_timings() {
+        let mut sim = sor_simulation(SorOrdering::Lexicographic, 100);
+        assert_eq!(sim.timings.ticks, 50);
+        for _ in 0..10 {
+            sim.run_simulation_tick().unwrap();
+        }
+        assert_eq!(sim.timings.ticks, 60);
+        for (name, duration) in sim.timings.sum.phases() {
+            assert!(!duration.is_zero(), "{} wasn't timed", name);
+        }
+        let summary = sim.timings.to_string();
+        assert!(summary.contains("60 ticks"), "{}", summary);
+    }
+
+    #[test]
+    fn symmetry_plane() {
+        use crate::cell::BoundaryCell;
+
+        // The full channel has 2 * half_height interior rows and an obstacle
+        // that is symmetric about the centerline between rows half_height
+        // and half_height + 1.
+        let half_height = 5;
+        let run = |size: GridSize, bottom: BoundaryCell| {
+            let inflow = BoundaryCell::Inflow {
+                velocity: [1.0, 0.0],
+            };
+            let mut grid = presets::simple_inflow(size, [1.0, 0.0], Walls::default());
+            // Prescribe the outflow as well, so that the pressure equation
+            // always has a solution.
+            for y in 1..size[1] - 1 {
+                grid.cell_type[(size[0] - 1, y)] = Cell::Boundary(inflow);
+            }
+            for x in 1..size[0] - 1 {
+                grid.cell_type[(x, size[1] - 1)] = Cell::Boundary(bottom);
+            }
+            for x in 10..13 {
+                for y in half_height - 1..(half_height + 3).min(size[1] - 1) {
+                    grid.cell_type[(x, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+                }
+            }
+            grid.rebuild_boundary_list().unwrap();
+            let mut sim = SimulationBuilder::new()
+                .grid(grid)
+                .cell_size([0.1, 0.1])
+                .sor_absolute_epsilon(1e-6)
+                .max_iterations(2000)
+                .build()
+                .unwrap();
+            // Converge each tick fully so that the different sweep orders of
+            // the two grids don't matter.
+            sim.initial_norm_squared = Some(0.0);
+            for _ in 0..30 {
+                sim.run_simulation_tick().unwrap();
+            }
+            sim
+        };
+
+        let full = run([30, 2 * half_height + 2], BoundaryCell::NoSlip);
+        let half = run([30, half_height + 2], BoundaryCell::Symmetry);
+
+        let tolerance = if cfg!(feature = "single-precision") {
+            1e-4
+        } else {
+            1e-6
+        };
+        for x in 1..29 {
+            for y in 1..=half_height {
+                if half.grid.cell_type[(x, y)] != Cell::Fluid {
+                    continue;
+                }
+                assert!((half.grid.u[(x, y)] - full.grid.u[(x, y)]).abs() < tolerance);
+                assert!((half.grid.v[(x, y)] - full.grid.v[(x, y)]).abs() < tolerance);
+                // The pressure is only defined up to a constant.
+                let half_p = half.grid.pressure[(x, y)] - half.grid.pressure[(1, 1)];
+                let full_p = full.grid.pressure[(x, y)] - full.grid.pressure[(1, 1)];
+                assert!((half_p - full_p).abs() < tolerance);
+            }
+        }
     }
 
     #[test]
@@ -501,6 +3763,7 @@ mod tests {
                     delt,
                     gamma,
                     reynolds,
+                    0.0,
                 ),
                 expected
             );
@@ -562,48 +3825,76 @@ mod tests {
                     delt,
                     gamma,
                     reynolds,
+                    0.0,
                 ),
                 expected
             );
         }
     }
 
+    #[test]
+    fn stretched_cell_diffusion() {
+        // The velocity only varies along its own row or column, where the
+        // convective terms vanish, leaving the diffusion of the peak. With
+        // cells 4 times as high as they're wide, the diffusion along y is 16
+        // times weaker than along x.
+        let (delx, dely, delt, reynolds) = (0.1, 0.4, 0.01, 1.0);
+        let along_y = array![[0., 1., 0.], [0., 1., 0.], [0., 1., 0.]];
+        let along_x = array![[0., 0., 0.], [1., 1., 1.], [0., 0., 0.]];
+        let zeros = Array::zeros((3, 3));
+        let diffused = |del: Real| 1.0 - delt * 2.0 / (reynolds * del.powi(2));
+
+        let f = |u: &GridArray<Real>| {
+            let (u, v) = (u.view(), zeros.view());
+            calculate_f(u, v, delx, dely, delt, 0.0, reynolds, 0.0)
+        };
+        assert_close!(f(&along_y), diffused(dely));
+        assert_close!(f(&along_x), diffused(delx));
+
+        let g = |v: &GridArray<Real>| {
+            let (u, v) = (zeros.view(), v.view());
+            calculate_g(u, v, delx, dely, delt, 0.0, reynolds, 0.0)
+        };
+        assert_close!(g(&along_y), diffused(dely));
+        assert_close!(g(&along_x), diffused(delx));
+    }
+
     #[test]
     fn simulation_tick() {
+        let _snapshots = precision_snapshots();
         let size = [4, 3];
-        let mut sim = Simulation::try_from(UnfinalizedSimulation {
-            size,
-            cell_size: [0.1, 0.2],
-            delt: 0.005,
-            gamma: 0.9,
-            reynolds: 100.0,
-            sor_absolute_epsilon: 0.001,
-            max_iterations: 100,
-            initial_norm_squared: None,
-            iterations: 0,
-            time: 0.0,
-            omega: 1.7,
-            grid: presets::simple_inflow(size).into(),
-        })
-        .unwrap();
+        let mut sim = SimulationBuilder::new()
+            .grid(presets::simple_inflow(size, [1.0, 0.0], Walls::default()))
+            .build()
+            .unwrap();
 
-        let (sor_iterations, norm_squared) = sim.run_simulation_tick().unwrap();
+        let stats = sim.run_simulation_tick().unwrap();
         insta::assert_json_snapshot!(sim.f);
         insta::assert_json_snapshot!(sim.g);
         insta::assert_json_snapshot!(sim.rhs);
         insta::assert_json_snapshot!(sim);
         // SOR is bad at converging on "unphysical" initial conditions, hence
         // the first few ticks are expected to stop after max_iterations.
-        assert_eq!(sor_iterations, 100);
-        assert_eq!(norm_squared, 562901.7447199143);
+        assert_eq!(stats.sor_iterations, 100);
+        assert_eq!(stats.convergence, ConvergenceStatus::MaxIterationsReached);
+        let expected_residual = if cfg!(feature = "single-precision") {
+            562950.0
+        } else {
+            562901.7447199143
+        };
+        assert_eq!(stats.residual.l2_squared, expected_residual);
 
-        let mut last_sor_iterations = 0;
-        let mut last_norm_squared = 0.0;
+        let mut last_stats = TickStats::default();
         for _ in 0..100 {
-            (last_sor_iterations, last_norm_squared) = sim.run_simulation_tick().unwrap();
+            last_stats = sim.run_simulation_tick().unwrap();
+        }
+        // In f32 the residual plateaus above the absolute epsilon, so SOR
+        // keeps running into max_iterations.
+        if !cfg!(feature = "single-precision") {
+            assert_eq!(last_stats.sor_iterations, 1);
+            assert_eq!(last_stats.convergence, ConvergenceStatus::Converged);
+            assert_eq!(last_stats.residual.l2_squared, 3.8344148218167323e-20);
         }
-        assert_eq!(last_sor_iterations, 1);
-        assert_eq!(last_norm_squared, 3.8344148218167323e-20);
         insta::assert_json_snapshot!(sim.f);
         insta::assert_json_snapshot!(sim.g);
         insta::assert_json_snapshot!(sim.rhs);
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+/// A point in time, for measuring how long something took
+///
+/// This is `std::time::Instant`, except in the browser, where
+/// `std::time::Instant::now` panics. There it's the time from macroquad's
+/// `get_time`, which only works once the window is open.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Instant(
+    #[cfg(not(target_arch = "wasm32"))] std::time::Instant,
+    #[cfg(target_arch = "wasm32")] f64,
+);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(std::time::Instant::now())
+    }
+
+    /// The time that has passed since `self`
+    pub fn elapsed(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(macroquad::time::get_time())
+    }
+
+    /// The time that has passed since `self`
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64((macroquad::time::get_time() - self.0).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed() {
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(2));
+        let first = start.elapsed();
+        assert!(first >= Duration::from_millis(2));
+        assert!(start.elapsed() >= first);
+        assert!(Instant::now() > start);
+    }
+}
@@ -1,63 +1,501 @@
+use crate::grid::presets::Shape;
+use crate::history::{EditHistory, SnapshotHistory};
+use crate::math::Real;
+use crate::particles::Tracer;
+use crate::probe::ProbeSet;
+use crate::scheduler::TickScheduler;
+use crate::simulation::{Parameter, PhaseTimings};
+use crate::types::{GridIndex, Velocity};
 use crate::visualization::ColorType;
 
-use strum_macros::EnumString;
+use macroquad::input::{get_keys_pressed, is_key_down, KeyCode};
+use strum::IntoEnumIterator;
 
-use thiserror::Error;
+pub use crate::grid::presets::{Preset, PresetError};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum MouseState {
     Inspection,
     Boundary,
     Fluid,
+    /// Drag to draw a rectangle between the two corners
+    RectTool,
+    /// Drag to draw a filled circle around the cell where the drag started
+    CircleTool,
+    /// Click to fill the region of connected cells of the same type
+    Fill,
+    /// Draw inflow cells with `UiState::inflow_velocity`
+    Inflow,
+    Outflow,
 }
 
-#[derive(Error, Debug)]
-pub enum PresetError {
-    #[error("An error occurred while parsing the Preset enum: `{0}`")]
-    PresetParsingError(String),
+impl MouseState {
+    /// The shape a drag from `anchor` to `cursor` draws, if this is one of
+    /// the shape tools
+    ///
+    /// The previews and the cells that get stamped both come from this, so
+    /// they always agree. The cell under the cursor is part of the shape, so
+    /// a drag that doesn't move covers just the anchor.
+    pub fn drag_shape(&self, anchor: GridIndex, cursor: GridIndex) -> Option<Shape> {
+        match self {
+            MouseState::RectTool => Some(Shape::Rect {
+                x0: anchor.0.min(cursor.0),
+                y0: anchor.1.min(cursor.1),
+                x1: anchor.0.max(cursor.0),
+                y1: anchor.1.max(cursor.1),
+            }),
+            MouseState::CircleTool => {
+                let dx = anchor.0.abs_diff(cursor.0) as Real;
+                let dy = anchor.1.abs_diff(cursor.1) as Real;
+                Some(Shape::Circle {
+                    x: anchor.0,
+                    y: anchor.1,
+                    r: dx.hypot(dy) + 0.5,
+                })
+            }
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, EnumString, strum_macros::VariantNames)]
-pub enum Preset {
-    Obstacle,
-    #[strum(serialize = "Empty")]
-    Inflow,
+/// What a reset starts over
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reset {
+    /// Only the flow, see `Simulation::reset_fields`. Drawn cells are kept.
+    Flow,
+    /// The whole simulation, from the preset or `--sim-file`
+    Everything,
 }
 
-impl TryFrom<usize> for Preset {
-    type Error = PresetError;
+// Number of frames the performance readout is averaged over
+const PERFORMANCE_WINDOW: usize = 60;
 
-    fn try_from(value: usize) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Preset::Obstacle),
-            1 => Ok(Preset::Inflow),
-            _ => Err(PresetError::PresetParsingError(format!(
-                "{:?} does not match to a known Preset",
-                value
-            ))),
+/// Rolling averages of the frame rate and simulation speed over the last
+/// `PERFORMANCE_WINDOW` frames
+#[derive(Debug, Clone)]
+pub struct PerformanceWindow {
+    frame_times: [f32; PERFORMANCE_WINDOW],
+    ticks: [u32; PERFORMANCE_WINDOW],
+    simulated: [Real; PERFORMANCE_WINDOW],
+    timings: [PhaseTimings; PERFORMANCE_WINDOW],
+    next: usize,
+    len: usize,
+}
+
+impl Default for PerformanceWindow {
+    fn default() -> Self {
+        PerformanceWindow {
+            frame_times: [0.0; PERFORMANCE_WINDOW],
+            ticks: [0; PERFORMANCE_WINDOW],
+            simulated: [0.0; PERFORMANCE_WINDOW],
+            timings: [PhaseTimings::default(); PERFORMANCE_WINDOW],
+            next: 0,
+            len: 0,
         }
     }
 }
 
+impl PerformanceWindow {
+    /// Record a frame that took `frame_time` seconds and ran `ticks`
+    /// simulation ticks covering `simulated` seconds, replacing the oldest
+    /// frame once the window is full
+    ///
+    /// `timings` is the sum of the stage timings of the frame's ticks.
+    pub fn push(
+        &mut self,
+        frame_time: f32,
+        ticks: u32,
+        simulated: Real,
+        timings: PhaseTimings,
+    ) {
+        self.frame_times[self.next] = frame_time;
+        self.ticks[self.next] = ticks;
+        self.simulated[self.next] = simulated;
+        self.timings[self.next] = timings;
+        self.next = (self.next + 1) % PERFORMANCE_WINDOW;
+        self.len = (self.len + 1).min(PERFORMANCE_WINDOW);
+    }
+
+    // Divide `count` by the total time of the frames in the window, or return
+    // 0 if no time has passed.
+    fn per_second(&self, count: f32) -> f32 {
+        let total_time: f32 = self.frame_times[..self.len].iter().sum();
+        if total_time > 0.0 {
+            count / total_time
+        } else {
+            0.0
+        }
+    }
+
+    pub fn frames_per_second(&self) -> f32 {
+        self.per_second(self.len as f32)
+    }
+
+    pub fn ticks_per_second(&self) -> f32 {
+        self.per_second(self.ticks[..self.len].iter().sum::<u32>() as f32)
+    }
+
+    pub fn ticks_per_frame(&self) -> f32 {
+        if self.len > 0 {
+            self.ticks[..self.len].iter().sum::<u32>() as f32 / self.len as f32
+        } else {
+            0.0
+        }
+    }
+
+    /// Simulated seconds per wall-clock second
+    pub fn simulation_rate(&self) -> f32 {
+        self.per_second(self.simulated[..self.len].iter().sum::<Real>() as f32)
+    }
+
+    /// How long the stages of a tick took on average, or all zeros if no
+    /// ticks ran
+    pub fn average_tick_timings(&self) -> PhaseTimings {
+        let ticks: u32 = self.ticks[..self.len].iter().sum();
+        if ticks == 0 {
+            return PhaseTimings::default();
+        }
+        let mut sum = PhaseTimings::default();
+        for timings in &self.timings[..self.len] {
+            sum += *timings;
+        }
+        sum / ticks
+    }
+}
+
 #[derive(Debug)]
 pub struct UiState {
     pub keep_running: bool,
     pub run: bool,
-    pub reset: bool,
+    /// Set to start over
+    pub reset: Option<Reset>,
+    /// Set to save the simulation to the save file
+    pub save: bool,
+    /// Set to replace the simulation with the one in the save file
+    pub load: bool,
+    /// Set to undo the last edit of the cells
+    pub undo: bool,
+    /// Set to redo the last undone edit of the cells
+    pub redo: bool,
+    pub show_help: bool,
     pub color_type: ColorType,
-    pub speed_multiplier: i32,
+    /// Color signed fields so that zero is in the middle of the colors
+    pub center_zero: bool,
+    /// The pressures at the ends of the colors, or None to follow the range
+    /// of the current pressure
+    pub fixed_pressure_range: Option<[Real; 2]>,
+    /// The speeds at the ends of the colors, or None to follow the range of
+    /// the current speed
+    pub fixed_speed_range: Option<[Real; 2]>,
+    pub show_boundary_outlines: bool,
+    /// Draw axes in physical units and a scale bar around the grid
+    pub show_axes: bool,
+    /// Runs the ticks of each frame at the speed set with Slower and Faster
+    pub scheduler: TickScheduler,
     pub mouse_state: MouseState,
+    /// The cell where the current drag of a shape tool started
+    pub drag_anchor: Option<GridIndex>,
+    /// The velocity of the inflow cells drawn with the mouse
+    pub inflow_velocity: Velocity,
     pub preset: Preset,
+    pub performance: PerformanceWindow,
+    /// Cells being recorded. Kept when pausing, cleared on reset.
+    pub probes: ProbeSet,
+    /// Particles carried along by the flow. Cleared on reset.
+    pub tracers: Tracer,
+    /// Recent states to roll back to when the simulation diverges. Cleared
+    /// on reset and whenever cells are drawn.
+    pub history: SnapshotHistory,
+    /// The edits of the cells, to undo and redo. Cleared when everything is
+    /// reset, but kept when only the flow is.
+    pub edits: EditHistory,
+    /// Why the last change to a parameter slider was rejected
+    pub parameter_error: Option<String>,
+    /// What happened when the simulation was last rolled back. Shown until
+    /// it runs again.
+    pub rollback_message: Option<String>,
+    /// The cells that made the last edit invalid, highlighted on the grid
+    /// for `invalid_cells_time` more seconds
+    pub invalid_cells: Vec<GridIndex>,
+    pub invalid_cells_time: f32,
+    /// The number of fluid regions that can't reach an inflow or outflow, or
+    /// None if the cells changed since they were last counted
+    pub isolated_regions: Option<usize>,
 }
 
 pub fn initialize_state() -> UiState {
     UiState {
         keep_running: true,
         run: false,
-        reset: false,
+        reset: None,
+        save: false,
+        load: false,
+        undo: false,
+        redo: false,
+        show_help: false,
         color_type: ColorType::Speed,
-        speed_multiplier: 20,
+        center_zero: false,
+        fixed_pressure_range: None,
+        fixed_speed_range: None,
+        show_boundary_outlines: false,
+        show_axes: false,
+        scheduler: TickScheduler::default(),
         mouse_state: MouseState::Boundary,
+        drag_anchor: None,
+        inflow_velocity: [1.0, 0.0],
         preset: Preset::Obstacle,
+        performance: PerformanceWindow::default(),
+        probes: ProbeSet::default(),
+        tracers: Tracer::default(),
+        history: SnapshotHistory::default(),
+        edits: EditHistory::default(),
+        parameter_error: None,
+        rollback_message: None,
+        invalid_cells: Vec::new(),
+        invalid_cells_time: 0.0,
+        isolated_regions: None,
+    }
+}
+
+/// The values the slider of `parameter` covers
+///
+/// Both ends are valid values of the parameter, see `Parameter::check`.
+pub fn slider_range(parameter: Parameter) -> std::ops::Range<f32> {
+    match parameter {
+        Parameter::Reynolds => 1.0..5000.0,
+        Parameter::Omega => 0.1..1.99,
+        Parameter::Delt => 0.0001..0.05,
+        Parameter::Gamma => 0.0..1.0,
+    }
+}
+
+/// The keyboard shortcuts and what they do, for the help overlay
+pub const KEY_BINDINGS: [(&str, &str); 11] = [
+    ("Space", "Run / pause"),
+    ("S", "Run one simulation step"),
+    ("R", "Reset the simulation"),
+    ("Ctrl+S", "Save the simulation"),
+    ("Ctrl+Z / Ctrl+Y", "Undo / redo drawing"),
+    ("+ / -", "Faster / slower"),
+    (
+        "1 - 4",
+        "Visualize pressure, speed, residual or streamlines",
+    ),
+    ("Z", "Center the pressure colors on zero"),
+    ("B / F / E", "Mouse draws boundaries, fluid or inspects"),
+    ("A", "Show or hide the axes"),
+    ("H", "Show or hide this help"),
+];
+
+/// Apply the keyboard shortcut for `key`, if it has one
+///
+/// # Arguments
+///
+/// * `ui_state` - The state to update
+/// * `key` - The key that was pressed
+/// * `control` - Whether a control key is held down
+pub fn handle_key(ui_state: &mut UiState, key: KeyCode, control: bool) {
+    match key {
+        KeyCode::Space => ui_state.keep_running = !ui_state.keep_running,
+        KeyCode::S if control => ui_state.save = true,
+        KeyCode::S => ui_state.run = true,
+        KeyCode::R => ui_state.reset = Some(Reset::Everything),
+        KeyCode::H => ui_state.show_help = !ui_state.show_help,
+        KeyCode::A => ui_state.show_axes = !ui_state.show_axes,
+        KeyCode::Z if control => ui_state.undo = true,
+        KeyCode::Y if control => ui_state.redo = true,
+        KeyCode::Z => ui_state.center_zero = !ui_state.center_zero,
+        KeyCode::Key1 | KeyCode::Key2 | KeyCode::Key3 | KeyCode::Key4 => {
+            let index = match key {
+                KeyCode::Key1 => 0,
+                KeyCode::Key2 => 1,
+                KeyCode::Key3 => 2,
+                _ => 3,
+            };
+            if let Some(color_type) = ColorType::iter().nth(index) {
+                ui_state.color_type = color_type;
+            }
+        }
+        // `+` shares a key with `=` on most layouts, so don't require shift.
+        KeyCode::Minus | KeyCode::KpSubtract | KeyCode::LeftBracket => {
+            ui_state.scheduler.slower()
+        }
+        KeyCode::Equal | KeyCode::KpAdd | KeyCode::RightBracket => {
+            ui_state.scheduler.faster()
+        }
+        KeyCode::B => ui_state.mouse_state = MouseState::Boundary,
+        KeyCode::F => ui_state.mouse_state = MouseState::Fluid,
+        KeyCode::E | KeyCode::I => ui_state.mouse_state = MouseState::Inspection,
+        _ => {}
+    }
+}
+
+/// Apply the shortcuts for the keys pressed this frame
+///
+/// See `KEY_BINDINGS` for the list of shortcuts. `[` and `]` also change the
+/// speed, and `I` also makes the mouse inspect. macroquad doesn't say which
+/// widget has the keyboard, so typing a number into a slider or drag field
+/// triggers the shortcuts too.
+pub fn handle_keyboard(ui_state: &mut UiState) {
+    let control = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    for key in get_keys_pressed() {
+        handle_key(ui_state, key, control);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn keyboard_shortcuts() {
+        let mut ui_state = initialize_state();
+
+        handle_key(&mut ui_state, KeyCode::Space, false);
+        assert!(!ui_state.keep_running);
+        handle_key(&mut ui_state, KeyCode::Space, false);
+        assert!(ui_state.keep_running);
+
+        handle_key(&mut ui_state, KeyCode::S, true);
+        assert!(ui_state.save);
+        assert!(!ui_state.run);
+        handle_key(&mut ui_state, KeyCode::S, false);
+        assert!(ui_state.run);
+        handle_key(&mut ui_state, KeyCode::R, false);
+        assert_eq!(ui_state.reset, Some(Reset::Everything));
+
+        handle_key(&mut ui_state, KeyCode::Key1, false);
+        assert_eq!(ui_state.color_type, ColorType::Pressure);
+        handle_key(&mut ui_state, KeyCode::Key2, false);
+        assert_eq!(ui_state.color_type, ColorType::Speed);
+        handle_key(&mut ui_state, KeyCode::Key4, false);
+        assert_eq!(ui_state.color_type, ColorType::StreamFunction);
+
+        ui_state.scheduler.rate = 2.0;
+        handle_key(&mut ui_state, KeyCode::LeftBracket, false);
+        handle_key(&mut ui_state, KeyCode::LeftBracket, false);
+        assert_eq!(ui_state.scheduler.rate, 0.5);
+        handle_key(&mut ui_state, KeyCode::RightBracket, false);
+        assert_eq!(ui_state.scheduler.rate, 1.0);
+        handle_key(&mut ui_state, KeyCode::Equal, false);
+        handle_key(&mut ui_state, KeyCode::KpAdd, false);
+        assert_eq!(ui_state.scheduler.rate, 4.0);
+        handle_key(&mut ui_state, KeyCode::Minus, false);
+        handle_key(&mut ui_state, KeyCode::KpSubtract, false);
+        assert_eq!(ui_state.scheduler.rate, 1.0);
+
+        handle_key(&mut ui_state, KeyCode::H, false);
+        assert!(ui_state.show_help);
+        handle_key(&mut ui_state, KeyCode::H, false);
+        assert!(!ui_state.show_help);
+        handle_key(&mut ui_state, KeyCode::A, false);
+        assert!(ui_state.show_axes);
+        handle_key(&mut ui_state, KeyCode::Z, false);
+        assert!(ui_state.center_zero);
+        handle_key(&mut ui_state, KeyCode::Z, true);
+        assert!(ui_state.undo);
+        assert!(ui_state.center_zero);
+        handle_key(&mut ui_state, KeyCode::Y, true);
+        assert!(ui_state.redo);
+
+        handle_key(&mut ui_state, KeyCode::F, false);
+        assert_eq!(ui_state.mouse_state, MouseState::Fluid);
+        handle_key(&mut ui_state, KeyCode::E, false);
+        assert_eq!(ui_state.mouse_state, MouseState::Inspection);
+        handle_key(&mut ui_state, KeyCode::B, false);
+        assert_eq!(ui_state.mouse_state, MouseState::Boundary);
+        handle_key(&mut ui_state, KeyCode::I, false);
+        assert_eq!(ui_state.mouse_state, MouseState::Inspection);
+        handle_key(&mut ui_state, KeyCode::B, false);
+        assert_eq!(ui_state.mouse_state, MouseState::Boundary);
+    }
+
+    #[test]
+    fn slider_ranges() {
+        for parameter in Parameter::iter() {
+            let range = slider_range(parameter);
+            assert!(range.start < range.end);
+            for value in [range.start, range.end] {
+                assert!(
+                    parameter.check(Real::from(value)).is_ok(),
+                    "{:?}",
+                    parameter
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn performance_window() {
+        let mut performance = PerformanceWindow::default();
+        assert_eq!(performance.frames_per_second(), 0.0);
+        assert_eq!(performance.ticks_per_second(), 0.0);
+        assert_eq!(performance.ticks_per_frame(), 0.0);
+
+        assert_eq!(performance.average_tick_timings(), PhaseTimings::default());
+
+        let timings = |millis| PhaseTimings {
+            solve_sor: Duration::from_millis(millis),
+            total: Duration::from_millis(2 * millis),
+            ..Default::default()
+        };
+        performance.push(0.5, 10, 0.05, timings(10));
+        performance.push(0.5, 20, 0.1, timings(50));
+        assert_eq!(performance.frames_per_second(), 2.0);
+        assert_eq!(performance.ticks_per_second(), 30.0);
+        assert_eq!(performance.ticks_per_frame(), 15.0);
+        assert!((performance.simulation_rate() - 0.15).abs() < 1e-6);
+        assert_eq!(performance.average_tick_timings(), timings(2));
+
+        // Old frames drop out once the window is full.
+        for _ in 0..PERFORMANCE_WINDOW {
+            performance.push(0.25, 1, 0.5, timings(1));
+        }
+        assert_eq!(performance.frames_per_second(), 4.0);
+        assert_eq!(performance.ticks_per_second(), 4.0);
+        assert_eq!(performance.ticks_per_frame(), 1.0);
+        assert_eq!(performance.simulation_rate(), 2.0);
+        assert_eq!(performance.average_tick_timings(), timings(1));
+    }
+
+    #[test]
+    fn drag_shapes() {
+        let size = [10, 8];
+        let tools = [MouseState::RectTool, MouseState::CircleTool];
+
+        // A drag that doesn't move covers just the cell it started on.
+        for tool in &tools {
+            let shape = tool.drag_shape((4, 3), (4, 3)).unwrap();
+            assert_eq!(shape.cells(size), vec![(4, 3)]);
+        }
+        assert_eq!(MouseState::Boundary.drag_shape((4, 3), (5, 5)), None);
+
+        // Rectangles span both corners whichever way the drag went.
+        let rect = MouseState::RectTool.drag_shape((5, 4), (3, 2)).unwrap();
+        assert_eq!(
+            rect,
+            Shape::Rect {
+                x0: 3,
+                y0: 2,
+                x1: 5,
+                y1: 4
+            }
+        );
+        assert_eq!(rect.cells(size).len(), 9);
+        // The circle reaches the cell under the cursor.
+        let circle = MouseState::CircleTool.drag_shape((4, 4), (6, 4)).unwrap();
+        assert!(circle.cells(size).contains(&(6, 4)));
+        assert!(!circle.cells(size).contains(&(7, 4)));
+
+        // Shapes dragged over the outer ring stop at the interior.
+        let interior = (size[0] - 2) * (size[1] - 2);
+        for tool in &tools {
+            let cells = tool.drag_shape((0, 0), (20, 20)).unwrap().cells(size);
+            assert_eq!(cells.len(), interior);
+            assert!(cells
+                .iter()
+                .all(|&(x, y)| x > 0 && x < size[0] - 1 && y > 0 && y < size[1] - 1));
+        }
     }
 }
@@ -0,0 +1,149 @@
+use crate::math::Real;
+use crate::simulation::Simulation;
+use crate::types::GridArray;
+
+/// The physical scales that the simulation's dimensionless quantities are
+/// measured in
+///
+/// The solver works with dimensionless lengths, velocities and pressures, and
+/// only the Reynolds number ties them to a physical flow. Picking a length
+/// scale (e.g. the width of the channel) and a velocity scale (e.g. the inflow
+/// velocity) for a fluid with a known kinematic viscosity fixes the Reynolds
+/// number and everything else follows from it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnitSystem {
+    /// The physical length of one dimensionless unit, in m
+    pub length_scale: Real,
+    /// The physical velocity of one dimensionless unit, in m/s
+    pub velocity_scale: Real,
+    /// The kinematic viscosity of the fluid, in m²/s
+    pub viscosity: Real,
+}
+
+impl UnitSystem {
+    /// The Reynolds number to simulate this flow with
+    pub fn reynolds(&self) -> Real {
+        Simulation::reynolds_from(self.length_scale, self.velocity_scale, self.viscosity)
+    }
+
+    /// The physical time of one dimensionless unit, in s
+    pub fn time_scale(&self) -> Real {
+        self.length_scale / self.velocity_scale
+    }
+
+    pub fn length_to_physical(&self, length: Real) -> Real {
+        length * self.length_scale
+    }
+
+    pub fn time_to_physical(&self, time: Real) -> Real {
+        time * self.time_scale()
+    }
+
+    pub fn velocity_to_physical(&self, velocity: Real) -> Real {
+        velocity * self.velocity_scale
+    }
+
+    pub fn velocity_to_dimensionless(&self, velocity: Real) -> Real {
+        velocity / self.velocity_scale
+    }
+
+    /// Convert a dimensionless pressure for a fluid of density 1 to Pa
+    ///
+    /// # Arguments
+    ///
+    /// * `pressure` - The dimensionless pressure
+    /// * `density` - The physical density of the fluid, in kg/m³
+    pub fn pressure_to_physical(&self, pressure: Real, density: Real) -> Real {
+        pressure * density * self.velocity_scale.powi(2)
+    }
+
+    /// The velocity at the center of every cell of `simulation`, in m/s
+    pub fn physical_velocity(
+        &self,
+        simulation: &Simulation,
+    ) -> (GridArray<Real>, GridArray<Real>) {
+        let (u, v) = simulation.grid.cell_centered_velocity();
+        (u * self.velocity_scale, v * self.velocity_scale)
+    }
+
+    /// The pressure of every cell of `simulation`, in Pa
+    ///
+    /// The simulation's pressure is scaled by `Simulation::density`, which is
+    /// divided out first.
+    ///
+    /// # Arguments
+    ///
+    /// * `simulation` - The simulation to read the pressure from
+    /// * `density` - The physical density of the fluid, in kg/m³
+    pub fn physical_pressure(
+        &self,
+        simulation: &Simulation,
+        density: Real,
+    ) -> GridArray<Real> {
+        simulation
+            .grid
+            .pressure
+            .mapv(|p| self.pressure_to_physical(p / simulation.density, density))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::SimulationBuilder;
+    use crate::grid::presets;
+
+    use crate::math::assert_close;
+
+    // Water at 20 °C flowing at 1 cm/s past a 10 cm obstacle
+    const WATER: UnitSystem = UnitSystem {
+        length_scale: 0.1,
+        velocity_scale: 0.01,
+        viscosity: 1.0e-6,
+    };
+
+    #[test]
+    fn reynolds_from() {
+        for (length, velocity, viscosity) in
+            [(0.1, 0.01, 1.0e-6), (2.0, 30.0, 1.5e-5), (1.0, 1.0, 0.01)]
+        {
+            let reynolds = Simulation::reynolds_from(length, velocity, viscosity);
+            let expected = velocity * length / viscosity;
+            assert_close!(reynolds, expected);
+        }
+        assert_close!(WATER.reynolds(), 1000.0);
+        assert_close!(WATER.time_scale(), 10.0);
+        assert_close!(WATER.time_to_physical(0.5), 5.0);
+    }
+
+    #[test]
+    fn velocity_round_trip() {
+        for velocity in [0.0, 1.0, -0.25, 3.7, 1e-9] {
+            let physical = WATER.velocity_to_physical(velocity);
+            assert_close!(WATER.velocity_to_dimensionless(physical), velocity);
+        }
+        assert_eq!(WATER.velocity_to_physical(2.0), 0.02);
+    }
+
+    #[test]
+    fn physical_fields() {
+        let mut grid = presets::empty([6, 5]);
+        grid.u.fill(2.0);
+        grid.pressure.fill(3.0);
+        let sim = SimulationBuilder::new()
+            .grid(grid)
+            .reynolds(WATER.reynolds())
+            .density(2.0)
+            .build()
+            .unwrap();
+
+        let (u, v) = WATER.physical_velocity(&sim);
+        assert_close!(u[(2, 2)], 0.02);
+        assert_eq!(v[(2, 2)], 0.0);
+        // 3 / 2 * 1000 kg/m³ * (0.01 m/s)²
+        let pressure = WATER.physical_pressure(&sim, 1000.0);
+        for &p in &pressure {
+            assert_close!(p, 0.15);
+        }
+    }
+}
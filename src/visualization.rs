@@ -1,10 +1,21 @@
 use crate::cell::Cell;
+use crate::grid::{EdgeType, SimulationGrid};
 use crate::math::Real;
-use crate::simulation::Simulation;
+use crate::particles::Tracer;
+use crate::probe::{ProbeSample, ProbeSet, MAX_PROBES};
+use crate::simulation::{PhaseTimings, Simulation};
+use crate::types::{GridArray, GridIndex, GridSize};
+use macroquad::color::colors;
 use macroquad::prelude::Color;
 use macroquad::prelude::Image;
+use macroquad::prelude::Rect;
+use macroquad::shapes::{draw_circle, draw_line, draw_rectangle, draw_rectangle_lines};
+use macroquad::text::{draw_text, measure_text};
+use ndarray::Array;
 
+// Hues outside of [0, 360) wrap around the color wheel.
 fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let hue = hue.rem_euclid(360.0);
     let c = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
     let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
     let m = lightness - c / 2.0;
@@ -32,9 +43,8 @@ fn color_speed(cell_type: Cell, u: Real, v: Real, speed_range: [Real; 2]) -> Col
             let speed = (u.powi(2) + v.powi(2)).sqrt();
 
             // 240 offset to map from blue to red instead of the whole range of hue
-            let hue: f32 = (240.0
-                - (speed - speed_range[0]) * 240.0 / (speed_range[1] - speed_range[0]))
-                as f32;
+            let offset = 240.0;
+            let hue = offset - normalize(speed, speed_range, false) * offset;
             let saturation = 1.0;
             let lightness = 0.5;
 
@@ -46,15 +56,42 @@ fn color_speed(cell_type: Cell, u: Real, v: Real, speed_range: [Real; 2]) -> Col
     }
 }
 
-fn color_pressure(cell_type: Cell, pressure: Real, pressure_range: [f64; 2]) -> Color {
+/// Where `value` lies in `range`, from 0 at the start to 1 at the end
+///
+/// With `center_zero`, the range is widened to be symmetric around zero, so
+/// that zero is always in the middle and values of the same size but
+/// opposite sign are equally far from it. Empty ranges put everything in the
+/// middle, and values outside of the range are clamped to its ends.
+fn normalize(value: Real, range: [Real; 2], center_zero: bool) -> f32 {
+    let position = if center_zero {
+        let half_range = range[0].abs().max(range[1].abs());
+        if half_range > 0.0 {
+            (0.5 + value / (2.0 * half_range)) as f32
+        } else {
+            0.5
+        }
+    } else {
+        let span = range[1] - range[0];
+        if span > 0.0 {
+            ((value - range[0]) / span) as f32
+        } else {
+            0.5
+        }
+    };
+    position.clamp(0.0, 1.0)
+}
+
+fn color_pressure(
+    cell_type: Cell,
+    pressure: Real,
+    pressure_range: [Real; 2],
+    center_zero: bool,
+) -> Color {
     match cell_type {
         Cell::Fluid => {
             // 240 offset to map from blue to red instead of the whole range of hue
             let offset = 240.0;
-            let hue: f32 = (offset
-                - (pressure - pressure_range[0]) * offset
-                    / (pressure_range[1] - pressure_range[0]))
-                as f32;
+            let hue = offset - normalize(pressure, pressure_range, center_zero) * offset;
             let saturation = 1.0;
             let lightness = 0.5;
 
@@ -69,20 +106,121 @@ fn color_pressure(cell_type: Cell, pressure: Real, pressure_range: [f64; 2]) ->
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+fn color_residual(cell_type: Cell, residual: Real, max_residual: Real) -> Color {
+    match cell_type {
+        Cell::Fluid => {
+            // Black where the residual is zero, through red to yellow at the
+            // largest residual
+            let value = if max_residual > 0.0 {
+                (residual.abs() / max_residual) as f32
+            } else {
+                0.0
+            };
+            Color::new(
+                (2.0 * value).min(1.0),
+                (2.0 * value - 1.0).max(0.0),
+                0.0,
+                1.0,
+            )
+        }
+        Cell::Boundary(_) => Color::new(0.5, 0.5, 0.5, 1.0),
+    }
+}
+
+fn color_stream_function(cell_type: Cell, psi: Real, psi_range: [Real; 2]) -> Color {
+    match cell_type {
+        Cell::Fluid => {
+            let span = psi_range[1] - psi_range[0];
+            let value = if span > 0.0 {
+                ((psi - psi_range[0]) / span) as f32
+            } else {
+                0.0
+            };
+            // Bands of hue, so that the streamlines show up as the borders
+            // between them
+            let hue = (value * 360.0 * 4.0) % 360.0;
+            let (r, g, b) = hsl_to_rgb(hue, 0.6, 0.5);
+            Color::new(r, g, b, 1.0)
+        }
+        Cell::Boundary(_) => Color::new(0.5, 0.5, 0.5, 1.0),
+    }
+}
+
+// The order of the variants is the order of the number key shortcuts.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, strum_macros::EnumIter, clap::ValueEnum,
+)]
 pub enum ColorType {
     #[default]
     Pressure,
     Speed,
+    /// The residual of the pressure equation, where SOR hasn't converged
+    Residual,
+    /// The stream function, whose contours are the streamlines
+    StreamFunction,
 }
 
+/// How the values of the fields are mapped to colors
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ColorScale {
+    /// Put zero in the middle of the colors of signed fields, such as the
+    /// pressure, instead of the middle of their range
+    pub center_zero: bool,
+    /// The pressures at the ends of the colors, instead of the range of the
+    /// current pressure. Pressures outside of it get the color of the nearest
+    /// end, so the colors don't flicker as extremes come and go.
+    pub pressure_range: Option<[Real; 2]>,
+    /// The speeds at the ends of the colors, like `pressure_range`
+    pub speed_range: Option<[Real; 2]>,
+}
+
+/// Draw the simulation into `image`, one pixel per cell
+///
+/// # Arguments
+///
+/// * `simulation` - The simulation to draw
+/// * `image` - The image to draw into
+/// * `w` - The width of the grid
+/// * `h` - The height of the grid
+/// * `color_type` - Which field to visualize
+/// * `scale` - How the values are mapped to colors
+/// * `scratch` - Space for fields that have to be calculated, such as the
+///   residual or the stream function, kept between frames to avoid
+///   reallocating it
 pub fn render_simulation(
     simulation: &Simulation,
     image: &mut Image,
     w: usize,
     h: usize,
     color_type: ColorType,
+    scale: ColorScale,
+    scratch: &mut GridArray<Real>,
 ) {
+    if matches!(color_type, ColorType::Residual | ColorType::StreamFunction)
+        && scratch.shape() != simulation.size
+    {
+        *scratch = Array::zeros(simulation.size);
+    }
+    let mut max_residual = 0.0;
+    let mut psi_range = [0.0; 2];
+    match color_type {
+        ColorType::Residual => {
+            simulation.residual_field_into(scratch);
+            max_residual = scratch.fold(0.0, |acc: Real, r| acc.max(r.abs()));
+        }
+        ColorType::StreamFunction => {
+            simulation.stream_function_into(scratch);
+            psi_range = scratch.fold([Real::MAX, Real::MIN], |[min, max], psi| {
+                [min.min(*psi), max.max(*psi)]
+            });
+        }
+        _ => {}
+    }
+    let pressure_range = scale
+        .pressure_range
+        .unwrap_or(simulation.grid.pressure_range);
+    let speed_range = scale.speed_range.unwrap_or(simulation.grid.speed_range);
+
     for x in 0..w {
         for y in 0..h {
             let cell_type = simulation.grid.cell_type[(x, y)];
@@ -90,16 +228,711 @@ pub fn render_simulation(
                 ColorType::Pressure => color_pressure(
                     cell_type,
                     simulation.grid.pressure[(x, y)],
-                    simulation.grid.pressure_range,
+                    pressure_range,
+                    scale.center_zero,
                 ),
                 ColorType::Speed => color_speed(
                     cell_type,
                     simulation.grid.u[(x, y)],
                     simulation.grid.v[(x, y)],
-                    simulation.grid.speed_range,
+                    speed_range,
                 ),
+                ColorType::Residual => {
+                    color_residual(cell_type, scratch[(x, y)], max_residual)
+                }
+                ColorType::StreamFunction => {
+                    color_stream_function(cell_type, scratch[(x, y)], psi_range)
+                }
             };
             image.set_pixel(x as u32, y as u32, color);
         }
     }
 }
+
+/// The color of cells that would make the grid invalid, see
+/// `highlight_cells`
+pub const WARNING_COLOR: Color = Color::new(1.0, 0.85, 0.0, 1.0);
+
+/// Paint `cells` of the `w` by `h` grid in `color` on an image drawn by
+/// `render_simulation`. Cells outside of the grid are skipped.
+pub fn highlight_cells(
+    image: &mut Image,
+    w: usize,
+    h: usize,
+    cells: &[GridIndex],
+    color: Color,
+) {
+    for &(x, y) in cells {
+        if x < w && y < h {
+            image.set_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+/// Where the grid is drawn on screen
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GridView {
+    /// The left edge of the grid, in pixels
+    pub x: f32,
+    /// The top edge of the grid, in pixels
+    pub y: f32,
+    /// The size of a cell on screen, in pixels
+    pub scaling: usize,
+    pub size: GridSize,
+}
+
+impl GridView {
+    /// Fit a grid into `area` with the largest whole number of pixels per
+    /// cell, centered horizontally and aligned to the top
+    ///
+    /// Cells are at least one pixel wide, even if the grid then doesn't fit.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size of the grid
+    /// * `area` - The space available for the grid, in pixels
+    pub fn fit(size: GridSize, area: Rect) -> Self {
+        let fit_x = (area.w / size[0] as f32).floor();
+        let fit_y = (area.h / size[1] as f32).floor();
+        let scaling = fit_x.min(fit_y).max(1.0) as usize;
+        let width = (size[0] * scaling) as f32;
+        GridView {
+            x: area.x + ((area.w - width) / 2.0).max(0.0).floor(),
+            y: area.y,
+            scaling,
+            size,
+        }
+    }
+
+    /// The width of the grid on screen, in pixels
+    pub fn width(&self) -> f32 {
+        (self.size[0] * self.scaling) as f32
+    }
+
+    /// The height of the grid on screen, in pixels
+    pub fn height(&self) -> f32 {
+        (self.size[1] * self.scaling) as f32
+    }
+
+    /// The screen position of the top left corner of `cell`
+    pub fn cell_position(&self, (x, y): GridIndex) -> (f32, f32) {
+        let scaling = self.scaling as f32;
+        (self.x + x as f32 * scaling, self.y + y as f32 * scaling)
+    }
+
+    /// The cell under the screen position (`x`, `y`), if there is one
+    pub fn cell_at(&self, x: f32, y: f32) -> Option<GridIndex> {
+        let scaling = self.scaling as f32;
+        let (dx, dy) = (x - self.x, y - self.y);
+        if dx < 0.0 || dy < 0.0 {
+            return None;
+        }
+        let cell = ((dx / scaling) as usize, (dy / scaling) as usize);
+        (cell.0 < self.size[0] && cell.1 < self.size[1]).then_some(cell)
+    }
+
+    /// The cell closest to the screen position (`x`, `y`), which is the cell
+    /// under it if there is one
+    pub fn nearest_cell(&self, x: f32, y: f32) -> GridIndex {
+        let scaling = self.scaling as f32;
+        let clamp =
+            |offset: f32, len: usize| ((offset / scaling).max(0.0) as usize).min(len - 1);
+        (
+            clamp(x - self.x, self.size[0]),
+            clamp(y - self.y, self.size[1]),
+        )
+    }
+}
+
+const OUTLINE_COLOR: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+const OUTLINE_THICKNESS: f32 = 1.0;
+
+// The screen segments along the sides of a boundary cell that face fluid.
+// Screen y grows downward like the grid's j index, so the north side of a
+// cell is its top edge.
+fn edge_segments(
+    (x, y): (usize, usize),
+    edge: EdgeType,
+    scaling: f32,
+) -> Vec<[(f32, f32); 2]> {
+    let (x0, y0) = (x as f32 * scaling, y as f32 * scaling);
+    let (x1, y1) = (x0 + scaling, y0 + scaling);
+    let north = [(x0, y0), (x1, y0)];
+    let east = [(x1, y0), (x1, y1)];
+    let south = [(x0, y1), (x1, y1)];
+    let west = [(x0, y0), (x0, y1)];
+    match edge {
+        EdgeType::North { .. } => vec![north],
+        EdgeType::NorthEast { .. } => vec![north, east],
+        EdgeType::East { .. } => vec![east],
+        EdgeType::SouthEast { .. } => vec![south, east],
+        EdgeType::South { .. } => vec![south],
+        EdgeType::SouthWest { .. } => vec![south, west],
+        EdgeType::West { .. } => vec![west],
+        EdgeType::NorthWest { .. } => vec![north, west],
+    }
+}
+
+/// Compute the line segments separating fluid from boundary cells, in screen
+/// coordinates
+///
+/// # Arguments
+///
+/// * `grid` - The grid whose boundaries are outlined
+/// * `scaling` - The size of a cell on screen, in pixels
+pub fn boundary_outline_segments(
+    grid: &SimulationGrid,
+    scaling: usize,
+) -> Vec<[(f32, f32); 2]> {
+    grid.boundaries
+        .sorted_boundary_list
+        .iter()
+        .filter_map(|&(idx, edge)| edge.map(|edge| (idx, edge)))
+        .flat_map(|(idx, edge)| edge_segments(idx, edge, scaling as f32))
+        .collect()
+}
+
+/// Draw a thin outline along every interface between fluid and boundary cells
+///
+/// # Arguments
+///
+/// * `simulation` - The simulation whose boundaries are outlined
+/// * `view` - Where the grid is drawn
+pub fn draw_boundary_outlines(simulation: &Simulation, view: &GridView) {
+    for [(x0, y0), (x1, y1)] in boundary_outline_segments(&simulation.grid, view.scaling)
+    {
+        draw_line(
+            view.x + x0,
+            view.y + y0,
+            view.x + x1,
+            view.y + y1,
+            OUTLINE_THICKNESS,
+            OUTLINE_COLOR,
+        );
+    }
+}
+
+/// The color of each probe's marker and plot, in the order they were added
+pub const PROBE_COLORS: [Color; MAX_PROBES] = [
+    colors::MAGENTA,
+    colors::DARKBLUE,
+    colors::ORANGE,
+    colors::DARKGREEN,
+    colors::RED,
+    colors::VIOLET,
+    colors::BROWN,
+    colors::BLACK,
+];
+
+/// Outline each probed cell in its probe's color
+///
+/// # Arguments
+///
+/// * `probes` - The probes to mark
+/// * `view` - Where the grid is drawn
+pub fn draw_probe_markers(probes: &ProbeSet, view: &GridView) {
+    let scaling = view.scaling as f32;
+    for (probe, color) in probes.probes().iter().zip(PROBE_COLORS) {
+        let (x, y) = view.cell_position(probe.location);
+        draw_rectangle_lines(x, y, scaling, scaling, 2.0, color);
+    }
+}
+
+const TRACER_COLOR: Color = Color::new(0.0, 0.0, 0.0, 0.8);
+
+/// Draw each tracer particle as a small dot
+///
+/// # Arguments
+///
+/// * `tracers` - The particles to draw
+/// * `view` - Where the grid is drawn
+pub fn draw_tracers(tracers: &Tracer, view: &GridView) {
+    let scaling = view.scaling as f32;
+    let radius = (scaling / 4.0).clamp(1.0, 3.0);
+    for &(x, y) in &tracers.positions {
+        draw_circle(
+            view.x + x as f32 * scaling,
+            view.y + y as f32 * scaling,
+            radius,
+            TRACER_COLOR,
+        );
+    }
+}
+
+const PREVIEW_BOUNDARY_COLOR: Color = Color::new(0.2, 0.2, 0.2, 0.4);
+const PREVIEW_FLUID_COLOR: Color = Color::new(0.15, 0.55, 0.9, 0.4);
+
+/// Shade the cells a shape tool is about to draw
+///
+/// # Arguments
+///
+/// * `cells` - The cells covered by the shape
+/// * `view` - Where the grid is drawn
+/// * `erase` - Whether the cells will become fluid instead of boundary
+pub fn draw_shape_preview(cells: &[GridIndex], view: &GridView, erase: bool) {
+    let scaling = view.scaling as f32;
+    let color = if erase {
+        PREVIEW_FLUID_COLOR
+    } else {
+        PREVIEW_BOUNDARY_COLOR
+    };
+    for &cell in cells {
+        let (x, y) = view.cell_position(cell);
+        draw_rectangle(x, y, scaling, scaling, color);
+    }
+}
+
+// Plot `value` of every probe's history as lines filling `area`, scaled so
+// that the smallest and largest values of all probes fit.
+fn draw_probe_plot(
+    probes: &ProbeSet,
+    area: Rect,
+    label: &str,
+    value: impl Fn(&ProbeSample) -> Real,
+) {
+    draw_rectangle_lines(area.x, area.y, area.w, area.h, 1.0, colors::GRAY);
+    draw_text(label, area.x + 4.0, area.y + 14.0, 16.0, colors::DARKGRAY);
+
+    let (min, max) = probes
+        .probes()
+        .iter()
+        .flat_map(|probe| probe.history.iter().map(&value))
+        .fold((Real::MAX, Real::MIN), |(min, max), v| {
+            (min.min(v), max.max(v))
+        });
+    let range = if max > min { max - min } else { 1.0 };
+
+    for (probe, color) in probes.probes().iter().zip(PROBE_COLORS) {
+        let samples = probe.history.len();
+        if samples < 2 {
+            continue;
+        }
+        let point = |i: usize, sample: &ProbeSample| {
+            (
+                area.x + area.w * i as f32 / (samples - 1) as f32,
+                area.y + area.h * (1.0 - ((value(sample) - min) / range) as f32),
+            )
+        };
+        let mut last = point(0, &probe.history[0]);
+        for (i, sample) in probe.history.iter().enumerate().skip(1) {
+            let next = point(i, sample);
+            draw_line(last.0, last.1, next.0, next.1, 1.0, color);
+            last = next;
+        }
+    }
+}
+
+/// Plot the pressure and speed history of the probes
+///
+/// # Arguments
+///
+/// * `probes` - The probes to plot
+/// * `area` - Where to draw, split between a pressure plot on top and a speed
+///   plot below
+pub fn draw_probe_plots(probes: &ProbeSet, area: Rect) {
+    if probes.is_empty() {
+        return;
+    }
+    let half = area.h / 2.0;
+    draw_probe_plot(
+        probes,
+        Rect::new(area.x, area.y, area.w, half),
+        "pressure",
+        |sample| sample.pressure,
+    );
+    draw_probe_plot(
+        probes,
+        Rect::new(area.x, area.y + half, area.w, half),
+        "speed",
+        |sample| sample.speed,
+    );
+}
+
+// One color per stage of `PhaseTimings::phases`
+const PHASE_COLORS: [Color; 5] = [
+    colors::GRAY,
+    colors::BLUE,
+    colors::ORANGE,
+    colors::RED,
+    colors::DARKGREEN,
+];
+const PHASE_FONT_SIZE: f32 = 16.0;
+
+/// Draw a bar split into one segment per stage of a tick, each as wide as
+/// the stage's share of the total time, with a legend below
+///
+/// # Arguments
+///
+/// * `timings` - How long the stages took
+/// * `area` - Where to draw; the top half holds the bar and the bottom half
+///   the legend
+pub fn draw_phase_bar(timings: &PhaseTimings, area: Rect) {
+    let half = area.h / 2.0;
+    draw_rectangle_lines(area.x, area.y, area.w, half, 1.0, colors::GRAY);
+    let total = timings.total.as_secs_f32();
+    let mut x = area.x;
+    let mut legend_x = area.x;
+    for ((name, duration), color) in timings.phases().into_iter().zip(PHASE_COLORS) {
+        if total > 0.0 {
+            let width = area.w * duration.as_secs_f32() / total;
+            draw_rectangle(x, area.y, width, half, color);
+            x += width;
+        }
+        draw_rectangle(legend_x, area.y + half + 4.0, 10.0, 10.0, color);
+        draw_text(
+            name,
+            legend_x + 14.0,
+            area.y + half + 14.0,
+            PHASE_FONT_SIZE,
+            colors::DARKGRAY,
+        );
+        legend_x += 24.0 + measure_text(name, None, PHASE_FONT_SIZE as u16, 1.0).width;
+    }
+}
+
+/// Space to leave to the left of and below the grid for the axes, in pixels
+pub const AXIS_MARGIN: f32 = 40.0;
+const AXIS_FONT_SIZE: f32 = 16.0;
+const AXIS_TICK_LENGTH: f32 = 5.0;
+// Ticks closer than this, in pixels, would have overlapping labels.
+const AXIS_MIN_TICK_SPACING: f32 = 50.0;
+
+/// Pick a distance between axis ticks of 1, 2 or 5 times a power of ten, so
+/// that ticks are at least `min_spacing` pixels apart
+///
+/// # Arguments
+///
+/// * `pixels_per_unit` - The length on screen of one physical unit
+/// * `min_spacing` - The smallest distance between ticks, in pixels
+pub fn tick_step(pixels_per_unit: f32, min_spacing: f32) -> Real {
+    let smallest = (min_spacing / pixels_per_unit) as Real;
+    let magnitude = (10.0 as Real).powf(smallest.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= smallest)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Compute the ticks along one side of the grid, as the distance from the
+/// start of the side in pixels and the physical distance it's labeled with
+///
+/// # Arguments
+///
+/// * `cells` - The number of cells along the side
+/// * `cell_length` - The physical length of a cell along the side
+/// * `scaling` - The size of a cell on screen, in pixels
+pub fn axis_ticks(cells: usize, cell_length: Real, scaling: usize) -> Vec<(f32, Real)> {
+    let pixels_per_unit = scaling as f32 / cell_length as f32;
+    let step = tick_step(pixels_per_unit, AXIS_MIN_TICK_SPACING);
+    let length = cells as Real * cell_length;
+    // Allow for rounding errors, so the far end gets a tick when it's a
+    // multiple of the step.
+    let count = (length / step + 1e-9).floor() as usize;
+    (0..=count)
+        .map(|tick| {
+            let value = tick as Real * step;
+            (value as f32 * pixels_per_unit, value)
+        })
+        .collect()
+}
+
+// The number of decimals needed to tell ticks `step` apart
+fn tick_decimals(step: Real) -> usize {
+    (-step.log10().floor()).max(0.0) as usize
+}
+
+/// Draw axes labeled with physical distances along the left and bottom of the
+/// grid, and a scale bar in its top right corner
+///
+/// Distances are measured from the top left corner, with y growing downward
+/// like the grid's cell indices. The axes are drawn in the `AXIS_MARGIN`
+/// pixels next to the grid.
+///
+/// # Arguments
+///
+/// * `simulation` - The simulation whose cell size labels the axes
+/// * `view` - Where the grid is drawn
+pub fn draw_axes(simulation: &Simulation, view: &GridView) {
+    let [delx, dely] = simulation.cell_size();
+    let color = colors::DARKGRAY;
+    let (left, top) = (view.x, view.y);
+    let (right, bottom) = (left + view.width(), top + view.height());
+    draw_line(left, bottom, right, bottom, 1.0, color);
+    draw_line(left, top, left, bottom, 1.0, color);
+
+    let x_ticks = axis_ticks(view.size[0], delx, view.scaling);
+    let decimals = x_ticks.get(1).map_or(0, |(_, step)| tick_decimals(*step));
+    for (offset, value) in x_ticks {
+        let x = left + offset;
+        draw_line(x, bottom, x, bottom + AXIS_TICK_LENGTH, 1.0, color);
+        let label = format!("{:.*}", decimals, value);
+        let width = measure_text(&label, None, AXIS_FONT_SIZE as u16, 1.0).width;
+        draw_text(
+            &label,
+            x - width / 2.0,
+            bottom + AXIS_TICK_LENGTH + AXIS_FONT_SIZE,
+            AXIS_FONT_SIZE,
+            color,
+        );
+    }
+
+    let y_ticks = axis_ticks(view.size[1], dely, view.scaling);
+    let decimals = y_ticks.get(1).map_or(0, |(_, step)| tick_decimals(*step));
+    for (offset, value) in y_ticks {
+        let y = top + offset;
+        draw_line(left - AXIS_TICK_LENGTH, y, left, y, 1.0, color);
+        let label = format!("{:.*}", decimals, value);
+        let width = measure_text(&label, None, AXIS_FONT_SIZE as u16, 1.0).width;
+        draw_text(
+            &label,
+            left - AXIS_TICK_LENGTH - width - 2.0,
+            y + AXIS_FONT_SIZE / 3.0,
+            AXIS_FONT_SIZE,
+            color,
+        );
+    }
+
+    // A scale bar of a round length, about a fifth of the grid's width
+    let pixels_per_unit = view.scaling as f32 / delx as f32;
+    let length = tick_step(pixels_per_unit, view.width() / 5.0);
+    let bar = length as f32 * pixels_per_unit;
+    let padding = 6.0;
+    let label = format!("{:.*}", tick_decimals(length), length);
+    let (x0, y0) = (right - bar - 3.0 * padding, top + padding);
+    draw_rectangle(x0, y0, bar + 2.0 * padding, 30.0, HELP_BACKGROUND);
+    draw_line(
+        x0 + padding,
+        y0 + 22.0,
+        x0 + padding + bar,
+        y0 + 22.0,
+        3.0,
+        colors::BLACK,
+    );
+    draw_text(
+        &label,
+        x0 + padding,
+        y0 + 15.0,
+        AXIS_FONT_SIZE,
+        colors::BLACK,
+    );
+}
+
+const HELP_BACKGROUND: Color = Color::new(1.0, 1.0, 1.0, 0.85);
+const HELP_FONT_SIZE: f32 = 24.0;
+
+/// Draw a box listing keyboard shortcuts and what they do
+///
+/// # Arguments
+///
+/// * `bindings` - Pairs of key names and descriptions
+/// * `x` - The left edge of the box
+/// * `y` - The top edge of the box
+pub fn draw_help_overlay(bindings: &[(&str, &str)], x: f32, y: f32) {
+    let padding = 10.0;
+    let key_width = 110.0;
+    let width = 520.0;
+    let height = HELP_FONT_SIZE * bindings.len() as f32 + 2.0 * padding;
+    draw_rectangle(x, y, width, height, HELP_BACKGROUND);
+    draw_rectangle_lines(x, y, width, height, 1.0, colors::DARKGRAY);
+    for (index, (key, description)) in bindings.iter().enumerate() {
+        let baseline = y + padding + HELP_FONT_SIZE * (index as f32 + 0.8);
+        draw_text(
+            key,
+            x + padding,
+            baseline,
+            HELP_FONT_SIZE,
+            colors::DARKGREEN,
+        );
+        draw_text(
+            description,
+            x + padding + key_width,
+            baseline,
+            HELP_FONT_SIZE,
+            colors::BLACK,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::presets::{self, Walls};
+    use std::collections::HashSet;
+
+    #[test]
+    fn grid_view_fit() {
+        // Limited by the height, and centered horizontally.
+        let view = GridView::fit([100, 20], Rect::new(0.0, 0.0, 1060.0, 130.0));
+        assert_eq!(view.scaling, 6);
+        assert_eq!((view.width(), view.height()), (600.0, 120.0));
+        assert_eq!((view.x, view.y), (230.0, 0.0));
+
+        // Limited by the width.
+        let view = GridView::fit([100, 20], Rect::new(10.0, 5.0, 450.0, 480.0));
+        assert_eq!(view.scaling, 4);
+        assert_eq!((view.x, view.y), (35.0, 5.0));
+
+        // Tiny grids are blown up.
+        let view = GridView::fit([5, 4], Rect::new(0.0, 0.0, 800.0, 480.0));
+        assert_eq!(view.scaling, 120);
+        assert_eq!(view.x, 100.0);
+
+        // Cells never get smaller than a pixel, even if the grid overflows.
+        let view = GridView::fit([1000, 20], Rect::new(0.0, 0.0, 500.0, 480.0));
+        assert_eq!(view.scaling, 1);
+        assert_eq!(view.x, 0.0);
+    }
+
+    #[test]
+    fn centered_colors() {
+        let fluid = Cell::Fluid;
+        let (r, g, b) = hsl_to_rgb(120.0, 1.0, 0.5);
+        let midpoint = Color::new(r, g, b, 1.0);
+        // Zero is in the middle of the colors, however lopsided the range is.
+        for range in [[-1.0, 3.0], [-5.0, 0.5], [-2.0, 2.0]] {
+            assert_eq!(color_pressure(fluid, 0.0, range, true), midpoint);
+        }
+        assert_ne!(color_pressure(fluid, 0.0, [-1.0, 3.0], false), midpoint);
+        assert_eq!(color_pressure(fluid, 1.0, [-1.0, 3.0], false), midpoint);
+
+        // The ends of the colors are at the largest magnitude.
+        assert_eq!(normalize(3.0, [-1.0, 3.0], true), 1.0);
+        assert_eq!(normalize(-3.0, [-1.0, 3.0], true), 0.0);
+        assert_eq!(normalize(-1.5, [-1.0, 3.0], true), 0.25);
+        assert_eq!(normalize(0.0, [0.0, 0.0], true), 0.5);
+        assert_eq!(normalize(0.0, [0.0, 0.0], false), 0.5);
+    }
+
+    #[test]
+    fn clamped_colors() {
+        let fluid = Cell::Fluid;
+        let in_gamut = |color: Color| {
+            [color.r, color.g, color.b]
+                .iter()
+                .all(|c| (0.0..=1.0).contains(c))
+        };
+        let range = [-1.0, 1.0];
+        for center_zero in [false, true] {
+            let low = color_pressure(fluid, -1.0, range, center_zero);
+            let high = color_pressure(fluid, 1.0, range, center_zero);
+            assert_ne!(low, high);
+            for pressure in [-1e6, -1.5] {
+                let color = color_pressure(fluid, pressure, range, center_zero);
+                assert_eq!(color, low);
+            }
+            for pressure in [1.5, 1e6] {
+                let color = color_pressure(fluid, pressure, range, center_zero);
+                assert_eq!(color, high);
+            }
+        }
+
+        let range = [0.5, 2.0];
+        let slow = color_speed(fluid, 0.5, 0.0, range);
+        let fast = color_speed(fluid, 2.0, 0.0, range);
+        assert_eq!(color_speed(fluid, 0.0, 0.0, range), slow);
+        assert_eq!(color_speed(fluid, 30.0, -40.0, range), fast);
+        for speed in [0.0, 1.0, 50.0] {
+            assert!(in_gamut(color_speed(fluid, speed, 0.0, range)));
+        }
+        assert!(in_gamut(color_speed(fluid, 1.0, 0.0, [1.0, 1.0])));
+    }
+
+    #[test]
+    fn out_of_range_hues() {
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        let blue = Color::new(0.0, 0.0, 1.0, 1.0);
+        // Values past the ends of the range stay at red and blue instead of
+        // wrapping around the color wheel.
+        assert_eq!(color_speed(Cell::Fluid, 3.0, 4.0, [0.0, 1.0]), red);
+        assert_eq!(color_pressure(Cell::Fluid, 7.0, [0.0, 1.0], false), red);
+        assert_eq!(color_pressure(Cell::Fluid, -7.0, [0.0, 1.0], false), blue);
+
+        assert_eq!(hsl_to_rgb(-120.0, 1.0, 0.5), hsl_to_rgb(240.0, 1.0, 0.5));
+        assert_eq!(hsl_to_rgb(360.0, 1.0, 0.5), (1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn axis_tick_spacing() {
+        assert_eq!(tick_step(100.0, 50.0), 0.5);
+        assert_eq!(tick_step(100.0, 30.0), 0.5);
+        assert_eq!(tick_step(100.0, 100.0), 1.0);
+        assert_eq!(tick_step(100.0, 101.0), 2.0);
+        assert_eq!(tick_step(4.0, 50.0), 20.0);
+
+        // 100 cells of 0.1 at 10 pixels per cell are 10 units long.
+        let ticks = axis_ticks(100, 0.1, 10);
+        assert_eq!(ticks.len(), 21);
+        assert_eq!(ticks[1], (50.0, 0.5));
+        assert_eq!(ticks[20], (1000.0, 10.0));
+
+        // The far end doesn't get a tick if it's between two.
+        let ticks = axis_ticks(30, 0.1, 4);
+        assert_eq!(
+            ticks.iter().map(|tick| tick.1).collect::<Vec<_>>(),
+            [0.0, 2.0]
+        );
+        assert_eq!(tick_decimals(2.0), 0);
+        assert_eq!(tick_decimals(0.5), 1);
+        assert_eq!(tick_decimals(0.02), 2);
+    }
+
+    #[test]
+    fn grid_view_cells() {
+        let view = GridView::fit([10, 5], Rect::new(20.0, 10.0, 60.0, 100.0));
+        assert_eq!(view.scaling, 6);
+        assert_eq!(view.cell_position((0, 0)), (20.0, 10.0));
+        assert_eq!(view.cell_position((3, 2)), (38.0, 22.0));
+
+        // The mouse mapping is the inverse of the cell positions.
+        for idx in [(0, 0), (3, 2), (9, 4)] {
+            let (x, y) = view.cell_position(idx);
+            assert_eq!(view.cell_at(x, y), Some(idx));
+            assert_eq!(view.cell_at(x + 5.9, y + 5.9), Some(idx));
+        }
+        assert_eq!(view.cell_at(19.9, 15.0), None);
+        assert_eq!(view.cell_at(25.0, 9.9), None);
+        assert_eq!(view.cell_at(80.0, 15.0), None);
+        assert_eq!(view.cell_at(25.0, 40.0), None);
+
+        // Outside the grid, the nearest cell is on its edge.
+        assert_eq!(view.nearest_cell(38.0, 22.0), (3, 2));
+        assert_eq!(view.nearest_cell(0.0, 22.0), (0, 2));
+        assert_eq!(view.nearest_cell(38.0, 200.0), (3, 4));
+        assert_eq!(view.nearest_cell(500.0, -50.0), (9, 0));
+    }
+
+    #[test]
+    fn obstacle_outline() {
+        let size = [30, 14];
+        let grid =
+            presets::obstacle(size, [1.0, 0.0], (12, 7), 4.0, Walls::default()).unwrap();
+        let segments = boundary_outline_segments(&grid, 1);
+
+        // Every face between a fluid and a boundary cell, including the
+        // staircase around the circle, is stroked exactly once.
+        let mut expected = HashSet::new();
+        for x in 0..size[0] {
+            for y in 0..size[1] {
+                if grid.cell_type[(x, y)] != Cell::Fluid {
+                    continue;
+                }
+                let (x, y) = (x as f32, y as f32);
+                for (neighbor, face) in [
+                    ((x, y - 1.0), [(x, y), (x + 1.0, y)]),
+                    ((x + 1.0, y), [(x + 1.0, y), (x + 1.0, y + 1.0)]),
+                    ((x, y + 1.0), [(x, y + 1.0), (x + 1.0, y + 1.0)]),
+                    ((x - 1.0, y), [(x, y), (x, y + 1.0)]),
+                ] {
+                    let neighbor = (neighbor.0 as usize, neighbor.1 as usize);
+                    if let Cell::Boundary(_) = grid.cell_type[neighbor] {
+                        expected.insert(format!("{:?}", face));
+                    }
+                }
+            }
+        }
+        let actual: HashSet<_> = segments.iter().map(|s| format!("{:?}", s)).collect();
+        assert_eq!(actual.len(), segments.len());
+        assert_eq!(actual, expected);
+    }
+}
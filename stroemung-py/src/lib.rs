@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use ndarray::Array;
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2, ToPyArray};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+
+use stroemung::args::Omega;
+use stroemung::builder::SimulationBuilder;
+use stroemung::cell::{BoundaryCell, Cell};
+use stroemung::grid::SimulationGrid;
+use stroemung::math::Real;
+use stroemung::simulation::{Format, Simulation};
+use stroemung::types::{GridArray, GridIndex};
+
+create_exception!(
+    stroemung,
+    SimulationError,
+    PyException,
+    "An error from setting up or running a simulation"
+);
+create_exception!(
+    stroemung,
+    GridError,
+    PyException,
+    "A change to the grid that would leave it invalid"
+);
+
+fn simulation_error(err: stroemung::simulation::SimulationError) -> PyErr {
+    SimulationError::new_err(err.to_string())
+}
+
+fn grid_error(err: stroemung::grid::SimulationGridError) -> PyErr {
+    GridError::new_err(err.to_string())
+}
+
+// The codes of the cell types in the arrays returned by `cell_type`
+const FLUID: u8 = 0;
+const NO_SLIP: u8 = 1;
+const SYMMETRY: u8 = 2;
+const INFLOW: u8 = 3;
+const OUTFLOW: u8 = 4;
+const CONVECTIVE_OUTFLOW: u8 = 5;
+
+fn cell_code(cell: &Cell) -> u8 {
+    match cell {
+        Cell::Fluid => FLUID,
+        Cell::Boundary(BoundaryCell::NoSlip) => NO_SLIP,
+        Cell::Boundary(BoundaryCell::Symmetry) => SYMMETRY,
+        Cell::Boundary(BoundaryCell::Inflow { .. }) => INFLOW,
+        Cell::Boundary(BoundaryCell::Outflow) => OUTFLOW,
+        Cell::Boundary(BoundaryCell::ConvectiveOutflow { .. }) => CONVECTIVE_OUTFLOW,
+    }
+}
+
+/// A simulation, with the grid indexed as `[x, y]` like in the Rust crate
+///
+/// The arrays are copies, since the simulation updates its own arrays in
+/// place on every tick.
+#[pyclass]
+struct PySimulation {
+    sim: Simulation,
+}
+
+#[pymethods]
+impl PySimulation {
+    /// A new simulation of `size` cells, including the outer ring of no-slip
+    /// walls, with the fluid at rest unless `u`, `v` or `pressure` are given
+    ///
+    /// The other parameters default to the same values as on the command line.
+    #[new]
+    #[pyo3(signature = (
+        size,
+        *,
+        u = None,
+        v = None,
+        pressure = None,
+        cell_size = (0.1, 0.2),
+        delt = 0.005,
+        gamma = 0.9,
+        reynolds = 100.0,
+        density = 1.0,
+        omega = 1.7,
+        sor_epsilon = 0.001,
+        max_iterations = 100,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        size: (usize, usize),
+        u: Option<PyReadonlyArray2<Real>>,
+        v: Option<PyReadonlyArray2<Real>>,
+        pressure: Option<PyReadonlyArray2<Real>>,
+        cell_size: (Real, Real),
+        delt: Real,
+        gamma: Real,
+        reynolds: Real,
+        density: Real,
+        omega: Real,
+        sor_epsilon: Real,
+        max_iterations: u32,
+    ) -> PyResult<Self> {
+        let (w, h) = size;
+        let field = |array: Option<PyReadonlyArray2<Real>>| -> GridArray<Real> {
+            match array {
+                Some(array) => array.as_array().to_owned(),
+                None => Array::zeros(size),
+            }
+        };
+        let cell_type = Array::from_shape_fn(size, |(x, y)| {
+            if x == 0 || y == 0 || x == w - 1 || y == h - 1 {
+                Cell::Boundary(BoundaryCell::NoSlip)
+            } else {
+                Cell::Fluid
+            }
+        });
+        let grid = SimulationGrid::from_fields(
+            [w, h],
+            field(pressure),
+            field(u),
+            field(v),
+            cell_type,
+        )
+        .map_err(grid_error)?;
+        let sim = SimulationBuilder::new()
+            .grid(grid)
+            .cell_size([cell_size.0, cell_size.1])
+            .delt(delt)
+            .gamma(gamma)
+            .reynolds(reynolds)
+            .density(density)
+            .omega(Omega::Value(omega))
+            .sor_absolute_epsilon(sor_epsilon)
+            .max_iterations(max_iterations)
+            .build()
+            .map_err(simulation_error)?;
+        Ok(PySimulation { sim })
+    }
+
+    /// Load a saved simulation. Files ending in `.msgpack` or `.mpk` are read
+    /// as MessagePack, anything else as JSON.
+    #[staticmethod]
+    fn from_json(path: PathBuf) -> PyResult<Self> {
+        let reader = BufReader::new(File::open(&path)?);
+        let sim = Simulation::from_reader_format(reader, Format::from_path(&path))
+            .map_err(simulation_error)?;
+        Ok(PySimulation { sim })
+    }
+
+    /// Save the simulation in the format that matches the extension of `path`
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.sim
+            .to_writer_format(&mut writer, Format::from_path(&path))
+            .map_err(simulation_error)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Run `n` ticks and return the number of SOR iterations and the residual
+    /// norm of the last one
+    #[pyo3(signature = (n = 1))]
+    fn tick(&mut self, n: u32) -> PyResult<(u32, Real)> {
+        let mut result = (0, 0.0);
+        for _ in 0..n {
+            let stats = self.sim.run_simulation_tick().map_err(simulation_error)?;
+            result = (stats.sor_iterations, stats.residual.l2_squared.sqrt());
+        }
+        Ok(result)
+    }
+
+    /// Set the type of cells, given as `(x, y, kind)` where `kind` is
+    /// `"fluid"`, `"noslip"`, `"symmetry"`, `"outflow"`, `"inflow:<u>,<v>"` or
+    /// `"convective-outflow:<speed>"`
+    ///
+    /// Cells of the same kind are changed together, in the order in which
+    /// their kind first appears. Raises `GridError` and leaves the cells of
+    /// that kind unchanged if they would make the grid invalid.
+    fn set_cells(&mut self, cells: Vec<(usize, usize, String)>) -> PyResult<()> {
+        let mut kinds: Vec<String> = Vec::new();
+        let mut groups: HashMap<String, Vec<GridIndex>> = HashMap::new();
+        for (x, y, kind) in cells {
+            if !groups.contains_key(&kind) {
+                kinds.push(kind.clone());
+            }
+            groups.entry(kind).or_default().push((x, y));
+        }
+        for kind in kinds {
+            let cell: Cell = kind.parse().map_err(PyValueError::new_err)?;
+            self.sim
+                .grid
+                .set_cells(groups.remove(&kind).unwrap_or_default(), cell)
+                .map_err(grid_error)?;
+        }
+        Ok(())
+    }
+
+    fn pressure<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<Real>> {
+        self.sim.grid.pressure.to_pyarray(py)
+    }
+
+    fn u<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<Real>> {
+        self.sim.grid.u.to_pyarray(py)
+    }
+
+    fn v<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<Real>> {
+        self.sim.grid.v.to_pyarray(py)
+    }
+
+    /// The type of every cell as a `uint8` code, see the `FLUID`, `NO_SLIP`,
+    /// etc. constants of the module
+    fn cell_type<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<u8>> {
+        self.sim.grid.cell_type.map(cell_code).into_pyarray(py)
+    }
+
+    /// The simulated time
+    #[getter]
+    fn time(&self) -> Real {
+        self.sim.time
+    }
+
+    /// The number of ticks run so far
+    #[getter]
+    fn iterations(&self) -> u32 {
+        self.sim.iterations
+    }
+}
+
+/// Python bindings for the stroemung CFD solver
+#[pymodule]
+#[pyo3(name = "stroemung")]
+fn stroemung_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySimulation>()?;
+    m.add("SimulationError", m.py().get_type::<SimulationError>())?;
+    m.add("GridError", m.py().get_type::<GridError>())?;
+    m.add("FLUID", FLUID)?;
+    m.add("NO_SLIP", NO_SLIP)?;
+    m.add("SYMMETRY", SYMMETRY)?;
+    m.add("INFLOW", INFLOW)?;
+    m.add("OUTFLOW", OUTFLOW)?;
+    m.add("CONVECTIVE_OUTFLOW", CONVECTIVE_OUTFLOW)?;
+    Ok(())
+}
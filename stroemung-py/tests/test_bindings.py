@@ -0,0 +1,73 @@
+#!/usr/bin/env python3
+
+from pathlib import Path
+
+import numpy as np
+import pytest
+
+import stroemung
+
+
+TEST_DATA_DIR = Path(Path(__file__).parent.resolve(), "..", "..", "src", "test_data")
+
+
+def load_fixture():
+    return stroemung.PySimulation.from_json(
+        Path(TEST_DATA_DIR, "simple_simulation.json")
+    )
+
+
+def test_fixture_arrays():
+    sim = load_fixture()
+    iterations, norm = sim.tick(3)
+    assert iterations > 0
+    assert norm >= 0.0
+    assert sim.iterations == 3
+
+    for array in [sim.pressure(), sim.u(), sim.v()]:
+        assert array.shape == (5, 7)
+        assert array.dtype == np.float64
+    cell_type = sim.cell_type()
+    assert cell_type.shape == (5, 7)
+    assert cell_type.dtype == np.uint8
+    assert cell_type[2, 3] == stroemung.FLUID
+
+
+def test_save_and_load(tmp_path):
+    sim = load_fixture()
+    sim.tick(2)
+    for name in ["saved.json", "saved.msgpack"]:
+        sim.save(tmp_path / name)
+        loaded = stroemung.PySimulation.from_json(tmp_path / name)
+        assert loaded.iterations == 2
+        np.testing.assert_array_equal(loaded.pressure(), sim.pressure())
+
+
+def test_new_and_set_cells():
+    u = np.full((10, 8), 1.0)
+    sim = stroemung.PySimulation((10, 8), u=u, delt=0.001)
+    np.testing.assert_array_equal(sim.u(), u)
+    sim.set_cells(
+        [(0, y, "inflow:1,0") for y in range(1, 7)]
+        + [(9, y, "outflow") for y in range(1, 7)]
+    )
+    assert sim.cell_type()[0, 3] == stroemung.INFLOW
+    assert sim.cell_type()[9, 3] == stroemung.OUTFLOW
+    sim.tick(5)
+    assert sim.time == pytest.approx(0.005)
+
+
+def test_errors():
+    with pytest.raises(stroemung.SimulationError, match="delt"):
+        stroemung.PySimulation((10, 8), delt=-1.0)
+    with pytest.raises(stroemung.GridError, match="shape"):
+        stroemung.PySimulation((10, 8), u=np.zeros((3, 3)))
+
+    sim = load_fixture()
+    # A single wall cell would have fluid on opposite sides.
+    with pytest.raises(stroemung.GridError, match="opposing sides"):
+        sim.set_cells([(2, 3, "noslip")])
+    with pytest.raises(ValueError, match="wall"):
+        sim.set_cells([(2, 3, "wall")])
+    with pytest.raises(OSError):
+        stroemung.PySimulation.from_json("does_not_exist.json")
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use ndarray::Array;
+use proptest::prelude::*;
+
+use stroemung::builder::SimulationBuilder;
+use stroemung::cell::{BoundaryCell, Cell};
+use stroemung::grid::{EdgeType, SimulationGrid, SimulationGridError};
+use stroemung::math::Real;
+use stroemung::simulation::Simulation;
+use stroemung::types::{GridIndex, GridSize, Velocity};
+
+const DELT: Real = 0.005;
+const CELL_SIZE: [Real; 2] = [0.1, 0.2];
+
+// A channel with inflow on the left, outflow on the right and walls on the
+// top and bottom. The interior is split into 2x2 blocks starting at (1, 1),
+// and each block is either fluid or an obstacle. Every boundary cell then has
+// another boundary cell next to it horizontally and vertically, so no
+// boundary has fluid on opposing sides and the grid passes `calculate_edges`.
+fn channel_with_blocks(
+    blocks: [usize; 2],
+    obstacles: &[Option<BoundaryCell>],
+    inflow: Velocity,
+    seed: usize,
+) -> Result<SimulationGrid, SimulationGridError> {
+    let size: GridSize = [2 * blocks[0] + 2, 2 * blocks[1] + 2];
+    let mut cell_type = Array::from_elem(size, Cell::Fluid);
+    for x in 0..size[0] {
+        cell_type[(x, 0)] = Cell::Boundary(BoundaryCell::NoSlip);
+        cell_type[(x, size[1] - 1)] = Cell::Boundary(BoundaryCell::NoSlip);
+    }
+    for y in 1..(size[1] - 1) {
+        cell_type[(0, y)] = Cell::Boundary(BoundaryCell::Inflow { velocity: inflow });
+        cell_type[(size[0] - 1, y)] = Cell::Boundary(BoundaryCell::Outflow);
+    }
+    for (block, obstacle) in obstacles.iter().enumerate() {
+        let Some(boundary) = obstacle else {
+            continue;
+        };
+        let (x, y) = (1 + 2 * (block % blocks[0]), 1 + 2 * (block / blocks[0]));
+        for idx in [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)] {
+            cell_type[idx] = Cell::Boundary(*boundary);
+        }
+    }
+
+    // Arbitrary but reproducible velocities, so that any velocity the
+    // boundary conditions touch is likely to change.
+    let velocity = |(x, y): GridIndex, offset: usize| {
+        ((x * 7 + y * 13 + seed + offset) % 11) as Real / 10.0 - 0.5
+    };
+    SimulationGrid::from_fields(
+        size,
+        Array::zeros(size),
+        Array::from_shape_fn(size, |idx| velocity(idx, 0)),
+        Array::from_shape_fn(size, |idx| velocity(idx, 5)),
+        cell_type,
+    )
+}
+
+// The block counts, obstacles and velocity seed of a `channel_with_blocks`
+fn random_grid_parts(
+) -> impl Strategy<Value = ([usize; 2], Vec<Option<BoundaryCell>>, usize)> {
+    let obstacle = prop_oneof![
+        3 => Just(None),
+        1 => Just(Some(BoundaryCell::NoSlip)),
+        1 => Just(Some(BoundaryCell::Symmetry)),
+    ];
+    (2usize..7, 2usize..6)
+        .prop_flat_map(move |(x, y)| {
+            (
+                Just([x, y]),
+                proptest::collection::vec(obstacle.clone(), x * y),
+                0usize..11,
+            )
+        })
+        // Without any fluid there's no pressure equation to solve.
+        .prop_filter("no fluid cells", |(_, obstacles, _)| {
+            obstacles.iter().any(Option::is_none)
+        })
+}
+
+fn random_grid() -> impl Strategy<Value = Result<SimulationGrid, SimulationGridError>> {
+    (random_grid_parts(), (0.0..2.0 as Real, -0.5..0.5 as Real)).prop_map(
+        |((blocks, obstacles, seed), (u, v))| {
+            channel_with_blocks(blocks, &obstacles, [u, v], seed)
+        },
+    )
+}
+
+// Edits of a grid: the top left corner of a square, modulo the grid size, its
+// width and the cell type to fill it with
+fn edits() -> impl Strategy<Value = Vec<(GridIndex, usize, Cell)>> {
+    let cell = prop_oneof![
+        Just(Cell::Fluid),
+        Just(Cell::Boundary(BoundaryCell::NoSlip)),
+        Just(Cell::Boundary(BoundaryCell::Symmetry)),
+        Just(Cell::Boundary(BoundaryCell::Outflow)),
+        Just(Cell::Boundary(BoundaryCell::Inflow {
+            velocity: [1.0, 0.5]
+        })),
+    ];
+    proptest::collection::vec(((0usize..20, 0usize..20), 1usize..=2, cell), 1..10)
+}
+
+// The velocities normal to the boundary between a boundary cell and its fluid
+// neighbors, as (cell, component) pairs with 0 for u and 1 for v
+fn normal_faces(boundary_idx: GridIndex, edge: EdgeType) -> Vec<(GridIndex, usize)> {
+    match edge {
+        EdgeType::North { north_neighbor } => vec![(north_neighbor, 1)],
+        EdgeType::NorthEast { north_neighbor, .. } => {
+            vec![(north_neighbor, 1), (boundary_idx, 0)]
+        }
+        EdgeType::East { .. } => vec![(boundary_idx, 0)],
+        EdgeType::SouthEast { .. } => vec![(boundary_idx, 1), (boundary_idx, 0)],
+        EdgeType::South { .. } => vec![(boundary_idx, 1)],
+        EdgeType::SouthWest { west_neighbor, .. } => {
+            vec![(boundary_idx, 1), (west_neighbor, 0)]
+        }
+        EdgeType::West { west_neighbor } => vec![(west_neighbor, 0)],
+        EdgeType::NorthWest {
+            north_neighbor,
+            west_neighbor,
+        } => vec![(north_neighbor, 1), (west_neighbor, 0)],
+    }
+}
+
+fn simulation(grid: SimulationGrid) -> Simulation {
+    SimulationBuilder::new()
+        .grid(grid)
+        .cell_size(CELL_SIZE)
+        .delt(DELT)
+        .build()
+        .unwrap()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn restore_list_matches_boundary_updates(grid in random_grid()) {
+        let mut grid = grid.unwrap();
+        let (old_u, old_v) = (grid.u.clone(), grid.v.clone());
+        grid.set_boundary_u_and_v(DELT, CELL_SIZE).unwrap();
+
+        // Replaying the list in order, like `set_u_and_v` does.
+        let mut restored = HashMap::new();
+        for (idx, u, v) in &grid.boundaries.u_v_restore {
+            if let Some(u) = u {
+                restored.insert((*idx, 0), *u);
+            }
+            if let Some(v) = v {
+                restored.insert((*idx, 1), *v);
+            }
+        }
+
+        // Restoring must not change what the boundary conditions set...
+        for (&(idx, component), value) in &restored {
+            let current = [grid.u[idx], grid.v[idx]][component];
+            prop_assert_eq!(*value, current, "restore of {:?}[{}]", idx, component);
+        }
+        // ...and must cover every velocity they changed.
+        for (idx, old) in old_u.indexed_iter() {
+            if grid.u[idx] != *old {
+                prop_assert!(restored.contains_key(&(idx, 0)), "u {:?} not restored", idx);
+            }
+        }
+        for (idx, old) in old_v.indexed_iter() {
+            if grid.v[idx] != *old {
+                prop_assert!(restored.contains_key(&(idx, 1)), "v {:?} not restored", idx);
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_update_matches_rebuild(
+        (blocks, obstacles, seed) in random_grid_parts(),
+        edits in edits(),
+    ) {
+        let build = || channel_with_blocks(blocks, &obstacles, [1.0, 0.0], seed).unwrap();
+        let (mut incremental, mut rebuilt) = (build(), build());
+        let size = incremental.size;
+        for ((x, y), width, cell) in edits {
+            let (x, y) = (x % size[0], y % size[1]);
+            let mut affected = Vec::new();
+            for idx in [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)] {
+                if idx.0 < size[0] && idx.1 < size[1] && (idx == (x, y) || width == 2) {
+                    affected.push((idx, incremental.cell_type[idx]));
+                }
+            }
+            for grid in [&mut incremental, &mut rebuilt] {
+                for &(idx, _) in &affected {
+                    grid.cell_type[idx] = cell;
+                }
+            }
+            let cells: Vec<GridIndex> = affected.iter().map(|(idx, _)| *idx).collect();
+            let before = incremental.boundaries.sorted_boundary_list.clone();
+            let result = incremental.update_boundary_region(&cells);
+            prop_assert_eq!(result.is_ok(), rebuilt.rebuild_boundary_list().is_ok());
+            if result.is_err() {
+                // The list is untouched, so putting the cells back makes the
+                // grid valid again.
+                prop_assert_eq!(&incremental.boundaries.sorted_boundary_list, &before);
+                for grid in [&mut incremental, &mut rebuilt] {
+                    for &(idx, old) in &affected {
+                        grid.cell_type[idx] = old;
+                    }
+                }
+                rebuilt.rebuild_boundary_list().unwrap();
+            }
+
+            let (a, b) = (&incremental.boundaries, &rebuilt.boundaries);
+            prop_assert_eq!(&a.sorted_boundary_list, &b.sorted_boundary_list);
+            prop_assert_eq!(&a.sources, &b.sources);
+            prop_assert_eq!(a.fluid_cells, b.fluid_cells);
+        }
+    }
+
+    #[test]
+    fn walls_stay_closed_after_tick(grid in random_grid()) {
+        let mut sim = simulation(grid.unwrap());
+        sim.run_simulation_tick().unwrap();
+
+        let grid = &sim.grid;
+        for &(boundary_idx, edge) in &grid.boundaries.sorted_boundary_list {
+            let Some(edge) = edge else {
+                continue;
+            };
+            let expected = match grid.cell_type[boundary_idx] {
+                Cell::Boundary(BoundaryCell::NoSlip | BoundaryCell::Symmetry) => [0.0, 0.0],
+                Cell::Boundary(BoundaryCell::Inflow { velocity }) => velocity,
+                _ => continue,
+            };
+            for (idx, component) in normal_faces(boundary_idx, edge) {
+                let value = [grid.u[idx], grid.v[idx]][component];
+                prop_assert_eq!(
+                    value,
+                    expected[component],
+                    "face {:?}[{}] of {:?}",
+                    idx,
+                    component,
+                    boundary_idx
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn stroemung(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_stroemung"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("stroemung-cli-{}-{}", std::process::id(), name))
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+const SIMULATION: &str = "src/test_data/simple_simulation.json";
+const GRID: &str = "src/test_data/small_grid_with_boundaries.json";
+
+#[test]
+fn run_is_the_default() {
+    let with_run = stdout(&stroemung(&["run", "--x-cells", "64", "--dump-config"]));
+    let without = stdout(&stroemung(&["--x-cells", "64", "--dump-config"]));
+    assert_eq!(with_run, without);
+    assert!(with_run.contains("x-cells = 64"), "{}", with_run);
+}
+
+#[test]
+fn flags_before_a_subcommand() {
+    let output = stroemung(&["--x-cells", "64", "simulate"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn simulate() {
+    let output = stroemung(&[
+        "simulate",
+        "--x-cells",
+        "40",
+        "--y-cells",
+        "16",
+        "--ticks",
+        "3",
+        "-q",
+    ]);
+    stdout(&output);
+}
+
+#[test]
+fn info() {
+    let info = stdout(&stroemung(&["info", SIMULATION]));
+    assert!(info.contains("5x7 cells"), "{}", info);
+    assert!(info.contains("Cells: 35 fluid"), "{}", info);
+    assert!(!stroemung(&["info", "does-not-exist.json"]).status.success());
+}
+
+#[test]
+fn convert() {
+    let compressed = temp_file("convert.msgpack.gz");
+    let json = temp_file("convert.json");
+    stdout(&stroemung(&["convert", SIMULATION, path(&compressed)]));
+    stdout(&stroemung(&["convert", path(&compressed), path(&json)]));
+    let original = stdout(&stroemung(&["info", SIMULATION]));
+    let converted = stdout(&stroemung(&["info", path(&json)]));
+    std::fs::remove_file(&compressed).unwrap();
+    std::fs::remove_file(&json).unwrap();
+    // Only the file names differ.
+    assert_eq!(
+        original.lines().skip(1).collect::<Vec<_>>(),
+        converted.lines().skip(1).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn convert_grid_to_ascii() {
+    let ascii = temp_file("convert.txt");
+    stdout(&stroemung(&[
+        "convert",
+        "--from",
+        "grid",
+        GRID,
+        path(&ascii),
+        "--to",
+        "ascii",
+    ]));
+    let text = std::fs::read_to_string(&ascii).unwrap();
+    std::fs::remove_file(&ascii).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+    assert!(text.contains('#'), "{}", text);
+}
+
+#[test]
+fn render() {
+    let png = temp_file("render.png");
+    stdout(&stroemung(&[
+        "render",
+        SIMULATION,
+        "--out",
+        path(&png),
+        "--scale",
+        "3",
+    ]));
+    let dimensions = image::image_dimensions(&png).unwrap();
+    std::fs::remove_file(&png).unwrap();
+    assert_eq!(dimensions, (15, 21));
+}
+
+#[test]
+fn invalid_settings() {
+    let output =
+        stroemung(&["simulate", "--omega", "3", "--delta-t", "0", "--ticks", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--omega must be"), "{}", stderr);
+    assert!(stderr.contains("--delta-t must be"), "{}", stderr);
+}
+
+#[test]
+fn time_average() {
+    let csv = temp_file("time-average.csv");
+    stdout(&stroemung(&[
+        "simulate",
+        "--x-cells",
+        "40",
+        "--y-cells",
+        "16",
+        "--ticks",
+        "3",
+        "-q",
+        "--time-average",
+        path(&csv),
+    ]));
+    let text = std::fs::read_to_string(&csv).unwrap();
+    std::fs::remove_file(&csv).unwrap();
+    let mut lines = text.lines();
+    assert_eq!(
+        lines.next(),
+        Some("x,y,mean_u,mean_v,mean_pressure,variance_u,variance_v,variance_pressure")
+    );
+    assert_eq!(lines.count(), 40 * 16);
+}
+
+#[test]
+fn repair_geometry() {
+    use stroemung::cell::{BoundaryCell, Cell};
+    use stroemung::grid::presets::{self, Walls};
+
+    // A channel with a wall one cell thick hanging down from the top
+    let mut grid = presets::simple_inflow([40, 16], [1.0, 0.0], Walls::default());
+    for y in 1..6 {
+        grid.cell_type[(10, y)] = Cell::Boundary(BoundaryCell::NoSlip);
+    }
+    let input = temp_file("thin-wall.json");
+    let output = temp_file("repaired.txt");
+    std::fs::write(&input, serde_json::to_string(&grid).unwrap()).unwrap();
+    let args = [
+        "convert",
+        "--from",
+        "grid",
+        path(&input),
+        path(&output),
+        "--to",
+        "ascii",
+    ];
+    let rejected = stroemung(&args);
+    assert!(!rejected.status.success());
+    let stderr = String::from_utf8_lossy(&rejected.stderr);
+    assert!(
+        stderr.contains("(10, 5): fluid to the left and right"),
+        "{}",
+        stderr
+    );
+
+    stdout(&stroemung(&[&args[..], &["--repair-geometry"]].concat()));
+    let ascii = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    for line in ascii.lines().skip(1).take(5) {
+        assert_eq!(&line[10..12], "##", "{}", ascii);
+    }
+}
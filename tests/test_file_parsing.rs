@@ -17,5 +17,9 @@ fn deserialize() {
     let unfinalized: UnfinalizedSimulationGrid =
         serde_json::from_reader(load_test_file("small_data.out.json")).unwrap();
     let result = SimulationGrid::try_from(unfinalized).unwrap();
-    insta::assert_json_snapshot!(result);
+    let mut settings = insta::Settings::clone_current();
+    if cfg!(feature = "single-precision") {
+        settings.set_snapshot_suffix("single_precision");
+    }
+    settings.bind(|| insta::assert_json_snapshot!(result));
 }